@@ -0,0 +1,245 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+pub const AES_BLOCK_SIZE: usize = 16;
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Inverse of SBOX, computed at compile time
+const INV_SBOX: [u8; 256] = {
+    let mut inv = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv[SBOX[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv
+};
+
+/// Multiplication by x in GF(2^8)
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    result
+}
+
+/// AES block cipher (FIPS 197) with a 128, 192 or 256 bit key
+#[derive(Clone)]
+pub struct Aes {
+    round_keys: Vec<[u8; AES_BLOCK_SIZE]>,
+}
+
+impl Aes {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let nk = match key.len() {
+            16 | 24 | 32 => key.len() / 4,
+            len => return Err(anyhow!("Invalid AES key length: {}", len)),
+        };
+        let rounds = nk + 6;
+        let mut words: Vec<[u8; 4]> = key.chunks(4).map(|w| [w[0], w[1], w[2], w[3]]).collect();
+        for i in nk..4 * (rounds + 1) {
+            let mut temp = words[i - 1];
+            if i % nk == 0 {
+                temp = [SBOX[temp[1] as usize] ^ RCON[i / nk - 1], SBOX[temp[2] as usize], SBOX[temp[3] as usize], SBOX[temp[0] as usize]];
+            } else if nk > 6 && i % nk == 4 {
+                temp = [SBOX[temp[0] as usize], SBOX[temp[1] as usize], SBOX[temp[2] as usize], SBOX[temp[3] as usize]];
+            }
+            let prev = words[i - nk];
+            words.push([prev[0] ^ temp[0], prev[1] ^ temp[1], prev[2] ^ temp[2], prev[3] ^ temp[3]]);
+        }
+        let round_keys = words.chunks(4).map(|w| {
+            let mut k = [0u8; AES_BLOCK_SIZE];
+            for (i, word) in w.iter().enumerate() {
+                k[i * 4..i * 4 + 4].copy_from_slice(word);
+            }
+            k
+        }).collect();
+        Ok(Self {
+            round_keys,
+        })
+    }
+
+    fn rounds(&self) -> usize {
+        self.round_keys.len() - 1
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..self.rounds() {
+            sub_bytes(block, &SBOX);
+            shift_rows(block);
+            mix_columns(block);
+            add_round_key(block, &self.round_keys[round]);
+        }
+        sub_bytes(block, &SBOX);
+        shift_rows(block);
+        add_round_key(block, &self.round_keys[self.rounds()]);
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        add_round_key(block, &self.round_keys[self.rounds()]);
+        for round in (1..self.rounds()).rev() {
+            inv_shift_rows(block);
+            sub_bytes(block, &INV_SBOX);
+            add_round_key(block, &self.round_keys[round]);
+            inv_mix_columns(block);
+        }
+        inv_shift_rows(block);
+        sub_bytes(block, &INV_SBOX);
+        add_round_key(block, &self.round_keys[0]);
+    }
+}
+
+fn add_round_key(block: &mut [u8; AES_BLOCK_SIZE], key: &[u8; AES_BLOCK_SIZE]) {
+    for (b, k) in block.iter_mut().zip(key) {
+        *b ^= k;
+    }
+}
+
+fn sub_bytes(block: &mut [u8; AES_BLOCK_SIZE], table: &[u8; 256]) {
+    for b in block.iter_mut() {
+        *b = table[*b as usize];
+    }
+}
+
+// the state is column-major: block[4 * column + row]
+fn shift_rows(block: &mut [u8; AES_BLOCK_SIZE]) {
+    let s = *block;
+    for row in 1..4 {
+        for col in 0..4 {
+            block[4 * col + row] = s[4 * ((col + row) % 4) + row];
+        }
+    }
+}
+
+fn inv_shift_rows(block: &mut [u8; AES_BLOCK_SIZE]) {
+    let s = *block;
+    for row in 1..4 {
+        for col in 0..4 {
+            block[4 * ((col + row) % 4) + row] = s[4 * col + row];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; AES_BLOCK_SIZE]) {
+    for col in block.chunks_mut(4) {
+        let a = [col[0], col[1], col[2], col[3]];
+        let all = a[0] ^ a[1] ^ a[2] ^ a[3];
+        for i in 0..4 {
+            col[i] = a[i] ^ all ^ xtime(a[i] ^ a[(i + 1) % 4]);
+        }
+    }
+}
+
+fn inv_mix_columns(block: &mut [u8; AES_BLOCK_SIZE]) {
+    for col in block.chunks_mut(4) {
+        let a = [col[0], col[1], col[2], col[3]];
+        for i in 0..4 {
+            col[i] = gf_mul(a[i], 14) ^ gf_mul(a[(i + 1) % 4], 11) ^ gf_mul(a[(i + 2) % 4], 13) ^ gf_mul(a[(i + 3) % 4], 9);
+        }
+    }
+}
+
+/// CBC mode encryption. `data` must already be padded to the block size.
+pub fn aes_cbc_encrypt(aes: &Aes, iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if iv.len() != AES_BLOCK_SIZE || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+        return Err(anyhow!("Invalid CBC input: iv {} bytes, data {} bytes", iv.len(), data.len()));
+    }
+    let mut result: Vec<u8> = Vec::with_capacity(data.len());
+    let mut prev = [0u8; AES_BLOCK_SIZE];
+    prev.copy_from_slice(iv);
+    for chunk in data.chunks(AES_BLOCK_SIZE) {
+        for (p, c) in prev.iter_mut().zip(chunk) {
+            *p ^= c;
+        }
+        aes.encrypt_block(&mut prev);
+        result.extend_from_slice(&prev);
+    }
+    Ok(result)
+}
+
+/// CBC mode decryption. Padding is left for the caller to check.
+pub fn aes_cbc_decrypt(aes: &Aes, iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if iv.len() != AES_BLOCK_SIZE || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+        return Err(anyhow!("Invalid CBC input: iv {} bytes, data {} bytes", iv.len(), data.len()));
+    }
+    let mut result: Vec<u8> = Vec::with_capacity(data.len());
+    let mut prev = iv;
+    for chunk in data.chunks(AES_BLOCK_SIZE) {
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        block.copy_from_slice(chunk);
+        aes.decrypt_block(&mut block);
+        result.extend(block.iter().zip(prev).map(|(b, p)| b ^ p));
+        prev = chunk;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::from_hex;
+
+    #[test]
+    fn test_can_encrypt_fips197_vectors() {
+        let plain = from_hex("00112233445566778899aabbccddeeff");
+        let cases = [
+            ("000102030405060708090a0b0c0d0e0f", "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            ("000102030405060708090a0b0c0d0e0f1011121314151617", "dda97ca4864cdfe06eaf70a0ec0d7191"),
+            ("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f", "8ea2b7ca516745bfeafc49904b496089"),
+        ];
+        for (key, cipher) in cases.iter() {
+            let aes = Aes::new(&from_hex(key)).unwrap();
+            let mut block = [0u8; AES_BLOCK_SIZE];
+            block.copy_from_slice(&plain);
+            aes.encrypt_block(&mut block);
+            assert_eq!(from_hex(cipher), block.to_vec());
+            aes.decrypt_block(&mut block);
+            assert_eq!(plain, block.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_can_operate_cbc_mode() {
+        // NIST SP 800-38A F.2.1 CBC-AES128.Encrypt, first two blocks
+        let aes = Aes::new(&from_hex("2b7e151628aed2a6abf7158809cf4f3c")).unwrap();
+        let iv = from_hex("000102030405060708090a0b0c0d0e0f");
+        let plain = from_hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let cipher = aes_cbc_encrypt(&aes, &iv, &plain).unwrap();
+        assert_eq!(from_hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2"), cipher);
+        assert_eq!(plain, aes_cbc_decrypt(&aes, &iv, &cipher).unwrap());
+        assert!(aes_cbc_encrypt(&aes, &iv, &plain[..15]).is_err());
+        assert!(Aes::new(&[0u8; 15]).is_err());
+    }
+}
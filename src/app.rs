@@ -10,20 +10,53 @@ use anyhow::{
 };
 use std::{
     io::{
+        self,
+        BufRead,
         BufReader,
         BufWriter,
+        Read,
+        Write,
     },
+    sync::Arc,
 };
 
 use super::http::{
     ParsedUrl,
     ParsedProxyUrl,
-    http_get,
+    Method,
+    Request,
+    Response,
+    http_request,
+    request_event,
     tcp_write,
     tcp_read_line,
 };
-
-const HTTP_PORT: u32 = 80;
+use super::tls::client::{
+    self as tls_client,
+    ClientConfig,
+};
+use super::tls::keylog::{
+    KeyLog,
+    KeyLogFile,
+};
+use super::tls::policy::SecurityLevel;
+use super::tls::server::{
+    self as tls_server,
+    ClientAuth,
+    ServerConfig,
+};
+use super::tls::stream::RekeyLimit;
+use super::tls::suites::{
+    self,
+    CipherSuite,
+};
+use super::tls::trace::{
+    Direction,
+    StderrTracer,
+    TraceFormat,
+    Tracer,
+};
+use super::verify::TrustStore;
 
 pub trait App {
     fn run(&mut self) -> Result<()>;
@@ -32,32 +65,269 @@ pub trait App {
 pub struct Client {
     parsed_url: ParsedUrl,
     parsed_proxy_url: Option<ParsedProxyUrl>,
+    /// PEM file of trust anchors used instead of the system store for https
+    ca_file: Option<String>,
+    security_level: Option<SecurityLevel>,
+    cipher_suites: Option<Vec<&'static CipherSuite>>,
+    /// application protocols offered with ALPN
+    alpn_protocols: Vec<Vec<u8>>,
+    /// times the request is repeated over a new connection that resumes the session
+    reconnect: usize,
+    /// PEM files of the certificate chain and key sent when the server asks for one
+    cert_file: Option<String>,
+    key_file: Option<String>,
+    /// bytes written under one set of keys before they are changed
+    rekey_after: Option<u64>,
+    /// prints every record, handshake message and request to stderr
+    tracer: Option<Arc<dyn Tracer>>,
+    /// GET, or POST when there is data
+    method: Option<Method>,
+    /// header fields that replace those of the same name, where an empty value removes them
+    headers: Vec<(String, String)>,
+    data: Option<RequestData>,
+}
+
+/// A request body given on the command line
+enum RequestData {
+    Bytes(Vec<u8>),
+    /// a file sent as it is read
+    File(String),
+    Stdin,
 }
 
 impl Client {
-    pub fn new(args: &Vec<String>) -> Result<Self> {
-        // parsing url
+    pub fn new(args: &[String]) -> Result<Self> {
+        // parsing options
         let mut idx: usize = 2;
         let mut parsed_proxy_url: Option<ParsedProxyUrl> = None;
-        if args[idx] == "-p" {
-            idx += 1;
-            parsed_proxy_url = Some(ParsedProxyUrl::new(&args[idx])?);
-            idx += 1;
+        let mut ca_file: Option<String> = None;
+        let mut security_level: Option<SecurityLevel> = None;
+        let mut cipher_suites: Option<Vec<&'static CipherSuite>> = None;
+        let mut alpn_protocols: Vec<Vec<u8>> = vec![];
+        let mut reconnect: usize = 0;
+        let mut cert_file: Option<String> = None;
+        let mut key_file: Option<String> = None;
+        let mut rekey_after: Option<u64> = None;
+        let mut tracer: Option<Arc<dyn Tracer>> = None;
+        let mut method: Option<Method> = None;
+        let mut headers: Vec<(String, String)> = vec![];
+        let mut data: Option<RequestData> = None;
+        while idx < args.len() {
+            match &*args[idx] {
+                "-v" => {
+                    tracer = Some(stderr_tracer(TraceFormat::Text));
+                    idx += 1;
+                    continue;
+                },
+                "--trace" => tracer = Some(stderr_tracer(TraceFormat::from_name(option_value(args, idx)?)?)),
+                "-X" => method = Some(Method::from_name(option_value(args, idx)?)?),
+                "-H" => headers.push(parse_header(option_value(args, idx)?)?),
+                "-d" => data = Some(join_data(data, form_data(option_value(args, idx)?)?)?),
+                "--data-binary" => data = Some(join_data(data, binary_data(option_value(args, idx)?))?),
+                "-p" => parsed_proxy_url = Some(ParsedProxyUrl::new(option_value(args, idx)?)?),
+                "--cafile" => ca_file = Some(option_value(args, idx)?.to_string()),
+                "--security-level" => security_level = Some(SecurityLevel::from_name(option_value(args, idx)?)?),
+                "--ciphers" => cipher_suites = Some(parse_cipher_list(option_value(args, idx)?)?),
+                "--alpn" => alpn_protocols = parse_alpn_list(option_value(args, idx)?),
+                "--reconnect" => {
+                    let value = option_value(args, idx)?;
+                    reconnect = value.parse().map_err(|_| anyhow!("Error - invalid reconnect count '{}'", value))?;
+                },
+                "--cert" => cert_file = Some(option_value(args, idx)?.to_string()),
+                "--key" => key_file = Some(option_value(args, idx)?.to_string()),
+                "--rekey-after" => rekey_after = Some(parse_rekey_after(option_value(args, idx)?)?),
+                _ => break,
+            }
+            idx += 2;
         }
-        
-        let parsed_url = ParsedUrl::new(&args[idx]);
+
+        // parsing url
+        let url = args.get(idx).ok_or_else(|| anyhow!("Error - URL is not supplied"))?;
+        let parsed_url = ParsedUrl::new(url);
 
         if parsed_url.is_none() {
-            return Err(anyhow!("Error - malformed URL '{}'", args[idx]));
+            return Err(anyhow!("Error - malformed URL '{}'", url));
         }
 
         let parsed_url = parsed_url.unwrap();
 
+        if parsed_url.is_https() && parsed_proxy_url.is_some() {
+            return Err(anyhow!("Error - https through a proxy is not supported"));
+        }
+
+        if reconnect > 0 && !parsed_url.is_https() {
+            return Err(anyhow!("Error - --reconnect needs an https URL"));
+        }
+
+        if cert_file.is_some() != key_file.is_some() {
+            return Err(anyhow!("Error - --cert and --key must be supplied together"));
+        }
+
+        if rekey_after.is_some() && !parsed_url.is_https() {
+            return Err(anyhow!("Error - --rekey-after needs an https URL"));
+        }
+
+        if reconnect > 0 && matches!(data, Some(RequestData::Stdin)) {
+            return Err(anyhow!("Error - --reconnect cannot send data read from stdin again"));
+        }
+
         Ok(Self {
             parsed_url,
             parsed_proxy_url,
+            ca_file,
+            security_level,
+            cipher_suites,
+            alpn_protocols,
+            reconnect,
+            cert_file,
+            key_file,
+            rekey_after,
+            tracer,
+            method,
+            headers,
+            data,
+        })
+    }
+
+    fn request(&self) -> Result<Request> {
+        let method = self.method.unwrap_or(if self.data.is_some() { Method::Post } else { Method::Get });
+        let mut request = Request::new(method, &self.parsed_url, &self.parsed_proxy_url);
+        if self.data.is_some() {
+            request.headers.append("Content-Type", "application/x-www-form-urlencoded");
+        }
+        for (name, _) in &self.headers {
+            request.headers.remove(name);
+        }
+        for (name, value) in self.headers.iter().filter(|(_, value)| !value.is_empty()) {
+            request.headers.append(name, value);
+        }
+        Ok(match &self.data {
+            None => request,
+            Some(RequestData::Bytes(bytes)) => request.body_bytes(bytes.clone()),
+            Some(RequestData::File(path)) => request.body_file(path)?,
+            Some(RequestData::Stdin) => request.body_reader(io::stdin()),
         })
     }
+
+    fn trust_store(&self) -> Result<TrustStore> {
+        match &self.ca_file {
+            Some(path) => {
+                let mut store = TrustStore::new();
+                store.add_pem_file(path)?;
+                Ok(store)
+            },
+            None => TrustStore::system(),
+        }
+    }
+}
+
+/// Parses a comma separated list of IANA cipher suite names
+fn parse_cipher_list(list: &str) -> Result<Vec<&'static CipherSuite>> {
+    list.split(',')
+        .map(|name| suites::find_by_name(name.trim()).ok_or_else(|| anyhow!("Error - unknown cipher suite '{}'", name)))
+        .collect()
+}
+
+/// The suites pinned with --ciphers, narrowed to those the security level allows
+fn pinned_cipher_suites(cipher_suites: &[&'static CipherSuite], security_level: Option<SecurityLevel>) -> Result<Vec<&'static CipherSuite>> {
+    let pinned: Vec<&'static CipherSuite> = cipher_suites.iter()
+        .copied()
+        .filter(|s| security_level.is_none_or(|level| level.allows_suite(s)))
+        .collect();
+    if pinned.is_empty() {
+        return Err(anyhow!("Error - none of the cipher suites is allowed at security level {}", security_level.unwrap().name()));
+    }
+    Ok(pinned)
+}
+
+/// Parses a comma separated list of ALPN protocol names, such as "h2,http/1.1"
+fn parse_alpn_list(list: &str) -> Vec<Vec<u8>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| p.as_bytes().to_vec())
+        .collect()
+}
+
+/// "Name: value", as given to -H
+fn parse_header(header: &str) -> Result<(String, String)> {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(anyhow!("Error - malformed header '{}'", header)),
+    }
+}
+
+/// -d takes the data, or with '@' the contents of a file or stdin without line breaks, as curl does
+fn form_data(value: &str) -> Result<RequestData> {
+    let mut bytes = match value.strip_prefix('@') {
+        Some("-") => {
+            let mut bytes = vec![];
+            io::stdin().read_to_end(&mut bytes).with_context(|| "Unable to read data from stdin")?;
+            bytes
+        },
+        Some(path) => std::fs::read(path).with_context(|| format!("Unable to read data from {}", path))?,
+        None => return Ok(RequestData::Bytes(value.as_bytes().to_vec())),
+    };
+    bytes.retain(|b| *b != b'\r' && *b != b'\n');
+    Ok(RequestData::Bytes(bytes))
+}
+
+/// --data-binary takes the data, or with '@' a file or stdin sent unchanged
+fn binary_data(value: &str) -> RequestData {
+    match value.strip_prefix('@') {
+        Some("-") => RequestData::Stdin,
+        Some(path) => RequestData::File(path.to_string()),
+        None => RequestData::Bytes(value.as_bytes().to_vec()),
+    }
+}
+
+/// Data given more than once is joined with '&'
+fn join_data(data: Option<RequestData>, more: RequestData) -> Result<RequestData> {
+    match (data, more) {
+        (None, more) => Ok(more),
+        (Some(RequestData::Bytes(mut bytes)), RequestData::Bytes(more)) => {
+            bytes.push(b'&');
+            bytes.extend(more);
+            Ok(RequestData::Bytes(bytes))
+        },
+        _ => Err(anyhow!("Error - a file or stdin sent with --data-binary cannot be joined with other data")),
+    }
+}
+
+fn parse_rekey_after(value: &str) -> Result<u64> {
+    value.parse().map_err(|_| anyhow!("Error - invalid rekey byte count '{}'", value))
+}
+
+/// Logs the secrets of every handshake to the file SSLKEYLOGFILE names, if it is set
+fn key_log_from_env() -> Result<Option<Arc<dyn KeyLog>>> {
+    Ok(KeyLogFile::from_env()?.map(|file| Arc::new(file) as Arc<dyn KeyLog>))
+}
+
+/// Prints the header, then copies the body to stdout as it arrives, whatever its encoding
+fn print_response<R: BufRead>(mut response: Response<R>) -> Result<()> {
+    println!("{}", response.status_line());
+    for (name, value) in response.headers.iter() {
+        println!("{}: {}", name, value);
+    }
+    println!();
+    let mut stdout = io::stdout();
+    io::copy(response.body(), &mut stdout).with_context(|| "Failed to read the response body")?;
+    if !response.body().trailers().is_empty() {
+        println!();
+    }
+    for (name, value) in response.body().trailers().iter() {
+        println!("{}: {}", name, value);
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn stderr_tracer(format: TraceFormat) -> Arc<dyn Tracer> {
+    Arc::new(StderrTracer::new(format))
+}
+
+fn option_value(args: &[String], idx: usize) -> Result<&str> {
+    args.get(idx + 1).map(|s| s.as_str()).ok_or_else(|| anyhow!("Error - {} requires a value", args[idx]))
 }
 
 impl App for Client {
@@ -68,7 +338,7 @@ impl App for Client {
         let addrs = if let Some(proxy) = &self.parsed_proxy_url {
             format!("{}:{}", proxy.host, proxy.port).to_socket_addrs()
         } else {
-            format!("{}:{}", self.parsed_url.host, HTTP_PORT).to_socket_addrs()
+            (&*self.parsed_url.host, self.parsed_url.port).to_socket_addrs()
         };
         
         println!("Resolved IP: {:?}", addrs);
@@ -79,9 +349,42 @@ impl App for Client {
 
         let mut addrs = addrs.unwrap();
 
-        if let Some(addr) = addrs.find(|x| (*x).is_ipv4()) {
-            let stream = TcpStream::connect(addr).with_context(|| "Unable to connect to host.")?;
-            http_get(&stream, &self.parsed_url, &self.parsed_proxy_url);
+        if let Some(addr) = addrs.find(|x| (*x).is_ipv4()).or_else(|| addrs.next()) {
+            if self.parsed_url.is_https() {
+                let mut config = ClientConfig::new(self.trust_store()?);
+                if let Some(level) = self.security_level {
+                    config.apply_security_level(level);
+                }
+                if let Some(cipher_suites) = &self.cipher_suites {
+                    config.cipher_suites = pinned_cipher_suites(cipher_suites, self.security_level)?;
+                }
+                config.alpn_protocols = self.alpn_protocols.clone();
+                if let (Some(cert), Some(key)) = (&self.cert_file, &self.key_file) {
+                    config.set_certificate_from_pem_files(cert, key)?;
+                }
+                config.key_log = key_log_from_env()?;
+                config.tracer = self.tracer.clone();
+                // sessions are cached per host:port, so each reconnect can resume the previous one
+                let session_key = format!("{}:{}", self.parsed_url.host, self.parsed_url.port);
+                for _ in 0..=self.reconnect {
+                    let stream = TcpStream::connect(addr).with_context(|| "Unable to connect to host.")?;
+                    let mut tls_stream = tls_client::connect_resumable(stream, &config, &self.parsed_url.host, &session_key)
+                        .with_context(|| "TLS handshake failed.")?;
+                    println!("Negotiated {} with {}", tls_stream.session().version, tls_stream.session().cipher_suite.name);
+                    println!("Session resumed: {}", if tls_stream.session().resumed { "yes" } else { "no" });
+                    if let Some(protocol) = &tls_stream.session().alpn_protocol {
+                        println!("Application protocol: {}", String::from_utf8_lossy(protocol));
+                    }
+                    tls_stream.set_rekey_limit(self.rekey_after.map(RekeyLimit::Bytes));
+                    print_response(http_request(&mut tls_stream, self.request()?, self.tracer.as_deref())?)?;
+                    if tls_stream.rekeys() > 0 {
+                        println!("Key changes: {}", tls_stream.rekeys());
+                    }
+                }
+            } else {
+                let mut stream = TcpStream::connect(addr).with_context(|| "Unable to connect to host.")?;
+                print_response(http_request(&mut stream, self.request()?, self.tracer.as_deref())?)?;
+            }
         } else {
             return Err(anyhow!("Invalid Host:Port combination."));
         }
@@ -97,54 +400,146 @@ enum ResponseStatus {
 
 use ResponseStatus::*;
 
-pub struct Server;
+const HTTP_LISTEN_ADDR: &str = "127.0.0.1:8080";
+const HTTPS_LISTEN_ADDR: &str = "127.0.0.1:8443";
+
+pub struct Server {
+    /// https is served when a certificate and key are configured
+    tls_config: Option<ServerConfig>,
+    /// bytes written under one set of keys before they are changed
+    rekey_after: Option<u64>,
+    /// prints every record, handshake message and request to stderr
+    tracer: Option<Arc<dyn Tracer>>,
+}
 
 impl Server {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(args: &[String]) -> Result<Self> {
+        // parsing options
+        let mut idx: usize = 2;
+        let mut cert_file: Option<&str> = None;
+        let mut key_file: Option<&str> = None;
+        let mut security_level: Option<SecurityLevel> = None;
+        let mut cipher_suites: Option<Vec<&'static CipherSuite>> = None;
+        let mut alpn_protocols: Vec<Vec<u8>> = vec![];
+        let mut client_ca_file: Option<&str> = None;
+        let mut client_auth_required = true;
+        let mut rekey_after: Option<u64> = None;
+        let mut tracer: Option<Arc<dyn Tracer>> = None;
+        while idx < args.len() {
+            match &*args[idx] {
+                "-v" => {
+                    tracer = Some(stderr_tracer(TraceFormat::Text));
+                    idx += 1;
+                    continue;
+                },
+                "--trace" => tracer = Some(stderr_tracer(TraceFormat::from_name(option_value(args, idx)?)?)),
+                "--cert" => cert_file = Some(option_value(args, idx)?),
+                "--key" => key_file = Some(option_value(args, idx)?),
+                "--security-level" => security_level = Some(SecurityLevel::from_name(option_value(args, idx)?)?),
+                "--ciphers" => cipher_suites = Some(parse_cipher_list(option_value(args, idx)?)?),
+                "--alpn" => alpn_protocols = parse_alpn_list(option_value(args, idx)?),
+                "--client-ca" => client_ca_file = Some(option_value(args, idx)?),
+                "--client-auth" => client_auth_required = match option_value(args, idx)? {
+                    "required" => true,
+                    "optional" => false,
+                    value => return Err(anyhow!("Error - unknown client auth policy '{}'", value)),
+                },
+                "--rekey-after" => rekey_after = Some(parse_rekey_after(option_value(args, idx)?)?),
+                _ => return Err(anyhow!("Error - unknown option '{}'", args[idx])),
+            }
+            idx += 2;
+        }
+
+        let mut tls_config = match (cert_file, key_file) {
+            (Some(cert), Some(key)) => Some(ServerConfig::from_pem_files(cert, key)?),
+            (None, None) => None,
+            _ => return Err(anyhow!("Error - --cert and --key must be supplied together")),
+        };
+        if let Some(config) = &mut tls_config {
+            if let Some(level) = security_level {
+                config.apply_security_level(level);
+            }
+            if let Some(cipher_suites) = &cipher_suites {
+                config.cipher_suites = pinned_cipher_suites(cipher_suites, security_level)?;
+            }
+            config.alpn_protocols = alpn_protocols;
+            config.key_log = key_log_from_env()?;
+            config.tracer = tracer.clone();
+            if let Some(path) = client_ca_file {
+                let mut trust_store = TrustStore::new();
+                trust_store.add_pem_file(path)?;
+                config.client_auth = if client_auth_required { ClientAuth::Required(trust_store) } else { ClientAuth::Optional(trust_store) };
+            }
+        } else if security_level.is_some() || cipher_suites.is_some() || !alpn_protocols.is_empty() || client_ca_file.is_some() || rekey_after.is_some() {
+            return Err(anyhow!("Error - --security-level, --ciphers, --alpn, --client-ca and --rekey-after need --cert and --key"));
+        }
+
+        Ok(Self {
+            tls_config,
+            rekey_after,
+            tracer,
+        })
     }
 
-    fn process_http_request(&self, stream: &TcpStream) -> Result<()> {
-        let mut reader = BufReader::new(stream);
+    fn process_http_request<S: Read + Write>(&self, stream: &mut S) -> Result<()> {
+        let request = {
+            let mut reader = BufReader::new(&mut *stream);
+            tcp_read_line(&mut reader)?
+        };
+        if let Some(tracer) = &self.tracer {
+            tracer.trace(&request_event(Direction::Received, &request));
+        }
         let mut writer = BufWriter::new(stream);
-        
-        let request = tcp_read_line(&mut reader);
-        let mut response = String::new();
-        // println!("Received request: ");
-        // println!("{}", request);
 
         let get_pos = request.find("GET");
-        if let Some(_) = get_pos {
-            response = self.build_response(Success);
+        let response = if get_pos.is_some() {
+            self.build_response(Success)
         } else {
-            response = self.build_response(NotImplemented);
-        }
+            self.build_response(NotImplemented)
+        };
 
-        tcp_write(&mut writer, &response);
+        tcp_write(&mut writer, &response)?;
         
         Ok(())
     }
 
     fn build_response(&self, status: ResponseStatus) -> String {
-        let mut result = String::new();
         match status {
             NotImplemented => {
-                result = format!("HTTP/1.1 501 Error Occurred\r\n\r\n");
+                String::from("HTTP/1.1 501 Error Occurred\r\n\r\n")
             },
             Success => {
-                result = format!("HTTP/1.1 200 Success\r\nConnection: Close\r\nContent-Type:text/html\r\n\r\n<html><head><title>Test Page</title></head><body>Nothing here</body></html>\r\n");
+                String::from("HTTP/1.1 200 Success\r\nConnection: Close\r\nContent-Type:text/html\r\n\r\n<html><head><title>Test Page</title></head><body>Nothing here</body></html>\r\n")
             },
         }
-        result
     }
 }
 
 impl App for Server {
     fn run(&mut self) -> Result<()> {
-        let listener = TcpListener::bind("127.0.0.1:8080")?;
+        let listen_addr = if self.tls_config.is_some() { HTTPS_LISTEN_ADDR } else { HTTP_LISTEN_ADDR };
+        let listener = TcpListener::bind(listen_addr)?;
+        println!("Listening on {}", listen_addr);
         for stream in listener.incoming() {
             // TODO: ideally, this would spawn a new thread.
-            self.process_http_request(&stream?)?
+            let mut stream = stream?;
+            // a client that fails the handshake or the request should not bring the server down
+            let result = match &self.tls_config {
+                Some(config) => match tls_server::accept(&mut stream, config) {
+                    Ok(mut tls_stream) => {
+                        tls_stream.set_rekey_limit(self.rekey_after.map(RekeyLimit::Bytes));
+                        self.process_http_request(&mut tls_stream).and_then(|_| tls_stream.close())
+                    },
+                    Err(e) => {
+                        eprintln!("TLS handshake failed: {:#}", e);
+                        continue;
+                    },
+                },
+                None => self.process_http_request(&mut stream),
+            };
+            if let Err(e) = result {
+                eprintln!("Request failed: {:#}", e);
+            }
         }
         Ok(())
     }
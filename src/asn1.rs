@@ -0,0 +1,504 @@
+use std::fmt;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+pub const TAG_BOOLEAN: u8 = 0x01;
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0c;
+pub const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub const TAG_T61_STRING: u8 = 0x14;
+pub const TAG_IA5_STRING: u8 = 0x16;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_UNIVERSAL_STRING: u8 = 0x1c;
+pub const TAG_BMP_STRING: u8 = 0x1e;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+
+const CONSTRUCTED: u8 = 0x20;
+const CONTEXT_SPECIFIC: u8 = 0x80;
+
+/// Tag of an explicitly tagged (constructed) context-specific field, e.g. [0] EXPLICIT
+pub const fn context_explicit(n: u8) -> u8 {
+    CONTEXT_SPECIFIC | CONSTRUCTED | n
+}
+
+/// Tag of an implicitly tagged primitive context-specific field, e.g. [2] IMPLICIT IA5String
+pub const fn context_implicit(n: u8) -> u8 {
+    CONTEXT_SPECIFIC | n
+}
+
+/// A single DER encoded TLV borrowed from the input buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DerElement<'a> {
+    pub tag: u8,
+    /// contents octets (without tag and length)
+    pub value: &'a [u8],
+    /// whole encoding (tag, length and contents)
+    pub raw: &'a [u8],
+}
+
+impl<'a> DerElement<'a> {
+    pub fn is_constructed(&self) -> bool {
+        self.tag & CONSTRUCTED != 0
+    }
+
+    /// Returns a reader over the children of a constructed element
+    pub fn children(&self) -> Result<DerReader<'a>> {
+        if !self.is_constructed() {
+            return Err(anyhow!("DER element with tag 0x{:02x} is not constructed", self.tag));
+        }
+        Ok(DerReader::new(self.value))
+    }
+
+    pub fn expect_tag(&self, tag: u8) -> Result<()> {
+        if self.tag != tag {
+            return Err(anyhow!("Unexpected DER tag. expected: 0x{:02x}, actual: 0x{:02x}", tag, self.tag));
+        }
+        Ok(())
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        self.expect_tag(TAG_BOOLEAN)?;
+        match self.value {
+            [0x00] => Ok(false),
+            [0xff] => Ok(true),
+            _ => Err(anyhow!("Malformed DER boolean: {:?}", self.value)),
+        }
+    }
+
+    /// Returns the big-endian magnitude of a non-negative INTEGER, leading zeros stripped
+    pub fn as_unsigned_bytes(&self) -> Result<&'a [u8]> {
+        if self.value.is_empty() {
+            return Err(anyhow!("DER integer has no contents"));
+        }
+        if self.value[0] & 0x80 != 0 {
+            return Err(anyhow!("DER integer is negative"));
+        }
+        let start = self.value.iter().position(|b| *b != 0).unwrap_or(self.value.len());
+        Ok(&self.value[start..])
+    }
+
+    pub fn as_u64(&self) -> Result<u64> {
+        self.expect_tag(TAG_INTEGER)?;
+        let bytes = self.as_unsigned_bytes()?;
+        if bytes.len() > 8 {
+            return Err(anyhow!("DER integer is too large: {} bytes", bytes.len()));
+        }
+        Ok(bytes.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    /// Returns the number of unused trailing bits and the bit string contents
+    pub fn as_bit_string(&self) -> Result<(u8, &'a [u8])> {
+        self.expect_tag(TAG_BIT_STRING)?;
+        if self.value.is_empty() || self.value[0] > 7 {
+            return Err(anyhow!("Malformed DER bit string"));
+        }
+        Ok((self.value[0], &self.value[1..]))
+    }
+
+    pub fn as_oid(&self) -> Result<ObjectIdentifier> {
+        self.expect_tag(TAG_OID)?;
+        ObjectIdentifier::from_der_value(self.value)
+    }
+
+    /// Decodes any of the character string types used in certificates
+    pub fn as_string(&self) -> Result<String> {
+        match self.tag {
+            TAG_UTF8_STRING | TAG_PRINTABLE_STRING | TAG_IA5_STRING => {
+                String::from_utf8(self.value.to_vec()).map_err(|_| anyhow!("DER string is not valid UTF-8"))
+            },
+            // T61String is treated as Latin-1, which is what is found in practice
+            TAG_T61_STRING => Ok(self.value.iter().map(|b| char::from(*b)).collect()),
+            TAG_BMP_STRING => {
+                if !self.value.len().is_multiple_of(2) {
+                    return Err(anyhow!("Malformed DER BMPString"));
+                }
+                let units: Vec<u16> = self.value.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+                String::from_utf16(&units).map_err(|_| anyhow!("DER BMPString is not valid UTF-16"))
+            },
+            TAG_UNIVERSAL_STRING => {
+                if !self.value.len().is_multiple_of(4) {
+                    return Err(anyhow!("Malformed DER UniversalString"));
+                }
+                self.value.chunks(4)
+                    .map(|c| char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+                    .collect::<Option<String>>()
+                    .ok_or_else(|| anyhow!("DER UniversalString is not valid UTF-32"))
+            },
+            _ => Err(anyhow!("DER tag 0x{:02x} is not a string type", self.tag)),
+        }
+    }
+
+    pub fn as_time(&self) -> Result<Asn1Time> {
+        let s = std::str::from_utf8(self.value).map_err(|_| anyhow!("Malformed DER time"))?;
+        match self.tag {
+            TAG_UTC_TIME => Asn1Time::parse_utc_time(s),
+            TAG_GENERALIZED_TIME => Asn1Time::parse_generalized_time(s),
+            _ => Err(anyhow!("DER tag 0x{:02x} is not a time type", self.tag)),
+        }
+    }
+}
+
+/// Sequential reader over concatenated DER elements
+#[derive(Debug, Clone)]
+pub struct DerReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> DerReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    pub fn read(&mut self) -> Result<DerElement<'a>> {
+        let start = self.pos;
+        let tag = *self.data.get(self.pos).ok_or_else(|| anyhow!("Unexpected end of DER data"))?;
+        if tag & 0x1f == 0x1f {
+            return Err(anyhow!("High tag numbers are not supported"));
+        }
+        self.pos += 1;
+        let first = *self.data.get(self.pos).ok_or_else(|| anyhow!("Unexpected end of DER data"))?;
+        self.pos += 1;
+        let len = if first & 0x80 == 0 {
+            first as usize
+        } else {
+            let num_bytes = (first & 0x7f) as usize;
+            if num_bytes == 0 {
+                return Err(anyhow!("Indefinite length is not allowed in DER"));
+            }
+            if num_bytes > 4 {
+                return Err(anyhow!("DER length is too large"));
+            }
+            let bytes = self.data.get(self.pos..self.pos + num_bytes).ok_or_else(|| anyhow!("Unexpected end of DER data"))?;
+            self.pos += num_bytes;
+            if bytes[0] == 0 {
+                return Err(anyhow!("DER length is not minimally encoded"));
+            }
+            let len = bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            if len < 0x80 {
+                return Err(anyhow!("DER length is not minimally encoded"));
+            }
+            len
+        };
+        let end = self.pos.checked_add(len).ok_or_else(|| anyhow!("DER length overflow"))?;
+        if end > self.data.len() {
+            return Err(anyhow!("DER element is truncated. needed: {}, remaining: {}", len, self.data.len() - self.pos));
+        }
+        let value = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(DerElement {
+            tag,
+            value,
+            raw: &self.data[start..end],
+        })
+    }
+
+    /// Reads the next element and fails unless it has the given tag
+    pub fn expect(&mut self, tag: u8) -> Result<DerElement<'a>> {
+        let element = self.read()?;
+        element.expect_tag(tag)?;
+        Ok(element)
+    }
+
+    /// Reads the next element only if it has the given tag
+    pub fn read_optional(&mut self, tag: u8) -> Result<Option<DerElement<'a>>> {
+        if self.peek_tag() == Some(tag) {
+            Ok(Some(self.read()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn expect_end(&self) -> Result<()> {
+        if !self.is_empty() {
+            return Err(anyhow!("Trailing data after DER element: {} bytes", self.data.len() - self.pos));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a buffer which must contain exactly one DER element
+pub fn parse(data: &[u8]) -> Result<DerElement<'_>> {
+    let mut reader = DerReader::new(data);
+    let element = reader.read()?;
+    reader.expect_end()?;
+    Ok(element)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectIdentifier(pub Vec<u64>);
+
+impl ObjectIdentifier {
+    pub fn from_der_value(value: &[u8]) -> Result<Self> {
+        if value.is_empty() || value[value.len() - 1] & 0x80 != 0 {
+            return Err(anyhow!("Malformed DER object identifier"));
+        }
+        let mut arcs: Vec<u64> = vec![];
+        let mut acc: u64 = 0;
+        for (i, b) in value.iter().enumerate() {
+            if acc == 0 && *b == 0x80 {
+                return Err(anyhow!("DER object identifier is not minimally encoded"));
+            }
+            if acc > (u64::MAX >> 7) {
+                return Err(anyhow!("DER object identifier arc is too large"));
+            }
+            acc = (acc << 7) | u64::from(b & 0x7f);
+            if b & 0x80 == 0 {
+                if arcs.is_empty() {
+                    // the first subidentifier packs the first two arcs
+                    let first = std::cmp::min(acc / 40, 2);
+                    arcs.push(first);
+                    arcs.push(acc - first * 40);
+                } else {
+                    arcs.push(acc);
+                }
+                acc = 0;
+            } else if i == value.len() - 1 {
+                return Err(anyhow!("Malformed DER object identifier"));
+            }
+        }
+        Ok(Self(arcs))
+    }
+}
+
+impl PartialEq<[u64]> for ObjectIdentifier {
+    fn eq(&self, other: &[u64]) -> bool {
+        self.0 == other
+    }
+}
+
+impl fmt::Display for ObjectIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arcs: Vec<String> = self.0.iter().map(|a| a.to_string()).collect();
+        write!(f, "{}", arcs.join("."))
+    }
+}
+
+/// Calendar time in UTC with second precision, as carried by UTCTime and GeneralizedTime
+/// Fields are ordered so that the derived ordering is chronological.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Asn1Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Asn1Time {
+    pub fn now() -> Self {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Self::from_unix(secs as i64)
+    }
+
+    /// Converts seconds since the unix epoch, negative before 1970, into a calendar time
+    pub fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        // civil_from_days (Howard Hinnant's algorithm)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u16;
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// Returns seconds since the unix epoch, negative before 1970
+    pub fn to_unix(&self) -> i64 {
+        let y = i64::from(self.year) - if self.month <= 2 { 1 } else { 0 };
+        let m = i64::from(self.month);
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    /// Parses "YYMMDDHHMMSSZ". Years 50-99 are 19xx as specified by RFC 5280.
+    pub fn parse_utc_time(s: &str) -> Result<Self> {
+        if !is_time_string(s, 13) {
+            return Err(anyhow!("Unsupported UTCTime format: {}", s));
+        }
+        let yy = parse_digits(&s[0..2])?;
+        let year = if yy >= 50 { 1900 + yy } else { 2000 + yy };
+        Self::from_fields(year, &s[2..12])
+    }
+
+    /// Parses "YYYYMMDDHHMMSSZ"
+    pub fn parse_generalized_time(s: &str) -> Result<Self> {
+        if !is_time_string(s, 15) {
+            return Err(anyhow!("Unsupported GeneralizedTime format: {}", s));
+        }
+        let year = parse_digits(&s[0..4])?;
+        Self::from_fields(year, &s[4..14])
+    }
+
+    fn from_fields(year: u16, rest: &str) -> Result<Self> {
+        let time = Self {
+            year,
+            month: parse_digits(&rest[0..2])? as u8,
+            day: parse_digits(&rest[2..4])? as u8,
+            hour: parse_digits(&rest[4..6])? as u8,
+            minute: parse_digits(&rest[6..8])? as u8,
+            second: parse_digits(&rest[8..10])? as u8,
+        };
+        if time.month == 0 || time.month > 12 || time.day == 0 || time.day > days_in_month(time.year, time.month)
+            || time.hour > 23 || time.minute > 59 || time.second > 59 {
+            return Err(anyhow!("Time is out of range: {}", time));
+        }
+        Ok(time)
+    }
+}
+
+impl fmt::Display for Asn1Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Checks for `len` bytes of ASCII digits ending in 'Z' before any slicing, which a
+/// multibyte character would otherwise make panic
+fn is_time_string(s: &str, len: usize) -> bool {
+    s.len() == len && s.ends_with('Z') && s.as_bytes()[..len - 1].iter().all(|b| b.is_ascii_digit())
+}
+
+fn parse_digits(s: &str) -> Result<u16> {
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!("Expected digits: {}", s));
+    }
+    Ok(s.parse::<u16>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_read_nested_elements() {
+        // SEQUENCE { INTEGER 5, BOOLEAN TRUE, OCTET STRING 'ab' }
+        let data: Vec<u8> = vec![0x30, 0x0a, 0x02, 0x01, 0x05, 0x01, 0x01, 0xff, 0x04, 0x02, 0x61, 0x62];
+        let seq = parse(&data).unwrap();
+        assert_eq!(TAG_SEQUENCE, seq.tag);
+        let mut children = seq.children().unwrap();
+        assert_eq!(5, children.expect(TAG_INTEGER).unwrap().as_u64().unwrap());
+        assert!(children.read().unwrap().as_bool().unwrap());
+        assert!(children.read_optional(TAG_NULL).unwrap().is_none());
+        assert_eq!(b"ab", children.expect(TAG_OCTET_STRING).unwrap().value);
+        assert!(children.is_empty());
+    }
+
+    #[test]
+    fn test_can_read_long_form_length() {
+        let mut data: Vec<u8> = vec![0x04, 0x81, 0xc8];
+        data.extend(vec![0x2a; 200]);
+        let element = parse(&data).unwrap();
+        assert_eq!(200, element.value.len());
+        assert_eq!(data.len(), element.raw.len());
+    }
+
+    #[test]
+    fn test_can_return_error_non_minimal_length() {
+        let data: Vec<u8> = vec![0x04, 0x81, 0x01, 0x00];
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_truncated_element() {
+        let data: Vec<u8> = vec![0x30, 0x05, 0x02, 0x01];
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_can_decode_oid() {
+        // 1.2.840.113549.1.1.11 (sha256WithRSAEncryption)
+        let data: Vec<u8> = vec![0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+        let oid = parse(&data).unwrap().as_oid().unwrap();
+        assert_eq!("1.2.840.113549.1.1.11", oid.to_string());
+        assert!(oid == [1, 2, 840, 113549, 1, 1, 11][..]);
+    }
+
+    #[test]
+    fn test_can_parse_times() {
+        let utc = Asn1Time::parse_utc_time("491231235959Z").unwrap();
+        assert_eq!(2049, utc.year);
+        let utc = Asn1Time::parse_utc_time("500101000000Z").unwrap();
+        assert_eq!(1950, utc.year);
+        let generalized = Asn1Time::parse_generalized_time("21260925071551Z").unwrap();
+        assert_eq!((2126, 9, 25, 7, 15, 51), (generalized.year, generalized.month, generalized.day, generalized.hour, generalized.minute, generalized.second));
+        assert!(utc < generalized);
+        assert!(Asn1Time::parse_utc_time("201301000000Z").is_err());
+        assert!(Asn1Time::parse_utc_time("210431000000Z").is_err());
+        assert!(Asn1Time::parse_utc_time("230229000000Z").is_err());
+        assert!(Asn1Time::parse_utc_time("240229000000Z").is_ok());
+        assert!(Asn1Time::parse_generalized_time("21000229000000Z").is_err());
+        assert!(Asn1Time::parse_generalized_time("20000229000000Z").is_ok());
+        // the right number of bytes, with a two-byte character across the end of the year
+        assert!(Asn1Time::parse_utc_time("2\u{e9}123123595Z").is_err());
+        assert!(Asn1Time::parse_generalized_time("202\u{e9}092507155Z").is_err());
+    }
+
+    #[test]
+    fn test_can_convert_unix_time() {
+        let time = Asn1Time::from_unix(1_600_000_000);
+        assert_eq!("2020-09-13 12:26:40 UTC", time.to_string());
+        assert_eq!(1_600_000_000, time.to_unix());
+        assert_eq!(0, Asn1Time::from_unix(0).to_unix());
+        let leap = Asn1Time::parse_generalized_time("20240229120000Z").unwrap();
+        assert_eq!(leap, Asn1Time::from_unix(leap.to_unix()));
+        let before_epoch = Asn1Time::parse_utc_time("691231235959Z").unwrap();
+        assert_eq!(-1, before_epoch.to_unix());
+        let earliest = Asn1Time::parse_utc_time("500101000000Z").unwrap();
+        assert_eq!(-631_152_000, earliest.to_unix());
+        assert_eq!(earliest, Asn1Time::from_unix(earliest.to_unix()));
+    }
+}
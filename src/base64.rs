@@ -1,71 +1,386 @@
-const BASE64: [char; 64] = ['A','B','C','D','E','F','G','H','I','J','K','L','M','N','O','P','Q','R','S','T','U','V','W','X','Y','Z','a','b','c','d','e','f','g','h','i','j','k','l','m','n','o','p','q','r','s','t','u','v','w','x','y','z','0','1','2','3','4','5','6','7','8','9','+','/'];
-const BASE64_STR: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+use std::io::{
+    self,
+    Read,
+    Write,
+};
 
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+const STANDARD_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const INVALID: u8 = 0xff;
+const STANDARD_DECODE_TABLE: [u8; 256] = decode_table(STANDARD_ALPHABET);
+const URL_SAFE_DECODE_TABLE: [u8; 256] = decode_table(URL_SAFE_ALPHABET);
+const PAD: u8 = b'=';
+/// Input bytes processed per step by the streaming adapters (a multiple of 3)
+const STREAM_CHUNK_SIZE: usize = 3 * 1024;
+
+const fn decode_table(alphabet: &[u8; 64]) -> [u8; 256] {
+    let mut table = [INVALID; 256];
+    let mut i = 0;
+    while i < 64 {
+        table[alphabet[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alphabet {
+    /// RFC 4648 section 4 ('+' and '/')
+    Standard,
+    /// RFC 4648 section 5 ('-' and '_')
+    UrlSafe,
+}
+
+impl Alphabet {
+    fn encode_table(&self) -> &'static [u8; 64] {
+        match self {
+            Alphabet::Standard => STANDARD_ALPHABET,
+            Alphabet::UrlSafe => URL_SAFE_ALPHABET,
+        }
+    }
+
+    fn decode_table(&self) -> &'static [u8; 256] {
+        match self {
+            Alphabet::Standard => &STANDARD_DECODE_TABLE,
+            Alphabet::UrlSafe => &URL_SAFE_DECODE_TABLE,
+        }
+    }
+}
+
+/// How data is encoded and how forgiving decoding is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub alphabet: Alphabet,
+    /// whether the encoder emits '=' padding and the strict decoder requires it
+    pub padding: bool,
+    /// Strict decoding rejects whitespace, missing or misplaced padding and
+    /// non-zero trailing bits. Lenient decoding skips whitespace and accepts
+    /// input with or without padding. Both reject characters outside the alphabet.
+    pub strict: bool,
+}
+
+pub const STANDARD: Config = Config { alphabet: Alphabet::Standard, padding: true, strict: true };
+pub const STANDARD_NO_PAD: Config = Config { alphabet: Alphabet::Standard, padding: false, strict: true };
+pub const URL_SAFE: Config = Config { alphabet: Alphabet::UrlSafe, padding: true, strict: true };
+pub const URL_SAFE_NO_PAD: Config = Config { alphabet: Alphabet::UrlSafe, padding: false, strict: true };
+/// Standard alphabet, tolerating line breaks as found in PEM and MIME bodies
+pub const MIME: Config = Config { alphabet: Alphabet::Standard, padding: true, strict: false };
+
+/// Encodes binary data
+pub fn encode(input: &[u8], config: Config) -> String {
+    let mut result: Vec<u8> = Vec::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        encode_chunk(chunk, config, &mut result);
+    }
+    // only alphabet characters were pushed
+    String::from_utf8(result).unwrap()
+}
+
+/// Encodes a group of 1 to 3 bytes
+fn encode_chunk(chunk: &[u8], config: Config, out: &mut Vec<u8>) {
+    let table = config.alphabet.encode_table();
+    let n = (u32::from(chunk[0]) << 16)
+        | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+        | u32::from(*chunk.get(2).unwrap_or(&0));
+    // 1 byte -> 2 characters, 2 bytes -> 3 characters, 3 bytes -> 4 characters
+    for i in 0..=chunk.len() {
+        out.push(table[((n >> (18 - 6 * i)) & 0x3f) as usize]);
+    }
+    if config.padding {
+        out.resize(out.len() + 3 - chunk.len(), PAD);
+    }
+}
+
+/// Decodes into binary data, reporting malformed input instead of guessing
+pub fn decode(input: &str, config: Config) -> Result<Vec<u8>> {
+    let mut result: Vec<u8> = Vec::with_capacity(input.len() / 4 * 3 + 2);
+    let mut state = DecodeState::new(config);
+    state.feed(input.as_bytes(), &mut result)?;
+    state.finish()?;
+    Ok(result)
+}
+
+/// Incremental decoder shared by `decode` and `DecoderReader`
+struct DecodeState {
+    config: Config,
+    acc: u32,
+    bits: u32,
+    symbols: usize,
+    padding: usize,
+    offset: usize,
+}
+
+impl DecodeState {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            acc: 0,
+            bits: 0,
+            symbols: 0,
+            padding: 0,
+            offset: 0,
+        }
+    }
+
+    fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let table = self.config.alphabet.decode_table();
+        for c in input {
+            let pos = self.offset;
+            self.offset += 1;
+            if c.is_ascii_whitespace() && !self.config.strict {
+                continue;
+            }
+            if *c == PAD {
+                self.padding += 1;
+                continue;
+            }
+            let v = table[*c as usize];
+            if v == INVALID {
+                return Err(anyhow!("Invalid base64 character {:?} at offset {}", *c as char, pos));
+            }
+            if self.padding > 0 {
+                return Err(anyhow!("Unexpected base64 data after padding at offset {}", pos));
+            }
+            self.acc = (self.acc << 6) | u32::from(v);
+            self.bits += 6;
+            self.symbols += 1;
+            if self.bits >= 8 {
+                self.bits -= 8;
+                out.push((self.acc >> self.bits) as u8);
+                self.acc &= (1 << self.bits) - 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the end of input
+    fn finish(&self) -> Result<()> {
+        let remainder = self.symbols % 4;
+        if remainder == 1 {
+            return Err(anyhow!("Truncated base64 input: {} symbols", self.symbols));
+        }
+        let expected_padding = if remainder == 0 { 0 } else { 4 - remainder };
+        if self.padding != 0 && self.padding != expected_padding {
+            return Err(anyhow!("Incorrect base64 padding. expected: {}, actual: {}", expected_padding, self.padding));
+        }
+        if self.config.strict {
+            if self.config.padding && self.padding != expected_padding {
+                return Err(anyhow!("Missing base64 padding"));
+            }
+            if !self.config.padding && self.padding != 0 {
+                return Err(anyhow!("Unexpected base64 padding"));
+            }
+            if self.acc != 0 {
+                return Err(anyhow!("Non-zero trailing bits in base64 input"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes UTF-8 text with the standard alphabet and padding
 pub fn base64_encode(input: &str) -> String {
-    let input_bytes = input.as_bytes();
-    let mut bits = String::new();
-    input_bytes.iter().for_each(|byte| {
-        bits = format!("{}{:08b}", bits, byte);
-    });
-    println!("{}", bits);
-    let last_idx = bits.len();
-    let mut idx: usize = 0;
-    let mut result = String::new();
-    while last_idx > idx {
-        let end = idx.saturating_add(6);
-        if last_idx < end {
-            let pos = usize::from_str_radix(
-                &format!("{:0<6}", &bits[idx..last_idx]),
-                2
-            ).unwrap();
-            result = format!("{}{}", result, BASE64[pos]);
-            break;
-        } else {
-            let pos = usize::from_str_radix(
-                &format!("{}", &bits[idx..end]),
-                2
-            ).unwrap();
-            result = format!("{}{}", result, BASE64[pos]);
-        }
-        idx = end;
-    }
-    if result.len() % 4 > 0 {
-        for _ in 0..(4 - (result.len() % 4)) {
-            result = format!("{}=", result);
-        }
-    }
-    // println!("{}", result);
-    result
+    encode(input.as_bytes(), STANDARD)
 }
 
+/// Decodes base64 that is known to contain text. Invalid UTF-8 is replaced with U+FFFD
+/// and malformed base64 yields an empty string; use `decode` to handle errors.
 pub fn base64_decode(input: &str) -> String {
-    let mut result = String::new();
-    let mut bits = String::new();
-    for c in input.chars() {
-        let base64_pos = BASE64_STR.find(c);
-        if let Some(pos) = base64_pos {
-            bits = format!("{}{:0>6b}", bits, pos);
-        } else {
-            bits = format!("{}000000", bits);
-        }
-    }
-    let mut idx: usize = 0;
-    println!("len: {}", bits.len());
-    while idx < bits.len() {
-        println!("idx: {}", idx);
-        let utf8: &[u8] = &[
-            u8::from_str_radix(
-                &bits[idx..idx.saturating_add(8)],
-                2,
-            ).unwrap()
-        ];
-        if utf8[0] != 0 {
-            let s = std::str::from_utf8(&utf8).unwrap();
-            result = format!("{}{}", result, s);
-        }
-        idx = idx.saturating_add(8);
-    }
-    result
+    decode(input, MIME)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default()
+}
+
+/// Encodes binary data (e.g. DER) with the standard alphabet and padding
+pub fn base64_encode_bytes(input: &[u8]) -> String {
+    encode(input, STANDARD)
+}
+
+/// Decodes into binary data. Whitespace is skipped, padding is only allowed at the end
+/// and any other character outside the alphabet is an error.
+pub fn base64_decode_bytes(input: &str) -> Result<Vec<u8>> {
+    decode(input, MIME)
+}
+
+/// `Write` adapter that base64 encodes everything written to it into `inner`
+/// At most two input bytes are buffered between writes, so memory use is constant.
+/// Call `finish` to emit the final group and padding; dropping the encoder does
+/// the same but ignores errors.
+pub struct EncoderWriter<W: Write> {
+    inner: Option<W>,
+    config: Config,
+    pending: [u8; 3],
+    pending_len: usize,
+    line_length: Option<usize>,
+    line_ending: &'static str,
+    column: usize,
+}
+
+impl<W: Write> EncoderWriter<W> {
+    pub fn new(inner: W, config: Config) -> Self {
+        Self {
+            inner: Some(inner),
+            config,
+            pending: [0; 3],
+            pending_len: 0,
+            line_length: None,
+            line_ending: "\n",
+            column: 0,
+        }
+    }
+
+    /// Breaks the output into lines of `line_length` characters, e.g. 76 and "\r\n" for MIME
+    pub fn wrap_lines(mut self, line_length: usize, line_ending: &'static str) -> Self {
+        assert!(line_length > 0, "line_length must not be zero");
+        self.line_length = Some(line_length);
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Writes the remaining partial group and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_final()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn write_final(&mut self) -> io::Result<()> {
+        let mut out: Vec<u8> = Vec::with_capacity(4);
+        if self.pending_len > 0 {
+            encode_chunk(&self.pending[..self.pending_len], self.config, &mut out);
+            self.pending_len = 0;
+        }
+        self.write_encoded(&out)?;
+        if self.line_length.is_some() && self.column > 0 {
+            self.column = 0;
+            let line_ending = self.line_ending;
+            self.inner_mut().write_all(line_ending.as_bytes())?;
+        }
+        self.inner_mut().flush()
+    }
+
+    fn inner_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("EncoderWriter used after finish")
+    }
+
+    fn write_encoded(&mut self, encoded: &[u8]) -> io::Result<()> {
+        let line_length = match self.line_length {
+            Some(len) => len,
+            None => return self.inner_mut().write_all(encoded),
+        };
+        let mut out: Vec<u8> = Vec::with_capacity(encoded.len() + encoded.len() / line_length * 2 + 2);
+        for c in encoded {
+            if self.column == line_length {
+                out.extend_from_slice(self.line_ending.as_bytes());
+                self.column = 0;
+            }
+            out.push(*c);
+            self.column += 1;
+        }
+        self.inner_mut().write_all(&out)
+    }
+}
+
+impl<W: Write> Write for EncoderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+        let mut out: Vec<u8> = Vec::with_capacity(STREAM_CHUNK_SIZE / 3 * 4);
+        // complete a group left over from the previous write
+        if self.pending_len > 0 {
+            let n = std::cmp::min(3 - self.pending_len, input.len());
+            self.pending[self.pending_len..self.pending_len + n].copy_from_slice(&input[..n]);
+            self.pending_len += n;
+            input = &input[n..];
+            if self.pending_len < 3 {
+                return Ok(buf.len());
+            }
+            encode_chunk(&self.pending, self.config, &mut out);
+            self.pending_len = 0;
+        }
+        while input.len() >= 3 {
+            let n = std::cmp::min(STREAM_CHUNK_SIZE, input.len() / 3 * 3);
+            for chunk in input[..n].chunks(3) {
+                encode_chunk(chunk, self.config, &mut out);
+            }
+            self.write_encoded(&out)?;
+            out.clear();
+            input = &input[n..];
+        }
+        self.write_encoded(&out)?;
+        self.pending[..input.len()].copy_from_slice(input);
+        self.pending_len = input.len();
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer. A trailing partial group stays buffered until `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for EncoderWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_final();
+        }
+    }
+}
+
+/// `Read` adapter that decodes base64 read from `inner`
+/// Malformed input is reported as `io::ErrorKind::InvalidData`.
+pub struct DecoderReader<R: Read> {
+    inner: R,
+    state: DecodeState,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecoderReader<R> {
+    pub fn new(inner: R, config: Config) -> Self {
+        Self {
+            inner,
+            state: DecodeState::new(config),
+            input: vec![0; STREAM_CHUNK_SIZE],
+            output: Vec::with_capacity(STREAM_CHUNK_SIZE),
+            output_pos: 0,
+            eof: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DecoderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output.len() && !self.eof {
+            self.output.clear();
+            self.output_pos = 0;
+            let n = match self.inner.read(&mut self.input) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let result = if n == 0 {
+                self.eof = true;
+                self.state.finish()
+            } else {
+                self.state.feed(&self.input[..n], &mut self.output)
+            };
+            result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        }
+        let n = std::cmp::min(buf.len(), self.output.len() - self.output_pos);
+        buf[..n].copy_from_slice(&self.output[self.output_pos..self.output_pos + n]);
+        self.output_pos += n;
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
@@ -113,4 +428,148 @@ mod tests {
         let expected = "!base64_encoding_is_awesome?".to_string();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_can_base64_encode_and_decode_binary() {
+        let input: Vec<u8> = vec![0x30, 0x82, 0x00, 0xff, 0x00, 0x00, 0x7f];
+        let encoded = base64_encode_bytes(&input);
+        assert_eq!("MIIA/wAAfw==", encoded);
+        assert_eq!(input, base64_decode_bytes(&encoded).unwrap());
+        assert_eq!(input, base64_decode_bytes("MIIA\r\n/wAA\nfw==").unwrap());
+    }
+
+    #[test]
+    fn test_can_return_error_invalid_base64_bytes() {
+        assert!(base64_decode_bytes("MII*").is_err());
+        assert!(base64_decode_bytes("MI==II").is_err());
+    }
+
+    #[test]
+    fn test_can_round_trip_every_byte_value() {
+        let input: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+        for config in [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD, MIME].iter() {
+            for len in 0..8 {
+                let data = &input[..input.len() - len];
+                assert_eq!(data.to_vec(), decode(&encode(data, *config), *config).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_can_keep_zero_bytes() {
+        let input: Vec<u8> = vec![0, 0, 0, 1, 0];
+        let encoded = encode(&input, STANDARD);
+        assert_eq!("AAAAAQA=", encoded);
+        assert_eq!(input, decode(&encoded, STANDARD).unwrap());
+    }
+
+    #[test]
+    fn test_can_encode_url_safe_and_unpadded() {
+        let input: Vec<u8> = vec![0xfb, 0xff, 0xbf, 0xfe];
+        assert_eq!("+/+//g==", encode(&input, STANDARD));
+        assert_eq!("-_-__g==", encode(&input, URL_SAFE));
+        assert_eq!("-_-__g", encode(&input, URL_SAFE_NO_PAD));
+        assert_eq!(input, decode("-_-__g", URL_SAFE_NO_PAD).unwrap());
+        assert!(decode("-_-__g==", STANDARD).is_err());
+        assert!(decode("+/+//g==", URL_SAFE).is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_in_strict_mode() {
+        // missing padding
+        assert!(decode("AAAAAQA", STANDARD).is_err());
+        // padding where none is expected
+        assert!(decode("AAAAAQA=", STANDARD_NO_PAD).is_err());
+        // wrong amount of padding
+        assert!(decode("AAAAAQ=", STANDARD).is_err());
+        assert!(decode("AAAAAQA==", STANDARD).is_err());
+        // data after padding
+        assert!(decode("AA==AAAA", STANDARD).is_err());
+        // non-alphabet characters and whitespace
+        assert!(decode("AAAA*AAA", STANDARD).is_err());
+        assert!(decode("AAAA\nAAAA", STANDARD).is_err());
+        // a single trailing symbol can not encode a byte
+        assert!(decode("AAAAA", STANDARD_NO_PAD).is_err());
+        // non-canonical trailing bits
+        assert!(decode("AAAAAQB=", STANDARD).is_err());
+    }
+
+    #[test]
+    fn test_can_decode_leniently() {
+        assert_eq!(vec![0, 0, 0, 1, 0], decode("AAAA\r\nAQA", MIME).unwrap());
+        assert_eq!(vec![0, 0, 0, 1, 0], decode(" AAAA AQA= ", MIME).unwrap());
+        assert!(decode("AAAA*AQA=", MIME).is_err());
+    }
+
+    /// Reader that hands out at most one byte per call
+    struct ByteByByte<'a>(&'a [u8]);
+
+    impl<'a> Read for ByteByByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_can_encode_with_writer_in_arbitrary_chunks() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        for chunk_size in [1, 2, 3, 5, 1000, 4096].iter() {
+            let mut encoder = EncoderWriter::new(Vec::new(), URL_SAFE_NO_PAD);
+            for chunk in input.chunks(*chunk_size) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let output = encoder.finish().unwrap();
+            assert_eq!(encode(&input, URL_SAFE_NO_PAD).into_bytes(), output);
+        }
+    }
+
+    #[test]
+    fn test_can_finish_encoder_on_drop() {
+        let mut output: Vec<u8> = vec![];
+        {
+            let mut encoder = EncoderWriter::new(&mut output, STANDARD);
+            encoder.write_all(b"username:password").unwrap();
+        }
+        assert_eq!(b"dXNlcm5hbWU6cGFzc3dvcmQ=".to_vec(), output);
+    }
+
+    #[test]
+    fn test_can_wrap_encoded_lines() {
+        let input = vec![0u8; 100];
+        let mut encoder = EncoderWriter::new(Vec::new(), STANDARD).wrap_lines(64, "\r\n");
+        encoder.write_all(&input).unwrap();
+        let output = String::from_utf8(encoder.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = output.split("\r\n").collect();
+        assert_eq!(vec![64, 64, 8, 0], lines.iter().map(|l| l.len()).collect::<Vec<usize>>());
+        assert_eq!(input, decode(&output, MIME).unwrap());
+    }
+
+    #[test]
+    fn test_can_decode_with_reader() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i * 13 % 256) as u8).collect();
+        let encoded = encode(&input, STANDARD);
+        let mut decoded: Vec<u8> = vec![];
+        DecoderReader::new(encoded.as_bytes(), STANDARD).read_to_end(&mut decoded).unwrap();
+        assert_eq!(input, decoded);
+
+        let wrapped = format!("{}\r\n{}\r\n", &encoded[..100], &encoded[100..]);
+        let mut decoded: Vec<u8> = vec![];
+        DecoderReader::new(ByteByByte(wrapped.as_bytes()), MIME).read_to_end(&mut decoded).unwrap();
+        assert_eq!(input, decoded);
+    }
+
+    #[test]
+    fn test_can_return_error_from_reader() {
+        let mut decoded: Vec<u8> = vec![];
+        let err = DecoderReader::new(&b"AAAA*AAA"[..], STANDARD).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        // missing padding is only detected at the end of input
+        let err = DecoderReader::new(&b"AAAAAQA"[..], STANDARD).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
 }
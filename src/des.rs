@@ -131,7 +131,7 @@ pub enum OpType {
     DECRYPT,
 }
 
-pub fn des_block_operate(plain_data: &mut Vec<u8>, key_data: &Vec<u8>, op_type: OpType) -> Result<Vec<u8>> {
+pub fn des_block_operate(plain_data: &mut [u8], key_data: &[u8], op_type: OpType) -> Result<Vec<u8>> {
     if plain_data.len() != DES_BLOCK_SIZE as usize {
         return Err(anyhow!("plain_data length is incorrect. expected: {}, actual: {}", DES_BLOCK_SIZE, plain_data.len()));
     }
@@ -207,7 +207,7 @@ pub fn des_block_operate(plain_data: &mut Vec<u8>, key_data: &Vec<u8>, op_type:
     Ok(result)
 }
 
-fn permute(src: &Vec<u8>, permute_table: &Vec<u8>) -> Result<Vec<u8>> {
+fn permute(src: &[u8], permute_table: &[u8]) -> Result<Vec<u8>> {
     let mut max = 0;
     permute_table.iter().for_each(|v| {
         if &max < v {
@@ -218,8 +218,8 @@ fn permute(src: &Vec<u8>, permute_table: &Vec<u8>) -> Result<Vec<u8>> {
         return Err(anyhow!("length of src length was expected to be {} at least. passed: {}", max, src.len()));
     }
     let mut result: Vec<u8> = vec![0; permute_table.len() / 8];
-    for i in 0..permute_table.len() {
-        if get_bit(src, (permute_table[i] - 1) as usize) {
+    for (i, pos) in permute_table.iter().enumerate() {
+        if get_bit(src, (pos - 1) as usize) {
             set_bit(&mut result, i);
         } else {
             clear_bit(&mut result, i);
@@ -229,18 +229,18 @@ fn permute(src: &Vec<u8>, permute_table: &Vec<u8>) -> Result<Vec<u8>> {
 }
 
 fn get_bit(bytes: &[u8], bit: usize) -> bool {
-    bytes[bit / 8 as usize] & 0x80 >> (bit % 8) > 0
+    bytes[bit / 8] & 0x80 >> (bit % 8) > 0
 }
 
-fn set_bit(bytes: &mut Vec<u8>, bit: usize) {
-    bytes[bit / 8 as usize] |= 0x80 >> (bit % 8);
+fn set_bit(bytes: &mut [u8], bit: usize) {
+    bytes[bit / 8] |= 0x80 >> (bit % 8);
 }
 
-fn clear_bit(bytes: &mut Vec<u8>, bit: usize) {
-    bytes[bit / 8 as usize] &= !(0x80 >> (bit % 8));
+fn clear_bit(bytes: &mut [u8], bit: usize) {
+    bytes[bit / 8] &= !(0x80 >> (bit % 8));
 }
 
-fn xor(bytes_1: &Vec<u8>, bytes_2: &Vec<u8>) -> Result<Vec<u8>> {
+fn xor(bytes_1: &[u8], bytes_2: &[u8]) -> Result<Vec<u8>> {
     if bytes_1.len() != bytes_2.len() {
         return Err(anyhow!("bytes_1 and bytes_2 length are wrong. bytes_1.len(): {}, bytes_2.len(): {}", bytes_1.len(), bytes_2.len()));
     }
@@ -256,7 +256,7 @@ fn xor(bytes_1: &Vec<u8>, bytes_2: &Vec<u8>) -> Result<Vec<u8>> {
 /// For example:                        \/<-----split point
 /// in  : 01100111 01000111 00011100 00101001 00010110 10111101 01011000
 /// out : 11001110 10001110 00111000 01010010 00101101 01111010 10110001
-fn key_rotate_left(bytes: &mut Vec<u8>) -> Result<()> {
+fn key_rotate_left(bytes: &mut [u8]) -> Result<()> {
     if bytes.len() != 7 {
         return Err(anyhow!("Key length is incorrect. expected: {}, actual: {}", 7, bytes.len()));
     }
@@ -282,7 +282,7 @@ fn key_rotate_left(bytes: &mut Vec<u8>) -> Result<()> {
 /// For example:                        \/<-----split point
 /// in  : 01100111 01000111 00011100 00101001 00010110 10111101 01011000
 /// out : 00110011 10100011 10001110 00010100 10001011 01011110 10101100
-fn key_rotate_right(bytes: &mut Vec<u8>) -> Result<()> {
+fn key_rotate_right(bytes: &mut [u8]) -> Result<()> {
     if bytes.len() != 7 {
         return Err(anyhow!("Key length is incorrect. expected: {}, actual: {}", 7, bytes.len()));
     }
@@ -302,6 +302,7 @@ fn key_rotate_right(bytes: &mut Vec<u8>) -> Result<()> {
     Ok(())
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,89 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::huge::Huge;
+use super::random::random_bytes;
+
+/// Length in bytes of generated private exponents, well above twice the
+/// security level of the 2048-bit groups we use
+const PRIVATE_KEY_LEN: usize = 32;
+
+/// Finite field Diffie-Hellman group
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhParams {
+    pub p: Huge,
+    pub g: Huge,
+}
+
+impl DhParams {
+    /// The ffdhe2048 group from RFC 7919 appendix A.1
+    pub fn ffdhe2048() -> Self {
+        Self {
+            p: Huge::from_hex(concat!(
+                "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695",
+                "A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A",
+                "D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935",
+                "984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A",
+                "BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4",
+                "AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61",
+                "9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005",
+                "C58EF1837D1683B2C6F34A26C1B2EFFA886B423861285C97FFFFFFFFFFFFFFFF",
+            )),
+            g: Huge::from_u64(2),
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        self.p.bits()
+    }
+
+    /// Returns a random private exponent and g^x mod p
+    pub fn generate_keypair(&self) -> Result<(Huge, Huge)> {
+        loop {
+            let x = Huge::from_bytes(&random_bytes(PRIVATE_KEY_LEN)?);
+            if x > Huge::one() {
+                let public = self.g.modpow(&x, &self.p);
+                return Ok((x, public));
+            }
+        }
+    }
+
+    /// Checks that a peer's public value lies in [2, p - 2], which rules out
+    /// the degenerate subgroups {1} and {1, p - 1}
+    pub fn validate_public(&self, y: &Huge) -> Result<()> {
+        let p_minus_1 = self.p.sub(&Huge::one());
+        if *y <= Huge::one() || *y >= p_minus_1 {
+            return Err(anyhow!("Invalid Diffie-Hellman public value"));
+        }
+        Ok(())
+    }
+
+    /// Computes peer^private mod p in the minimal big-endian encoding, which
+    /// strips leading zeros as required for the TLS premaster secret
+    pub fn shared_secret(&self, private: &Huge, peer: &Huge) -> Result<Vec<u8>> {
+        self.validate_public(peer)?;
+        Ok(peer.modpow(private, &self.p).to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_agree_on_shared_secret() {
+        let params = DhParams::ffdhe2048();
+        assert_eq!(2048, params.bits());
+        let (a, a_public) = params.generate_keypair().unwrap();
+        let (b, b_public) = params.generate_keypair().unwrap();
+        let shared = params.shared_secret(&a, &b_public).unwrap();
+        assert_eq!(shared, params.shared_secret(&b, &a_public).unwrap());
+        assert!(shared.len() <= 256);
+
+        assert!(params.shared_secret(&a, &Huge::one()).is_err());
+        assert!(params.shared_secret(&a, &params.p.sub(&Huge::one())).is_err());
+        assert!(params.shared_secret(&a, &params.p).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use super::sha::{
+    Sha1,
+    Sha256,
+    Sha512,
+};
+#[cfg(feature = "legacy-protocols")]
+use super::md5::Md5;
+
+/// Incremental message digest
+pub trait Digest: Send {
+    fn update(&mut self, data: &[u8]);
+    /// Returns the digest of everything passed to `update` so far.
+    /// The state is left untouched, so more data can be added afterwards.
+    fn finish(&self) -> Vec<u8>;
+    fn output_size(&self) -> usize;
+    fn block_size(&self) -> usize;
+    /// Bytes passed to `update` that wait for a whole block to be compressed
+    fn buffered_len(&self) -> usize;
+    /// Bytes of the message length that `finish` stores at the end of the last block
+    fn length_field_size(&self) -> usize;
+    fn box_clone(&self) -> Box<dyn Digest>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    #[cfg(feature = "legacy-protocols")]
+    Md5,
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn new_digest(&self) -> Box<dyn Digest> {
+        match self {
+            #[cfg(feature = "legacy-protocols")]
+            HashAlgorithm::Md5 => Box::new(Md5::new()),
+            HashAlgorithm::Sha1 => Box::new(Sha1::new()),
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Sha384 => Box::new(Sha512::new_384()),
+            HashAlgorithm::Sha512 => Box::new(Sha512::new()),
+        }
+    }
+
+    /// One-shot digest of `data`
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = self.new_digest();
+        digest.update(data);
+        digest.finish()
+    }
+
+    pub fn output_size(&self) -> usize {
+        match self {
+            #[cfg(feature = "legacy-protocols")]
+            HashAlgorithm::Md5 => 16,
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha384 => 48,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+}
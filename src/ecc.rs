@@ -0,0 +1,315 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::huge::Huge;
+use super::random::random_bytes;
+
+/// Tag of the SEC 1 uncompressed point encoding
+const UNCOMPRESSED_POINT: u8 = 4;
+
+/// Short Weierstrass curve y^2 = x^3 + ax + b over GF(p), with a = -3
+#[derive(Clone, Debug)]
+pub struct Curve {
+    pub name: &'static str,
+    pub p: Huge,
+    pub a: Huge,
+    pub b: Huge,
+    pub g: Point,
+    /// order of the base point
+    pub n: Huge,
+    /// length in bytes of a field element
+    pub field_len: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Point {
+    Infinity,
+    Affine {
+        x: Huge,
+        y: Huge,
+    },
+}
+
+/// A point in Jacobian coordinates (X / Z^2, Y / Z^3); Z = 0 is the point at infinity
+#[derive(Clone)]
+struct Jacobian {
+    x: Huge,
+    y: Huge,
+    z: Huge,
+}
+
+impl Curve {
+    pub fn p256() -> Self {
+        let p = Huge::from_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff");
+        Self {
+            name: "P-256",
+            a: p.sub(&Huge::from_u64(3)),
+            p,
+            b: Huge::from_hex("5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b"),
+            g: Point::Affine {
+                x: Huge::from_hex("6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296"),
+                y: Huge::from_hex("4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5"),
+            },
+            n: Huge::from_hex("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551"),
+            field_len: 32,
+        }
+    }
+
+    pub fn p384() -> Self {
+        let p = Huge::from_hex(
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffeffffffff0000000000000000ffffffff",
+        );
+        Self {
+            name: "P-384",
+            a: p.sub(&Huge::from_u64(3)),
+            p,
+            b: Huge::from_hex(
+                "b3312fa7e23ee7e4988e056be3f82d19181d9c6efe8141120314088f5013875ac656398d8a2ed19d2a85c8edd3ec2aef",
+            ),
+            g: Point::Affine {
+                x: Huge::from_hex(
+                    "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a385502f25dbf55296c3a545e3872760ab7",
+                ),
+                y: Huge::from_hex(
+                    "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c00a60b1ce1d7e819d7a431d7c90ea0e5f",
+                ),
+            },
+            n: Huge::from_hex(
+                "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf581a0db248b0a77aecec196accc52973",
+            ),
+            field_len: 48,
+        }
+    }
+
+    pub fn is_on_curve(&self, point: &Point) -> bool {
+        match point {
+            Point::Infinity => true,
+            Point::Affine { x, y } => {
+                if *x >= self.p || *y >= self.p {
+                    return false;
+                }
+                let lhs = y.mod_mul(y, &self.p);
+                let x3 = x.mod_mul(x, &self.p).mod_mul(x, &self.p);
+                let rhs = x3.mod_add(&self.a.mod_mul(x, &self.p), &self.p).mod_add(&self.b, &self.p);
+                lhs == rhs
+            }
+        }
+    }
+
+    /// Encodes a point as 04 || x || y (SEC 1 section 2.3.3)
+    pub fn encode_point(&self, point: &Point) -> Vec<u8> {
+        match point {
+            Point::Infinity => vec![0],
+            Point::Affine { x, y } => {
+                let mut out = vec![UNCOMPRESSED_POINT];
+                out.extend(x.to_bytes_padded(self.field_len));
+                out.extend(y.to_bytes_padded(self.field_len));
+                out
+            }
+        }
+    }
+
+    /// Decodes an uncompressed point, rejecting anything that is not on the curve
+    pub fn decode_point(&self, bytes: &[u8]) -> Result<Point> {
+        if bytes.len() != 1 + 2 * self.field_len || bytes[0] != UNCOMPRESSED_POINT {
+            return Err(anyhow!("Invalid {} point encoding", self.name));
+        }
+        let point = Point::Affine {
+            x: Huge::from_bytes(&bytes[1..1 + self.field_len]),
+            y: Huge::from_bytes(&bytes[1 + self.field_len..]),
+        };
+        if !self.is_on_curve(&point) {
+            return Err(anyhow!("Point is not on {}", self.name));
+        }
+        Ok(point)
+    }
+
+    pub fn add(&self, p1: &Point, p2: &Point) -> Point {
+        self.to_affine(&self.add_jacobian(&self.to_jacobian(p1), &self.to_jacobian(p2)))
+    }
+
+    /// Computes k * point with a double-and-add ladder over the bits of k
+    pub fn mul(&self, k: &Huge, point: &Point) -> Point {
+        let base = self.to_jacobian(point);
+        let mut result = self.to_jacobian(&Point::Infinity);
+        for i in (0..k.bits()).rev() {
+            result = self.double_jacobian(&result);
+            if k.bit(i) {
+                result = self.add_jacobian(&result, &base);
+            }
+        }
+        self.to_affine(&result)
+    }
+
+    /// Returns a private scalar in [1, n - 1] and its public point
+    pub fn generate_keypair(&self) -> Result<(Huge, Point)> {
+        loop {
+            // rejection sampling keeps the scalar uniform
+            let mut bytes = random_bytes(self.field_len)?;
+            let excess = self.field_len * 8 - self.n.bits();
+            if excess > 0 {
+                bytes[0] &= 0xff >> excess;
+            }
+            let k = Huge::from_bytes(&bytes);
+            if !k.is_zero() && k < self.n {
+                let public = self.mul(&k, &self.g);
+                return Ok((k, public));
+            }
+        }
+    }
+
+    /// ECDH: the x-coordinate of private * peer, padded to the field length
+    pub fn shared_secret(&self, private: &Huge, peer: &Point) -> Result<Vec<u8>> {
+        if !self.is_on_curve(peer) {
+            return Err(anyhow!("Peer point is not on {}", self.name));
+        }
+        match self.mul(private, peer) {
+            Point::Infinity => Err(anyhow!("ECDH produced the point at infinity")),
+            Point::Affine { x, .. } => Ok(x.to_bytes_padded(self.field_len)),
+        }
+    }
+
+    fn to_jacobian(&self, point: &Point) -> Jacobian {
+        match point {
+            Point::Infinity => Jacobian {
+                x: Huge::one(),
+                y: Huge::one(),
+                z: Huge::zero(),
+            },
+            Point::Affine { x, y } => Jacobian {
+                x: x.clone(),
+                y: y.clone(),
+                z: Huge::one(),
+            },
+        }
+    }
+
+    fn to_affine(&self, point: &Jacobian) -> Point {
+        if point.z.is_zero() {
+            return Point::Infinity;
+        }
+        let p = &self.p;
+        let z_inv = point.z.modinv(p).expect("p is prime");
+        let z_inv2 = z_inv.mod_mul(&z_inv, p);
+        let z_inv3 = z_inv2.mod_mul(&z_inv, p);
+        Point::Affine {
+            x: point.x.mod_mul(&z_inv2, p),
+            y: point.y.mod_mul(&z_inv3, p),
+        }
+    }
+
+    /// dbl-2001-b, which relies on a = -3
+    fn double_jacobian(&self, point: &Jacobian) -> Jacobian {
+        if point.z.is_zero() || point.y.is_zero() {
+            return self.to_jacobian(&Point::Infinity);
+        }
+        let p = &self.p;
+        let delta = point.z.mod_mul(&point.z, p);
+        let gamma = point.y.mod_mul(&point.y, p);
+        let beta = point.x.mod_mul(&gamma, p);
+        let alpha = Huge::from_u64(3).mod_mul(&point.x.mod_sub(&delta, p).mod_mul(&point.x.mod_add(&delta, p), p), p);
+        let beta8 = Huge::from_u64(8).mod_mul(&beta, p);
+        let x = alpha.mod_mul(&alpha, p).mod_sub(&beta8, p);
+        let yz = point.y.mod_add(&point.z, p);
+        let z = yz.mod_mul(&yz, p).mod_sub(&gamma, p).mod_sub(&delta, p);
+        let beta4 = Huge::from_u64(4).mod_mul(&beta, p);
+        let gamma2_8 = Huge::from_u64(8).mod_mul(&gamma.mod_mul(&gamma, p), p);
+        let y = alpha.mod_mul(&beta4.mod_sub(&x, p), p).mod_sub(&gamma2_8, p);
+        Jacobian {
+            x,
+            y,
+            z,
+        }
+    }
+
+    /// add-2007-bl, falling back to doubling when both inputs are the same point
+    fn add_jacobian(&self, p1: &Jacobian, p2: &Jacobian) -> Jacobian {
+        if p1.z.is_zero() {
+            return p2.clone();
+        }
+        if p2.z.is_zero() {
+            return p1.clone();
+        }
+        let p = &self.p;
+        let z1z1 = p1.z.mod_mul(&p1.z, p);
+        let z2z2 = p2.z.mod_mul(&p2.z, p);
+        let u1 = p1.x.mod_mul(&z2z2, p);
+        let u2 = p2.x.mod_mul(&z1z1, p);
+        let s1 = p1.y.mod_mul(&p2.z, p).mod_mul(&z2z2, p);
+        let s2 = p2.y.mod_mul(&p1.z, p).mod_mul(&z1z1, p);
+        if u1 == u2 {
+            return if s1 == s2 {
+                self.double_jacobian(p1)
+            } else {
+                self.to_jacobian(&Point::Infinity)
+            };
+        }
+        let h = u2.mod_sub(&u1, p);
+        let h2 = h.mod_add(&h, p);
+        let i = h2.mod_mul(&h2, p);
+        let j = h.mod_mul(&i, p);
+        let r = s2.mod_sub(&s1, p);
+        let r = r.mod_add(&r, p);
+        let v = u1.mod_mul(&i, p);
+        let x = r.mod_mul(&r, p).mod_sub(&j, p).mod_sub(&v.mod_add(&v, p), p);
+        let s1j = s1.mod_mul(&j, p);
+        let y = r.mod_mul(&v.mod_sub(&x, p), p).mod_sub(&s1j.mod_add(&s1j, p), p);
+        let z1z2 = p1.z.mod_add(&p2.z, p);
+        let z = z1z2.mod_mul(&z1z2, p).mod_sub(&z1z1, p).mod_sub(&z2z2, p).mod_mul(&h, p);
+        Jacobian {
+            x,
+            y,
+            z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_multiply_points() {
+        let curve = Curve::p256();
+        assert!(curve.is_on_curve(&curve.g));
+        assert_eq!(Point::Infinity, curve.mul(&curve.n, &curve.g));
+        let g2 = curve.mul(&Huge::from_u64(2), &curve.g);
+        assert_eq!(g2, curve.add(&curve.g, &curve.g));
+        let g3 = curve.mul(&Huge::from_u64(3), &curve.g);
+        assert_eq!(g3, curve.add(&g2, &curve.g));
+        assert_eq!(
+            Point::Affine {
+                x: Huge::from_hex("5ecbe4d1a6330a44c8f7ef951d4bf165e6c6b721efada985fb41661bc6e7fd6c"),
+                y: Huge::from_hex("8734640c4998ff7e374b06ce1a64a2ecd82ab036384fb83d9a79b127a27d5032"),
+            },
+            g3
+        );
+    }
+
+    #[test]
+    fn test_can_agree_on_shared_secret() {
+        for curve in &[Curve::p256(), Curve::p384()] {
+            assert!(curve.is_on_curve(&curve.g));
+            let (a, a_public) = curve.generate_keypair().unwrap();
+            let (b, b_public) = curve.generate_keypair().unwrap();
+            let encoded = curve.encode_point(&b_public);
+            assert_eq!(1 + 2 * curve.field_len, encoded.len());
+            let decoded = curve.decode_point(&encoded).unwrap();
+            let shared = curve.shared_secret(&a, &decoded).unwrap();
+            assert_eq!(shared, curve.shared_secret(&b, &a_public).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_can_reject_invalid_points() {
+        let curve = Curve::p256();
+        let mut encoded = curve.encode_point(&curve.g);
+        *encoded.last_mut().unwrap() ^= 1;
+        assert!(curve.decode_point(&encoded).is_err());
+        assert!(curve.decode_point(&encoded[1..]).is_err());
+        encoded[0] = 2;
+        assert!(curve.decode_point(&encoded).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::asn1::{
+    self,
+    TAG_INTEGER,
+    TAG_SEQUENCE,
+};
+use super::digest::HashAlgorithm;
+use super::ecc::{
+    Curve,
+    Point,
+};
+use super::huge::Huge;
+use super::x509::{
+    OID_EC_PUBLIC_KEY,
+    SubjectPublicKeyInfo,
+};
+
+pub const OID_PRIME256V1: &[u64] = &[1, 2, 840, 10045, 3, 1, 7];
+pub const OID_SECP384R1: &[u64] = &[1, 3, 132, 0, 34];
+
+#[derive(Debug, Clone)]
+pub struct EcPublicKey {
+    pub curve: Curve,
+    pub point: Point,
+}
+
+impl EcPublicKey {
+    /// Parses an id-ecPublicKey SubjectPublicKeyInfo with a named curve (RFC 5480)
+    pub fn from_spki(spki: &SubjectPublicKeyInfo) -> Result<Self> {
+        if spki.algorithm.algorithm != *OID_EC_PUBLIC_KEY {
+            return Err(anyhow!("Not an EC public key: {}", spki.algorithm.algorithm));
+        }
+        let parameters = spki.algorithm.parameters.as_ref().ok_or_else(|| anyhow!("EC public key without curve parameters"))?;
+        let curve_oid = asn1::parse(parameters)?.as_oid()?;
+        let curve = if curve_oid == *OID_PRIME256V1 {
+            Curve::p256()
+        } else if curve_oid == *OID_SECP384R1 {
+            Curve::p384()
+        } else {
+            return Err(anyhow!("Unsupported elliptic curve: {}", curve_oid));
+        };
+        let point = curve.decode_point(&spki.public_key)?;
+        Ok(Self {
+            curve,
+            point,
+        })
+    }
+
+    /// Verifies a DER encoded ECDSA-Sig-Value over `message` (SEC 1 section 4.1.4)
+    pub fn verify(&self, hash: HashAlgorithm, message: &[u8], signature: &[u8]) -> Result<()> {
+        let (r, s) = parse_signature(signature)?;
+        let n = &self.curve.n;
+        if r.is_zero() || s.is_zero() || r >= *n || s >= *n {
+            return Err(anyhow!("ECDSA signature out of range"));
+        }
+        let e = hash_to_scalar(&self.curve, &hash.digest(message));
+        let w = s.modinv(n).ok_or_else(|| anyhow!("ECDSA signature is not invertible"))?;
+        let u1 = e.mod_mul(&w, n);
+        let u2 = r.mod_mul(&w, n);
+        let point = self.curve.add(&self.curve.mul(&u1, &self.curve.g), &self.curve.mul(&u2, &self.point));
+        match point {
+            Point::Affine { x, .. } if x.rem(n) == r => Ok(()),
+            _ => Err(anyhow!("ECDSA signature verification failed")),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EcPrivateKey {
+    pub curve: Curve,
+    pub private: Huge,
+}
+
+impl EcPrivateKey {
+    pub fn generate(curve: Curve) -> Result<Self> {
+        let (private, _) = curve.generate_keypair()?;
+        Ok(Self {
+            curve,
+            private,
+        })
+    }
+
+    pub fn public_key(&self) -> EcPublicKey {
+        EcPublicKey {
+            point: self.curve.mul(&self.private, &self.curve.g),
+            curve: self.curve.clone(),
+        }
+    }
+
+    /// Signs with a fresh random nonce and returns a DER encoded ECDSA-Sig-Value
+    pub fn sign(&self, hash: HashAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        let n = &self.curve.n;
+        let e = hash_to_scalar(&self.curve, &hash.digest(message));
+        loop {
+            let (k, point) = self.curve.generate_keypair()?;
+            let r = match point {
+                Point::Affine { x, .. } => x.rem(n),
+                Point::Infinity => continue,
+            };
+            let k_inv = k.modinv(n).ok_or_else(|| anyhow!("ECDSA nonce is not invertible"))?;
+            let s = k_inv.mod_mul(&e.mod_add(&r.mod_mul(&self.private, n), n), n);
+            if !r.is_zero() && !s.is_zero() {
+                return Ok(encode_signature(&r, &s));
+            }
+        }
+    }
+}
+
+/// The leftmost bits of the digest, as many as the order has, reduced mod n
+fn hash_to_scalar(curve: &Curve, digest: &[u8]) -> Huge {
+    let e = Huge::from_bytes(digest);
+    let n_bits = curve.n.bits();
+    let e = if digest.len() * 8 > n_bits { e.shr(digest.len() * 8 - n_bits) } else { e };
+    e.rem(&curve.n)
+}
+
+/// ECDSA-Sig-Value ::= SEQUENCE { r INTEGER, s INTEGER }
+fn parse_signature(signature: &[u8]) -> Result<(Huge, Huge)> {
+    let element = asn1::parse(signature)?;
+    element.expect_tag(TAG_SEQUENCE)?;
+    let mut reader = element.children()?;
+    let r = Huge::from_bytes(reader.expect(TAG_INTEGER)?.as_unsigned_bytes()?);
+    let s = Huge::from_bytes(reader.expect(TAG_INTEGER)?.as_unsigned_bytes()?);
+    reader.expect_end()?;
+    Ok((r, s))
+}
+
+fn encode_signature(r: &Huge, s: &Huge) -> Vec<u8> {
+    let mut body: Vec<u8> = vec![];
+    for value in [r, s].iter() {
+        // a leading zero keeps the INTEGER positive
+        let mut bytes = value.to_bytes();
+        if bytes.first().is_none_or(|b| b & 0x80 != 0) {
+            bytes.insert(0, 0);
+        }
+        body.push(TAG_INTEGER);
+        body.push(bytes.len() as u8);
+        body.extend(bytes);
+    }
+    let mut result = vec![TAG_SEQUENCE];
+    if body.len() >= 0x80 {
+        result.push(0x81);
+    }
+    result.push(body.len() as u8);
+    result.extend(body);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 6979 appendix A.2.5 key
+    fn rfc6979_key() -> EcPrivateKey {
+        EcPrivateKey {
+            curve: Curve::p256(),
+            private: Huge::from_hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721"),
+        }
+    }
+
+    #[test]
+    fn test_can_verify_rfc6979_signature() {
+        let public = rfc6979_key().public_key();
+        assert_eq!(
+            Point::Affine {
+                x: Huge::from_hex("60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6"),
+                y: Huge::from_hex("7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"),
+            },
+            public.point
+        );
+        let signature = encode_signature(
+            &Huge::from_hex("efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716"),
+            &Huge::from_hex("f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8"),
+        );
+        public.verify(HashAlgorithm::Sha256, b"sample", &signature).unwrap();
+        assert!(public.verify(HashAlgorithm::Sha256, b"test", &signature).is_err());
+        assert!(public.verify(HashAlgorithm::Sha384, b"sample", &signature).is_err());
+    }
+
+    #[test]
+    fn test_can_sign_and_verify() {
+        for key in &[rfc6979_key(), EcPrivateKey::generate(Curve::p384()).unwrap()] {
+            let signature = key.sign(HashAlgorithm::Sha384, b"message").unwrap();
+            key.public_key().verify(HashAlgorithm::Sha384, b"message", &signature).unwrap();
+            assert!(key.public_key().verify(HashAlgorithm::Sha384, b"massage", &signature).is_err());
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::aes::{
+    Aes,
+    AES_BLOCK_SIZE,
+};
+use super::hmac::constant_time_eq;
+
+pub const GCM_NONCE_LEN: usize = 12;
+pub const GCM_TAG_LEN: usize = 16;
+
+/// The reduction constant of GF(2^128) in GCM's reflected bit order
+const R: u128 = 0xe1 << 120;
+
+/// AES in Galois/Counter Mode (NIST SP 800-38D) with 96-bit nonces and full-length tags
+#[derive(Clone)]
+pub struct AesGcm {
+    aes: Aes,
+    /// hash subkey E(K, 0^128)
+    h: u128,
+}
+
+impl AesGcm {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let aes = Aes::new(key)?;
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        aes.encrypt_block(&mut block);
+        Ok(Self {
+            aes,
+            h: u128::from_be_bytes(block),
+        })
+    }
+
+    /// Returns ciphertext || tag
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let j0 = Self::initial_counter(nonce)?;
+        let mut result = self.ctr(j0, plaintext);
+        let tag = self.tag(j0, aad, &result);
+        result.extend_from_slice(&tag);
+        Ok(result)
+    }
+
+    /// Checks the tag of ciphertext || tag and returns the plaintext
+    pub fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        let j0 = Self::initial_counter(nonce)?;
+        if sealed.len() < GCM_TAG_LEN {
+            return Err(anyhow!("GCM input shorter than the tag"));
+        }
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - GCM_TAG_LEN);
+        if !constant_time_eq(&self.tag(j0, aad, ciphertext), tag) {
+            return Err(anyhow!("GCM authentication failed"));
+        }
+        Ok(self.ctr(j0, ciphertext))
+    }
+
+    /// J0 = nonce || 0^31 || 1
+    fn initial_counter(nonce: &[u8]) -> Result<u128> {
+        if nonce.len() != GCM_NONCE_LEN {
+            return Err(anyhow!("Invalid GCM nonce length: {}", nonce.len()));
+        }
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        block[..GCM_NONCE_LEN].copy_from_slice(nonce);
+        block[AES_BLOCK_SIZE - 1] = 1;
+        Ok(u128::from_be_bytes(block))
+    }
+
+    fn encrypt_counter(&self, counter: u128) -> [u8; AES_BLOCK_SIZE] {
+        let mut block = counter.to_be_bytes();
+        self.aes.encrypt_block(&mut block);
+        block
+    }
+
+    /// GCTR starting at inc32(J0)
+    fn ctr(&self, j0: u128, data: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::with_capacity(data.len());
+        let mut counter = j0;
+        for chunk in data.chunks(AES_BLOCK_SIZE) {
+            counter = inc32(counter);
+            let keystream = self.encrypt_counter(counter);
+            result.extend(chunk.iter().zip(keystream.iter()).map(|(d, k)| d ^ k));
+        }
+        result
+    }
+
+    fn tag(&self, j0: u128, aad: &[u8], ciphertext: &[u8]) -> [u8; GCM_TAG_LEN] {
+        let mut y = 0u128;
+        for data in [aad, ciphertext].iter() {
+            for chunk in data.chunks(AES_BLOCK_SIZE) {
+                let mut block = [0u8; AES_BLOCK_SIZE];
+                block[..chunk.len()].copy_from_slice(chunk);
+                y = gf128_mul(y ^ u128::from_be_bytes(block), self.h);
+            }
+        }
+        let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+        y = gf128_mul(y ^ lengths, self.h);
+        let mask = u128::from_be_bytes(self.encrypt_counter(j0));
+        (y ^ mask).to_be_bytes()
+    }
+}
+
+/// Increments the low 32 bits of the counter block, wrapping within them
+fn inc32(counter: u128) -> u128 {
+    let low = (counter as u32).wrapping_add(1);
+    (counter & !0xffff_ffffu128) | u128::from(low)
+}
+
+/// Multiplication in GF(2^128) (SP 800-38D algorithm 1), without data dependent branches
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    let mut z = 0u128;
+    let mut v = y;
+    for i in 0..128 {
+        let bit = (x >> (127 - i)) & 1;
+        z ^= v & bit.wrapping_neg();
+        let carry = v & 1;
+        v = (v >> 1) ^ (R & carry.wrapping_neg());
+    }
+    z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::from_hex;
+
+    #[test]
+    fn test_can_seal_gcm_vectors() {
+        // GCM spec test case 4, with AAD and a partial final block
+        let gcm = AesGcm::new(&from_hex("feffe9928665731c6d6a8f9467308308")).unwrap();
+        let nonce = from_hex("cafebabefacedbaddecaf888");
+        let aad = from_hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plain = from_hex("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39");
+        let sealed = gcm.seal(&nonce, &aad, &plain).unwrap();
+        assert_eq!(from_hex(concat!(
+            "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091",
+            "5bc94fbc3221a5db94fae95ae7121a47",
+        )), sealed);
+        assert_eq!(plain, gcm.open(&nonce, &aad, &sealed).unwrap());
+
+        // AES-256, empty input: only the tag
+        let gcm = AesGcm::new(&[0u8; 32]).unwrap();
+        assert_eq!(from_hex("530f8afbc74536b9a963b4f1c4cb738b"), gcm.seal(&[0u8; 12], &[], &[]).unwrap());
+    }
+
+    #[test]
+    fn test_can_reject_tampered_input() {
+        let gcm = AesGcm::new(&[7u8; 16]).unwrap();
+        let nonce = [1u8; GCM_NONCE_LEN];
+        let mut sealed = gcm.seal(&nonce, b"header", b"attack at dawn").unwrap();
+        assert!(gcm.open(&nonce, b"header!", &sealed).is_err());
+        sealed[0] ^= 1;
+        assert!(gcm.open(&nonce, b"header", &sealed).is_err());
+        assert!(gcm.open(&nonce, b"header", &sealed[..10]).is_err());
+        assert!(gcm.seal(&[0u8; 8], b"", b"").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+/// Lowercase hex, two digits per byte
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Inverse of `hex_encode`; accepts either case
+pub fn hex_decode(input: &str) -> Result<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return Err(anyhow!("Odd-length hex string"));
+    }
+    input
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(anyhow!("Invalid hex digits: {:?}", String::from_utf8_lossy(pair))),
+        })
+        .collect()
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_hex_encode_bytes() {
+        assert_eq!("00ff7f0a", hex_encode(&[0x00, 0xff, 0x7f, 0x0a]));
+        assert_eq!("", hex_encode(&[]));
+    }
+
+    #[test]
+    fn test_can_hex_decode_either_case() {
+        assert_eq!(vec![0x00, 0xff, 0xab], hex_decode("00FFab").unwrap());
+    }
+
+    #[test]
+    fn test_can_reject_malformed_hex() {
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert!(hex_decode("+1").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use super::digest::HashAlgorithm;
+use super::hmac::{
+    Hmac,
+    hmac,
+};
+
+/// HKDF-Extract (RFC 5869 section 2.2). An empty salt stands for HashLen zeros.
+pub fn hkdf_extract(hash: HashAlgorithm, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    if salt.is_empty() {
+        return hmac(hash, &vec![0u8; hash.output_size()], ikm);
+    }
+    hmac(hash, salt, ikm)
+}
+
+/// HKDF-Expand (RFC 5869 section 2.3)
+/// Panics if more than 255 * HashLen bytes are requested, which no caller needs.
+pub fn hkdf_expand(hash: HashAlgorithm, prk: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    assert!(len <= 255 * hash.output_size(), "HKDF output too long");
+    let base = Hmac::new(hash, prk);
+    let mut okm: Vec<u8> = Vec::with_capacity(len);
+    let mut t: Vec<u8> = vec![];
+    let mut counter = 1u8;
+    while okm.len() < len {
+        let mut mac = base.clone();
+        mac.update(&t);
+        mac.update(info);
+        mac.update(&[counter]);
+        t = mac.finish();
+        okm.extend_from_slice(&t);
+        counter += 1;
+    }
+    okm.truncate(len);
+    okm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::from_hex;
+
+    #[test]
+    fn test_can_derive_rfc5869_vectors() {
+        // test case 1
+        let ikm = [0x0bu8; 22];
+        let salt = from_hex("000102030405060708090a0b0c");
+        let info = from_hex("f0f1f2f3f4f5f6f7f8f9");
+        let prk = hkdf_extract(HashAlgorithm::Sha256, &salt, &ikm);
+        assert_eq!(from_hex("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"), prk);
+        let okm = hkdf_expand(HashAlgorithm::Sha256, &prk, &info, 42);
+        assert_eq!(from_hex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"), okm);
+
+        // test case 3: empty salt and info
+        let prk = hkdf_extract(HashAlgorithm::Sha256, &[], &ikm);
+        let okm = hkdf_expand(HashAlgorithm::Sha256, &prk, &[], 42);
+        assert_eq!(from_hex("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8"), okm);
+    }
+}
@@ -0,0 +1,109 @@
+use super::digest::{
+    Digest,
+    HashAlgorithm,
+};
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// Incremental HMAC (RFC 2104)
+pub struct Hmac {
+    inner: Box<dyn Digest>,
+    outer: Box<dyn Digest>,
+}
+
+impl Hmac {
+    pub fn new(hash: HashAlgorithm, key: &[u8]) -> Self {
+        let mut inner = hash.new_digest();
+        let mut outer = hash.new_digest();
+        let block_size = inner.block_size();
+        let mut key = if key.len() > block_size {
+            hash.digest(key)
+        } else {
+            key.to_vec()
+        };
+        key.resize(block_size, 0);
+        inner.update(&key.iter().map(|b| b ^ IPAD).collect::<Vec<u8>>());
+        outer.update(&key.iter().map(|b| b ^ OPAD).collect::<Vec<u8>>());
+        Self {
+            inner,
+            outer,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Returns the MAC of everything passed to `update` so far, leaving the state untouched
+    pub fn finish(&self) -> Vec<u8> {
+        let mut outer = self.outer.box_clone();
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.outer.output_size()
+    }
+
+    /// The keyed inner and outer digests, for callers that hash in constant time
+    pub fn keyed_digests(&self) -> (&dyn Digest, &dyn Digest) {
+        (&*self.inner, &*self.outer)
+    }
+}
+
+impl Clone for Hmac {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.box_clone(),
+            outer: self.outer.box_clone(),
+        }
+    }
+}
+
+/// One-shot HMAC of `data`
+pub fn hmac(hash: HashAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::new(hash, key);
+    mac.update(data);
+    mac.finish()
+}
+
+/// Compares two MACs without an early exit on the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::hex::hex_encode;
+
+    #[test]
+    fn test_can_compute_rfc4231_vectors() {
+        // test case 2
+        let mac = hmac(HashAlgorithm::Sha256, b"Jefe", b"what do ya want for nothing?");
+        assert_eq!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843", hex_encode(&mac));
+        // test case 6: key longer than the block size
+        let key = [0xaau8; 131];
+        let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        assert_eq!("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54", hex_encode(&hmac(HashAlgorithm::Sha256, &key, data)));
+        assert_eq!(
+            "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+            hex_encode(&hmac(HashAlgorithm::Sha512, &key, data)),
+        );
+    }
+
+    #[test]
+    fn test_can_compute_incrementally() {
+        let mut mac = Hmac::new(HashAlgorithm::Sha1, b"key");
+        mac.update(b"The quick brown fox ");
+        let snapshot = mac.clone();
+        mac.update(b"jumps over the lazy dog");
+        assert_eq!("de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9", hex_encode(&mac.finish()));
+        assert_ne!(snapshot.finish(), mac.finish());
+        assert!(constant_time_eq(&mac.finish(), &hmac(HashAlgorithm::Sha1, b"key", b"The quick brown fox jumps over the lazy dog")));
+    }
+}
@@ -0,0 +1,516 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Arbitrary precision unsigned integer
+/// Stored as little-endian 32-bit limbs with no trailing zero limbs, so that
+/// zero is an empty vector and the derived equality is value equality.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct Huge {
+    limbs: Vec<u32>,
+}
+
+impl Huge {
+    pub fn zero() -> Self {
+        Self {
+            limbs: vec![],
+        }
+    }
+
+    pub fn one() -> Self {
+        Self::from_u64(1)
+    }
+
+    pub fn from_u64(n: u64) -> Self {
+        Self::from_limbs(vec![n as u32, (n >> 32) as u32])
+    }
+
+    fn from_limbs(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Self {
+            limbs,
+        }
+    }
+
+    /// Interprets `bytes` as a big-endian unsigned integer
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut limbs: Vec<u32> = Vec::with_capacity(bytes.len() / 4 + 1);
+        for chunk in bytes.rchunks(4) {
+            let limb = chunk.iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+            limbs.push(limb);
+        }
+        Self::from_limbs(limbs)
+    }
+
+    /// Parses big-endian hex digits, e.g. for curve and group constants
+    /// Panics on anything but hex digits, which would be a bug in the constant.
+    pub fn from_hex(hex: &str) -> Self {
+        let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).expect("invalid hex digit") as u8).collect();
+        let bytes: Vec<u8> = digits.rchunks(2).rev().map(|pair| pair.iter().fold(0u8, |acc, d| (acc << 4) | d)).collect();
+        Self::from_bytes(&bytes)
+    }
+
+    /// Returns the minimal big-endian encoding (empty for zero)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes: Vec<u8> = self.limbs.iter().rev().flat_map(|l| l.to_be_bytes().to_vec()).collect();
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        bytes[start..].to_vec()
+    }
+
+    /// Returns the big-endian encoding left-padded with zeros to `len` bytes
+    /// Panics if the value does not fit, which would be a bug in the caller.
+    pub fn to_bytes_padded(&self, len: usize) -> Vec<u8> {
+        let bytes = self.to_bytes();
+        assert!(bytes.len() <= len, "Huge does not fit in {} bytes", len);
+        let mut result = vec![0u8; len - bytes.len()];
+        result.extend(bytes);
+        result
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.limbs.first().map(|l| l & 1 == 1).unwrap_or(false)
+    }
+
+    /// Number of significant bits
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn bit(&self, i: usize) -> bool {
+        self.limbs.get(i / 32).map(|l| (l >> (i % 32)) & 1 == 1).unwrap_or(false)
+    }
+
+    pub fn add(&self, other: &Huge) -> Huge {
+        let (long, short) = if self.limbs.len() >= other.limbs.len() { (self, other) } else { (other, self) };
+        let mut result: Vec<u32> = Vec::with_capacity(long.limbs.len() + 1);
+        let mut carry: u64 = 0;
+        for (i, l) in long.limbs.iter().enumerate() {
+            let sum = u64::from(*l) + u64::from(*short.limbs.get(i).unwrap_or(&0)) + carry;
+            result.push(sum as u32);
+            carry = sum >> 32;
+        }
+        result.push(carry as u32);
+        Self::from_limbs(result)
+    }
+
+    /// Returns self - other. Panics if other is larger than self.
+    pub fn sub(&self, other: &Huge) -> Huge {
+        assert!(*self >= *other, "Huge subtraction underflow");
+        let mut result: Vec<u32> = Vec::with_capacity(self.limbs.len());
+        let mut borrow: i64 = 0;
+        for (i, l) in self.limbs.iter().enumerate() {
+            let diff = i64::from(*l) - i64::from(*other.limbs.get(i).unwrap_or(&0)) - borrow;
+            result.push(diff as u32);
+            borrow = if diff < 0 { 1 } else { 0 };
+        }
+        Self::from_limbs(result)
+    }
+
+    pub fn mul(&self, other: &Huge) -> Huge {
+        if self.is_zero() || other.is_zero() {
+            return Self::zero();
+        }
+        let mut result = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry: u64 = 0;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let t = u64::from(*a) * u64::from(*b) + u64::from(result[i + j]) + carry;
+                result[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            result[i + other.limbs.len()] = carry as u32;
+        }
+        Self::from_limbs(result)
+    }
+
+    pub fn shl(&self, bits: usize) -> Huge {
+        if self.is_zero() {
+            return Self::zero();
+        }
+        let (limb_shift, bit_shift) = (bits / 32, bits % 32);
+        let mut result = vec![0u32; limb_shift];
+        let mut carry: u32 = 0;
+        for l in &self.limbs {
+            if bit_shift == 0 {
+                result.push(*l);
+            } else {
+                result.push((l << bit_shift) | carry);
+                carry = l >> (32 - bit_shift);
+            }
+        }
+        result.push(carry);
+        Self::from_limbs(result)
+    }
+
+    pub fn shr(&self, bits: usize) -> Huge {
+        let (limb_shift, bit_shift) = (bits / 32, bits % 32);
+        if limb_shift >= self.limbs.len() {
+            return Self::zero();
+        }
+        let src = &self.limbs[limb_shift..];
+        let mut result: Vec<u32> = Vec::with_capacity(src.len());
+        for i in 0..src.len() {
+            if bit_shift == 0 {
+                result.push(src[i]);
+            } else {
+                let high = src.get(i + 1).map(|h| h << (32 - bit_shift)).unwrap_or(0);
+                result.push((src[i] >> bit_shift) | high);
+            }
+        }
+        Self::from_limbs(result)
+    }
+
+    /// Returns (quotient, remainder). Panics on division by zero.
+    /// Long division as described in Knuth's TAOCP vol.2, algorithm D.
+    pub fn divmod(&self, divisor: &Huge) -> (Huge, Huge) {
+        assert!(!divisor.is_zero(), "Huge division by zero");
+        if *self < *divisor {
+            return (Self::zero(), self.clone());
+        }
+        if divisor.limbs.len() == 1 {
+            let d = u64::from(divisor.limbs[0]);
+            let mut quotient = vec![0u32; self.limbs.len()];
+            let mut rem: u64 = 0;
+            for i in (0..self.limbs.len()).rev() {
+                let cur = (rem << 32) | u64::from(self.limbs[i]);
+                quotient[i] = (cur / d) as u32;
+                rem = cur % d;
+            }
+            return (Self::from_limbs(quotient), Self::from_u64(rem));
+        }
+
+        // normalize so that the top bit of the divisor is set
+        let shift = divisor.limbs.last().unwrap().leading_zeros() as usize;
+        let v = divisor.shl(shift).limbs;
+        let mut u = self.shl(shift).limbs;
+        u.resize(self.limbs.len() + 1, 0);
+        let n = v.len();
+        let m = u.len() - n - 1;
+        let mut quotient = vec![0u32; m + 1];
+        let base: u64 = 1 << 32;
+        for j in (0..=m).rev() {
+            let num = (u64::from(u[j + n]) << 32) | u64::from(u[j + n - 1]);
+            let mut qhat = num / u64::from(v[n - 1]);
+            let mut rhat = num % u64::from(v[n - 1]);
+            while qhat >= base || qhat * u64::from(v[n - 2]) > ((rhat << 32) | u64::from(u[j + n - 2])) {
+                qhat -= 1;
+                rhat += u64::from(v[n - 1]);
+                if rhat >= base {
+                    break;
+                }
+            }
+            // multiply and subtract
+            let mut borrow: i64 = 0;
+            let mut carry: u64 = 0;
+            for i in 0..n {
+                let p = qhat * u64::from(v[i]) + carry;
+                carry = p >> 32;
+                let t = i64::from(u[i + j]) - borrow - (p & 0xffff_ffff) as i64;
+                u[i + j] = t as u32;
+                borrow = if t < 0 { 1 } else { 0 };
+            }
+            let t = i64::from(u[j + n]) - borrow - carry as i64;
+            u[j + n] = t as u32;
+            if t < 0 {
+                // qhat was one too large; add back
+                qhat -= 1;
+                let mut carry: u64 = 0;
+                for i in 0..n {
+                    let s = u64::from(u[i + j]) + u64::from(v[i]) + carry;
+                    u[i + j] = s as u32;
+                    carry = s >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = qhat as u32;
+        }
+        u.truncate(n);
+        (Self::from_limbs(quotient), Self::from_limbs(u).shr(shift))
+    }
+
+    pub fn rem(&self, modulus: &Huge) -> Huge {
+        self.divmod(modulus).1
+    }
+
+    /// (self + other) mod modulus, both operands already reduced
+    pub fn mod_add(&self, other: &Huge, modulus: &Huge) -> Huge {
+        let sum = self.add(other);
+        if sum >= *modulus { sum.sub(modulus) } else { sum }
+    }
+
+    /// (self - other) mod modulus, both operands already reduced
+    pub fn mod_sub(&self, other: &Huge, modulus: &Huge) -> Huge {
+        if *self >= *other {
+            self.sub(other)
+        } else {
+            self.add(modulus).sub(other)
+        }
+    }
+
+    pub fn mod_mul(&self, other: &Huge, modulus: &Huge) -> Huge {
+        self.mul(other).rem(modulus)
+    }
+
+    /// Computes self^exponent mod modulus
+    pub fn modpow(&self, exponent: &Huge, modulus: &Huge) -> Huge {
+        assert!(!modulus.is_zero(), "Huge modpow with zero modulus");
+        if *modulus == Self::one() {
+            return Self::zero();
+        }
+        if modulus.is_odd() {
+            return Montgomery::new(modulus).modpow(self, exponent);
+        }
+        // plain square-and-multiply for even moduli
+        let base = self.rem(modulus);
+        let mut result = Self::one();
+        for i in (0..exponent.bits()).rev() {
+            result = result.mod_mul(&result, modulus);
+            if exponent.bit(i) {
+                result = result.mod_mul(&base, modulus);
+            }
+        }
+        result
+    }
+
+    /// Returns the inverse of self modulo `modulus`, if it exists
+    pub fn modinv(&self, modulus: &Huge) -> Option<Huge> {
+        // extended Euclid, tracking only the coefficient of self (mod modulus)
+        let mut old_r = self.rem(modulus);
+        let mut r = modulus.clone();
+        let mut old_s = Self::one();
+        let mut s = Self::zero();
+        while !r.is_zero() {
+            let (q, rem) = old_r.divmod(&r);
+            old_r = std::mem::replace(&mut r, rem);
+            let qs = q.mod_mul(&s, modulus);
+            let new_s = old_s.mod_sub(&qs, modulus);
+            old_s = std::mem::replace(&mut s, new_s);
+        }
+        if old_r == Self::one() {
+            Some(old_s.rem(modulus))
+        } else {
+            None
+        }
+    }
+}
+
+impl Ord for Huge {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.len().cmp(&other.limbs.len()).then_with(|| {
+            self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+        })
+    }
+}
+
+impl PartialOrd for Huge {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Huge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Huge(0x{:x})", self)
+    }
+}
+
+impl fmt::LowerHex for Huge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut s = format!("{:x}", self.limbs.last().unwrap());
+        for l in self.limbs.iter().rev().skip(1) {
+            s = format!("{}{:08x}", s, l);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+/// Montgomery multiplication context for an odd modulus
+struct Montgomery {
+    modulus: Vec<u32>,
+    /// -modulus^-1 mod 2^32
+    m0_inv: u32,
+    /// R^2 mod modulus, where R = 2^(32 * modulus.len())
+    r2: Vec<u32>,
+}
+
+impl Montgomery {
+    fn new(modulus: &Huge) -> Self {
+        let m0 = modulus.limbs[0];
+        // Newton's iteration for the inverse of m0 mod 2^32
+        let mut inv: u32 = 1;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u32.wrapping_sub(m0.wrapping_mul(inv)));
+        }
+        let n = modulus.limbs.len();
+        let r2 = Huge::one().shl(64 * n).rem(modulus);
+        Self {
+            modulus: modulus.limbs.clone(),
+            m0_inv: inv.wrapping_neg(),
+            r2: Self::pad(&r2, n),
+        }
+    }
+
+    fn pad(x: &Huge, n: usize) -> Vec<u32> {
+        let mut limbs = x.limbs.clone();
+        limbs.resize(n, 0);
+        limbs
+    }
+
+    /// Returns a * b * R^-1 mod modulus (CIOS method)
+    fn mul(&self, a: &[u32], b: &[u32]) -> Vec<u32> {
+        let m = &self.modulus;
+        let n = m.len();
+        let mut t = vec![0u32; n + 2];
+        for ai in a.iter() {
+            let mut carry: u64 = 0;
+            for j in 0..n {
+                let s = u64::from(t[j]) + u64::from(*ai) * u64::from(b[j]) + carry;
+                t[j] = s as u32;
+                carry = s >> 32;
+            }
+            let s = u64::from(t[n]) + carry;
+            t[n] = s as u32;
+            t[n + 1] = (s >> 32) as u32;
+
+            let mm = t[0].wrapping_mul(self.m0_inv);
+            let s = u64::from(t[0]) + u64::from(mm) * u64::from(m[0]);
+            let mut carry = s >> 32;
+            for j in 1..n {
+                let s = u64::from(t[j]) + u64::from(mm) * u64::from(m[j]) + carry;
+                t[j - 1] = s as u32;
+                carry = s >> 32;
+            }
+            let s = u64::from(t[n]) + carry;
+            t[n - 1] = s as u32;
+            t[n] = t[n + 1] + (s >> 32) as u32;
+            t[n + 1] = 0;
+        }
+        // conditional final subtraction
+        let ge = t[n] != 0 || t[..n].iter().rev().cmp(m.iter().rev()) != Ordering::Less;
+        if ge {
+            let mut borrow: i64 = 0;
+            for j in 0..n {
+                let d = i64::from(t[j]) - i64::from(m[j]) - borrow;
+                t[j] = d as u32;
+                borrow = if d < 0 { 1 } else { 0 };
+            }
+        }
+        t.truncate(n);
+        t
+    }
+
+    fn modpow(&self, base: &Huge, exponent: &Huge) -> Huge {
+        let n = self.modulus.len();
+        let modulus = Huge::from_limbs(self.modulus.clone());
+        let base = Self::pad(&base.rem(&modulus), n);
+        let mut one = vec![0u32; n];
+        one[0] = 1;
+        // precompute base^0..15 in Montgomery form for a 4-bit fixed window
+        let mut table: Vec<Vec<u32>> = Vec::with_capacity(16);
+        table.push(self.mul(&one, &self.r2));
+        table.push(self.mul(&base, &self.r2));
+        for i in 2..16 {
+            let next = self.mul(&table[i - 1], &table[1]);
+            table.push(next);
+        }
+        let mut result = table[0].clone();
+        let windows = exponent.bits().div_ceil(4);
+        for w in (0..windows).rev() {
+            for _ in 0..4 {
+                result = self.mul(&result, &result);
+            }
+            let mut idx = 0usize;
+            for b in 0..4 {
+                if exponent.bit(w * 4 + b) {
+                    idx |= 1 << b;
+                }
+            }
+            if idx != 0 {
+                result = self.mul(&result, &table[idx]);
+            }
+        }
+        Huge::from_limbs(self.mul(&result, &one))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h(s: &str) -> Huge {
+        Huge::from_hex(s)
+    }
+
+    #[test]
+    fn test_can_convert_bytes() {
+        let n = Huge::from_bytes(&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(vec![0x01, 0x02, 0x03, 0x04, 0x05], n.to_bytes());
+        assert_eq!(vec![0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05], n.to_bytes_padded(7));
+        assert_eq!(33, n.bits());
+        assert!(Huge::from_bytes(&[0, 0]).is_zero());
+        assert_eq!(n, Huge::from_hex("102030405"));
+    }
+
+    #[test]
+    fn test_can_add_and_sub() {
+        let a = h("ffffffffffffffffffffffff");
+        let b = Huge::one();
+        let sum = a.add(&b);
+        assert_eq!(h("1000000000000000000000000"), sum);
+        assert_eq!(a, sum.sub(&b));
+        assert!(Huge::zero() < b);
+    }
+
+    #[test]
+    fn test_can_mul_and_divmod() {
+        let a = h("123456789abcdef0123456789abcdef0fedcba9876543210");
+        let b = h("fedcba98765432100123456789");
+        let c = h("abcdef");
+        let product = a.mul(&b).add(&c);
+        let (q, r) = product.divmod(&b);
+        assert_eq!(a, q);
+        assert_eq!(c, r);
+        let (q, r) = product.divmod(&Huge::from_u64(10));
+        assert_eq!(product, q.mul(&Huge::from_u64(10)).add(&r));
+    }
+
+    #[test]
+    fn test_can_shift() {
+        let a = h("123456789abcdef");
+        assert_eq!(h("123456789abcdef000000000"), a.shl(36));
+        assert_eq!(h("123456789"), a.shr(24));
+        assert!(a.shr(100).is_zero());
+    }
+
+    #[test]
+    fn test_can_modpow() {
+        // 4^13 mod 497 = 445
+        assert_eq!(Huge::from_u64(445), Huge::from_u64(4).modpow(&Huge::from_u64(13), &Huge::from_u64(497)));
+        // even modulus
+        assert_eq!(Huge::from_u64(43), Huge::from_u64(3).modpow(&Huge::from_u64(5), &Huge::from_u64(100)));
+        // Fermat's little theorem with a multi-limb prime (2^127 - 1)
+        let p = h("7fffffffffffffffffffffffffffffff");
+        let a = h("123456789abcdef0123456789");
+        assert_eq!(Huge::one(), a.modpow(&p.sub(&Huge::one()), &p));
+    }
+
+    #[test]
+    fn test_can_modinv() {
+        let p = h("7fffffffffffffffffffffffffffffff");
+        let a = h("123456789abcdef0123456789");
+        let inv = a.modinv(&p).unwrap();
+        assert_eq!(Huge::one(), a.mod_mul(&inv, &p));
+        assert!(Huge::from_u64(6).modinv(&Huge::from_u64(9)).is_none());
+    }
+}
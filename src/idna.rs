@@ -0,0 +1,126 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+// Punycode parameters (RFC 3492 section 5)
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+const ACE_PREFIX: &str = "xn--";
+
+/// Converts a domain name to its ASCII form, encoding every non-ASCII label
+/// as an A-label ("xn--..."). Labels are lowercased, which is the part of the
+/// IDNA mapping that matters for comparing names; full UTS #46 mapping is not done.
+pub fn domain_to_ascii(domain: &str) -> Result<String> {
+    let labels: Result<Vec<String>> = domain.split('.').map(label_to_ascii).collect();
+    Ok(labels?.join("."))
+}
+
+fn label_to_ascii(label: &str) -> Result<String> {
+    let label = label.to_lowercase();
+    if label.is_ascii() {
+        return Ok(label);
+    }
+    let encoded = punycode_encode(&label)?;
+    let result = format!("{}{}", ACE_PREFIX, encoded);
+    if result.len() > 63 {
+        return Err(anyhow!("Label is too long after IDNA encoding: {}", result));
+    }
+    Ok(result)
+}
+
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(d: u32) -> char {
+    if d < 26 {
+        (b'a' + d as u8) as char
+    } else {
+        (b'0' + (d - 26) as u8) as char
+    }
+}
+
+/// Punycode encoding of a single label (RFC 3492 section 6.3)
+pub fn punycode_encode(input: &str) -> Result<String> {
+    let code_points: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let mut output: String = input.chars().filter(|c| c.is_ascii()).collect();
+    let basic_len = output.len() as u32;
+    let mut handled = basic_len;
+    if basic_len > 0 {
+        output.push('-');
+    }
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let overflow = || anyhow!("Punycode overflow encoding: {}", input);
+    while (handled as usize) < code_points.len() {
+        let m = *code_points.iter().filter(|c| **c >= n).min().unwrap();
+        delta = (m - n).checked_mul(handled + 1).and_then(|d| d.checked_add(delta)).ok_or_else(overflow)?;
+        n = m;
+        for c in &code_points {
+            if *c < n {
+                delta = delta.checked_add(1).ok_or_else(overflow)?;
+            }
+            if *c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        TMIN
+                    } else if k >= bias + TMAX {
+                        TMAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic_len);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_punycode_encode() {
+        assert_eq!("mnchen-3ya", punycode_encode("münchen").unwrap());
+        assert_eq!("bcher-kva", punycode_encode("bücher").unwrap());
+        // RFC 3492 7.1 (B) Chinese (simplified)
+        assert_eq!("ihqwcrb4cv8a8dqg056pqjye", punycode_encode("他们为什么不说中文").unwrap());
+    }
+
+    #[test]
+    fn test_can_convert_domain_to_ascii() {
+        assert_eq!("xn--bcher-kva.example.com", domain_to_ascii("Bücher.Example.com").unwrap());
+        assert_eq!("www.example.com", domain_to_ascii("WWW.example.com").unwrap());
+        assert_eq!("xn--wgv71a119e.jp", domain_to_ascii("日本語.jp").unwrap());
+    }
+}
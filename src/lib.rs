@@ -1,4 +1,28 @@
 pub mod http;
 pub mod base64;
+pub mod hex;
 pub mod app;
 pub mod des;
+pub mod asn1;
+pub mod x509;
+pub mod digest;
+pub mod sha;
+#[cfg(feature = "legacy-protocols")]
+pub mod md5;
+pub mod huge;
+pub mod rsa;
+pub mod idna;
+pub mod pem;
+pub mod verify;
+pub mod random;
+pub mod hmac;
+pub mod aes;
+pub mod gcm;
+pub mod hkdf;
+pub mod x25519;
+pub mod ecc;
+pub mod ecdsa;
+pub mod dh;
+pub mod tls;
+#[cfg(test)]
+mod test_util;
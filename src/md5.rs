@@ -0,0 +1,119 @@
+use super::digest::Digest;
+use super::sha::BlockBuffer;
+
+const MD5_INITIAL_HASH: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+/// Left rotations of each round (RFC 1321 section 3.4)
+const MD5_SHIFTS: [[u32; 4]; 4] = [
+    [7, 12, 17, 22],
+    [5, 9, 14, 20],
+    [4, 11, 16, 23],
+    [6, 10, 15, 21],
+];
+
+/// MD5 (RFC 1321). Broken as a hash; it is only here for the PRF, MACs and
+/// signatures of SSL 3.0, TLS 1.0 and TLS 1.1.
+#[derive(Clone)]
+pub struct Md5 {
+    hash: [u32; 4],
+    buffer: BlockBuffer<64>,
+}
+
+impl Md5 {
+    pub fn new() -> Self {
+        Self {
+            hash: MD5_INITIAL_HASH,
+            buffer: BlockBuffer::new(),
+        }
+    }
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// T[i] = floor(abs(sin(i + 1)) * 2^32)
+fn md5_sine_table() -> [u32; 64] {
+    let mut table = [0u32; 64];
+    for (i, t) in table.iter_mut().enumerate() {
+        *t = (((i + 1) as f64).sin().abs() * 4294967296.0) as u32;
+    }
+    table
+}
+
+fn md5_block_operate(hash: &mut [u32; 4], block: &[u8; 64]) {
+    let table = md5_sine_table();
+    let mut m = [0u32; 16];
+    for (i, word) in m.iter_mut().enumerate() {
+        *word = u32::from_le_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+    }
+    let [mut a, mut b, mut c, mut d] = *hash;
+    for i in 0..64 {
+        let (f, g) = match i {
+            0..=15 => ((b & c) | (!b & d), i),
+            16..=31 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            32..=47 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let f = f.wrapping_add(a).wrapping_add(table[i]).wrapping_add(m[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16][i % 4]));
+    }
+    for (h, v) in hash.iter_mut().zip([a, b, c, d].iter()) {
+        *h = h.wrapping_add(*v);
+    }
+}
+
+impl Digest for Md5 {
+    fn update(&mut self, data: &[u8]) {
+        let hash = &mut self.hash;
+        self.buffer.input(data, |block| md5_block_operate(hash, block));
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut hash = self.hash;
+        self.buffer.pad(self.length_field_size(), true, |block| md5_block_operate(&mut hash, block));
+        hash.iter().flat_map(|h| h.to_le_bytes().to_vec()).collect()
+    }
+
+    fn output_size(&self) -> usize {
+        16
+    }
+
+    fn block_size(&self) -> usize {
+        64
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer.buffered_len()
+    }
+
+    fn length_field_size(&self) -> usize {
+        8
+    }
+
+    fn box_clone(&self) -> Box<dyn Digest> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::digest::HashAlgorithm;
+    use super::super::hex::hex_encode;
+
+    #[test]
+    fn test_can_md5_digest() {
+        assert_eq!("d41d8cd98f00b204e9800998ecf8427e", hex_encode(&HashAlgorithm::Md5.digest(b"")));
+        assert_eq!("900150983cd24fb0d6963f7d28e17f72", hex_encode(&HashAlgorithm::Md5.digest(b"abc")));
+        // two blocks
+        assert_eq!(
+            "57edf4a22be3c955ac49da2e2107b67a",
+            hex_encode(&HashAlgorithm::Md5.digest(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"))
+        );
+    }
+}
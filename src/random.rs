@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{
+    Result,
+    Context as _,
+};
+
+const RANDOM_DEVICE: &str = "/dev/urandom";
+
+/// Fills `buf` from the operating system's CSPRNG
+pub fn fill_random(buf: &mut [u8]) -> Result<()> {
+    let mut device = File::open(RANDOM_DEVICE).with_context(|| format!("Unable to open {}", RANDOM_DEVICE))?;
+    device.read_exact(buf).with_context(|| format!("Unable to read from {}", RANDOM_DEVICE))?;
+    Ok(())
+}
+
+pub fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    fill_random(&mut buf)?;
+    Ok(buf)
+}
+
+/// Random bytes none of which is zero, as required by PKCS#1 v1.5 padding
+pub fn random_nonzero_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut result: Vec<u8> = Vec::with_capacity(len);
+    while result.len() < len {
+        let chunk = random_bytes(len - result.len() + 16)?;
+        result.extend(chunk.into_iter().filter(|b| *b != 0).take(len - result.len()));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_generate_random_bytes() {
+        let a = random_bytes(32).unwrap();
+        let b = random_bytes(32).unwrap();
+        assert_eq!(32, a.len());
+        assert_ne!(a, b);
+        assert!(random_nonzero_bytes(1000).unwrap().iter().all(|b| *b != 0));
+    }
+}
@@ -0,0 +1,480 @@
+use std::fmt;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::asn1::{
+    self,
+    TAG_INTEGER,
+    TAG_OCTET_STRING,
+    TAG_SEQUENCE,
+};
+use super::digest::HashAlgorithm;
+use super::huge::Huge;
+use super::pem::{
+    Pem,
+    LABEL_ENCRYPTED_PRIVATE_KEY,
+    LABEL_PRIVATE_KEY,
+    LABEL_RSA_PRIVATE_KEY,
+};
+use super::hmac::constant_time_eq;
+use super::random::{
+    random_bytes,
+    random_nonzero_bytes,
+};
+use super::x509::{
+    OID_RSA_ENCRYPTION,
+    SubjectPublicKeyInfo,
+    parse_algorithm_identifier,
+};
+
+/// DER encoded DigestInfo prefixes for EMSA-PKCS1-v1_5 (RFC 8017 section 9.2, note 1)
+#[cfg(feature = "legacy-protocols")]
+const MD5_DIGEST_INFO: &[u8] = &[0x30, 0x20, 0x30, 0x0c, 0x06, 0x08, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x05, 0x05, 0x00, 0x04, 0x10];
+const SHA1_DIGEST_INFO: &[u8] = &[0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14];
+const SHA256_DIGEST_INFO: &[u8] = &[0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20];
+const SHA384_DIGEST_INFO: &[u8] = &[0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05, 0x00, 0x04, 0x30];
+const SHA512_DIGEST_INFO: &[u8] = &[0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RsaPublicKey {
+    pub modulus: Huge,
+    pub exponent: Huge,
+}
+
+impl RsaPublicKey {
+    /// Parses a PKCS#1 RSAPublicKey
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<Self> {
+        let key = asn1::parse(der)?;
+        key.expect_tag(TAG_SEQUENCE)?;
+        let mut reader = key.children()?;
+        let modulus = Huge::from_bytes(reader.expect(TAG_INTEGER)?.as_unsigned_bytes()?);
+        let exponent = Huge::from_bytes(reader.expect(TAG_INTEGER)?.as_unsigned_bytes()?);
+        reader.expect_end()?;
+        if modulus.bits() < 512 || exponent.is_zero() {
+            return Err(anyhow!("Unacceptable RSA public key: {} bits", modulus.bits()));
+        }
+        Ok(Self {
+            modulus,
+            exponent,
+        })
+    }
+
+    pub fn from_spki(spki: &SubjectPublicKeyInfo) -> Result<Self> {
+        if spki.algorithm.algorithm != *OID_RSA_ENCRYPTION {
+            return Err(anyhow!("Not an RSA public key: {}", spki.algorithm.algorithm));
+        }
+        Self::from_pkcs1_der(&spki.public_key)
+    }
+
+    /// Length of the modulus in bytes
+    pub fn size(&self) -> usize {
+        self.modulus.bits().div_ceil(8)
+    }
+
+    /// RSAEP/RSAVP1: input^e mod n
+    fn public_operate(&self, input: &[u8]) -> Result<Vec<u8>> {
+        if input.len() != self.size() {
+            return Err(anyhow!("RSA input length is incorrect. expected: {}, actual: {}", self.size(), input.len()));
+        }
+        let m = Huge::from_bytes(input);
+        if m >= self.modulus {
+            return Err(anyhow!("RSA input is out of range"));
+        }
+        Ok(m.modpow(&self.exponent, &self.modulus).to_bytes_padded(self.size()))
+    }
+
+    /// RSAES-PKCS1-v1_5 encryption (RFC 8017 section 7.2.1)
+    pub fn encrypt_pkcs1_v15(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let k = self.size();
+        if message.len() + 11 > k {
+            return Err(anyhow!("Message too long for RSA encryption: {} bytes", message.len()));
+        }
+        // EM = 0x00 || 0x02 || PS (non-zero random) || 0x00 || M
+        let mut em: Vec<u8> = vec![0x00, 0x02];
+        em.extend(random_nonzero_bytes(k - message.len() - 3)?);
+        em.push(0x00);
+        em.extend_from_slice(message);
+        self.public_operate(&em)
+    }
+
+    /// Verifies an RSASSA-PKCS1-v1_5 signature over `message`
+    pub fn verify_pkcs1_v15(&self, hash: HashAlgorithm, message: &[u8], signature: &[u8]) -> Result<()> {
+        let em = self.public_operate(signature)?;
+        // compare against the expected encoding instead of parsing the signature,
+        // which avoids the classic forgeries against lenient DigestInfo parsers
+        let expected = emsa_pkcs1_v15_encode(hash, &hash.digest(message), self.size())?;
+        if em != expected {
+            return Err(anyhow!("RSA signature verification failed"));
+        }
+        Ok(())
+    }
+
+    /// Verifies a TLS 1.0/1.1 signature: PKCS#1 v1.5 over MD5(message) || SHA-1(message)
+    /// without a DigestInfo (RFC 2246 section 7.4.3)
+    #[cfg(feature = "legacy-protocols")]
+    pub fn verify_pkcs1_v15_md5_sha1(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let em = self.public_operate(signature)?;
+        let expected = emsa_pkcs1_v15_pad(&md5_sha1(message), self.size())?;
+        if em != expected {
+            return Err(anyhow!("RSA signature verification failed"));
+        }
+        Ok(())
+    }
+
+    /// Verifies an RSASSA-PSS signature with MGF1 over the same hash and a
+    /// salt as long as the digest, the only parameters TLS 1.3 allows
+    pub fn verify_pss(&self, hash: HashAlgorithm, message: &[u8], signature: &[u8]) -> Result<()> {
+        let em = self.public_operate(signature)?;
+        let em_bits = self.modulus.bits() - 1;
+        let em_len = em_bits.div_ceil(8);
+        // EM is one byte shorter than the modulus when modBits - 1 is a multiple of 8
+        let (leading, em) = em.split_at(em.len() - em_len);
+        if leading.iter().any(|b| *b != 0) || !emsa_pss_verify(hash, &hash.digest(message), em, em_bits) {
+            return Err(anyhow!("RSA-PSS signature verification failed"));
+        }
+        Ok(())
+    }
+}
+
+/// RSA private key with the CRT parameters of PKCS#1 RSAPrivateKey
+#[derive(Clone, PartialEq)]
+pub struct RsaPrivateKey {
+    pub modulus: Huge,
+    pub public_exponent: Huge,
+    pub private_exponent: Huge,
+    pub prime1: Huge,
+    pub prime2: Huge,
+    /// d mod (p - 1)
+    pub exponent1: Huge,
+    /// d mod (q - 1)
+    pub exponent2: Huge,
+    /// q^-1 mod p
+    pub coefficient: Huge,
+}
+
+impl fmt::Debug for RsaPrivateKey {
+    /// Only the public half is printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RsaPrivateKey {{ modulus: {:?}, public_exponent: {:?} }}", self.modulus, self.public_exponent)
+    }
+}
+
+impl RsaPrivateKey {
+    /// Parses a PKCS#1 RSAPrivateKey (two-prime only)
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<Self> {
+        let key = asn1::parse(der)?;
+        key.expect_tag(TAG_SEQUENCE)?;
+        let mut reader = key.children()?;
+        let version = reader.expect(TAG_INTEGER)?.as_u64()?;
+        if version != 0 {
+            return Err(anyhow!("Unsupported RSAPrivateKey version: {}", version));
+        }
+        let mut next = || -> Result<Huge> { Ok(Huge::from_bytes(reader.expect(TAG_INTEGER)?.as_unsigned_bytes()?)) };
+        let key = Self {
+            modulus: next()?,
+            public_exponent: next()?,
+            private_exponent: next()?,
+            prime1: next()?,
+            prime2: next()?,
+            exponent1: next()?,
+            exponent2: next()?,
+            coefficient: next()?,
+        };
+        reader.expect_end()?;
+        if key.modulus.bits() < 512 || key.prime1.mul(&key.prime2) != key.modulus {
+            return Err(anyhow!("Inconsistent RSA private key"));
+        }
+        Ok(key)
+    }
+
+    /// Parses a PKCS#8 PrivateKeyInfo holding an RSA key
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        let info = asn1::parse(der)?;
+        info.expect_tag(TAG_SEQUENCE)?;
+        let mut reader = info.children()?;
+        let version = reader.expect(TAG_INTEGER)?.as_u64()?;
+        if version > 1 {
+            return Err(anyhow!("Unsupported PrivateKeyInfo version: {}", version));
+        }
+        let algorithm = parse_algorithm_identifier(&reader.expect(TAG_SEQUENCE)?)?;
+        if algorithm.algorithm != *OID_RSA_ENCRYPTION {
+            return Err(anyhow!("Not an RSA private key: {}", algorithm.algorithm));
+        }
+        Self::from_pkcs1_der(reader.expect(TAG_OCTET_STRING)?.value)
+    }
+
+    /// Accepts "RSA PRIVATE KEY" (PKCS#1) and unencrypted "PRIVATE KEY" (PKCS#8) blocks
+    pub fn from_pem(pem: &Pem) -> Result<Self> {
+        match &*pem.label {
+            LABEL_RSA_PRIVATE_KEY if pem.header("Proc-Type").is_some() => Err(anyhow!("Encrypted PEM private keys are not supported")),
+            LABEL_RSA_PRIVATE_KEY => Self::from_pkcs1_der(&pem.contents),
+            LABEL_PRIVATE_KEY => Self::from_pkcs8_der(&pem.contents),
+            LABEL_ENCRYPTED_PRIVATE_KEY => Err(anyhow!("Encrypted PKCS#8 private keys are not supported")),
+            label => Err(anyhow!("Not a private key: {}", label)),
+        }
+    }
+
+    pub fn public_key(&self) -> RsaPublicKey {
+        RsaPublicKey {
+            modulus: self.modulus.clone(),
+            exponent: self.public_exponent.clone(),
+        }
+    }
+
+    /// Length of the modulus in bytes
+    pub fn size(&self) -> usize {
+        self.modulus.bits().div_ceil(8)
+    }
+
+    /// RSADP/RSASP1 with the Chinese remainder theorem
+    fn private_operate(&self, input: &[u8]) -> Result<Vec<u8>> {
+        if input.len() != self.size() {
+            return Err(anyhow!("RSA input length is incorrect. expected: {}, actual: {}", self.size(), input.len()));
+        }
+        let c = Huge::from_bytes(input);
+        if c >= self.modulus {
+            return Err(anyhow!("RSA input is out of range"));
+        }
+        let m1 = c.rem(&self.prime1).modpow(&self.exponent1, &self.prime1);
+        let m2 = c.rem(&self.prime2).modpow(&self.exponent2, &self.prime2);
+        let h = self.coefficient.mod_mul(&m1.mod_sub(&m2.rem(&self.prime1), &self.prime1), &self.prime1);
+        let m = m2.add(&h.mul(&self.prime2));
+        // a faulty CRT computation would leak a factor of n through the output
+        if m.modpow(&self.public_exponent, &self.modulus) != c {
+            return Err(anyhow!("RSA private operation self-check failed"));
+        }
+        Ok(m.to_bytes_padded(self.size()))
+    }
+
+    /// RSAES-PKCS1-v1_5 decryption (RFC 8017 section 7.2.2)
+    /// Callers in a protocol must not reveal which check failed (Bleichenbacher's attack).
+    pub fn decrypt_pkcs1_v15(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let em = self.private_operate(ciphertext)?;
+        let separator = em.iter().skip(2).position(|b| *b == 0x00);
+        match separator {
+            // at least 8 bytes of padding
+            Some(pos) if em[0] == 0x00 && em[1] == 0x02 && pos >= 8 => Ok(em[pos + 3..].to_vec()),
+            _ => Err(anyhow!("RSA decryption error")),
+        }
+    }
+
+    /// RSASSA-PKCS1-v1_5 signature over `message`
+    pub fn sign_pkcs1_v15(&self, hash: HashAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        let em = emsa_pkcs1_v15_encode(hash, &hash.digest(message), self.size())?;
+        self.private_operate(&em)
+    }
+
+    /// TLS 1.0/1.1 signature: PKCS#1 v1.5 over MD5(message) || SHA-1(message) without a DigestInfo
+    #[cfg(feature = "legacy-protocols")]
+    pub fn sign_pkcs1_v15_md5_sha1(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let em = emsa_pkcs1_v15_pad(&md5_sha1(message), self.size())?;
+        self.private_operate(&em)
+    }
+
+    /// RSASSA-PSS signature with MGF1 over `hash` and a salt of the digest length
+    pub fn sign_pss(&self, hash: HashAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        let em_bits = self.modulus.bits() - 1;
+        let salt = random_bytes(hash.output_size())?;
+        let mut em = vec![0u8; self.size() - em_bits.div_ceil(8)];
+        em.extend(emsa_pss_encode(hash, &hash.digest(message), &salt, em_bits)?);
+        self.private_operate(&em)
+    }
+}
+
+fn digest_info_prefix(hash: HashAlgorithm) -> &'static [u8] {
+    match hash {
+        #[cfg(feature = "legacy-protocols")]
+        HashAlgorithm::Md5 => MD5_DIGEST_INFO,
+        HashAlgorithm::Sha1 => SHA1_DIGEST_INFO,
+        HashAlgorithm::Sha256 => SHA256_DIGEST_INFO,
+        HashAlgorithm::Sha384 => SHA384_DIGEST_INFO,
+        HashAlgorithm::Sha512 => SHA512_DIGEST_INFO,
+    }
+}
+
+/// EM = 0x00 || 0x01 || PS (0xff...) || 0x00 || DigestInfo
+fn emsa_pkcs1_v15_encode(hash: HashAlgorithm, digest: &[u8], em_len: usize) -> Result<Vec<u8>> {
+    let mut t = digest_info_prefix(hash).to_vec();
+    t.extend_from_slice(digest);
+    emsa_pkcs1_v15_pad(&t, em_len)
+}
+
+/// EM = 0x00 || 0x01 || PS (0xff...) || 0x00 || T
+fn emsa_pkcs1_v15_pad(t: &[u8], em_len: usize) -> Result<Vec<u8>> {
+    if em_len < t.len() + 11 {
+        return Err(anyhow!("RSA modulus is too short for the digest"));
+    }
+    let mut em: Vec<u8> = vec![0x00, 0x01];
+    em.resize(em_len - t.len() - 1, 0xff);
+    em.push(0x00);
+    em.extend_from_slice(t);
+    Ok(em)
+}
+
+#[cfg(feature = "legacy-protocols")]
+fn md5_sha1(message: &[u8]) -> Vec<u8> {
+    let mut t = HashAlgorithm::Md5.digest(message);
+    t.extend(HashAlgorithm::Sha1.digest(message));
+    t
+}
+
+/// MGF1 mask generation (RFC 8017 appendix B.2.1)
+fn mgf1(hash: HashAlgorithm, seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask: Vec<u8> = Vec::with_capacity(len + hash.output_size());
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut input = seed.to_vec();
+        input.extend_from_slice(&counter.to_be_bytes());
+        mask.extend(hash.digest(&input));
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+/// H(0x00 * 8 || mHash || salt)
+fn pss_hash(hash: HashAlgorithm, digest: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut m: Vec<u8> = vec![0u8; 8];
+    m.extend_from_slice(digest);
+    m.extend_from_slice(salt);
+    hash.digest(&m)
+}
+
+/// EM = maskedDB || H || 0xbc (RFC 8017 section 9.1.1)
+fn emsa_pss_encode(hash: HashAlgorithm, digest: &[u8], salt: &[u8], em_bits: usize) -> Result<Vec<u8>> {
+    let h_len = digest.len();
+    let em_len = em_bits.div_ceil(8);
+    if em_len < h_len + salt.len() + 2 {
+        return Err(anyhow!("RSA modulus is too short for PSS"));
+    }
+    let h = pss_hash(hash, digest, salt);
+    // DB = PS || 0x01 || salt
+    let mut db = vec![0u8; em_len - salt.len() - h_len - 2];
+    db.push(0x01);
+    db.extend_from_slice(salt);
+    let mask = mgf1(hash, &h, db.len());
+    let mut em: Vec<u8> = db.iter().zip(mask.iter()).map(|(d, m)| d ^ m).collect();
+    em[0] &= 0xff >> (8 * em_len - em_bits);
+    em.extend(h);
+    em.push(0xbc);
+    Ok(em)
+}
+
+/// RFC 8017 section 9.1.2 with sLen = hLen
+fn emsa_pss_verify(hash: HashAlgorithm, digest: &[u8], em: &[u8], em_bits: usize) -> bool {
+    let h_len = digest.len();
+    let em_len = em.len();
+    if em_len < 2 * h_len + 2 || em[em_len - 1] != 0xbc {
+        return false;
+    }
+    let (masked_db, h) = em[..em_len - 1].split_at(em_len - h_len - 1);
+    let unused_bits = 8 * em_len - em_bits;
+    if masked_db[0] & !(0xff >> unused_bits) != 0 {
+        return false;
+    }
+    let mask = mgf1(hash, h, masked_db.len());
+    let mut db: Vec<u8> = masked_db.iter().zip(mask.iter()).map(|(d, m)| d ^ m).collect();
+    db[0] &= 0xff >> unused_bits;
+    let ps_len = em_len - 2 * h_len - 2;
+    if db[..ps_len].iter().any(|b| *b != 0) || db[ps_len] != 0x01 {
+        return false;
+    }
+    let salt = &db[ps_len + 1..];
+    constant_time_eq(&pss_hash(hash, digest, salt), h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::x509::Certificate;
+
+    const LEAF_DER: &[u8] = include_bytes!("../testdata/leaf.der");
+    const ROOT_DER: &[u8] = include_bytes!("../testdata/root.der");
+
+    #[test]
+    fn test_can_parse_public_key() {
+        let cert = Certificate::from_der(LEAF_DER).unwrap();
+        let key = RsaPublicKey::from_spki(&cert.subject_public_key_info).unwrap();
+        assert_eq!(256, key.size());
+        assert_eq!(Huge::from_u64(65537), key.exponent);
+    }
+
+    #[test]
+    fn test_can_verify_self_signed_certificate() {
+        let cert = Certificate::from_der(ROOT_DER).unwrap();
+        let key = RsaPublicKey::from_spki(&cert.subject_public_key_info).unwrap();
+        key.verify_pkcs1_v15(HashAlgorithm::Sha256, &cert.tbs_certificate, &cert.signature_value).unwrap();
+    }
+
+    fn leaf_key() -> RsaPrivateKey {
+        let pem = super::super::pem::parse(include_str!("../testdata/leaf.key")).unwrap();
+        RsaPrivateKey::from_pem(&pem).unwrap()
+    }
+
+    #[test]
+    fn test_can_parse_private_key_formats() {
+        let key = leaf_key();
+        let cert = Certificate::from_der(LEAF_DER).unwrap();
+        assert_eq!(RsaPublicKey::from_spki(&cert.subject_public_key_info).unwrap(), key.public_key());
+        let pkcs1 = super::super::pem::parse(include_str!("../testdata/leaf-pkcs1.key")).unwrap();
+        assert_eq!(LABEL_RSA_PRIVATE_KEY, pkcs1.label);
+        assert_eq!(key, RsaPrivateKey::from_pem(&pkcs1).unwrap());
+    }
+
+    #[test]
+    fn test_can_encrypt_and_decrypt() {
+        let key = leaf_key();
+        let ciphertext = key.public_key().encrypt_pkcs1_v15(b"pre-master secret").unwrap();
+        assert_eq!(256, ciphertext.len());
+        assert_eq!(b"pre-master secret".to_vec(), key.decrypt_pkcs1_v15(&ciphertext).unwrap());
+        // a signature is not a valid encryption block
+        let signature = key.sign_pkcs1_v15(HashAlgorithm::Sha256, b"message").unwrap();
+        assert!(key.decrypt_pkcs1_v15(&signature).is_err());
+    }
+
+    #[test]
+    fn test_can_sign_and_verify() {
+        let key = leaf_key();
+        let signature = key.sign_pkcs1_v15(HashAlgorithm::Sha384, b"message").unwrap();
+        key.public_key().verify_pkcs1_v15(HashAlgorithm::Sha384, b"message", &signature).unwrap();
+        assert!(key.public_key().verify_pkcs1_v15(HashAlgorithm::Sha384, b"massage", &signature).is_err());
+    }
+
+    #[cfg(feature = "legacy-protocols")]
+    #[test]
+    fn test_can_sign_and_verify_md5_sha1() {
+        let key = leaf_key();
+        let signature = key.sign_pkcs1_v15_md5_sha1(b"message").unwrap();
+        key.public_key().verify_pkcs1_v15_md5_sha1(b"message", &signature).unwrap();
+        assert!(key.public_key().verify_pkcs1_v15_md5_sha1(b"massage", &signature).is_err());
+        let sha1 = key.sign_pkcs1_v15(HashAlgorithm::Sha1, b"message").unwrap();
+        assert!(key.public_key().verify_pkcs1_v15_md5_sha1(b"message", &sha1).is_err());
+    }
+
+    #[test]
+    fn test_can_sign_and_verify_pss() {
+        let key = leaf_key();
+        let signature = key.sign_pss(HashAlgorithm::Sha256, b"message").unwrap();
+        assert_eq!(256, signature.len());
+        // randomized salt
+        assert_ne!(signature, key.sign_pss(HashAlgorithm::Sha256, b"message").unwrap());
+        key.public_key().verify_pss(HashAlgorithm::Sha256, b"message", &signature).unwrap();
+        assert!(key.public_key().verify_pss(HashAlgorithm::Sha256, b"massage", &signature).is_err());
+        assert!(key.public_key().verify_pss(HashAlgorithm::Sha384, b"message", &signature).is_err());
+        let pkcs1 = key.sign_pkcs1_v15(HashAlgorithm::Sha256, b"message").unwrap();
+        assert!(key.public_key().verify_pss(HashAlgorithm::Sha256, b"message", &pkcs1).is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_tampered_message() {
+        let cert = Certificate::from_der(ROOT_DER).unwrap();
+        let key = RsaPublicKey::from_spki(&cert.subject_public_key_info).unwrap();
+        let mut tbs = cert.tbs_certificate.clone();
+        tbs[10] ^= 0x01;
+        assert!(key.verify_pkcs1_v15(HashAlgorithm::Sha256, &tbs, &cert.signature_value).is_err());
+        assert!(key.verify_pkcs1_v15(HashAlgorithm::Sha1, &cert.tbs_certificate, &cert.signature_value).is_err());
+    }
+}
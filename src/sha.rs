@@ -0,0 +1,434 @@
+use super::digest::Digest;
+
+const SHA1_INITIAL_HASH: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+const SHA256_INITIAL_HASH: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA512_INITIAL_HASH: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const SHA384_INITIAL_HASH: [u64; 8] = [
+    0xcbbb9d5dc1059ed8, 0x629a292a367cd507, 0x9159015a3070dd17, 0x152fecd8f70e5939,
+    0x67332667ffc00b31, 0x8eb44a8768581511, 0xdb0c2e0d64f98fa7, 0x47b5481dbefa4fa4,
+];
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+/// Buffers input until a whole block is available
+#[derive(Clone)]
+pub(crate) struct BlockBuffer<const N: usize> {
+    block: [u8; N],
+    len: usize,
+    total: u128,
+}
+
+impl<const N: usize> BlockBuffer<N> {
+    pub(crate) fn new() -> Self {
+        Self {
+            block: [0; N],
+            len: 0,
+            total: 0,
+        }
+    }
+
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn input(&mut self, mut data: &[u8], mut process: impl FnMut(&[u8; N])) {
+        self.total += data.len() as u128;
+        while !data.is_empty() {
+            let n = std::cmp::min(N - self.len, data.len());
+            self.block[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len == N {
+                process(&self.block);
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Merkle-Damgård padding: 0x80, zeros, then the message length in bits
+    /// stored in `length_size` bytes (big-endian unless `little_endian`).
+    pub(crate) fn pad(&self, length_size: usize, little_endian: bool, mut process: impl FnMut(&[u8; N])) {
+        let mut block = self.block;
+        let mut len = self.len;
+        block[len] = 0x80;
+        len += 1;
+        if len > N - length_size {
+            block[len..].iter_mut().for_each(|b| *b = 0);
+            process(&block);
+            len = 0;
+        }
+        block[len..].iter_mut().for_each(|b| *b = 0);
+        let bits = self.total.wrapping_mul(8);
+        for i in 0..length_size {
+            let byte = (bits >> (8 * i)) as u8;
+            if little_endian {
+                block[N - length_size + i] = byte;
+            } else {
+                block[N - 1 - i] = byte;
+            }
+        }
+        process(&block);
+    }
+}
+
+#[derive(Clone)]
+pub struct Sha1 {
+    hash: [u32; 5],
+    buffer: BlockBuffer<64>,
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Self {
+            hash: SHA1_INITIAL_HASH,
+            buffer: BlockBuffer::new(),
+        }
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sha1_block_operate(hash: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for t in 0..16 {
+        w[t] = u32::from_be_bytes([block[t * 4], block[t * 4 + 1], block[t * 4 + 2], block[t * 4 + 3]]);
+    }
+    for t in 16..80 {
+        w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = *hash;
+    for (t, wt) in w.iter().enumerate() {
+        let (f, k) = match t {
+            0..=19 => ((b & c) | (!b & d), 0x5a827999),
+            20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+            _ => (b ^ c ^ d, 0xca62c1d6),
+        };
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wt);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (h, v) in hash.iter_mut().zip([a, b, c, d, e].iter()) {
+        *h = h.wrapping_add(*v);
+    }
+}
+
+impl Digest for Sha1 {
+    fn update(&mut self, data: &[u8]) {
+        let hash = &mut self.hash;
+        self.buffer.input(data, |block| sha1_block_operate(hash, block));
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut hash = self.hash;
+        self.buffer.pad(self.length_field_size(), false, |block| sha1_block_operate(&mut hash, block));
+        hash.iter().flat_map(|h| h.to_be_bytes().to_vec()).collect()
+    }
+
+    fn output_size(&self) -> usize {
+        20
+    }
+
+    fn block_size(&self) -> usize {
+        64
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer.buffered_len()
+    }
+
+    fn length_field_size(&self) -> usize {
+        8
+    }
+
+    fn box_clone(&self) -> Box<dyn Digest> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct Sha256 {
+    hash: [u32; 8],
+    buffer: BlockBuffer<64>,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            hash: SHA256_INITIAL_HASH,
+            buffer: BlockBuffer::new(),
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sha256_block_operate(hash: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 64];
+    for t in 0..16 {
+        w[t] = u32::from_be_bytes([block[t * 4], block[t * 4 + 1], block[t * 4 + 2], block[t * 4 + 3]]);
+    }
+    for t in 16..64 {
+        let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+        let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+        w[t] = w[t - 16].wrapping_add(s0).wrapping_add(w[t - 7]).wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *hash;
+    for t in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[t]).wrapping_add(w[t]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+    for (x, v) in hash.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+        *x = x.wrapping_add(*v);
+    }
+}
+
+impl Digest for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        let hash = &mut self.hash;
+        self.buffer.input(data, |block| sha256_block_operate(hash, block));
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut hash = self.hash;
+        self.buffer.pad(self.length_field_size(), false, |block| sha256_block_operate(&mut hash, block));
+        hash.iter().flat_map(|h| h.to_be_bytes().to_vec()).collect()
+    }
+
+    fn output_size(&self) -> usize {
+        32
+    }
+
+    fn block_size(&self) -> usize {
+        64
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer.buffered_len()
+    }
+
+    fn length_field_size(&self) -> usize {
+        8
+    }
+
+    fn box_clone(&self) -> Box<dyn Digest> {
+        Box::new(self.clone())
+    }
+}
+
+/// SHA-512, or SHA-384 which is a truncated SHA-512 with a different initial hash
+#[derive(Clone)]
+pub struct Sha512 {
+    hash: [u64; 8],
+    buffer: BlockBuffer<128>,
+    output_size: usize,
+}
+
+impl Sha512 {
+    pub fn new() -> Self {
+        Self {
+            hash: SHA512_INITIAL_HASH,
+            buffer: BlockBuffer::new(),
+            output_size: 64,
+        }
+    }
+
+    pub fn new_384() -> Self {
+        Self {
+            hash: SHA384_INITIAL_HASH,
+            buffer: BlockBuffer::new(),
+            output_size: 48,
+        }
+    }
+}
+
+impl Default for Sha512 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sha512_block_operate(hash: &mut [u64; 8], block: &[u8; 128]) {
+    let mut w = [0u64; 80];
+    for t in 0..16 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&block[t * 8..t * 8 + 8]);
+        w[t] = u64::from_be_bytes(bytes);
+    }
+    for t in 16..80 {
+        let s0 = w[t - 15].rotate_right(1) ^ w[t - 15].rotate_right(8) ^ (w[t - 15] >> 7);
+        let s1 = w[t - 2].rotate_right(19) ^ w[t - 2].rotate_right(61) ^ (w[t - 2] >> 6);
+        w[t] = w[t - 16].wrapping_add(s0).wrapping_add(w[t - 7]).wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *hash;
+    for t in 0..80 {
+        let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA512_K[t]).wrapping_add(w[t]);
+        let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+    for (x, v) in hash.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+        *x = x.wrapping_add(*v);
+    }
+}
+
+impl Digest for Sha512 {
+    fn update(&mut self, data: &[u8]) {
+        let hash = &mut self.hash;
+        self.buffer.input(data, |block| sha512_block_operate(hash, block));
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut hash = self.hash;
+        self.buffer.pad(self.length_field_size(), false, |block| sha512_block_operate(&mut hash, block));
+        let mut result: Vec<u8> = hash.iter().flat_map(|h| h.to_be_bytes().to_vec()).collect();
+        result.truncate(self.output_size);
+        result
+    }
+
+    fn output_size(&self) -> usize {
+        self.output_size
+    }
+
+    fn block_size(&self) -> usize {
+        128
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer.buffered_len()
+    }
+
+    fn length_field_size(&self) -> usize {
+        16
+    }
+
+    fn box_clone(&self) -> Box<dyn Digest> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::digest::HashAlgorithm;
+    use super::super::hex::hex_encode;
+
+    #[test]
+    fn test_can_sha1_digest() {
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", hex_encode(&HashAlgorithm::Sha1.digest(b"abc")));
+        assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex_encode(&HashAlgorithm::Sha1.digest(b"")));
+    }
+
+    #[test]
+    fn test_can_sha256_digest() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex_encode(&HashAlgorithm::Sha256.digest(b"abc"))
+        );
+        // two block message
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            hex_encode(&HashAlgorithm::Sha256.digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"))
+        );
+    }
+
+    #[test]
+    fn test_can_sha384_and_sha512_digest() {
+        assert_eq!(
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7",
+            hex_encode(&HashAlgorithm::Sha384.digest(b"abc"))
+        );
+        assert_eq!(
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            hex_encode(&HashAlgorithm::Sha512.digest(b"abc"))
+        );
+    }
+
+    #[test]
+    fn test_can_digest_incrementally() {
+        let data = vec![0x61u8; 1000];
+        let mut digest = Sha256::new();
+        for chunk in data.chunks(7) {
+            digest.update(chunk);
+        }
+        let intermediate = digest.finish();
+        assert_eq!(HashAlgorithm::Sha256.digest(&data), intermediate);
+        digest.update(b"more");
+        let mut all = data.clone();
+        all.extend_from_slice(b"more");
+        assert_eq!(HashAlgorithm::Sha256.digest(&all), digest.finish());
+    }
+}
@@ -0,0 +1,51 @@
+//! Helpers shared by the unit tests
+
+use std::io::{
+    self,
+    Cursor,
+    Read,
+    Write,
+};
+
+use super::hex::hex_decode;
+
+/// In-memory stream: reads come from `input`, writes go to `output`
+pub struct MockStream {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl MockStream {
+    pub fn new(input: Vec<u8>) -> Self {
+        Self {
+            input: Cursor::new(input),
+            output: vec![],
+        }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes a hex test vector, panicking on malformed input
+pub fn from_hex(s: &str) -> Vec<u8> {
+    hex_decode(s).unwrap()
+}
+
+/// Absolute path of a file under `testdata/`
+pub fn testdata(name: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+use std::io;
+
+use anyhow::{
+    Error,
+    Result,
+};
+
+use crate::verify::CertificateError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertLevel {
+    Warning,
+    Fatal,
+}
+
+impl AlertLevel {
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(AlertLevel::Warning),
+            2 => Ok(AlertLevel::Fatal),
+            _ => Err(fatal(DECODE_ERROR, format!("Unknown alert level {}", value))),
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            AlertLevel::Warning => 1,
+            AlertLevel::Fatal => 2,
+        }
+    }
+}
+
+/// AlertDescription (RFC 5246 section 7.2, RFC 8446 section 6). Values without
+/// a constant below are kept as received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AlertDescription(pub u8);
+
+pub const CLOSE_NOTIFY: AlertDescription = AlertDescription(0);
+pub const UNEXPECTED_MESSAGE: AlertDescription = AlertDescription(10);
+pub const BAD_RECORD_MAC: AlertDescription = AlertDescription(20);
+pub const DECRYPTION_FAILED: AlertDescription = AlertDescription(21);
+pub const RECORD_OVERFLOW: AlertDescription = AlertDescription(22);
+pub const DECOMPRESSION_FAILURE: AlertDescription = AlertDescription(30);
+pub const HANDSHAKE_FAILURE: AlertDescription = AlertDescription(40);
+pub const NO_CERTIFICATE: AlertDescription = AlertDescription(41);
+pub const BAD_CERTIFICATE: AlertDescription = AlertDescription(42);
+pub const UNSUPPORTED_CERTIFICATE: AlertDescription = AlertDescription(43);
+pub const CERTIFICATE_REVOKED: AlertDescription = AlertDescription(44);
+pub const CERTIFICATE_EXPIRED: AlertDescription = AlertDescription(45);
+pub const CERTIFICATE_UNKNOWN: AlertDescription = AlertDescription(46);
+pub const ILLEGAL_PARAMETER: AlertDescription = AlertDescription(47);
+pub const UNKNOWN_CA: AlertDescription = AlertDescription(48);
+pub const ACCESS_DENIED: AlertDescription = AlertDescription(49);
+pub const DECODE_ERROR: AlertDescription = AlertDescription(50);
+pub const DECRYPT_ERROR: AlertDescription = AlertDescription(51);
+pub const EXPORT_RESTRICTION: AlertDescription = AlertDescription(60);
+pub const PROTOCOL_VERSION: AlertDescription = AlertDescription(70);
+pub const INSUFFICIENT_SECURITY: AlertDescription = AlertDescription(71);
+pub const INTERNAL_ERROR: AlertDescription = AlertDescription(80);
+pub const INAPPROPRIATE_FALLBACK: AlertDescription = AlertDescription(86);
+pub const USER_CANCELED: AlertDescription = AlertDescription(90);
+pub const NO_RENEGOTIATION: AlertDescription = AlertDescription(100);
+pub const MISSING_EXTENSION: AlertDescription = AlertDescription(109);
+pub const UNSUPPORTED_EXTENSION: AlertDescription = AlertDescription(110);
+pub const CERTIFICATE_UNOBTAINABLE: AlertDescription = AlertDescription(111);
+pub const UNRECOGNIZED_NAME: AlertDescription = AlertDescription(112);
+pub const BAD_CERTIFICATE_STATUS_RESPONSE: AlertDescription = AlertDescription(113);
+pub const BAD_CERTIFICATE_HASH_VALUE: AlertDescription = AlertDescription(114);
+pub const UNKNOWN_PSK_IDENTITY: AlertDescription = AlertDescription(115);
+pub const CERTIFICATE_REQUIRED: AlertDescription = AlertDescription(116);
+pub const NO_APPLICATION_PROTOCOL: AlertDescription = AlertDescription(120);
+
+impl AlertDescription {
+    pub fn name(self) -> &'static str {
+        match self {
+            CLOSE_NOTIFY => "close_notify",
+            UNEXPECTED_MESSAGE => "unexpected_message",
+            BAD_RECORD_MAC => "bad_record_mac",
+            DECRYPTION_FAILED => "decryption_failed",
+            RECORD_OVERFLOW => "record_overflow",
+            DECOMPRESSION_FAILURE => "decompression_failure",
+            HANDSHAKE_FAILURE => "handshake_failure",
+            NO_CERTIFICATE => "no_certificate",
+            BAD_CERTIFICATE => "bad_certificate",
+            UNSUPPORTED_CERTIFICATE => "unsupported_certificate",
+            CERTIFICATE_REVOKED => "certificate_revoked",
+            CERTIFICATE_EXPIRED => "certificate_expired",
+            CERTIFICATE_UNKNOWN => "certificate_unknown",
+            ILLEGAL_PARAMETER => "illegal_parameter",
+            UNKNOWN_CA => "unknown_ca",
+            ACCESS_DENIED => "access_denied",
+            DECODE_ERROR => "decode_error",
+            DECRYPT_ERROR => "decrypt_error",
+            EXPORT_RESTRICTION => "export_restriction",
+            PROTOCOL_VERSION => "protocol_version",
+            INSUFFICIENT_SECURITY => "insufficient_security",
+            INTERNAL_ERROR => "internal_error",
+            INAPPROPRIATE_FALLBACK => "inappropriate_fallback",
+            USER_CANCELED => "user_canceled",
+            NO_RENEGOTIATION => "no_renegotiation",
+            MISSING_EXTENSION => "missing_extension",
+            UNSUPPORTED_EXTENSION => "unsupported_extension",
+            CERTIFICATE_UNOBTAINABLE => "certificate_unobtainable",
+            UNRECOGNIZED_NAME => "unrecognized_name",
+            BAD_CERTIFICATE_STATUS_RESPONSE => "bad_certificate_status_response",
+            BAD_CERTIFICATE_HASH_VALUE => "bad_certificate_hash_value",
+            UNKNOWN_PSK_IDENTITY => "unknown_psk_identity",
+            CERTIFICATE_REQUIRED => "certificate_required",
+            NO_APPLICATION_PROTOCOL => "no_application_protocol",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for AlertDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.0)
+    }
+}
+
+/// Alert record content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alert {
+    pub level: AlertLevel,
+    pub description: AlertDescription,
+}
+
+impl Alert {
+    pub fn new(level: AlertLevel, description: AlertDescription) -> Self {
+        Self {
+            level,
+            description,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; 2] {
+        [self.level.as_u8(), self.description.0]
+    }
+
+    pub fn parse(fragment: &[u8]) -> Result<Self> {
+        if fragment.len() != 2 {
+            return Err(fatal(DECODE_ERROR, format!("Malformed alert of {} bytes", fragment.len())));
+        }
+        Ok(Self::new(AlertLevel::from_u8(fragment[0])?, AlertDescription(fragment[1])))
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            AlertLevel::Warning => "warning",
+            AlertLevel::Fatal => "fatal",
+        };
+        write!(f, "{} alert {}", level, self.description)
+    }
+}
+
+/// A local failure that has to be reported to the peer with a fatal alert
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertError {
+    pub description: AlertDescription,
+    pub reason: String,
+}
+
+impl fmt::Display for AlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (alert {})", self.reason, self.description)
+    }
+}
+
+impl std::error::Error for AlertError {}
+
+/// The peer ended the connection with an alert, so none is sent back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerAlert(pub Alert);
+
+impl fmt::Display for PeerAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Received {} from peer", self.0)
+    }
+}
+
+impl std::error::Error for PeerAlert {}
+
+pub fn fatal<S: Into<String>>(description: AlertDescription, reason: S) -> Error {
+    AlertError {
+        description,
+        reason: reason.into(),
+    }.into()
+}
+
+/// Picks the alert to send for `err`. Errors without an explicit alert are internal errors.
+pub fn description_of(err: &Error) -> AlertDescription {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<AlertError>() {
+            return e.description;
+        }
+        if let Some(e) = cause.downcast_ref::<CertificateError>() {
+            return certificate_error_description(e);
+        }
+    }
+    INTERNAL_ERROR
+}
+
+/// The alert the peer sent, if that is what ended the handshake or connection.
+/// Looks inside `io::Error`s too, as returned by `Read` on a TLS stream.
+pub fn received_alert(err: &Error) -> Option<Alert> {
+    for cause in err.chain() {
+        if let Some(PeerAlert(alert)) = cause.downcast_ref::<PeerAlert>() {
+            return Some(*alert);
+        }
+        if let Some(alert) = cause.downcast_ref::<io::Error>().and_then(received_alert_io) {
+            return Some(alert);
+        }
+    }
+    None
+}
+
+pub fn received_alert_io(err: &io::Error) -> Option<Alert> {
+    err.get_ref().and_then(|e| e.downcast_ref::<PeerAlert>()).map(|PeerAlert(alert)| *alert)
+}
+
+fn certificate_error_description(err: &CertificateError) -> AlertDescription {
+    match err {
+        CertificateError::UnknownIssuer { .. } => UNKNOWN_CA,
+        CertificateError::Expired { .. } | CertificateError::NotYetValid { .. } => CERTIFICATE_EXPIRED,
+        CertificateError::UnsupportedSignatureAlgorithm { .. } => UNSUPPORTED_CERTIFICATE,
+        _ => BAD_CERTIFICATE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_parse_alerts() {
+        let alert = Alert::parse(&[2, 40]).unwrap();
+        assert_eq!(Alert::new(AlertLevel::Fatal, HANDSHAKE_FAILURE), alert);
+        assert_eq!([2, 40], alert.encode());
+        assert_eq!("fatal alert handshake_failure (40)", alert.to_string());
+        assert_eq!("unknown", Alert::parse(&[1, 255]).unwrap().description.name());
+        assert_eq!(DECODE_ERROR, description_of(&Alert::parse(&[3, 0]).unwrap_err()));
+        assert_eq!(DECODE_ERROR, description_of(&Alert::parse(&[2]).unwrap_err()));
+
+        let err = anyhow::Error::new(io::Error::new(io::ErrorKind::ConnectionAborted, PeerAlert(alert))).context("Reading the response");
+        assert_eq!(Some(alert), received_alert(&err));
+        assert_eq!(None, received_alert(&fatal(HANDSHAKE_FAILURE, "local")));
+    }
+}
@@ -0,0 +1,663 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::aes::{
+    AES_BLOCK_SIZE,
+    Aes,
+    aes_cbc_decrypt,
+    aes_cbc_encrypt,
+};
+use crate::digest::{
+    Digest,
+    HashAlgorithm,
+};
+use crate::gcm::{
+    AesGcm,
+    GCM_NONCE_LEN,
+    GCM_TAG_LEN,
+};
+use crate::hmac::{
+    Hmac,
+    constant_time_eq,
+};
+use crate::random::random_bytes;
+
+use super::alert::{
+    self,
+    BAD_RECORD_MAC,
+    UNEXPECTED_MESSAGE,
+};
+use super::key_schedule::traffic_key_iv;
+#[cfg(feature = "legacy-protocols")]
+use super::legacy;
+use super::prf::Prf;
+use super::record::{
+    ContentType,
+    ProtocolVersion,
+    Record,
+    RecordProtection,
+    MAX_FRAGMENT_LEN,
+};
+use super::suites::{
+    CipherSuite,
+    Mac,
+};
+
+/// CBC protection of TLS 1.1/1.2 with explicit IVs: MAC-then-encrypt (RFC 5246 section 6.2.3.2),
+/// or encrypt-then-MAC when negotiated with encrypt_then_mac (RFC 7366)
+pub struct CbcHmacProtection {
+    aes: Aes,
+    mac: Hmac,
+    encrypt_then_mac: bool,
+    sequence_number: u64,
+}
+
+impl CbcHmacProtection {
+    pub fn new(key: &[u8], mac_hash: HashAlgorithm, mac_key: &[u8], encrypt_then_mac: bool) -> Result<Self> {
+        Ok(Self {
+            aes: Aes::new(key)?,
+            mac: Hmac::new(mac_hash, mac_key),
+            encrypt_then_mac,
+            sequence_number: 0,
+        })
+    }
+
+    /// seq_num + type + version, which the MAC covers ahead of the length and content
+    fn mac_header(&self, content_type: ContentType, version: ProtocolVersion) -> Vec<u8> {
+        let mut header = self.sequence_number.to_be_bytes().to_vec();
+        header.push(content_type.as_u8());
+        header.extend_from_slice(&version.as_u16().to_be_bytes());
+        header
+    }
+
+    fn compute_mac(&self, content_type: ContentType, version: ProtocolVersion, content: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac.clone();
+        mac.update(&self.mac_header(content_type, version));
+        mac.update(&(content.len() as u16).to_be_bytes());
+        mac.update(content);
+        mac.finish()
+    }
+
+    fn next_sequence_number(&mut self) -> Result<()> {
+        self.sequence_number = self.sequence_number.checked_add(1).ok_or_else(|| alert::fatal(alert::INTERNAL_ERROR, "Sequence number overflow"))?;
+        Ok(())
+    }
+
+    fn open_mac_then_encrypt(&self, record: &Record) -> Result<Vec<u8>> {
+        let ciphertext = &record.fragment;
+        let mac_len = self.mac.output_size();
+        if ciphertext.len() < AES_BLOCK_SIZE * 2 || !ciphertext.len().is_multiple_of(AES_BLOCK_SIZE) || ciphertext.len() < AES_BLOCK_SIZE + mac_len + 1 {
+            return Err(bad_record_mac());
+        }
+        let (iv, encrypted) = ciphertext.split_at(AES_BLOCK_SIZE);
+        let data = aes_cbc_decrypt(&self.aes, iv, encrypted)?;
+        let (inner, outer) = self.mac.keyed_digests();
+        open_padded_record(&data, inner, outer, &self.mac_header(record.content_type, record.version), false)
+    }
+
+    /// Opens an encrypt-then-MAC record, whose MAC over IV and ciphertext is checked
+    /// before anything is decrypted
+    fn open_encrypt_then_mac(&self, record: &Record) -> Result<Vec<u8>> {
+        let mac_len = self.mac.output_size();
+        if record.fragment.len() < AES_BLOCK_SIZE * 2 + mac_len {
+            return Err(bad_record_mac());
+        }
+        let (ciphertext, received) = record.fragment.split_at(record.fragment.len() - mac_len);
+        let expected = self.compute_mac(record.content_type, record.version, ciphertext);
+        if !constant_time_eq(&expected, received) {
+            return Err(bad_record_mac());
+        }
+        if !ciphertext.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(bad_record_mac());
+        }
+        let (iv, encrypted) = ciphertext.split_at(AES_BLOCK_SIZE);
+        let mut data = aes_cbc_decrypt(&self.aes, iv, encrypted)?;
+        let padding_len = data[data.len() - 1] as usize;
+        if padding_len + 1 > data.len() || data[data.len() - padding_len - 1..].iter().any(|b| *b as usize != padding_len) {
+            return Err(bad_record_mac());
+        }
+        data.truncate(data.len() - padding_len - 1);
+        Ok(data)
+    }
+}
+
+pub(super) fn bad_record_mac() -> anyhow::Error {
+    alert::fatal(BAD_RECORD_MAC, "Record failed to authenticate")
+}
+
+/// Most padding a CBC record carries, counting its length byte
+const MAX_PADDING_LEN: usize = 256;
+
+/// All ones when `a <= b` and zero otherwise, without a branch. Both are lengths far below
+/// `usize::MAX / 2`.
+fn mask_le(a: usize, b: usize) -> usize {
+    (b.wrapping_sub(a) >> (usize::BITS - 1)).wrapping_sub(1)
+}
+
+fn mask_eq(a: usize, b: usize) -> usize {
+    mask_le(a, b) & mask_le(b, a)
+}
+
+/// Compressions that hashing `len` more bytes and finishing costs a digest in this state
+fn compressions(digest: &dyn Digest, len: usize) -> usize {
+    (digest.buffered_len() + len + digest.length_field_size()) / digest.block_size() + 1
+}
+
+/// Checks the padding and the MAC of a decrypted MAC-then-encrypt record, given the keyed
+/// inner and outer digests of its MAC (HMAC, or the SSL 3.0 MAC) and the MAC header that
+/// comes before the content length. Against Lucky Thirteen, the padding bytes looked at,
+/// the bytes the MAC is read from and the number of compressions run depend on the length
+/// of the record alone, and a bad padding fails the same way as a bad MAC. The padding
+/// bytes of SSL 3.0 are arbitrary, so only their length is checked.
+pub(super) fn open_padded_record(data: &[u8], inner: &dyn Digest, outer: &dyn Digest, header: &[u8], ssl3: bool) -> Result<Vec<u8>> {
+    let mac_len = outer.output_size();
+    if data.len() < mac_len + 1 {
+        return Err(bad_record_mac());
+    }
+    let padding_len = data[data.len() - 1] as usize;
+    // the content is longest with only the length byte as padding
+    let max_content_len = data.len() - mac_len - 1;
+    let mut good = mask_le(padding_len, max_content_len);
+    if ssl3 {
+        good &= mask_le(padding_len + 1, AES_BLOCK_SIZE);
+    } else {
+        let mut diff = 0;
+        for i in 0..std::cmp::min(MAX_PADDING_LEN, data.len() - mac_len) {
+            diff |= mask_le(i, padding_len) & (data[data.len() - 1 - i] as usize ^ padding_len);
+        }
+        good &= mask_eq(diff, 0);
+    }
+    let content_len = max_content_len - (padding_len & good);
+
+    let mut mac = inner.box_clone();
+    mac.update(header);
+    mac.update(&(content_len as u16).to_be_bytes());
+    mac.update(&data[..content_len]);
+    let inner_hash = mac.finish();
+    // make up the compressions the longest content would have cost on a scratch state
+    let header_len = header.len() + 2;
+    let dummy_blocks = compressions(inner, header_len + max_content_len) - compressions(inner, header_len + content_len);
+    let mut scratch = inner.box_clone();
+    scratch.update(&vec![0u8; dummy_blocks * inner.block_size()]);
+    std::hint::black_box(&scratch);
+    let mut mac = outer.box_clone();
+    mac.update(&inner_hash);
+    let expected = mac.finish();
+
+    // copy the MAC out of every place it can start at, keeping the one at content_len
+    let mut received = vec![0u8; mac_len];
+    for start in max_content_len.saturating_sub(MAX_PADDING_LEN - 1)..=max_content_len {
+        let mask = mask_eq(start, content_len) as u8;
+        for (r, b) in received.iter_mut().zip(&data[start..start + mac_len]) {
+            *r |= b & mask;
+        }
+    }
+    good &= mask_eq(constant_time_eq(&expected, &received) as usize, 1);
+    if good == 0 {
+        return Err(bad_record_mac());
+    }
+    Ok(data[..content_len].to_vec())
+}
+
+/// Pads `data` to whole blocks. Every padding byte, including the length byte itself,
+/// holds the padding length.
+pub(super) fn pad(data: &mut Vec<u8>) {
+    let padding_len = AES_BLOCK_SIZE - 1 - data.len() % AES_BLOCK_SIZE;
+    data.resize(data.len() + padding_len + 1, padding_len as u8);
+}
+
+impl RecordProtection for CbcHmacProtection {
+    fn encrypt(&mut self, record: Record) -> Result<Record> {
+        let mut data = record.fragment.clone();
+        if !self.encrypt_then_mac {
+            data.extend(self.compute_mac(record.content_type, record.version, &record.fragment));
+        }
+        pad(&mut data);
+        let iv = random_bytes(AES_BLOCK_SIZE)?;
+        let mut result = iv.clone();
+        result.extend(aes_cbc_encrypt(&self.aes, &iv, &data)?);
+        if self.encrypt_then_mac {
+            result.extend(self.compute_mac(record.content_type, record.version, &result));
+        }
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment: result,
+            ..record
+        })
+    }
+
+    fn decrypt(&mut self, record: Record) -> Result<Record> {
+        let fragment = if self.encrypt_then_mac { self.open_encrypt_then_mac(&record)? } else { self.open_mac_then_encrypt(&record)? };
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment,
+            ..record
+        })
+    }
+}
+
+/// Length of the implicit part of the TLS 1.2 AES-GCM nonce, taken from the key block
+const GCM_SALT_LEN: usize = 4;
+/// Length of the explicit part of the TLS 1.2 AES-GCM nonce, sent with each record
+const GCM_EXPLICIT_NONCE_LEN: usize = GCM_NONCE_LEN - GCM_SALT_LEN;
+
+/// AES-GCM protection of TLS 1.2 (RFC 5288). The sequence number doubles as the
+/// explicit nonce, which keeps nonces unique without a random source.
+pub struct GcmProtection {
+    gcm: AesGcm,
+    salt: [u8; GCM_SALT_LEN],
+    sequence_number: u64,
+}
+
+impl GcmProtection {
+    pub fn new(key: &[u8], salt: &[u8]) -> Result<Self> {
+        if salt.len() != GCM_SALT_LEN {
+            return Err(anyhow!("Invalid AES-GCM salt length: {}", salt.len()));
+        }
+        let mut fixed_salt = [0u8; GCM_SALT_LEN];
+        fixed_salt.copy_from_slice(salt);
+        Ok(Self {
+            gcm: AesGcm::new(key)?,
+            salt: fixed_salt,
+            sequence_number: 0,
+        })
+    }
+
+    fn nonce(&self, explicit: &[u8]) -> [u8; GCM_NONCE_LEN] {
+        let mut nonce = [0u8; GCM_NONCE_LEN];
+        nonce[..GCM_SALT_LEN].copy_from_slice(&self.salt);
+        nonce[GCM_SALT_LEN..].copy_from_slice(explicit);
+        nonce
+    }
+
+    /// seq_num + type + version + length of the plaintext
+    fn additional_data(&self, content_type: ContentType, version: ProtocolVersion, len: usize) -> Vec<u8> {
+        let mut aad = self.sequence_number.to_be_bytes().to_vec();
+        aad.push(content_type.as_u8());
+        aad.extend_from_slice(&version.as_u16().to_be_bytes());
+        aad.extend_from_slice(&(len as u16).to_be_bytes());
+        aad
+    }
+
+    fn next_sequence_number(&mut self) -> Result<()> {
+        self.sequence_number = self.sequence_number.checked_add(1).ok_or_else(|| alert::fatal(alert::INTERNAL_ERROR, "Sequence number overflow"))?;
+        Ok(())
+    }
+}
+
+impl RecordProtection for GcmProtection {
+    fn encrypt(&mut self, record: Record) -> Result<Record> {
+        let explicit = self.sequence_number.to_be_bytes();
+        let aad = self.additional_data(record.content_type, record.version, record.fragment.len());
+        let mut fragment = explicit.to_vec();
+        fragment.extend(self.gcm.seal(&self.nonce(&explicit), &aad, &record.fragment)?);
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment,
+            ..record
+        })
+    }
+
+    fn decrypt(&mut self, record: Record) -> Result<Record> {
+        if record.fragment.len() < GCM_EXPLICIT_NONCE_LEN + GCM_TAG_LEN {
+            return Err(bad_record_mac());
+        }
+        let (explicit, sealed) = record.fragment.split_at(GCM_EXPLICIT_NONCE_LEN);
+        let aad = self.additional_data(record.content_type, record.version, sealed.len() - GCM_TAG_LEN);
+        let fragment = self.gcm.open(&self.nonce(explicit), &aad, sealed).map_err(|_| bad_record_mac())?;
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment,
+            ..record
+        })
+    }
+}
+
+/// TLS 1.3 AEAD protection (RFC 8446 section 5.2). The real content type and
+/// padding go inside the ciphertext; the outer record always claims to be
+/// TLS 1.2 application data.
+pub struct Tls13Protection {
+    gcm: AesGcm,
+    iv: [u8; GCM_NONCE_LEN],
+    sequence_number: u64,
+}
+
+impl Tls13Protection {
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Self> {
+        if iv.len() != GCM_NONCE_LEN {
+            return Err(anyhow!("Invalid AEAD IV length: {}", iv.len()));
+        }
+        let mut fixed_iv = [0u8; GCM_NONCE_LEN];
+        fixed_iv.copy_from_slice(iv);
+        Ok(Self {
+            gcm: AesGcm::new(key)?,
+            iv: fixed_iv,
+            sequence_number: 0,
+        })
+    }
+
+    /// The per-record nonce: the sequence number, left-padded, XORed with the IV
+    fn nonce(&self) -> [u8; GCM_NONCE_LEN] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[GCM_NONCE_LEN - 8..].iter_mut().zip(self.sequence_number.to_be_bytes().iter()) {
+            *n ^= s;
+        }
+        nonce
+    }
+
+    /// The record header of the ciphertext is the additional data
+    fn additional_data(len: usize) -> [u8; 5] {
+        let mut aad = [ContentType::ApplicationData.as_u8(), 3, 3, 0, 0];
+        aad[3..].copy_from_slice(&(len as u16).to_be_bytes());
+        aad
+    }
+
+    fn next_sequence_number(&mut self) -> Result<()> {
+        self.sequence_number = self.sequence_number.checked_add(1).ok_or_else(|| alert::fatal(alert::INTERNAL_ERROR, "Sequence number overflow"))?;
+        Ok(())
+    }
+}
+
+impl RecordProtection for Tls13Protection {
+    fn encrypt(&mut self, record: Record) -> Result<Record> {
+        // TLSInnerPlaintext without padding
+        let mut inner = record.fragment;
+        inner.push(record.content_type.as_u8());
+        let aad = Self::additional_data(inner.len() + GCM_TAG_LEN);
+        let fragment = self.gcm.seal(&self.nonce(), &aad, &inner)?;
+        self.next_sequence_number()?;
+        Ok(Record {
+            content_type: ContentType::ApplicationData,
+            version: ProtocolVersion::TLS1_2,
+            fragment,
+        })
+    }
+
+    fn decrypt(&mut self, record: Record) -> Result<Record> {
+        if record.content_type != ContentType::ApplicationData {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unprotected {:?} record", record.content_type)));
+        }
+        let aad = Self::additional_data(record.fragment.len());
+        let mut inner = self.gcm.open(&self.nonce(), &aad, &record.fragment)
+            .map_err(|_| alert::fatal(BAD_RECORD_MAC, "Record failed to authenticate"))?;
+        self.next_sequence_number()?;
+        if inner.len() > MAX_FRAGMENT_LEN + 256 {
+            return Err(alert::fatal(alert::RECORD_OVERFLOW, "Record overflow"));
+        }
+        // the content type is the last non-zero byte; the zeros after it are padding
+        let type_pos = inner.iter().rposition(|b| *b != 0)
+            .ok_or_else(|| alert::fatal(UNEXPECTED_MESSAGE, "Record without a content type"))?;
+        let content_type = ContentType::from_u8(inner[type_pos])?;
+        inner.truncate(type_pos);
+        Ok(Record {
+            content_type,
+            version: record.version,
+            fragment: inner,
+        })
+    }
+}
+
+/// Record protection for a TLS 1.3 traffic secret
+pub fn tls13_protection(suite: &CipherSuite, traffic_secret: &[u8]) -> Result<Box<dyn RecordProtection>> {
+    let (key, iv) = traffic_key_iv(suite.prf_hash, traffic_secret, suite.bulk_cipher.key_len(), GCM_NONCE_LEN);
+    Ok(Box::new(Tls13Protection::new(&key, &iv)?))
+}
+
+/// Expands the master secret into the key block and builds the protection for
+/// (client write, server write). `encrypt_then_mac` only applies to CBC suites.
+pub fn derive_protections(suite: &CipherSuite, version: ProtocolVersion, master_secret: &[u8], client_random: &[u8], server_random: &[u8], encrypt_then_mac: bool) -> Result<(Box<dyn RecordProtection>, Box<dyn RecordProtection>)> {
+    #[cfg(feature = "legacy-protocols")]
+    if version < ProtocolVersion::TLS1_1 {
+        return legacy::derive_protections(suite, version, master_secret, client_random, server_random, encrypt_then_mac);
+    }
+    let prf = Prf::new(version, suite);
+    let mac_hash = match suite.mac {
+        Mac::Hmac(hash) => hash,
+        Mac::Aead => {
+            // no MAC keys, but a salt for each direction (RFC 5288 section 3)
+            let key_len = suite.bulk_cipher.key_len();
+            let key_block = prf.key_block(master_secret, client_random, server_random, 2 * (key_len + GCM_SALT_LEN));
+            let (client_key, rest) = key_block.split_at(key_len);
+            let (server_key, rest) = rest.split_at(key_len);
+            let (client_salt, server_salt) = rest.split_at(GCM_SALT_LEN);
+            let client_write = GcmProtection::new(client_key, client_salt)?;
+            let server_write = GcmProtection::new(server_key, server_salt)?;
+            return Ok((Box::new(client_write), Box::new(server_write)));
+        },
+    };
+    let mac_key_len = mac_hash.output_size();
+    let key_len = suite.bulk_cipher.key_len();
+    let key_block = prf.key_block(master_secret, client_random, server_random, 2 * (mac_key_len + key_len));
+    let (client_mac_key, rest) = key_block.split_at(mac_key_len);
+    let (server_mac_key, rest) = rest.split_at(mac_key_len);
+    let (client_key, server_key) = rest.split_at(key_len);
+    let client_write = CbcHmacProtection::new(client_key, mac_hash, client_mac_key, encrypt_then_mac)?;
+    let server_write = CbcHmacProtection::new(server_key, mac_hash, server_mac_key, encrypt_then_mac)?;
+    Ok((Box::new(client_write), Box::new(server_write)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+    use std::sync::Arc;
+
+    use super::super::suites::{
+        TLS_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        TLS_RSA_WITH_AES_128_CBC_SHA,
+    };
+
+    fn record(content_type: ContentType, fragment: &[u8]) -> Record {
+        Record {
+            content_type,
+            version: ProtocolVersion::TLS1_2,
+            fragment: fragment.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_can_round_trip_cbc_records() {
+        let (mut client, _) = derive_protections(&TLS_RSA_WITH_AES_128_CBC_SHA, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        let (mut peer, _) = derive_protections(&TLS_RSA_WITH_AES_128_CBC_SHA, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        for len in [0usize, 1, 11, 12, 15, 16, 1000].iter() {
+            let plaintext = record(ContentType::ApplicationData, &vec![0x5au8; *len]);
+            let ciphertext = client.encrypt(plaintext.clone()).unwrap();
+            assert!(ciphertext.fragment.len().is_multiple_of(AES_BLOCK_SIZE));
+            assert_eq!(plaintext, peer.decrypt(ciphertext).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_can_return_bad_record_mac() {
+        let (mut client, _) = derive_protections(&TLS_RSA_WITH_AES_128_CBC_SHA, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        let (mut peer, _) = derive_protections(&TLS_RSA_WITH_AES_128_CBC_SHA, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        let mut ciphertext = client.encrypt(record(ContentType::Handshake, b"finished")).unwrap();
+        let last = ciphertext.fragment.len() - 20;
+        ciphertext.fragment[last] ^= 1;
+        let err = peer.decrypt(ciphertext).unwrap_err();
+        assert_eq!(BAD_RECORD_MAC, alert::description_of(&err));
+        // replaying a record under the wrong sequence number or type fails as well
+        let mut ciphertext = client.encrypt(record(ContentType::Handshake, b"finished")).unwrap();
+        ciphertext.content_type = ContentType::ApplicationData;
+        assert!(peer.decrypt(ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_can_return_bad_record_mac_for_bad_padding() {
+        let mut peer = CbcHmacProtection::new(&[1u8; 16], HashAlgorithm::Sha1, &[2u8; 20], false).unwrap();
+        // a valid MAC followed by padding whose bytes disagree with its length
+        let mut data = b"finished".to_vec();
+        data.extend(peer.compute_mac(ContentType::Handshake, ProtocolVersion::TLS1_2, b"finished"));
+        data.extend([2u8, 2, 3, 3]);
+        let iv = [9u8; AES_BLOCK_SIZE];
+        let mut fragment = iv.to_vec();
+        fragment.extend(aes_cbc_encrypt(&Aes::new(&[1u8; 16]).unwrap(), &iv, &data).unwrap());
+        assert_eq!(BAD_RECORD_MAC, alert::description_of(&peer.decrypt(record(ContentType::Handshake, &fragment)).unwrap_err()));
+    }
+
+    /// Counts the compressions run by a digest and every clone of it
+    struct CountingDigest {
+        digest: Box<dyn Digest>,
+        compressions: Arc<AtomicUsize>,
+    }
+
+    impl Digest for CountingDigest {
+        fn update(&mut self, data: &[u8]) {
+            self.compressions.fetch_add((self.digest.buffered_len() + data.len()) / self.digest.block_size(), Ordering::SeqCst);
+            self.digest.update(data);
+        }
+
+        /// 0x80 and the length field go after the buffered bytes, in one block or two
+        fn finish(&self) -> Vec<u8> {
+            let padded_len = self.digest.buffered_len() + 1 + self.digest.length_field_size();
+            self.compressions.fetch_add(padded_len.div_ceil(self.digest.block_size()), Ordering::SeqCst);
+            self.digest.finish()
+        }
+
+        fn output_size(&self) -> usize {
+            self.digest.output_size()
+        }
+
+        fn block_size(&self) -> usize {
+            self.digest.block_size()
+        }
+
+        fn buffered_len(&self) -> usize {
+            self.digest.buffered_len()
+        }
+
+        fn length_field_size(&self) -> usize {
+            self.digest.length_field_size()
+        }
+
+        fn box_clone(&self) -> Box<dyn Digest> {
+            Box::new(CountingDigest {
+                digest: self.digest.box_clone(),
+                compressions: self.compressions.clone(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_can_open_padded_records_in_constant_compressions() {
+        // HMAC-SHA256 after its key block, and SHA-1 after the 60 bytes of an SSL 3.0 secret and pad
+        for (hash, prefix_len, ssl3, max_padding_len) in [(HashAlgorithm::Sha256, 64, false, 255), (HashAlgorithm::Sha1, 60, true, 15)] {
+            let compressions = Arc::new(AtomicUsize::new(0));
+            let keyed = |byte: u8| {
+                let mut digest = hash.new_digest();
+                digest.update(&vec![byte; prefix_len]);
+                CountingDigest {
+                    digest,
+                    compressions: compressions.clone(),
+                }
+            };
+            let (inner, outer) = (keyed(0x36), keyed(0x5c));
+            let header = [7u8; 11];
+            let record_len = 400;
+            let seal = |padding_len: usize| {
+                let content = vec![0xa5u8; record_len - hash.output_size() - 1 - padding_len];
+                let mut mac = inner.digest.box_clone();
+                mac.update(&header);
+                mac.update(&(content.len() as u16).to_be_bytes());
+                mac.update(&content);
+                let mut outer_mac = outer.digest.box_clone();
+                outer_mac.update(&mac.finish());
+                let mut data = content;
+                data.extend(outer_mac.finish());
+                data.extend(vec![padding_len as u8; padding_len + 1]);
+                data
+            };
+            let open = |data: &[u8]| {
+                compressions.store(0, Ordering::SeqCst);
+                let result = open_padded_record(data, &inner, &outer, &header, ssl3).map(|content| content.len());
+                (result.ok(), compressions.load(Ordering::SeqCst))
+            };
+
+            let (shortest, shortest_count) = open(&seal(0));
+            let (longest, longest_count) = open(&seal(max_padding_len));
+            assert_eq!((Some(record_len - hash.output_size() - 1), Some(record_len - hash.output_size() - 1 - max_padding_len)), (shortest, longest));
+            assert_eq!(shortest_count, longest_count);
+            // a bad MAC costs as much as a good one
+            let mut data = seal(max_padding_len);
+            data[0] ^= 1;
+            assert_eq!((None, longest_count), open(&data));
+        }
+    }
+
+    #[test]
+    fn test_can_round_trip_encrypt_then_mac_records() {
+        let suite = &TLS_RSA_WITH_AES_128_CBC_SHA;
+        let (mut client, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], true).unwrap();
+        let (mut peer, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], true).unwrap();
+        for len in [0usize, 1, 15, 16, 1000].iter() {
+            let plaintext = record(ContentType::ApplicationData, &vec![0x5au8; *len]);
+            let ciphertext = client.encrypt(plaintext.clone()).unwrap();
+            // IV, the padded plaintext and the MAC after them
+            assert_eq!(AES_BLOCK_SIZE + (len / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE + 20, ciphertext.fragment.len());
+            assert_eq!(plaintext, peer.decrypt(ciphertext).unwrap());
+        }
+        let mut ciphertext = client.encrypt(record(ContentType::Handshake, b"finished")).unwrap();
+        ciphertext.fragment[AES_BLOCK_SIZE] ^= 1;
+        assert_eq!(BAD_RECORD_MAC, alert::description_of(&peer.decrypt(ciphertext).unwrap_err()));
+
+        // a MAC-then-encrypt peer cannot open them
+        let (mut client, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], true).unwrap();
+        let (mut peer, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        let ciphertext = client.encrypt(record(ContentType::Handshake, b"finished")).unwrap();
+        assert!(peer.decrypt(ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_can_round_trip_gcm_records() {
+        let suite = &TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384;
+        let (mut client, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        let (mut peer, _) = derive_protections(suite, ProtocolVersion::TLS1_2, &[1u8; 48], &[2u8; 32], &[3u8; 32], false).unwrap();
+        for len in [0usize, 1, 16, 1000].iter() {
+            let plaintext = record(ContentType::ApplicationData, &vec![0x5au8; *len]);
+            let ciphertext = client.encrypt(plaintext.clone()).unwrap();
+            assert_eq!(len + GCM_EXPLICIT_NONCE_LEN + GCM_TAG_LEN, ciphertext.fragment.len());
+            assert_eq!(plaintext, peer.decrypt(ciphertext).unwrap());
+        }
+        let mut ciphertext = client.encrypt(record(ContentType::Handshake, b"finished")).unwrap();
+        ciphertext.fragment[GCM_EXPLICIT_NONCE_LEN] ^= 1;
+        assert_eq!(BAD_RECORD_MAC, alert::description_of(&peer.decrypt(ciphertext).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_round_trip_tls13_records() {
+        let mut client = tls13_protection(&TLS_AES_128_GCM_SHA256, &[7u8; 32]).unwrap();
+        let mut peer = tls13_protection(&TLS_AES_128_GCM_SHA256, &[7u8; 32]).unwrap();
+        let plaintext = record(ContentType::Handshake, b"finished");
+        let ciphertext = client.encrypt(plaintext.clone()).unwrap();
+        // the content type is hidden behind application data
+        assert_eq!(ContentType::ApplicationData, ciphertext.content_type);
+        assert_eq!(b"finished".len() + 1 + GCM_TAG_LEN, ciphertext.fragment.len());
+        assert_eq!(plaintext, peer.decrypt(ciphertext).unwrap());
+
+        // a replayed record fails under the next sequence number
+        let ciphertext = client.encrypt(record(ContentType::Alert, &[1, 0])).unwrap();
+        let replay = ciphertext.clone();
+        assert_eq!(record(ContentType::Alert, &[1, 0]), peer.decrypt(ciphertext).unwrap());
+        assert_eq!(BAD_RECORD_MAC, alert::description_of(&peer.decrypt(replay).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_strip_tls13_padding() {
+        let mut peer = Tls13Protection::new(&[7u8; 16], &[9u8; GCM_NONCE_LEN]).unwrap();
+        let gcm = AesGcm::new(&[7u8; 16]).unwrap();
+        let inner = [b'h', b'i', ContentType::ApplicationData.as_u8(), 0, 0, 0];
+        let aad = Tls13Protection::additional_data(inner.len() + GCM_TAG_LEN);
+        let fragment = gcm.seal(&[9u8; GCM_NONCE_LEN], &aad, &inner).unwrap();
+        assert_eq!(b"hi".to_vec(), peer.decrypt(record(ContentType::ApplicationData, &fragment)).unwrap().fragment);
+
+        // all padding and no content type
+        let fragment = gcm.seal(&peer.nonce(), &Tls13Protection::additional_data(3 + GCM_TAG_LEN), &[0, 0, 0]).unwrap();
+        assert_eq!(UNEXPECTED_MESSAGE, alert::description_of(&peer.decrypt(record(ContentType::ApplicationData, &fragment)).unwrap_err()));
+    }
+}
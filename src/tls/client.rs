@@ -0,0 +1,1352 @@
+use std::io::{
+    Read,
+    Write,
+};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::asn1::Asn1Time;
+use crate::ecdsa::EcPublicKey;
+use crate::hmac::constant_time_eq;
+use crate::random::{
+    fill_random,
+    random_bytes,
+};
+use crate::rsa::{
+    RsaPrivateKey,
+    RsaPublicKey,
+};
+use crate::verify::{
+    KeyPurpose,
+    TrustStore,
+    verify_hostname,
+};
+use crate::x509::{
+    Certificate,
+    KeyUsage,
+};
+
+use super::alert::{
+    self,
+    PeerAlert,
+    BAD_CERTIFICATE,
+    DECODE_ERROR,
+    DECRYPT_ERROR,
+    HANDSHAKE_FAILURE,
+    ILLEGAL_PARAMETER,
+    INSUFFICIENT_SECURITY,
+    MISSING_EXTENSION,
+    PROTOCOL_VERSION,
+    UNEXPECTED_MESSAGE,
+    UNSUPPORTED_CERTIFICATE,
+    UNSUPPORTED_EXTENSION,
+};
+use super::cipher::{
+    derive_protections,
+    tls13_protection,
+};
+use super::extensions::{
+    self,
+    Extension,
+    HelloContext,
+};
+use super::handshake::{
+    self,
+    CertificateRequest,
+    ClientHello,
+    KeyShareEntry,
+    OfferedPsks,
+    PskIdentity,
+    ServerHello,
+    Transcript,
+    HELLO_RETRY_REQUEST_RANDOM,
+    MAX_SESSION_ID_LEN,
+    PRE_MASTER_SECRET_LEN,
+    RANDOM_LEN,
+};
+use super::keylog::{
+    self,
+    KeyLog,
+};
+use super::key_schedule::{
+    finished_verify_data,
+    psk_binder,
+    KeySchedule,
+};
+use super::kx::{
+    self,
+    EphemeralKey,
+    NamedGroup,
+    ServerKeyExchange,
+    ServerKeyExchangeParams,
+    ALL_GROUPS,
+};
+use super::policy::SecurityLevel;
+use super::prf::Prf;
+use super::signature;
+use super::record::{
+    HandshakeMessage,
+    Message,
+    ProtocolVersion,
+    RecordLayer,
+    RecordProtection,
+};
+use super::session::{
+    ClientSession,
+    ClientSessionCache,
+    TicketReceiver,
+    DEFAULT_CACHE_CAPACITY,
+    DEFAULT_SESSION_LIFETIME,
+};
+use super::stream::{
+    Endpoint,
+    Rekeying,
+    SecureRenegotiation,
+    SessionInfo,
+    TlsStream,
+};
+use super::suites::{
+    ALL_SUITES,
+    Authentication,
+    CipherSuite,
+    KeyExchange,
+    TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+};
+use super::trace::Tracer;
+
+/// Signature schemes offered in signature_algorithms. TLS 1.3 ignores the PKCS#1 ones.
+const SUPPORTED_SIGNATURE_SCHEMES: [u16; 9] = [
+    signature::ECDSA_SECP256R1_SHA256,
+    signature::ECDSA_SECP384R1_SHA384,
+    signature::RSA_PSS_RSAE_SHA256,
+    signature::RSA_PSS_RSAE_SHA384,
+    signature::RSA_PSS_RSAE_SHA512,
+    signature::RSA_PKCS1_SHA256,
+    signature::RSA_PKCS1_SHA384,
+    signature::RSA_PKCS1_SHA512,
+    signature::RSA_PKCS1_SHA1,
+];
+
+/// Schemes we sign CertificateVerify with, in order of preference. Our key is RSA.
+const CLIENT_SIGNATURE_SCHEMES: [u16; 7] = [
+    signature::RSA_PSS_RSAE_SHA256,
+    signature::RSA_PSS_RSAE_SHA384,
+    signature::RSA_PSS_RSAE_SHA512,
+    signature::RSA_PKCS1_SHA256,
+    signature::RSA_PKCS1_SHA384,
+    signature::RSA_PKCS1_SHA512,
+    signature::RSA_PKCS1_SHA1,
+];
+
+/// Smallest DHE group we accept, following the 2048-bit floor of current guidance
+const MIN_DH_BITS: usize = 2048;
+
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// anchors the server's chain has to lead to
+    pub trust_store: TrustStore,
+    /// offered in order of preference
+    pub cipher_suites: Vec<&'static CipherSuite>,
+    /// ECDHE groups offered in supported_groups, in order of preference.
+    /// TLS 1.3 sends a key share for the first one.
+    pub groups: Vec<NamedGroup>,
+    /// protocol versions to offer, TLS 1.3 and TLS 1.2
+    pub versions: Vec<ProtocolVersion>,
+    /// offered in application_layer_protocol_negotiation in order of preference, if any
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// sessions to resume, shared by the connections made with this config. None disables resumption.
+    pub session_cache: Option<Arc<ClientSessionCache>>,
+    /// DER certificates sent when the server asks for one, our own first. Empty sends none.
+    pub certificate_chain: Vec<Vec<u8>>,
+    /// the key of the first certificate in `certificate_chain`
+    pub private_key: Option<RsaPrivateKey>,
+    /// where the secrets of each handshake go, e.g. a `KeyLogFile` for SSLKEYLOGFILE
+    pub key_log: Option<Arc<dyn KeyLog>>,
+    /// takes every record, handshake message and alert of the connections
+    pub tracer: Option<Arc<dyn Tracer>>,
+    /// TLS 1.2: only resume sessions whose full handshake used extended_master_secret
+    /// (RFC 7627 section 5.4). Full handshakes without it still go ahead.
+    pub require_extended_master_secret: bool,
+}
+
+impl ClientConfig {
+    pub fn new(trust_store: TrustStore) -> Self {
+        Self {
+            trust_store,
+            cipher_suites: ALL_SUITES.to_vec(),
+            groups: ALL_GROUPS.to_vec(),
+            versions: vec![ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2],
+            alpn_protocols: vec![],
+            session_cache: Some(Arc::new(ClientSessionCache::new(DEFAULT_CACHE_CAPACITY))),
+            certificate_chain: vec![],
+            private_key: None,
+            key_log: None,
+            tracer: None,
+            require_extended_master_secret: false,
+        }
+    }
+
+    /// Sets the chain and key to authenticate with when the server asks for a certificate
+    pub fn set_certificate(&mut self, certificate_chain: Vec<Vec<u8>>, private_key: RsaPrivateKey) -> Result<()> {
+        signature::check_certificate_key(&certificate_chain, &private_key)?;
+        self.certificate_chain = certificate_chain;
+        self.private_key = Some(private_key);
+        Ok(())
+    }
+
+    /// Loads the chain (leaf first) and the key from PEM files. Both may be the same file.
+    pub fn set_certificate_from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, certificate_path: P, key_path: Q) -> Result<()> {
+        let (chain, key) = signature::read_certificate_and_key(certificate_path.as_ref(), key_path.as_ref())?;
+        self.set_certificate(chain, key)
+    }
+
+    /// Restricts the versions and cipher suites to those of `level`, keeping its order,
+    /// and takes on its resumption policy
+    pub fn apply_security_level(&mut self, level: SecurityLevel) {
+        self.versions = level.versions();
+        self.cipher_suites = level.cipher_suites();
+        self.require_extended_master_secret = level.requires_extended_master_secret();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    ExpectServerHello,
+    ExpectEncryptedExtensions,
+    ExpectCertificate,
+    ExpectCertificateVerify,
+    ExpectServerKeyExchange,
+    ExpectServerHelloDone,
+    ExpectNewSessionTicket,
+    ExpectChangeCipherSpec,
+    ExpectFinished,
+    Connected,
+}
+
+pub(crate) struct ClientHandshake {
+    config: Arc<ClientConfig>,
+    server_name: String,
+    /// the name sent in server_name, unless `server_name` is an IP address
+    server_name_indication: Option<String>,
+    state: State,
+    /// offered versions, highest first
+    versions: Vec<ProtocolVersion>,
+    /// the negotiated version, known from ServerHello or HelloRetryRequest
+    version: Option<ProtocolVersion>,
+    transcript: Transcript,
+    client_random: [u8; RANDOM_LEN],
+    /// the ID of the session to resume, or random when offering TLS 1.3 for middlebox
+    /// compatibility (RFC 8446 appendix D.4)
+    session_id: Vec<u8>,
+    /// where the session is cached, if it is
+    session_key: Option<String>,
+    /// the cached session offered for resumption
+    cached_session: Option<ClientSession>,
+    resumed: bool,
+    /// TLS 1.2: the ID the server gave the session, and the ticket it sent
+    /// after acknowledging session_ticket, with its lifetime hint
+    server_session_id: Vec<u8>,
+    expect_new_session_ticket: bool,
+    new_session_ticket: Option<(u32, Vec<u8>)>,
+    server_random: [u8; RANDOM_LEN],
+    cipher_suite: Option<&'static CipherSuite>,
+    server_certificates: Vec<Certificate>,
+    /// the server's signed ephemeral key, for DHE and ECDHE suites
+    server_key_exchange: Option<ServerKeyExchangeParams>,
+    /// TLS 1.2: the server agreed to encrypt_then_mac and extended_master_secret
+    encrypt_then_mac: bool,
+    extended_master_secret: bool,
+    master_secret: Vec<u8>,
+    /// protections for (server write, client write), activated by each side's ChangeCipherSpec
+    pending_read_protection: Option<Box<dyn RecordProtection>>,
+    pending_write_protection: Option<Box<dyn RecordProtection>>,
+    /// TLS 1.3: our key share and the cookie to send in ClientHello
+    key_share: Option<(NamedGroup, EphemeralKey)>,
+    cookie: Option<Vec<u8>>,
+    hello_retry_request: bool,
+    sent_change_cipher_spec: bool,
+    key_schedule: Option<KeySchedule>,
+    client_handshake_secret: Vec<u8>,
+    server_handshake_secret: Vec<u8>,
+    /// TLS 1.3: the traffic secrets that KeyUpdate steps forward after the handshake
+    client_application_secret: Vec<u8>,
+    server_application_secret: Vec<u8>,
+    /// TLS 1.3: the secret the PSKs of the server's tickets derive from
+    resumption_master_secret: Vec<u8>,
+    alpn_protocol: Option<Vec<u8>>,
+    /// ServerHello and EncryptedExtensions extensions, for the session
+    server_extensions: Vec<Extension>,
+    /// the context and signature schemes of the server's CertificateRequest, if it sent one.
+    /// The context is always empty in TLS 1.2.
+    certificate_request: Option<(Vec<u8>, Vec<u16>)>,
+    /// TLS 1.2: the Finished messages of the previous handshake, replaced by ours when done
+    secure_renegotiation: SecureRenegotiation,
+}
+
+/// Performs a TLS 1.3 or 1.2 handshake over `stream` and verifies that the server's
+/// certificate chains to `config.trust_store` and is valid for `server_name`.
+/// On failure the matching fatal alert is sent before the error is returned.
+/// Sessions are neither resumed nor cached, see `connect_resumable`.
+pub fn connect<S: Read + Write>(stream: S, config: &ClientConfig, server_name: &str) -> Result<TlsStream<S>> {
+    handshake(stream, config, server_name, None)
+}
+
+/// Like `connect`, but first offers the session that `config.session_cache` holds for
+/// `session_key`, usually "host:port", and caches the new session under it
+pub fn connect_resumable<S: Read + Write>(stream: S, config: &ClientConfig, server_name: &str, session_key: &str) -> Result<TlsStream<S>> {
+    handshake(stream, config, server_name, Some(session_key))
+}
+
+fn handshake<S: Read + Write>(stream: S, config: &ClientConfig, server_name: &str, session_key: Option<&str>) -> Result<TlsStream<S>> {
+    let mut record_layer = RecordLayer::new(stream);
+    record_layer.set_tracer(config.tracer.clone());
+    let mut handshake = ClientHandshake::new(Arc::new(config.clone()), server_name, session_key)?;
+    if let Err(e) = handshake.run(&mut record_layer) {
+        if !e.is::<PeerAlert>() {
+            let _ = record_layer.send_fatal_alert(alert::description_of(&e));
+        }
+        return Err(e);
+    }
+    Ok(handshake.into_stream(record_layer))
+}
+
+/// Renegotiates an established TLS 1.2 (or earlier) connection with a full handshake, bound to
+/// the previous one by renegotiation_info (RFC 5746). The session is not cached.
+pub(crate) fn renegotiate<S: Read + Write>(record_layer: &mut RecordLayer<S>, config: &Arc<ClientConfig>, server_name: &str, previous: &SecureRenegotiation) -> Result<(SessionInfo, SecureRenegotiation)> {
+    let mut handshake = ClientHandshake::new(Arc::clone(config), server_name, None)?;
+    // the version stays the one of the connection
+    let version = record_layer.version();
+    if !handshake.versions.contains(&version) {
+        return Err(anyhow!("No {} cipher suites configured to renegotiate with", version));
+    }
+    handshake.versions = vec![version];
+    handshake.key_share = None;
+    handshake.session_id = vec![];
+    handshake.secure_renegotiation = previous.clone();
+    handshake.run(record_layer)?;
+    Ok((handshake.session_info(), handshake.secure_renegotiation))
+}
+
+fn unsupported_extension(extension: &Extension) -> anyhow::Error {
+    let extension_type = extension.extension_type();
+    alert::fatal(UNSUPPORTED_EXTENSION, format!("Server sent extension {} ({}) that was not offered", extensions::extension_name(extension_type), extension_type))
+}
+
+impl ClientHandshake {
+    pub(crate) fn new(config: Arc<ClientConfig>, server_name: &str, session_key: Option<&str>) -> Result<Self> {
+        // a version is only offered along with a cipher suite for it
+        let mut versions: Vec<ProtocolVersion> = ProtocolVersion::IMPLEMENTED.iter()
+            .filter(|v| config.versions.contains(v))
+            .filter(|v| config.cipher_suites.iter().any(|s| s.supports_version(**v)))
+            .copied()
+            .collect();
+        if config.groups.is_empty() {
+            versions.retain(|v| *v != ProtocolVersion::TLS1_3);
+        }
+        if versions.is_empty() {
+            return Err(anyhow!("No cipher suites configured for the enabled protocol versions"));
+        }
+        let mut client_random = [0u8; RANDOM_LEN];
+        fill_random(&mut client_random)?;
+        let session_key = session_key.filter(|_| config.session_cache.is_some());
+        // a session is only offered along with its version and a suite that can resume it
+        let cached_session = session_key.and_then(|key| config.session_cache.as_ref().unwrap().get(key))
+            .filter(|s| versions.contains(&s.version))
+            .filter(|s| match s.version {
+                ProtocolVersion::TLS1_3 => config.cipher_suites.iter().any(|c| c.is_tls13() && c.prf_hash == s.cipher_suite.prf_hash),
+                _ => config.cipher_suites.contains(&s.cipher_suite),
+            })
+            .filter(|s| s.version == ProtocolVersion::TLS1_3 || s.extended_master_secret || !config.require_extended_master_secret);
+        let tls13 = versions.contains(&ProtocolVersion::TLS1_3);
+        let session_id = match &cached_session {
+            Some(s) if s.version != ProtocolVersion::TLS1_3 && s.ticket.is_empty() => s.session_id.clone(),
+            // the server accepts a ticket by echoing the session ID that comes with it (RFC 5077 section 3.4)
+            Some(s) if s.version != ProtocolVersion::TLS1_3 => random_bytes(MAX_SESSION_ID_LEN)?,
+            _ if tls13 => random_bytes(MAX_SESSION_ID_LEN)?,
+            _ => vec![],
+        };
+        let key_share = if tls13 {
+            let group = config.groups[0];
+            Some((group, EphemeralKey::generate_ecdhe(group)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            config,
+            server_name: server_name.to_string(),
+            server_name_indication: extensions::server_name_indication(server_name),
+            state: State::ExpectServerHello,
+            versions,
+            version: None,
+            transcript: Transcript::new(),
+            client_random,
+            session_id,
+            session_key: session_key.map(str::to_string),
+            cached_session,
+            resumed: false,
+            server_session_id: vec![],
+            expect_new_session_ticket: false,
+            new_session_ticket: None,
+            server_random: [0u8; RANDOM_LEN],
+            cipher_suite: None,
+            server_certificates: vec![],
+            server_key_exchange: None,
+            encrypt_then_mac: false,
+            extended_master_secret: false,
+            master_secret: vec![],
+            pending_read_protection: None,
+            pending_write_protection: None,
+            key_share,
+            cookie: None,
+            hello_retry_request: false,
+            sent_change_cipher_spec: false,
+            key_schedule: None,
+            client_handshake_secret: vec![],
+            server_handshake_secret: vec![],
+            client_application_secret: vec![],
+            server_application_secret: vec![],
+            resumption_master_secret: vec![],
+            alpn_protocol: None,
+            server_extensions: vec![],
+            certificate_request: None,
+            secure_renegotiation: SecureRenegotiation::default(),
+        })
+    }
+
+    /// The established connection, once the handshake is done
+    pub(crate) fn into_stream<S: Read + Write>(mut self, record_layer: RecordLayer<S>) -> TlsStream<S> {
+        let ticket_receiver = self.cache_session();
+        let rekeying = if self.is_tls13() {
+            Rekeying::KeyUpdate {
+                read_secret: std::mem::take(&mut self.server_application_secret),
+                write_secret: std::mem::take(&mut self.client_application_secret),
+            }
+        } else {
+            Rekeying::Renegotiation {
+                endpoint: Endpoint::Client {
+                    config: Arc::clone(&self.config),
+                    server_name: self.server_name.clone(),
+                },
+                secure_renegotiation: self.secure_renegotiation.clone(),
+            }
+        };
+        let session = self.session_info();
+        TlsStream::new(record_layer, session, ticket_receiver, rekeying)
+    }
+
+    fn session_info(&mut self) -> SessionInfo {
+        SessionInfo {
+            version: self.version.unwrap(),
+            cipher_suite: self.cipher_suite.unwrap(),
+            peer_certificates: std::mem::take(&mut self.server_certificates),
+            server_name: self.server_name_indication.clone(),
+            alpn_protocol: self.alpn_protocol.take(),
+            peer_extensions: std::mem::take(&mut self.server_extensions),
+            resumed: self.resumed,
+        }
+    }
+
+    fn is_tls13(&self) -> bool {
+        self.version == Some(ProtocolVersion::TLS1_3)
+    }
+
+    /// The configured suites of the offered versions
+    fn offered_suites(&self) -> impl Iterator<Item = &'static CipherSuite> + '_ {
+        self.config.cipher_suites.iter().copied().filter(move |s| self.versions.iter().any(|v| s.supports_version(*v)))
+    }
+
+    /// ClientHello.version, the highest version offered below TLS 1.3, which is only
+    /// offered in supported_versions (RFC 8446 section 4.1.2)
+    fn client_version(&self) -> ProtocolVersion {
+        self.versions.iter().copied().find(|v| *v < ProtocolVersion::TLS1_3).unwrap_or(ProtocolVersion::TLS1_2)
+    }
+
+    /// Whether a version with TLS 1.2 style extensions is offered
+    fn offers_pre_tls13(&self) -> bool {
+        self.versions.iter().any(|v| *v != ProtocolVersion::TLS1_3 && *v != ProtocolVersion::SSL3_0)
+    }
+
+    /// Passes a secret of this handshake to the key log, if there is one
+    fn log_key(&self, label: &str, secret: &[u8]) {
+        if let Some(key_log) = &self.config.key_log {
+            key_log.log(label, &self.client_random, secret);
+        }
+    }
+
+    /// The key derivation of the negotiated version and suite, before TLS 1.3
+    fn prf(&self) -> Prf {
+        Prf::new(self.version.unwrap(), self.cipher_suite.unwrap())
+    }
+
+    /// The cached TLS 1.3 session to offer as a PSK. After a HelloRetryRequest its
+    /// hash has to match the suite the server picked.
+    fn psk_session(&self) -> Option<&ClientSession> {
+        self.cached_session.as_ref()
+            .filter(|s| s.version == ProtocolVersion::TLS1_3 && self.key_share.is_some())
+            .filter(|s| self.cipher_suite.is_none_or(|c| c.prf_hash == s.cipher_suite.prf_hash))
+    }
+
+    fn run<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>) -> Result<()> {
+        self.send_client_hello(record_layer)?;
+        while !self.is_connected() {
+            let message = record_layer.read_message()?.ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "Connection closed during the handshake"))?;
+            self.handle_message(record_layer, message)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Takes the next message from the server, answering it if it ends a flight
+    pub(crate) fn handle_message<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, message: Message) -> Result<()> {
+        match message {
+            Message::Handshake(message) => self.handle_handshake(record_layer, message),
+            Message::ChangeCipherSpec => self.handle_change_cipher_spec(record_layer),
+            Message::Alert(alert) => Err(PeerAlert(alert).into()),
+            // the server may carry on sending while we renegotiate
+            Message::ApplicationData(data) if self.secure_renegotiation.is_renegotiation() => {
+                record_layer.buffer_application_data(data);
+                Ok(())
+            },
+            Message::ApplicationData(_) => Err(alert::fatal(UNEXPECTED_MESSAGE, "Application data during the handshake")),
+        }
+    }
+
+    fn send(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        self.transcript.add(&message);
+        record_layer.send_handshake(&message)
+    }
+
+    pub(crate) fn send_client_hello(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        let extensions = self.client_hello_extensions();
+        // the SCSV asks the server to confirm RFC 5746 support with renegotiation_info.
+        // A renegotiation sends the extension instead (RFC 5746 section 3.5).
+        let scsv = Some(TLS_EMPTY_RENEGOTIATION_INFO_SCSV).filter(|_| !self.secure_renegotiation.is_renegotiation());
+        let mut client_hello = ClientHello {
+            version: self.client_version(),
+            random: self.client_random,
+            session_id: self.session_id.clone(),
+            cipher_suites: self.offered_suites().map(|s| s.id).chain(scsv).collect(),
+            compression_methods: vec![0],
+            extensions: extensions::encode_extensions(&extensions),
+        };
+        if let Some(Extension::PreSharedKey(mut psks)) = extensions.last().cloned() {
+            // the binder covers the ClientHello up to the binders themselves
+            let session = self.psk_session().unwrap();
+            let hash = session.cipher_suite.prf_hash;
+            let mut transcript = self.transcript.clone();
+            transcript.add(&client_hello.encode());
+            psks.binders = vec![psk_binder(hash, &session.secret, &transcript.hash_truncated(hash, psks.binders_len()))];
+            *client_hello.extensions.last_mut().unwrap() = Extension::PreSharedKey(psks).encode();
+        }
+        self.send(record_layer, client_hello.encode())
+    }
+
+    fn client_hello_extensions(&self) -> Vec<Extension> {
+        let mut extensions: Vec<Extension> = vec![];
+        if let Some(name) = &self.server_name_indication {
+            extensions.push(Extension::ServerName(Some(name.clone())));
+        }
+        // without signature_algorithms servers assume SHA-1 (RFC 5246 section 7.4.1.4.1),
+        // which current ones refuse to use with their certificates
+        extensions.push(Extension::SignatureAlgorithms(SUPPORTED_SIGNATURE_SCHEMES.to_vec()));
+        if self.secure_renegotiation.is_renegotiation() {
+            extensions.push(Extension::RenegotiationInfo(self.secure_renegotiation.client_verify_data.clone()));
+        }
+        if self.offers_pre_tls13() {
+            extensions.push(Extension::ExtendedMasterSecret);
+            if self.offered_suites().any(|s| s.is_cbc()) {
+                extensions.push(Extension::EncryptThenMac);
+            }
+        }
+        // groups only mean something to the server if an ECDHE suite is on offer (RFC 8422 section 4)
+        let ecdhe = self.offered_suites().any(|s| s.key_exchange == KeyExchange::Ecdhe) && !self.config.groups.is_empty();
+        if ecdhe || self.key_share.is_some() {
+            extensions.push(Extension::SupportedGroups(self.config.groups.iter().map(|g| g.id()).collect()));
+        }
+        if ecdhe {
+            extensions.push(Extension::EcPointFormats(vec![handshake::EC_POINT_FORMAT_UNCOMPRESSED]));
+        }
+        if !self.config.alpn_protocols.is_empty() {
+            extensions.push(Extension::Alpn(self.config.alpn_protocols.clone()));
+        }
+        if self.session_key.is_some() && self.offers_pre_tls13() {
+            let ticket = self.cached_session.as_ref().filter(|s| s.version != ProtocolVersion::TLS1_3).map(|s| s.ticket.clone());
+            extensions.push(Extension::SessionTicket(ticket.unwrap_or_default()));
+        }
+        if let Some((group, key)) = &self.key_share {
+            extensions.push(Extension::SupportedVersions(self.versions.clone()));
+            extensions.push(Extension::KeyShares(vec![KeyShareEntry {
+                group: group.id(),
+                key_exchange: key.public_bytes(),
+            }]));
+        }
+        if let Some(cookie) = &self.cookie {
+            extensions.push(Extension::Cookie(cookie.clone()));
+        }
+        // without psk_key_exchange_modes the server sends no tickets
+        if self.session_key.is_some() && self.key_share.is_some() {
+            extensions.push(Extension::PskKeyExchangeModes(vec![handshake::PSK_DHE_KE]));
+        }
+        // pre_shared_key has to come last; its binder is filled in once the rest is encoded
+        if let Some(session) = self.psk_session() {
+            extensions.push(Extension::PreSharedKey(OfferedPsks {
+                identities: vec![PskIdentity {
+                    identity: session.ticket.clone(),
+                    obfuscated_ticket_age: session.obfuscated_ticket_age(),
+                }],
+                binders: vec![vec![0; session.cipher_suite.prf_hash.output_size()]],
+            }));
+        }
+        extensions
+    }
+
+    /// Handles the extensions that ServerHello in TLS 1.2 and EncryptedExtensions in TLS 1.3
+    /// have in common, and refuses any that we did not offer
+    fn handle_server_extension(&mut self, extension: &Extension) -> Result<()> {
+        match extension {
+            Extension::ServerName(None) if self.server_name_indication.is_some() => (),
+            Extension::Alpn(protocols) if !self.config.alpn_protocols.is_empty() => {
+                if !self.config.alpn_protocols.contains(&protocols[0]) {
+                    return Err(alert::fatal(ILLEGAL_PARAMETER, "Server selected an application protocol that was not offered"));
+                }
+                self.alpn_protocol = Some(protocols[0].clone());
+            },
+            extension => return Err(unsupported_extension(extension)),
+        }
+        Ok(())
+    }
+
+    fn handle_handshake(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        match (self.state, message.msg_type) {
+            (State::ExpectServerHello, handshake::SERVER_HELLO) => self.handle_server_hello(record_layer, message),
+            (State::ExpectEncryptedExtensions, handshake::ENCRYPTED_EXTENSIONS) => {
+                self.transcript.add(&message);
+                self.handle_encrypted_extensions(&message.body)
+            },
+            (State::ExpectCertificate, handshake::CERTIFICATE_REQUEST) if self.is_tls13() && self.certificate_request.is_none() => {
+                self.handle_certificate_request_tls13(&message.body)?;
+                self.transcript.add(&message);
+                Ok(())
+            },
+            (State::ExpectCertificate, handshake::CERTIFICATE) => {
+                self.transcript.add(&message);
+                self.handle_certificate(&message.body)
+            },
+            (State::ExpectCertificateVerify, handshake::CERTIFICATE_VERIFY) => {
+                self.handle_certificate_verify(&message.body)?;
+                self.transcript.add(&message);
+                self.state = State::ExpectFinished;
+                Ok(())
+            },
+            (State::ExpectServerKeyExchange, handshake::SERVER_KEY_EXCHANGE) => {
+                self.transcript.add(&message);
+                self.handle_server_key_exchange(&message.body)
+            },
+            (State::ExpectServerHelloDone, handshake::CERTIFICATE_REQUEST) if !self.is_tls13() && self.certificate_request.is_none() => {
+                let version = self.version.unwrap();
+                // SSL 3.0 CertificateVerify is keyed with the master secret, which we do not implement
+                if version == ProtocolVersion::SSL3_0 {
+                    return Err(alert::fatal(HANDSHAKE_FAILURE, "Client certificates are not supported in SSL 3.0"));
+                }
+                let certificate_request = CertificateRequest::parse(&message.body, version)?;
+                self.transcript.add(&message);
+                // our RSA key only answers a server that takes rsa_sign certificates.
+                // Before TLS 1.2 the key type alone decides the signature.
+                let schemes = if !certificate_request.certificate_types.contains(&handshake::CERTIFICATE_TYPE_RSA_SIGN) {
+                    vec![]
+                } else if version < ProtocolVersion::TLS1_2 {
+                    CLIENT_SIGNATURE_SCHEMES.to_vec()
+                } else {
+                    certificate_request.signature_schemes
+                };
+                self.certificate_request = Some((vec![], schemes));
+                Ok(())
+            },
+            (State::ExpectServerHelloDone, handshake::SERVER_HELLO_DONE) => {
+                if !message.body.is_empty() {
+                    return Err(alert::fatal(DECODE_ERROR, "ServerHelloDone is not empty"));
+                }
+                self.transcript.add(&message);
+                self.send_key_exchange(record_layer)
+            },
+            (State::ExpectNewSessionTicket, handshake::NEW_SESSION_TICKET) => {
+                let ticket = handshake::parse_new_session_ticket(&message.body)?;
+                self.transcript.add(&message);
+                self.new_session_ticket = Some(ticket);
+                self.state = State::ExpectChangeCipherSpec;
+                Ok(())
+            },
+            (State::ExpectFinished, handshake::FINISHED) if self.is_tls13() => self.handle_finished_tls13(record_layer, message),
+            (State::ExpectFinished, handshake::FINISHED) => {
+                self.handle_finished(&message.body)?;
+                self.transcript.add(&message);
+                // in an abbreviated handshake the server finishes first
+                if self.resumed {
+                    self.send_finished(record_layer)?;
+                }
+                self.state = State::Connected;
+                Ok(())
+            },
+            // a HelloRequest while negotiating is ignored (RFC 5246 section 7.4.1.1)
+            (state, handshake::HELLO_REQUEST) if state != State::ExpectFinished && !self.is_tls13() => Ok(()),
+            (state, msg_type) => Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected {} in state {:?}", handshake::handshake_type_name(msg_type), state))),
+        }
+    }
+
+    fn handle_server_hello(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        let server_hello = ServerHello::parse(&message.body)?;
+        let version = self.negotiated_version(&server_hello)?;
+        if server_hello.compression_method != 0 {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server selected compression"));
+        }
+        if server_hello.random == HELLO_RETRY_REQUEST_RANDOM {
+            return self.handle_hello_retry_request(record_layer, message, &server_hello);
+        }
+        self.transcript.add(&message);
+        self.version = Some(version);
+        self.server_random = server_hello.random;
+        if version == ProtocolVersion::TLS1_3 {
+            return self.handle_server_hello_tls13(record_layer, &server_hello);
+        }
+        let suite = self.offered_suites().find(|s| s.id == server_hello.cipher_suite && s.supports_version(version))
+            .ok_or_else(|| alert::fatal(ILLEGAL_PARAMETER, format!("Server selected a cipher suite that was not offered: {:04x}", server_hello.cipher_suite)))?;
+        let server_extensions = extensions::parse_extensions(&server_hello.extensions, HelloContext::ServerHello)?;
+        let renegotiation = self.secure_renegotiation.is_renegotiation();
+        if renegotiation && !server_extensions.iter().any(|e| matches!(e, Extension::RenegotiationInfo(_))) {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Server sent no renegotiation_info when renegotiating"));
+        }
+        for extension in &server_extensions {
+            match extension {
+                // both verify_data of the previous handshake, or nothing in the first (RFC 5746 section 3.4)
+                Extension::RenegotiationInfo(renegotiated_connection) => {
+                    let expected = [&self.secure_renegotiation.client_verify_data[..], &self.secure_renegotiation.server_verify_data[..]].concat();
+                    if !constant_time_eq(renegotiated_connection, &expected) {
+                        return Err(alert::fatal(HANDSHAKE_FAILURE, "renegotiation_info does not match the previous handshake"));
+                    }
+                    self.secure_renegotiation.supported = true;
+                },
+                Extension::SessionTicket(_) if self.session_key.is_some() => self.expect_new_session_ticket = true,
+                // neither has a definition for SSL 3.0
+                Extension::ExtendedMasterSecret if version != ProtocolVersion::SSL3_0 => self.extended_master_secret = true,
+                // the server only agrees for a CBC suite (RFC 7366 section 2)
+                Extension::EncryptThenMac if self.offered_suites().any(|s| s.is_cbc()) && version != ProtocolVersion::SSL3_0 => {
+                    if !suite.is_cbc() {
+                        return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server sent encrypt_then_mac for {}", suite.name)));
+                    }
+                    self.encrypt_then_mac = true;
+                },
+                Extension::EcPointFormats(formats) if suite.key_exchange == KeyExchange::Ecdhe => {
+                    if !formats.contains(&handshake::EC_POINT_FORMAT_UNCOMPRESSED) {
+                        return Err(alert::fatal(ILLEGAL_PARAMETER, "Peer does not support uncompressed points"));
+                    }
+                },
+                extension => self.handle_server_extension(extension)?,
+            }
+        }
+        if renegotiation && self.secure_renegotiation.encrypt_then_mac && suite.is_cbc() && !self.encrypt_then_mac {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Server gave up encrypt_then_mac when renegotiating"));
+        }
+        self.secure_renegotiation.encrypt_then_mac = self.encrypt_then_mac;
+        self.server_extensions = server_extensions;
+        record_layer.set_version(server_hello.version);
+        self.cipher_suite = Some(suite);
+        self.server_session_id = server_hello.session_id.clone();
+        // echoing our session ID resumes the session we offered
+        let resumed = self.cached_session.as_ref()
+            .filter(|_| !server_hello.session_id.is_empty() && server_hello.session_id == self.session_id);
+        let session = match resumed {
+            Some(session) => session,
+            None => {
+                self.state = State::ExpectCertificate;
+                return Ok(());
+            },
+        };
+        if session.version != version {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server resumed a {} session with {}", session.version, version)));
+        }
+        if session.cipher_suite != suite {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server resumed the session with another cipher suite"));
+        }
+        // a resumed session keeps the master secret it was made with (RFC 7627 section 5.3)
+        if session.extended_master_secret != self.extended_master_secret {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Server resumed the session with a different extended_master_secret"));
+        }
+        self.master_secret = session.secret.clone();
+        self.log_key(keylog::CLIENT_RANDOM, &self.master_secret);
+        self.server_certificates = session.peer_certificates.clone();
+        let (client_write, server_write) = derive_protections(suite, version, &self.master_secret, &self.client_random, &self.server_random, self.encrypt_then_mac)?;
+        self.pending_read_protection = Some(server_write);
+        self.pending_write_protection = Some(client_write);
+        self.resumed = true;
+        self.state = if self.expect_new_session_ticket { State::ExpectNewSessionTicket } else { State::ExpectChangeCipherSpec };
+        Ok(())
+    }
+
+    /// The version chosen by supported_versions or, without it, legacy_version. An answer
+    /// below the highest version offered must not carry its downgrade sentinel (RFC 8446 section 4.1.3).
+    fn negotiated_version(&self, server_hello: &ServerHello) -> Result<ProtocolVersion> {
+        if let Some(data) = handshake::find_extension(&server_hello.extensions, handshake::EXTENSION_SUPPORTED_VERSIONS) {
+            if !self.versions.contains(&ProtocolVersion::TLS1_3) {
+                return Err(alert::fatal(UNSUPPORTED_EXTENSION, "Server sent supported_versions without a TLS 1.3 offer"));
+            }
+            let version = handshake::parse_supported_versions_server_hello(data)?;
+            if version != ProtocolVersion::TLS1_3 || server_hello.version != ProtocolVersion::TLS1_2 {
+                return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server selected {} in supported_versions", version)));
+            }
+            return Ok(version);
+        }
+        let version = server_hello.version;
+        if version >= ProtocolVersion::TLS1_3 || !self.versions.contains(&version) {
+            return Err(alert::fatal(PROTOCOL_VERSION, format!("Server selected {}", version)));
+        }
+        let sentinel = &server_hello.random[RANDOM_LEN - 8..];
+        if self.versions.contains(&ProtocolVersion::TLS1_3) && (sentinel == handshake::DOWNGRADE_TLS12 || sentinel == handshake::DOWNGRADE_TLS11) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server signalled a downgrade from TLS 1.3"));
+        }
+        if version < ProtocolVersion::TLS1_2 && self.versions.contains(&ProtocolVersion::TLS1_2) && sentinel == handshake::DOWNGRADE_TLS11 {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server signalled a downgrade from TLS 1.2"));
+        }
+        Ok(version)
+    }
+
+    /// Answers a HelloRetryRequest with a second ClientHello that has a key share
+    /// for the requested group and echoes the cookie (RFC 8446 section 4.1.4)
+    fn handle_hello_retry_request(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage, hello_retry_request: &ServerHello) -> Result<()> {
+        if self.hello_retry_request {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, "Second HelloRetryRequest"));
+        }
+        let suite = self.check_server_hello_tls13(hello_retry_request)?;
+        let mut group: Option<NamedGroup> = None;
+        for extension in extensions::parse_extensions(&hello_retry_request.extensions, HelloContext::HelloRetryRequest)? {
+            match extension {
+                Extension::SelectedVersion(_) => (),
+                Extension::KeyShareRequest(id) => {
+                    let requested = NamedGroup::from_id(id).filter(|g| self.config.groups.contains(g))
+                        .ok_or_else(|| alert::fatal(ILLEGAL_PARAMETER, format!("Server requested group {} that was not offered", id)))?;
+                    if self.key_share.as_ref().map(|(g, _)| *g) == Some(requested) {
+                        return Err(alert::fatal(ILLEGAL_PARAMETER, "Server requested the key share that was sent"));
+                    }
+                    group = Some(requested);
+                },
+                Extension::Cookie(cookie) => self.cookie = Some(cookie),
+                extension => return Err(unsupported_extension(&extension)),
+            }
+        }
+        if group.is_none() && self.cookie.is_none() {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "HelloRetryRequest would not change the ClientHello"));
+        }
+        if let Some(group) = group {
+            self.key_share = Some((group, EphemeralKey::generate_ecdhe(group)?));
+        }
+        self.transcript.replace_with_message_hash(suite.prf_hash);
+        self.transcript.add(&message);
+        self.hello_retry_request = true;
+        self.version = Some(ProtocolVersion::TLS1_3);
+        self.cipher_suite = Some(suite);
+        record_layer.set_allow_plaintext_change_cipher_spec(true);
+        record_layer.send_change_cipher_spec()?;
+        self.sent_change_cipher_spec = true;
+        self.send_client_hello(record_layer)
+    }
+
+    /// Checks what ServerHello and HelloRetryRequest have in common and returns the suite
+    fn check_server_hello_tls13(&self, server_hello: &ServerHello) -> Result<&'static CipherSuite> {
+        let suite = self.offered_suites().find(|s| s.id == server_hello.cipher_suite && s.is_tls13())
+            .ok_or_else(|| alert::fatal(ILLEGAL_PARAMETER, format!("Server selected a cipher suite that was not offered: {:04x}", server_hello.cipher_suite)))?;
+        if self.hello_retry_request && self.cipher_suite != Some(suite) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server changed the cipher suite after HelloRetryRequest"));
+        }
+        if server_hello.session_id != self.session_id {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Server did not echo the session ID"));
+        }
+        Ok(suite)
+    }
+
+    /// Completes the key exchange and switches to the handshake traffic keys
+    fn handle_server_hello_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, server_hello: &ServerHello) -> Result<()> {
+        let suite = self.check_server_hello_tls13(server_hello)?;
+        let mut server_share: Option<KeyShareEntry> = None;
+        let mut psk: Option<Vec<u8>> = None;
+        let server_extensions = extensions::parse_extensions(&server_hello.extensions, HelloContext::ServerHello)?;
+        for extension in &server_extensions {
+            match extension {
+                Extension::SelectedVersion(_) => (),
+                Extension::ServerKeyShare(entry) => server_share = Some(entry.clone()),
+                Extension::SelectedPsk(0) if self.psk_session().is_some() => {
+                    let session = self.psk_session().unwrap();
+                    if session.cipher_suite.prf_hash != suite.prf_hash {
+                        return Err(alert::fatal(ILLEGAL_PARAMETER, "Server selected a PSK for another hash"));
+                    }
+                    psk = Some(session.secret.clone());
+                },
+                Extension::SelectedPsk(_) => return Err(alert::fatal(ILLEGAL_PARAMETER, "Server selected a PSK that was not offered")),
+                extension => return Err(unsupported_extension(extension)),
+            }
+        }
+        if psk.is_some() {
+            self.server_certificates = self.cached_session.as_ref().unwrap().peer_certificates.clone();
+            self.resumed = true;
+        }
+        self.server_extensions = server_extensions;
+        let server_share = server_share.ok_or_else(|| alert::fatal(MISSING_EXTENSION, "ServerHello without key_share"))?;
+        let (group, key) = self.key_share.take().unwrap();
+        if server_share.group != group.id() {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server key share for group {} instead of {}", server_share.group, group.name())));
+        }
+        let shared_secret = key.agree(&server_share.key_exchange)?;
+        let hash = suite.prf_hash;
+        let mut key_schedule = KeySchedule::new(hash, psk.as_deref());
+        key_schedule.advance(Some(&shared_secret));
+        let transcript_hash = self.transcript.hash(hash);
+        self.client_handshake_secret = key_schedule.derive(b"c hs traffic", &transcript_hash);
+        self.server_handshake_secret = key_schedule.derive(b"s hs traffic", &transcript_hash);
+        self.log_key(keylog::CLIENT_HANDSHAKE_TRAFFIC_SECRET, &self.client_handshake_secret);
+        self.log_key(keylog::SERVER_HANDSHAKE_TRAFFIC_SECRET, &self.server_handshake_secret);
+        self.key_schedule = Some(key_schedule);
+        record_layer.set_version(ProtocolVersion::TLS1_2);
+        record_layer.set_allow_plaintext_change_cipher_spec(true);
+        record_layer.set_read_protection(tls13_protection(suite, &self.server_handshake_secret)?);
+        self.cipher_suite = Some(suite);
+        self.state = State::ExpectEncryptedExtensions;
+        Ok(())
+    }
+
+    fn handle_encrypted_extensions(&mut self, body: &[u8]) -> Result<()> {
+        let encrypted_extensions = extensions::parse_extensions(&handshake::parse_encrypted_extensions(body)?, HelloContext::EncryptedExtensions)?;
+        for extension in &encrypted_extensions {
+            match extension {
+                // the server's own group preference, for later connections
+                Extension::SupportedGroups(_) => (),
+                extension => self.handle_server_extension(extension)?,
+            }
+        }
+        self.server_extensions.extend(encrypted_extensions);
+        // a resumed session is authenticated by the PSK
+        self.state = if self.resumed { State::ExpectFinished } else { State::ExpectCertificate };
+        Ok(())
+    }
+
+    /// Keeps the context and the signature schemes the server accepts. Other extensions,
+    /// certificate_authorities included, are not used to pick a certificate.
+    fn handle_certificate_request_tls13(&mut self, body: &[u8]) -> Result<()> {
+        let (context, raw_extensions) = handshake::parse_certificate_request_tls13(body)?;
+        let extensions = extensions::parse_extensions(&raw_extensions, HelloContext::CertificateRequest)?;
+        let schemes = extensions.into_iter()
+            .find_map(|e| match e {
+                Extension::SignatureAlgorithms(schemes) => Some(schemes),
+                _ => None,
+            })
+            .ok_or_else(|| alert::fatal(MISSING_EXTENSION, "CertificateRequest without signature_algorithms"))?;
+        self.certificate_request = Some((context, schemes));
+        Ok(())
+    }
+
+    /// Our key and the scheme to sign CertificateVerify with, if we have a certificate
+    /// and the server accepts a signature from it. Otherwise an empty Certificate is sent.
+    fn client_credentials<'c>(&self, config: &'c ClientConfig) -> Option<(&'c RsaPrivateKey, u16)> {
+        let (_, offered) = self.certificate_request.as_ref()?;
+        let key = config.private_key.as_ref().filter(|_| !config.certificate_chain.is_empty())?;
+        let tls13 = self.is_tls13();
+        let scheme = CLIENT_SIGNATURE_SCHEMES.iter().copied()
+            .filter(|s| !tls13 || signature::is_allowed_in_tls13(*s))
+            .find(|s| offered.contains(s))?;
+        Some((key, scheme))
+    }
+
+    fn handle_certificate(&mut self, body: &[u8]) -> Result<()> {
+        let tls13 = self.is_tls13();
+        let chain = if tls13 {
+            let (context, chain) = handshake::parse_certificate_tls13(body)?;
+            if !context.is_empty() {
+                return Err(alert::fatal(ILLEGAL_PARAMETER, "Server Certificate with a request context"));
+            }
+            chain
+        } else {
+            handshake::parse_certificate(body)?
+        };
+        if chain.is_empty() {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Server sent no certificate"));
+        }
+        let certificates = chain.iter()
+            .map(|der| Certificate::from_der(der).map_err(|e| alert::fatal(BAD_CERTIFICATE, format!("Malformed server certificate: {}", e))))
+            .collect::<Result<Vec<Certificate>>>()?;
+        let leaf = &certificates[0];
+        self.config.trust_store.verify_chain(leaf, &certificates[1..], KeyPurpose::ServerAuth, Asn1Time::now())?;
+        verify_hostname(leaf, &self.server_name)?;
+        // the key either decrypts the premaster secret or signs the ephemeral key (RFC 5246 section 7.4.2)
+        let ephemeral = self.cipher_suite.unwrap().key_exchange.is_ephemeral();
+        let signs = tls13 || ephemeral;
+        if let Some(key_usage) = &leaf.key_usage {
+            if signs && !key_usage.contains(KeyUsage::DIGITAL_SIGNATURE) {
+                return Err(alert::fatal(BAD_CERTIFICATE, "Server certificate does not allow digital signatures"));
+            }
+            if !signs && !key_usage.contains(KeyUsage::KEY_ENCIPHERMENT) {
+                return Err(alert::fatal(BAD_CERTIFICATE, "Server certificate does not allow key encipherment"));
+            }
+        }
+        // TLS 1.2 suites name the key type of the certificate; TLS 1.3 ones take either
+        let spki = &leaf.subject_public_key_info;
+        let key_type = match self.cipher_suite.unwrap().authentication {
+            Authentication::Rsa => RsaPublicKey::from_spki(spki).map(|_| ()),
+            Authentication::Ecdsa => EcPublicKey::from_spki(spki).map(|_| ()),
+            Authentication::Any => RsaPublicKey::from_spki(spki).map(|_| ()).or_else(|_| EcPublicKey::from_spki(spki).map(|_| ())),
+        };
+        if let Err(e) = key_type {
+            return Err(alert::fatal(UNSUPPORTED_CERTIFICATE, e.to_string()));
+        }
+        self.server_certificates = certificates;
+        self.state = if tls13 {
+            State::ExpectCertificateVerify
+        } else if ephemeral {
+            State::ExpectServerKeyExchange
+        } else {
+            State::ExpectServerHelloDone
+        };
+        Ok(())
+    }
+
+    /// Checks the server's signature over the transcript up to its Certificate
+    fn handle_certificate_verify(&mut self, body: &[u8]) -> Result<()> {
+        let (scheme, signature) = handshake::parse_certificate_verify(body)?;
+        if !SUPPORTED_SIGNATURE_SCHEMES.contains(&scheme) || !signature::is_allowed_in_tls13(scheme) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server used signature scheme {:04x} that was not offered", scheme)));
+        }
+        let content = handshake::certificate_verify_content(true, &self.transcript.hash(self.cipher_suite.unwrap().prf_hash));
+        signature::verify(&self.server_certificates[0].subject_public_key_info, ProtocolVersion::TLS1_3, scheme, &content, &signature)
+    }
+
+    /// Checks the server's ephemeral parameters and their signature by the certificate key
+    fn handle_server_key_exchange(&mut self, body: &[u8]) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        let version = self.version.unwrap();
+        let server_key_exchange = ServerKeyExchange::parse(body, suite.key_exchange, version)?;
+        match &server_key_exchange.params {
+            ServerKeyExchangeParams::Ecdhe { group, .. } if !self.config.groups.contains(group) => {
+                return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server selected group {} that was not offered", group.name())));
+            },
+            ServerKeyExchangeParams::Dhe { params, .. } if params.bits() < MIN_DH_BITS => {
+                return Err(alert::fatal(INSUFFICIENT_SECURITY, format!("Server Diffie-Hellman group has only {} bits", params.bits())));
+            },
+            ServerKeyExchangeParams::Dhe { params, .. } if params.validate_public(&params.g).is_err() => {
+                return Err(alert::fatal(ILLEGAL_PARAMETER, "Invalid Diffie-Hellman generator"));
+            },
+            _ => (),
+        }
+        let signed_data = ServerKeyExchange::signed_data(&server_key_exchange.params, &self.client_random, &self.server_random);
+        let spki = &self.server_certificates[0].subject_public_key_info;
+        match server_key_exchange.signature_scheme {
+            Some(scheme) => {
+                if !SUPPORTED_SIGNATURE_SCHEMES.contains(&scheme) {
+                    return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Server used signature scheme {:04x} that was not offered", scheme)));
+                }
+                signature::verify(spki, version, scheme, &signed_data, &server_key_exchange.signature)?;
+            },
+            #[cfg(feature = "legacy-protocols")]
+            None => signature::verify_legacy(spki, &signed_data, &server_key_exchange.signature)?,
+            #[cfg(not(feature = "legacy-protocols"))]
+            None => unreachable!("TLS 1.2 always names the signature scheme"),
+        }
+        self.server_key_exchange = Some(server_key_exchange.params);
+        self.state = State::ExpectServerHelloDone;
+        Ok(())
+    }
+
+    /// Sends the ClientKeyExchange of the negotiated key exchange and returns the premaster secret
+    fn send_client_key_exchange(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<Vec<u8>> {
+        let key_exchange = self.cipher_suite.unwrap().key_exchange;
+        let (key, server_public) = match self.server_key_exchange.take() {
+            Some(ServerKeyExchangeParams::Ecdhe { group, public }) => (EphemeralKey::generate_ecdhe(group)?, public),
+            Some(ServerKeyExchangeParams::Dhe { params, public }) => (EphemeralKey::generate_dhe(&params)?, public),
+            None => {
+                // the version offered in ClientHello guards against rollback (RFC 5246 section 7.4.7.1)
+                let mut pre_master_secret = self.client_version().as_u16().to_be_bytes().to_vec();
+                pre_master_secret.extend(random_bytes(PRE_MASTER_SECRET_LEN - 2)?);
+                let server_key = RsaPublicKey::from_spki(&self.server_certificates[0].subject_public_key_info)?;
+                let encrypted = server_key.encrypt_pkcs1_v15(&pre_master_secret)?;
+                self.send(record_layer, handshake::encode_rsa_client_key_exchange(&encrypted, self.version.unwrap()))?;
+                return Ok(pre_master_secret);
+            },
+        };
+        let pre_master_secret = key.agree(&server_public)?;
+        self.send(record_layer, kx::encode_ephemeral_client_key_exchange(key_exchange, &key.public_bytes()))?;
+        Ok(pre_master_secret)
+    }
+
+    /// Certificate if asked for, ClientKeyExchange, CertificateVerify if we sent a
+    /// certificate, ChangeCipherSpec and Finished
+    fn send_key_exchange(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        let config = Arc::clone(&self.config);
+        let credentials = self.client_credentials(&config);
+        if self.certificate_request.is_some() {
+            let chain: &[Vec<u8>] = if credentials.is_some() { &config.certificate_chain } else { &[] };
+            self.send(record_layer, handshake::encode_certificate(chain))?;
+        }
+        let pre_master_secret = self.send_client_key_exchange(record_layer)?;
+        // the session hash ends with ClientKeyExchange, before CertificateVerify
+        let version = self.version.unwrap();
+        let prf = self.prf();
+        self.master_secret = if self.extended_master_secret {
+            prf.extended_master_secret(&pre_master_secret, &self.transcript)
+        } else {
+            prf.master_secret(&pre_master_secret, &self.client_random, &self.server_random)
+        };
+        self.log_key(keylog::CLIENT_RANDOM, &self.master_secret);
+        // TLS 1.2 signs the handshake messages themselves (RFC 5246 section 7.4.8)
+        if let Some((key, scheme)) = credentials {
+            let message = match version {
+                #[cfg(feature = "legacy-protocols")]
+                version if version < ProtocolVersion::TLS1_2 => handshake::encode_legacy_certificate_verify(&signature::sign_legacy(key, self.transcript.messages())?),
+                _ => handshake::encode_certificate_verify(scheme, &signature::sign(key, scheme, self.transcript.messages())?),
+            };
+            self.send(record_layer, message)?;
+        }
+        let (client_write, server_write) = derive_protections(suite, version, &self.master_secret, &self.client_random, &self.server_random, self.encrypt_then_mac)?;
+        self.pending_read_protection = Some(server_write);
+        self.pending_write_protection = Some(client_write);
+        self.send_finished(record_layer)?;
+        self.state = if self.expect_new_session_ticket { State::ExpectNewSessionTicket } else { State::ExpectChangeCipherSpec };
+        Ok(())
+    }
+
+    /// ChangeCipherSpec and Finished
+    fn send_finished(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        record_layer.send_change_cipher_spec()?;
+        record_layer.set_write_protection(self.pending_write_protection.take().unwrap());
+        let verify_data = self.prf().verify_data(&self.master_secret, true, &self.transcript);
+        self.secure_renegotiation.client_verify_data = verify_data.clone();
+        self.send(record_layer, HandshakeMessage::new(handshake::FINISHED, verify_data))
+    }
+
+    fn handle_change_cipher_spec(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        // TLS 1.3 only sends it for middleboxes and it is dropped (RFC 8446 section 5)
+        if self.is_tls13() {
+            return Ok(());
+        }
+        if self.state != State::ExpectChangeCipherSpec {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected ChangeCipherSpec in state {:?}", self.state)));
+        }
+        record_layer.set_read_protection(self.pending_read_protection.take().unwrap());
+        self.state = State::ExpectFinished;
+        Ok(())
+    }
+
+    fn handle_finished(&mut self, body: &[u8]) -> Result<()> {
+        let expected = self.prf().verify_data(&self.master_secret, false, &self.transcript);
+        if body.len() != expected.len() {
+            return Err(alert::fatal(DECODE_ERROR, "Malformed Finished"));
+        }
+        if !constant_time_eq(&expected, body) {
+            return Err(alert::fatal(DECRYPT_ERROR, "Server Finished verify_data mismatch"));
+        }
+        self.secure_renegotiation.server_verify_data = expected;
+        Ok(())
+    }
+
+    /// Checks the server Finished, answers with our own and moves both directions
+    /// to the application traffic keys (RFC 8446 section 7.1)
+    fn handle_finished_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        let hash = suite.prf_hash;
+        let expected = finished_verify_data(hash, &self.server_handshake_secret, &self.transcript.hash(hash));
+        if !constant_time_eq(&expected, &message.body) {
+            return Err(alert::fatal(DECRYPT_ERROR, "Server Finished verify_data mismatch"));
+        }
+        self.transcript.add(&message);
+        let mut key_schedule = self.key_schedule.take().unwrap();
+        key_schedule.advance(None);
+        let transcript_hash = self.transcript.hash(hash);
+        let client_application_secret = key_schedule.derive(b"c ap traffic", &transcript_hash);
+        let server_application_secret = key_schedule.derive(b"s ap traffic", &transcript_hash);
+        self.log_key(keylog::CLIENT_TRAFFIC_SECRET_0, &client_application_secret);
+        self.log_key(keylog::SERVER_TRAFFIC_SECRET_0, &server_application_secret);
+        if self.config.key_log.is_some() {
+            self.log_key(keylog::EXPORTER_SECRET, &key_schedule.derive(b"exp master", &transcript_hash));
+        }
+
+        if !self.sent_change_cipher_spec {
+            record_layer.send_change_cipher_spec()?;
+            self.sent_change_cipher_spec = true;
+        }
+        record_layer.set_write_protection(tls13_protection(suite, &self.client_handshake_secret)?);
+        if let Some((context, _)) = self.certificate_request.clone() {
+            self.send_certificate_tls13(record_layer, &context)?;
+        }
+        let verify_data = finished_verify_data(hash, &self.client_handshake_secret, &self.transcript.hash(hash));
+        self.send(record_layer, HandshakeMessage::new(handshake::FINISHED, verify_data))?;
+        if self.session_key.is_some() {
+            self.resumption_master_secret = key_schedule.derive(b"res master", &self.transcript.hash(hash));
+        }
+        record_layer.set_write_protection(tls13_protection(suite, &client_application_secret)?);
+        record_layer.set_read_protection(tls13_protection(suite, &server_application_secret)?);
+        self.client_application_secret = client_application_secret;
+        self.server_application_secret = server_application_secret;
+        // from here on a ChangeCipherSpec is an unexpected record
+        record_layer.set_allow_plaintext_change_cipher_spec(false);
+        self.state = State::Connected;
+        Ok(())
+    }
+
+    /// Our Certificate for the server's CertificateRequest, empty if we have none it
+    /// accepts, and CertificateVerify over the transcript up to it
+    fn send_certificate_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, context: &[u8]) -> Result<()> {
+        let config = Arc::clone(&self.config);
+        let credentials = self.client_credentials(&config);
+        let chain: &[Vec<u8>] = if credentials.is_some() { &config.certificate_chain } else { &[] };
+        self.send(record_layer, handshake::encode_certificate_tls13(context, chain))?;
+        if let Some((key, scheme)) = credentials {
+            let content = handshake::certificate_verify_content(false, &self.transcript.hash(self.cipher_suite.unwrap().prf_hash));
+            let signature = signature::sign(key, scheme, &content)?;
+            self.send(record_layer, handshake::encode_certificate_verify(scheme, &signature))?;
+        }
+        Ok(())
+    }
+
+    /// Caches the TLS 1.2 session, or for TLS 1.3 returns what will cache the tickets
+    /// that follow the handshake
+    fn cache_session(&mut self) -> Option<TicketReceiver> {
+        let key = self.session_key.clone()?;
+        let cache = self.config.session_cache.clone()?;
+        let cipher_suite = self.cipher_suite.unwrap();
+        // a session the server would not resume is of no more use
+        if self.cached_session.is_some() && !self.resumed {
+            cache.remove(&key);
+        }
+        if self.is_tls13() {
+            return Some(TicketReceiver {
+                cache,
+                key,
+                cipher_suite,
+                peer_certificates: self.server_certificates.clone(),
+                resumption_master_secret: std::mem::take(&mut self.resumption_master_secret),
+            });
+        }
+        let (lifetime_hint, ticket) = match self.new_session_ticket.take() {
+            Some(ticket) => ticket,
+            // resumed without a new ticket, the cached session stays as it is
+            None if self.resumed => return None,
+            None => (0, vec![]),
+        };
+        if self.server_session_id.is_empty() && ticket.is_empty() {
+            return None;
+        }
+        let lifetime = if lifetime_hint == 0 { DEFAULT_SESSION_LIFETIME } else { lifetime_hint };
+        cache.insert(&key, ClientSession {
+            version: self.version.unwrap(),
+            cipher_suite,
+            session_id: self.server_session_id.clone(),
+            ticket,
+            secret: self.master_secret.clone(),
+            extended_master_secret: self.extended_master_secret,
+            ticket_age_add: 0,
+            peer_certificates: self.server_certificates.clone(),
+            received: Instant::now(),
+            lifetime: Duration::from_secs(lifetime as u64),
+        });
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockStream;
+    use super::super::alert::{
+        Alert,
+        AlertDescription,
+        AlertLevel,
+    };
+    use super::super::record::{
+        ContentType,
+        Record,
+    };
+    use super::super::suites::TLS_RSA_WITH_AES_128_CBC_SHA;
+
+    fn server_hello(version: ProtocolVersion, cipher_suite: u16) -> HandshakeMessage {
+        server_hello_with_random(version, cipher_suite, [1u8; RANDOM_LEN])
+    }
+
+    fn server_hello_with_random(version: ProtocolVersion, cipher_suite: u16, random: [u8; RANDOM_LEN]) -> HandshakeMessage {
+        ServerHello {
+            version,
+            random,
+            session_id: vec![],
+            cipher_suite,
+            compression_method: 0,
+            extensions: vec![],
+        }.encode()
+    }
+
+    /// Runs the handshake against `messages` and returns the alert the client sent
+    fn alert_sent_for(messages: &[HandshakeMessage]) -> AlertDescription {
+        let mut input: Vec<u8> = vec![];
+        for message in messages {
+            input.extend(Record {
+                content_type: ContentType::Handshake,
+                version: ProtocolVersion::TLS1_2,
+                fragment: message.encode(),
+            }.encode());
+        }
+        let mut stream = MockStream::new(input);
+        let config = ClientConfig::new(TrustStore::new());
+        assert!(connect(&mut stream, &config, "localhost").is_err());
+        let mut sent = RecordLayer::new(MockStream::new(stream.output));
+        let mut last = None;
+        while let Some(record) = sent.read_record().unwrap() {
+            last = Some(record);
+        }
+        let last = last.unwrap();
+        assert_eq!(ContentType::Alert, last.content_type);
+        let sent = Alert::parse(&last.fragment).unwrap();
+        assert_eq!(AlertLevel::Fatal, sent.level);
+        sent.description
+    }
+
+    #[test]
+    fn test_can_send_unexpected_message_alert() {
+        let done = HandshakeMessage::new(handshake::SERVER_HELLO_DONE, vec![]);
+        assert_eq!(UNEXPECTED_MESSAGE, alert_sent_for(&[done]));
+        let hello = server_hello(ProtocolVersion::TLS1_2, TLS_RSA_WITH_AES_128_CBC_SHA.id);
+        let finished = HandshakeMessage::new(handshake::FINISHED, vec![0; 12]);
+        assert_eq!(UNEXPECTED_MESSAGE, alert_sent_for(&[hello, finished]));
+    }
+
+    #[test]
+    fn test_can_send_alert_for_bad_server_hello() {
+        assert_eq!(PROTOCOL_VERSION, alert_sent_for(&[server_hello(ProtocolVersion::TLS1_1, TLS_RSA_WITH_AES_128_CBC_SHA.id)]));
+        assert_eq!(ILLEGAL_PARAMETER, alert_sent_for(&[server_hello(ProtocolVersion::TLS1_2, 0xcca8)]));
+        assert_eq!(DECODE_ERROR, alert_sent_for(&[HandshakeMessage::new(handshake::SERVER_HELLO, vec![3, 3, 0])]));
+        // TLS 1.3 suite in a TLS 1.2 ServerHello
+        assert_eq!(ILLEGAL_PARAMETER, alert_sent_for(&[server_hello(ProtocolVersion::TLS1_2, ALL_SUITES[0].id)]));
+    }
+
+    #[test]
+    fn test_can_detect_downgrade_sentinel() {
+        for sentinel in [handshake::DOWNGRADE_TLS12, handshake::DOWNGRADE_TLS11].iter() {
+            let mut random = [1u8; RANDOM_LEN];
+            random[RANDOM_LEN - 8..].copy_from_slice(sentinel);
+            let hello = server_hello_with_random(ProtocolVersion::TLS1_2, TLS_RSA_WITH_AES_128_CBC_SHA.id, random);
+            assert_eq!(ILLEGAL_PARAMETER, alert_sent_for(&[hello]));
+        }
+    }
+
+    #[test]
+    fn test_can_send_unknown_ca_alert() {
+        let hello = server_hello(ProtocolVersion::TLS1_2, TLS_RSA_WITH_AES_128_CBC_SHA.id);
+        let leaf = include_bytes!("../../testdata/leaf.der").to_vec();
+        let certificate = handshake::encode_certificate(&[leaf]);
+        assert_eq!(alert::UNKNOWN_CA, alert_sent_for(&[hello, certificate]));
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+
+use super::alert::{
+    self,
+    DECODE_ERROR,
+};
+
+/// Cursor over the wire encoding of handshake messages (RFC 5246 section 4)
+/// Every error is a decode_error alert.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(alert::fatal(DECODE_ERROR, format!("Truncated message: {} bytes needed, {} left", len, self.remaining())));
+        }
+        let result = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(result)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u24(&mut self) -> Result<usize> {
+        let b = self.read_bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array::<4>()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.read_array::<8>()?))
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut result = [0u8; N];
+        result.copy_from_slice(self.read_bytes(N)?);
+        Ok(result)
+    }
+
+    /// opaque vector<0..2^8-1>
+    pub fn read_vec8(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u8()? as usize;
+        self.read_bytes(len)
+    }
+
+    /// opaque vector<0..2^16-1>
+    pub fn read_vec16(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u16()? as usize;
+        self.read_bytes(len)
+    }
+
+    /// opaque vector<0..2^24-1>
+    pub fn read_vec24(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u24()?;
+        self.read_bytes(len)
+    }
+
+    pub fn expect_end(&self) -> Result<()> {
+        if !self.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, format!("{} trailing bytes in message", self.remaining())));
+        }
+        Ok(())
+    }
+}
+
+pub fn put_u8(out: &mut Vec<u8>, value: u8) {
+    out.push(value);
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u24(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_vec8(out: &mut Vec<u8>, data: &[u8]) {
+    put_u8(out, data.len() as u8);
+    out.extend_from_slice(data);
+}
+
+pub fn put_vec16(out: &mut Vec<u8>, data: &[u8]) {
+    put_u16(out, data.len() as u16);
+    out.extend_from_slice(data);
+}
+
+pub fn put_vec24(out: &mut Vec<u8>, data: &[u8]) {
+    put_u24(out, data.len());
+    out.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_round_trip_vectors() {
+        let mut out: Vec<u8> = vec![];
+        put_vec8(&mut out, &[1]);
+        put_vec16(&mut out, &[2, 3]);
+        put_vec24(&mut out, &[4, 5, 6]);
+        put_u16(&mut out, 0x0303);
+        assert_eq!(vec![1, 1, 0, 2, 2, 3, 0, 0, 3, 4, 5, 6, 3, 3], out);
+        let mut reader = Reader::new(&out);
+        assert_eq!(&[1], reader.read_vec8().unwrap());
+        assert_eq!(&[2, 3], reader.read_vec16().unwrap());
+        assert_eq!(&[4, 5, 6], reader.read_vec24().unwrap());
+        assert_eq!([3, 3], reader.read_array::<2>().unwrap());
+        reader.expect_end().unwrap();
+    }
+
+    #[test]
+    fn test_can_return_decode_error_truncated_vector() {
+        let mut reader = Reader::new(&[0, 5, 1, 2]);
+        let err = reader.read_vec16().unwrap_err();
+        assert_eq!(DECODE_ERROR, alert::description_of(&err));
+    }
+}
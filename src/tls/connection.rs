@@ -0,0 +1,497 @@
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::sync::Arc;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::alert::{
+    self,
+    PeerAlert,
+    HANDSHAKE_FAILURE,
+};
+use super::client::{
+    ClientConfig,
+    ClientHandshake,
+};
+use super::record::{
+    Message,
+    RecordLayer,
+    MAX_CIPHERTEXT_LEN,
+    MAX_FRAGMENT_LEN,
+    RECORD_HEADER_LEN,
+};
+use super::server::{
+    ServerConfig,
+    ServerHandshake,
+};
+use super::stream::{
+    SessionInfo,
+    TlsStream,
+};
+
+/// The record layer's stream: reads take the bytes received so far, but only those of
+/// records that arrived whole, and writes collect the bytes to send
+#[derive(Default)]
+struct TlsBuffers {
+    received: Vec<u8>,
+    read_pos: usize,
+    /// end of the last record that arrived whole
+    complete: usize,
+    /// the peer closed the transport, so nothing more will arrive
+    eof: bool,
+    to_send: Vec<u8>,
+}
+
+impl TlsBuffers {
+    fn receive(&mut self, data: &[u8]) {
+        self.received.drain(..self.read_pos);
+        self.complete -= self.read_pos;
+        self.read_pos = 0;
+        self.received.extend_from_slice(data);
+        while self.received.len() - self.complete >= RECORD_HEADER_LEN {
+            let header = &self.received[self.complete..];
+            let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+            // the record layer turns down an oversized record from its header alone
+            let record_len = if length > MAX_CIPHERTEXT_LEN { RECORD_HEADER_LEN } else { RECORD_HEADER_LEN + length };
+            if header.len() < record_len {
+                break;
+            }
+            self.complete += record_len;
+        }
+    }
+
+    /// A partial record left over makes the record layer fail as on a closed stream
+    fn receive_eof(&mut self) {
+        self.eof = true;
+        self.complete = self.received.len();
+    }
+}
+
+impl Read for TlsBuffers {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.complete - self.read_pos;
+        if available == 0 && !self.eof {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = std::cmp::min(buf.len(), available);
+        buf[..n].copy_from_slice(&self.received[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for TlsBuffers {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.to_send.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Handshake {
+    Client(Box<ClientHandshake>),
+    Server(Box<ServerHandshake>),
+}
+
+impl Handshake {
+    fn is_connected(&self) -> bool {
+        match self {
+            Handshake::Client(handshake) => handshake.is_connected(),
+            Handshake::Server(handshake) => handshake.is_connected(),
+        }
+    }
+
+    fn handle_message(&mut self, record_layer: &mut RecordLayer<TlsBuffers>, message: Message) -> Result<()> {
+        match self {
+            Handshake::Client(handshake) => handshake.handle_message(record_layer, message),
+            Handshake::Server(handshake) => handshake.handle_message(record_layer, message),
+        }
+    }
+
+    fn into_stream(self, record_layer: RecordLayer<TlsBuffers>) -> TlsStream<TlsBuffers> {
+        match self {
+            Handshake::Client(handshake) => handshake.into_stream(record_layer),
+            Handshake::Server(handshake) => handshake.into_stream(record_layer),
+        }
+    }
+}
+
+enum State {
+    Handshaking(Handshake, RecordLayer<TlsBuffers>),
+    Established(Box<TlsStream<TlsBuffers>>),
+    /// only the fatal alert, if any, is left to send
+    Failed(TlsBuffers),
+}
+
+/// TLS connection that does no I/O of its own, for event loops and in-memory pipes.
+/// Bytes from the peer go in with `read_tls` and are handled by `process_new_packets`;
+/// `write_tls` takes out what is to be sent. `Read` and `Write` carry application data,
+/// and return `WouldBlock` when there is none yet. Data written during the handshake
+/// is sent once it is done. Renegotiation is turned down, as it would have to block.
+pub struct Connection {
+    state: State,
+    /// application data written before the handshake was done
+    pending_plaintext: Vec<u8>,
+    /// application data received and not read yet
+    plaintext: Vec<u8>,
+    /// the peer sent close_notify
+    peer_closed: bool,
+}
+
+impl Connection {
+    /// Client side of a TLS 1.3 or 1.2 handshake with `server_name`, as in `client::connect`.
+    /// ClientHello is ready to be sent right away.
+    pub fn client(config: Arc<ClientConfig>, server_name: &str) -> Result<Self> {
+        Self::new_client(config, server_name, None)
+    }
+
+    /// Like `client`, but resumes and caches sessions under `session_key`, as in
+    /// `client::connect_resumable`
+    pub fn client_resumable(config: Arc<ClientConfig>, server_name: &str, session_key: &str) -> Result<Self> {
+        Self::new_client(config, server_name, Some(session_key))
+    }
+
+    fn new_client(config: Arc<ClientConfig>, server_name: &str, session_key: Option<&str>) -> Result<Self> {
+        let mut record_layer = RecordLayer::new(TlsBuffers::default());
+        record_layer.set_tracer(config.tracer.clone());
+        let mut handshake = ClientHandshake::new(config, server_name, session_key)?;
+        handshake.send_client_hello(&mut record_layer)?;
+        Ok(Self::new(State::Handshaking(Handshake::Client(Box::new(handshake)), record_layer)))
+    }
+
+    /// Server side of a handshake, as in `server::accept`
+    pub fn server(config: Arc<ServerConfig>) -> Result<Self> {
+        let mut record_layer = RecordLayer::new(TlsBuffers::default());
+        record_layer.set_tracer(config.tracer.clone());
+        let handshake = ServerHandshake::new(config)?;
+        Ok(Self::new(State::Handshaking(Handshake::Server(Box::new(handshake)), record_layer)))
+    }
+
+    fn new(state: State) -> Self {
+        Self {
+            state,
+            pending_plaintext: vec![],
+            plaintext: vec![],
+            peer_closed: false,
+        }
+    }
+
+    fn buffers(&self) -> &TlsBuffers {
+        match &self.state {
+            State::Handshaking(_, record_layer) => record_layer.get_ref(),
+            State::Established(stream) => stream.get_ref(),
+            State::Failed(buffers) => buffers,
+        }
+    }
+
+    fn buffers_mut(&mut self) -> &mut TlsBuffers {
+        match &mut self.state {
+            State::Handshaking(_, record_layer) => record_layer.get_mut(),
+            State::Established(stream) => stream.get_mut(),
+            State::Failed(buffers) => buffers,
+        }
+    }
+
+    /// Reads bytes from the peer once. Reading 0 bytes means the transport is closed.
+    /// Call `process_new_packets` afterwards.
+    pub fn read_tls(&mut self, rd: &mut dyn Read) -> io::Result<usize> {
+        let mut buf = vec![0u8; RECORD_HEADER_LEN + MAX_CIPHERTEXT_LEN];
+        let n = rd.read(&mut buf)?;
+        let buffers = self.buffers_mut();
+        if n == 0 {
+            buffers.receive_eof();
+        } else {
+            buffers.receive(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    /// Writes bytes for the peer once, returning how many were taken
+    pub fn write_tls(&mut self, wr: &mut dyn Write) -> io::Result<usize> {
+        let buffers = self.buffers_mut();
+        let n = wr.write(&buffers.to_send)?;
+        buffers.to_send.drain(..n);
+        Ok(n)
+    }
+
+    /// Handles every whole record received, advancing the handshake and collecting
+    /// application data. On failure the matching fatal alert is queued for `write_tls`
+    /// and the connection cannot be used any more.
+    pub fn process_new_packets(&mut self) -> Result<()> {
+        if let State::Failed(_) = self.state {
+            return Err(anyhow!("The connection has failed"));
+        }
+        let result = self.process();
+        if result.is_err() {
+            let buffers = std::mem::take(self.buffers_mut());
+            self.state = State::Failed(buffers);
+        }
+        result
+    }
+
+    fn process(&mut self) -> Result<()> {
+        if let State::Handshaking(handshake, record_layer) = &mut self.state {
+            if let Err(e) = advance_handshake(handshake, record_layer) {
+                if !e.is::<PeerAlert>() {
+                    let _ = record_layer.send_fatal_alert(alert::description_of(&e));
+                }
+                return Err(e);
+            }
+            if !handshake.is_connected() {
+                return Ok(());
+            }
+            let State::Handshaking(handshake, record_layer) = std::mem::replace(&mut self.state, State::Failed(TlsBuffers::default())) else {
+                unreachable!();
+            };
+            let mut stream = handshake.into_stream(record_layer);
+            stream.disallow_renegotiation();
+            if !self.pending_plaintext.is_empty() {
+                stream.write_all(&self.pending_plaintext)?;
+                self.pending_plaintext.clear();
+            }
+            self.state = State::Established(Box::new(stream));
+        }
+        if let State::Established(stream) = &mut self.state {
+            let mut buf = vec![0u8; MAX_FRAGMENT_LEN];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => {
+                        self.peer_closed = true;
+                        break;
+                    },
+                    Ok(n) => self.plaintext.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    // the stream has sent the alert already
+                    Err(e) => return Err(from_io_error(e)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        matches!(self.state, State::Handshaking(..))
+    }
+
+    /// Whether more bytes from the peer are of use
+    pub fn wants_read(&self) -> bool {
+        !matches!(self.state, State::Failed(_)) && !self.peer_closed && !self.buffers().eof
+    }
+
+    /// Whether there are bytes for `write_tls`
+    pub fn wants_write(&self) -> bool {
+        !self.buffers().to_send.is_empty()
+    }
+
+    /// The agreed parameters, once the handshake is done
+    pub fn session(&self) -> Option<&SessionInfo> {
+        match &self.state {
+            State::Established(stream) => Some(stream.session()),
+            _ => None,
+        }
+    }
+
+    /// Queues close_notify
+    pub fn send_close_notify(&mut self) -> Result<()> {
+        match &mut self.state {
+            State::Established(stream) => stream.close(),
+            _ => Err(anyhow!("The handshake is not done")),
+        }
+    }
+
+    /// Changes the keys with TLS 1.3 KeyUpdate, see `TlsStream::rekey`
+    pub fn rekey(&mut self) -> Result<()> {
+        match &mut self.state {
+            State::Established(stream) => stream.rekey(),
+            _ => Err(anyhow!("The handshake is not done")),
+        }
+    }
+}
+
+/// Runs the handshake over the messages received, until it is done or needs more
+fn advance_handshake(handshake: &mut Handshake, record_layer: &mut RecordLayer<TlsBuffers>) -> Result<()> {
+    while !handshake.is_connected() {
+        let message = match record_layer.read_message() {
+            Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::WouldBlock) => return Ok(()),
+            result => result?.ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "Connection closed during the handshake"))?,
+        };
+        handshake.handle_message(record_layer, message)?;
+    }
+    Ok(())
+}
+
+/// Undoes `record::to_io_error` for alerts from the peer
+fn from_io_error(e: io::Error) -> anyhow::Error {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<PeerAlert>()) {
+        Some(alert) => (*alert).into(),
+        None => e.into(),
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.plaintext.is_empty() {
+            return match self.state {
+                _ if self.peer_closed => Ok(0),
+                State::Failed(_) => Err(io::Error::new(io::ErrorKind::NotConnected, "The connection has failed")),
+                _ => Err(io::ErrorKind::WouldBlock.into()),
+            };
+        }
+        let n = std::cmp::min(buf.len(), self.plaintext.len());
+        buf[..n].copy_from_slice(&self.plaintext[..n]);
+        self.plaintext.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.state {
+            State::Handshaking(..) => {
+                self.pending_plaintext.extend_from_slice(buf);
+                Ok(buf.len())
+            },
+            State::Established(stream) => stream.write(buf),
+            State::Failed(_) => Err(io::Error::new(io::ErrorKind::NotConnected, "The connection has failed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::testdata;
+
+    use crate::pem;
+    use crate::verify::TrustStore;
+    use super::super::alert::UNKNOWN_CA;
+    use super::super::record::ProtocolVersion;
+
+    fn server_config(version: ProtocolVersion) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::from_pem_files(testdata("leaf.pem"), testdata("leaf.key")).unwrap();
+        config.certificate_chain.push(pem::read_file(testdata("inter.pem")).unwrap().remove(0).contents);
+        config.versions = vec![version];
+        Arc::new(config)
+    }
+
+    fn client_config() -> Arc<ClientConfig> {
+        Arc::new(ClientConfig::new(TrustStore::from_pem_dir(testdata("roots")).unwrap()))
+    }
+
+    /// Moves what `from` has to send over to `to`, `chunk` bytes at a time
+    fn transfer(from: &mut Connection, to: &mut Connection, chunk: usize) -> Result<()> {
+        let mut data: Vec<u8> = vec![];
+        while from.wants_write() {
+            from.write_tls(&mut data)?;
+        }
+        for mut piece in data.chunks(chunk) {
+            to.read_tls(&mut piece)?;
+            to.process_new_packets()?;
+        }
+        Ok(())
+    }
+
+    fn handshake(client: &mut Connection, server: &mut Connection, chunk: usize) -> Result<()> {
+        for _ in 0..10 {
+            transfer(client, server, chunk)?;
+            transfer(server, client, chunk)?;
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return Ok(());
+            }
+        }
+        Err(anyhow!("The handshake did not finish"))
+    }
+
+    fn read_to_vec(connection: &mut Connection) -> Vec<u8> {
+        let mut data = vec![];
+        let mut buf = [0u8; 64];
+        loop {
+            match connection.read(&mut buf) {
+                Ok(0) => return data,
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return data,
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_can_exchange_data_in_memory() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2] {
+            let mut client = Connection::client(client_config(), "localhost").unwrap();
+            let mut server = Connection::server(server_config(version)).unwrap();
+            // sent once the handshake is done
+            client.write_all(b"ping").unwrap();
+            handshake(&mut client, &mut server, 1 << 16).unwrap();
+            assert_eq!(version, client.session().unwrap().version);
+            assert_eq!(Some("localhost".to_string()), server.session().unwrap().server_name);
+            transfer(&mut client, &mut server, 1 << 16).unwrap();
+            assert_eq!(b"ping".to_vec(), read_to_vec(&mut server));
+            assert_eq!(io::ErrorKind::WouldBlock, server.read(&mut [0u8; 4]).unwrap_err().kind());
+
+            server.write_all(b"pong").unwrap();
+            transfer(&mut server, &mut client, 1 << 16).unwrap();
+            assert_eq!(b"pong".to_vec(), read_to_vec(&mut client));
+
+            client.send_close_notify().unwrap();
+            transfer(&mut client, &mut server, 1 << 16).unwrap();
+            assert_eq!(0, server.read(&mut [0u8; 4]).unwrap());
+            assert!(!server.wants_read());
+        }
+    }
+
+    #[test]
+    fn test_can_handshake_from_single_bytes() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2] {
+            let mut client = Connection::client(client_config(), "localhost").unwrap();
+            let mut server = Connection::server(server_config(version)).unwrap();
+            handshake(&mut client, &mut server, 1).unwrap();
+            let data = vec![0x5a; 3 * MAX_FRAGMENT_LEN];
+            client.write_all(&data).unwrap();
+            transfer(&mut client, &mut server, 1).unwrap();
+            assert_eq!(data, read_to_vec(&mut server));
+        }
+    }
+
+    #[test]
+    fn test_can_update_keys_but_not_renegotiate() {
+        let mut client = Connection::client(client_config(), "localhost").unwrap();
+        let mut server = Connection::server(server_config(ProtocolVersion::TLS1_3)).unwrap();
+        handshake(&mut client, &mut server, 1 << 16).unwrap();
+        client.rekey().unwrap();
+        client.write_all(b"after KeyUpdate").unwrap();
+        transfer(&mut client, &mut server, 1 << 16).unwrap();
+        assert_eq!(b"after KeyUpdate".to_vec(), read_to_vec(&mut server));
+
+        let mut client = Connection::client(client_config(), "localhost").unwrap();
+        let mut server = Connection::server(server_config(ProtocolVersion::TLS1_2)).unwrap();
+        handshake(&mut client, &mut server, 1 << 16).unwrap();
+        assert!(client.rekey().is_err());
+        assert!(server.rekey().is_err());
+    }
+
+    #[test]
+    fn test_can_send_alert_when_handshake_fails() {
+        let mut client = Connection::client(Arc::new(ClientConfig::new(TrustStore::new())), "localhost").unwrap();
+        let mut server = Connection::server(server_config(ProtocolVersion::TLS1_2)).unwrap();
+        assert!(handshake(&mut client, &mut server, 1 << 16).is_err());
+        assert!(client.process_new_packets().is_err());
+        assert!(client.write_all(b"ping").is_err());
+        // the server hears about it with the alert
+        let e = transfer(&mut client, &mut server, 1 << 16).unwrap_err();
+        assert_eq!(UNKNOWN_CA, e.downcast_ref::<PeerAlert>().unwrap().0.description);
+    }
+}
@@ -0,0 +1,376 @@
+use std::net::IpAddr;
+
+use anyhow::Result;
+
+use crate::idna;
+
+use super::alert::{
+    self,
+    DECODE_ERROR,
+    ILLEGAL_PARAMETER,
+};
+use super::codec::{
+    Reader,
+    put_u8,
+    put_vec8,
+    put_vec16,
+};
+use super::handshake::{
+    self,
+    KeyShareEntry,
+    OfferedPsks,
+    RawExtension,
+};
+use super::record::ProtocolVersion;
+
+/// NameType of a server_name entry (RFC 6066 section 3)
+const NAME_TYPE_HOST_NAME: u8 = 0;
+
+/// The message an extension is carried in, which decides the form of some of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelloContext {
+    ClientHello,
+    ServerHello,
+    HelloRetryRequest,
+    EncryptedExtensions,
+    /// TLS 1.3 CertificateRequest
+    CertificateRequest,
+}
+
+/// Hello extension in decoded form. Extensions we do not know are kept as they were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Extension {
+    /// host_name of ClientHello, or None for the server's empty acknowledgement
+    ServerName(Option<String>),
+    SupportedGroups(Vec<u16>),
+    EcPointFormats(Vec<u8>),
+    SignatureAlgorithms(Vec<u16>),
+    /// protocols offered by the client, or the single one selected by the server
+    Alpn(Vec<Vec<u8>>),
+    EncryptThenMac,
+    ExtendedMasterSecret,
+    /// RFC 5077 ticket of ClientHello, empty to ask for one. The server acknowledges with an empty one.
+    SessionTicket(Vec<u8>),
+    /// PSKs offered in ClientHello
+    PreSharedKey(OfferedPsks),
+    /// index of the PSK accepted in ServerHello
+    SelectedPsk(u16),
+    /// versions offered in ClientHello
+    SupportedVersions(Vec<ProtocolVersion>),
+    /// version selected in ServerHello and HelloRetryRequest
+    SelectedVersion(ProtocolVersion),
+    Cookie(Vec<u8>),
+    PskKeyExchangeModes(Vec<u8>),
+    /// DER distinguished names of the CAs whose certificates are accepted
+    CertificateAuthorities(Vec<Vec<u8>>),
+    /// key shares offered in ClientHello
+    KeyShares(Vec<KeyShareEntry>),
+    /// the server's share in ServerHello
+    ServerKeyShare(KeyShareEntry),
+    /// the group a HelloRetryRequest asks for
+    KeyShareRequest(u16),
+    /// renegotiated_connection, empty in an initial handshake
+    RenegotiationInfo(Vec<u8>),
+    Unknown(RawExtension),
+}
+
+impl Extension {
+    pub fn extension_type(&self) -> u16 {
+        match self {
+            Extension::ServerName(_) => handshake::EXTENSION_SERVER_NAME,
+            Extension::SupportedGroups(_) => handshake::EXTENSION_SUPPORTED_GROUPS,
+            Extension::EcPointFormats(_) => handshake::EXTENSION_EC_POINT_FORMATS,
+            Extension::SignatureAlgorithms(_) => handshake::EXTENSION_SIGNATURE_ALGORITHMS,
+            Extension::Alpn(_) => handshake::EXTENSION_ALPN,
+            Extension::EncryptThenMac => handshake::EXTENSION_ENCRYPT_THEN_MAC,
+            Extension::ExtendedMasterSecret => handshake::EXTENSION_EXTENDED_MASTER_SECRET,
+            Extension::SessionTicket(_) => handshake::EXTENSION_SESSION_TICKET,
+            Extension::PreSharedKey(_) | Extension::SelectedPsk(_) => handshake::EXTENSION_PRE_SHARED_KEY,
+            Extension::SupportedVersions(_) | Extension::SelectedVersion(_) => handshake::EXTENSION_SUPPORTED_VERSIONS,
+            Extension::Cookie(_) => handshake::EXTENSION_COOKIE,
+            Extension::PskKeyExchangeModes(_) => handshake::EXTENSION_PSK_KEY_EXCHANGE_MODES,
+            Extension::CertificateAuthorities(_) => handshake::EXTENSION_CERTIFICATE_AUTHORITIES,
+            Extension::KeyShares(_) | Extension::ServerKeyShare(_) | Extension::KeyShareRequest(_) => handshake::EXTENSION_KEY_SHARE,
+            Extension::RenegotiationInfo(_) => handshake::EXTENSION_RENEGOTIATION_INFO,
+            Extension::Unknown(raw) => raw.extension_type,
+        }
+    }
+
+    pub fn encode(&self) -> RawExtension {
+        let mut data: Vec<u8> = vec![];
+        match self {
+            Extension::ServerName(Some(host_name)) => {
+                let mut entry: Vec<u8> = vec![];
+                put_u8(&mut entry, NAME_TYPE_HOST_NAME);
+                put_vec16(&mut entry, host_name.as_bytes());
+                put_vec16(&mut data, &entry);
+            },
+            Extension::ServerName(None) | Extension::EncryptThenMac | Extension::ExtendedMasterSecret => (),
+            Extension::SupportedGroups(values) | Extension::SignatureAlgorithms(values) => {
+                return handshake::u16_list_extension(self.extension_type(), values);
+            },
+            Extension::EcPointFormats(formats) | Extension::PskKeyExchangeModes(formats) => put_vec8(&mut data, formats),
+            Extension::Alpn(protocols) => {
+                let mut list: Vec<u8> = vec![];
+                for protocol in protocols {
+                    put_vec8(&mut list, protocol);
+                }
+                put_vec16(&mut data, &list);
+            },
+            Extension::SessionTicket(ticket) => data.extend_from_slice(ticket),
+            Extension::CertificateAuthorities(names) => put_vec16(&mut data, &handshake::encode_distinguished_names(names)),
+            Extension::PreSharedKey(psks) => return handshake::pre_shared_key_client_hello(psks),
+            Extension::SelectedPsk(index) => return handshake::pre_shared_key_server_hello(*index),
+            Extension::SupportedVersions(versions) => return handshake::supported_versions_client_hello(versions),
+            Extension::SelectedVersion(version) => return handshake::supported_versions_server_hello(*version),
+            Extension::Cookie(cookie) => return handshake::cookie_extension(cookie),
+            Extension::KeyShares(entries) => return handshake::key_share_client_hello(entries),
+            Extension::ServerKeyShare(entry) => return handshake::key_share_server_hello(entry),
+            Extension::KeyShareRequest(group) => return handshake::key_share_hello_retry_request(*group),
+            Extension::RenegotiationInfo(renegotiated_connection) => put_vec8(&mut data, renegotiated_connection),
+            Extension::Unknown(raw) => return raw.clone(),
+        }
+        RawExtension {
+            extension_type: self.extension_type(),
+            data,
+        }
+    }
+
+    /// Decodes `raw` as it appears in `context`. Extensions of a type we know but that do
+    /// not belong in `context` are kept as unknown for the caller to refuse.
+    pub fn parse(raw: &RawExtension, context: HelloContext) -> Result<Self> {
+        let client_hello = context == HelloContext::ClientHello;
+        let extension = match raw.extension_type {
+            handshake::EXTENSION_SERVER_NAME if client_hello => Extension::ServerName(Some(parse_server_name(&raw.data)?)),
+            handshake::EXTENSION_SERVER_NAME if context != HelloContext::HelloRetryRequest => {
+                expect_empty(&raw.data)?;
+                Extension::ServerName(None)
+            },
+            handshake::EXTENSION_SUPPORTED_GROUPS if context != HelloContext::ServerHello => Extension::SupportedGroups(handshake::parse_u16_list(&raw.data)?),
+            handshake::EXTENSION_EC_POINT_FORMATS => {
+                let mut reader = Reader::new(&raw.data);
+                let formats = reader.read_vec8()?;
+                reader.expect_end()?;
+                if formats.is_empty() {
+                    return Err(alert::fatal(DECODE_ERROR, "Empty ec_point_formats"));
+                }
+                Extension::EcPointFormats(formats.to_vec())
+            },
+            handshake::EXTENSION_SIGNATURE_ALGORITHMS if client_hello || context == HelloContext::CertificateRequest => {
+                Extension::SignatureAlgorithms(handshake::parse_u16_list(&raw.data)?)
+            },
+            handshake::EXTENSION_ALPN if context != HelloContext::HelloRetryRequest => {
+                let protocols = parse_alpn(&raw.data)?;
+                if !client_hello && protocols.len() != 1 {
+                    return Err(alert::fatal(ILLEGAL_PARAMETER, "Server selected more than one application protocol"));
+                }
+                Extension::Alpn(protocols)
+            },
+            handshake::EXTENSION_ENCRYPT_THEN_MAC if matches!(context, HelloContext::ClientHello | HelloContext::ServerHello) => {
+                expect_empty(&raw.data)?;
+                Extension::EncryptThenMac
+            },
+            handshake::EXTENSION_EXTENDED_MASTER_SECRET => {
+                expect_empty(&raw.data)?;
+                Extension::ExtendedMasterSecret
+            },
+            handshake::EXTENSION_SESSION_TICKET if client_hello => Extension::SessionTicket(raw.data.clone()),
+            handshake::EXTENSION_SESSION_TICKET if context == HelloContext::ServerHello => {
+                expect_empty(&raw.data)?;
+                Extension::SessionTicket(vec![])
+            },
+            handshake::EXTENSION_PRE_SHARED_KEY if client_hello => Extension::PreSharedKey(handshake::parse_pre_shared_key_client_hello(&raw.data)?),
+            handshake::EXTENSION_PRE_SHARED_KEY if context == HelloContext::ServerHello => Extension::SelectedPsk(handshake::parse_pre_shared_key_server_hello(&raw.data)?),
+            handshake::EXTENSION_PSK_KEY_EXCHANGE_MODES if client_hello => {
+                let mut reader = Reader::new(&raw.data);
+                let modes = reader.read_vec8()?;
+                reader.expect_end()?;
+                if modes.is_empty() {
+                    return Err(alert::fatal(DECODE_ERROR, "Empty psk_key_exchange_modes"));
+                }
+                Extension::PskKeyExchangeModes(modes.to_vec())
+            },
+            handshake::EXTENSION_CERTIFICATE_AUTHORITIES if client_hello || context == HelloContext::CertificateRequest => {
+                let mut reader = Reader::new(&raw.data);
+                let names = handshake::parse_distinguished_names(reader.read_vec16()?)?;
+                reader.expect_end()?;
+                if names.is_empty() {
+                    return Err(alert::fatal(DECODE_ERROR, "Empty certificate_authorities"));
+                }
+                Extension::CertificateAuthorities(names)
+            },
+            handshake::EXTENSION_SUPPORTED_VERSIONS if client_hello => Extension::SupportedVersions(handshake::parse_supported_versions_client_hello(&raw.data)?),
+            handshake::EXTENSION_SUPPORTED_VERSIONS if matches!(context, HelloContext::ServerHello | HelloContext::HelloRetryRequest) => Extension::SelectedVersion(handshake::parse_supported_versions_server_hello(&raw.data)?),
+            handshake::EXTENSION_COOKIE if client_hello || context == HelloContext::HelloRetryRequest => Extension::Cookie(handshake::parse_cookie(&raw.data)?),
+            handshake::EXTENSION_KEY_SHARE => match context {
+                HelloContext::ClientHello => Extension::KeyShares(handshake::parse_key_share_client_hello(&raw.data)?),
+                HelloContext::ServerHello => Extension::ServerKeyShare(handshake::parse_key_share_server_hello(&raw.data)?),
+                HelloContext::HelloRetryRequest => Extension::KeyShareRequest(handshake::parse_key_share_hello_retry_request(&raw.data)?),
+                HelloContext::EncryptedExtensions | HelloContext::CertificateRequest => Extension::Unknown(raw.clone()),
+            },
+            handshake::EXTENSION_RENEGOTIATION_INFO => {
+                let mut reader = Reader::new(&raw.data);
+                let renegotiated_connection = reader.read_vec8()?;
+                reader.expect_end()?;
+                Extension::RenegotiationInfo(renegotiated_connection.to_vec())
+            },
+            _ => Extension::Unknown(raw.clone()),
+        };
+        Ok(extension)
+    }
+}
+
+/// Decodes every extension of a hello, in the order they were sent
+pub fn parse_extensions(extensions: &[RawExtension], context: HelloContext) -> Result<Vec<Extension>> {
+    extensions.iter().map(|raw| Extension::parse(raw, context)).collect()
+}
+
+pub fn encode_extensions(extensions: &[Extension]) -> Vec<RawExtension> {
+    extensions.iter().map(Extension::encode).collect()
+}
+
+pub fn extension_name(extension_type: u16) -> &'static str {
+    match extension_type {
+        handshake::EXTENSION_SERVER_NAME => "server_name",
+        handshake::EXTENSION_SUPPORTED_GROUPS => "supported_groups",
+        handshake::EXTENSION_EC_POINT_FORMATS => "ec_point_formats",
+        handshake::EXTENSION_SIGNATURE_ALGORITHMS => "signature_algorithms",
+        handshake::EXTENSION_ALPN => "application_layer_protocol_negotiation",
+        handshake::EXTENSION_ENCRYPT_THEN_MAC => "encrypt_then_mac",
+        handshake::EXTENSION_EXTENDED_MASTER_SECRET => "extended_master_secret",
+        handshake::EXTENSION_SESSION_TICKET => "session_ticket",
+        handshake::EXTENSION_PRE_SHARED_KEY => "pre_shared_key",
+        handshake::EXTENSION_SUPPORTED_VERSIONS => "supported_versions",
+        handshake::EXTENSION_COOKIE => "cookie",
+        handshake::EXTENSION_PSK_KEY_EXCHANGE_MODES => "psk_key_exchange_modes",
+        handshake::EXTENSION_CERTIFICATE_AUTHORITIES => "certificate_authorities",
+        handshake::EXTENSION_KEY_SHARE => "key_share",
+        handshake::EXTENSION_RENEGOTIATION_INFO => "renegotiation_info",
+        _ => "unknown",
+    }
+}
+
+/// The name to send in server_name: a DNS name without its trailing dot, with any
+/// U-label converted to its A-label. IP address literals and names that cannot be
+/// converted are not sent (RFC 6066 section 3).
+pub fn server_name_indication(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.');
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() || literal.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    idna::domain_to_ascii(host).ok()
+}
+
+fn expect_empty(data: &[u8]) -> Result<()> {
+    if !data.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Extension data where none is expected"));
+    }
+    Ok(())
+}
+
+/// Picks the host_name out of a ServerNameList. At most one name of each type is allowed.
+fn parse_server_name(data: &[u8]) -> Result<String> {
+    let mut reader = Reader::new(data);
+    let mut list = Reader::new(reader.read_vec16()?);
+    reader.expect_end()?;
+    let mut host_name: Option<String> = None;
+    while !list.is_empty() {
+        let name_type = list.read_u8()?;
+        let name = list.read_vec16()?;
+        if name_type != NAME_TYPE_HOST_NAME {
+            continue;
+        }
+        if host_name.is_some() {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "More than one host_name in server_name"));
+        }
+        let name = std::str::from_utf8(name).ok()
+            .filter(|n| !n.is_empty() && n.is_ascii())
+            .ok_or_else(|| alert::fatal(DECODE_ERROR, "Malformed host_name in server_name"))?;
+        host_name = Some(name.to_string());
+    }
+    host_name.ok_or_else(|| alert::fatal(DECODE_ERROR, "server_name without a host_name"))
+}
+
+fn parse_alpn(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(data);
+    let mut list = Reader::new(reader.read_vec16()?);
+    reader.expect_end()?;
+    let mut protocols: Vec<Vec<u8>> = vec![];
+    while !list.is_empty() {
+        let protocol = list.read_vec8()?;
+        if protocol.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty protocol name in application_layer_protocol_negotiation"));
+        }
+        protocols.push(protocol.to_vec());
+    }
+    if protocols.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Empty application_layer_protocol_negotiation"));
+    }
+    Ok(protocols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_round_trip_extensions() {
+        let client_hello = vec![
+            Extension::ServerName(Some("example.com".to_string())),
+            Extension::Alpn(vec![b"h2".to_vec(), b"http/1.1".to_vec()]),
+            Extension::SignatureAlgorithms(vec![0x0804, 0x0401]),
+            Extension::SupportedGroups(vec![29, 23]),
+            Extension::EcPointFormats(vec![0]),
+            Extension::RenegotiationInfo(vec![]),
+            Extension::EncryptThenMac,
+            Extension::ExtendedMasterSecret,
+            Extension::SupportedVersions(vec![ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2]),
+            Extension::KeyShares(vec![KeyShareEntry { group: 29, key_exchange: vec![9; 32] }]),
+            Extension::SessionTicket(vec![7; 40]),
+            Extension::PskKeyExchangeModes(vec![handshake::PSK_DHE_KE]),
+            Extension::Unknown(RawExtension { extension_type: 0xfe0d, data: vec![1, 2, 3] }),
+            Extension::PreSharedKey(OfferedPsks {
+                identities: vec![handshake::PskIdentity { identity: vec![5; 64], obfuscated_ticket_age: 0x01020304 }],
+                binders: vec![vec![6; 32]],
+            }),
+        ];
+        let raw = encode_extensions(&client_hello);
+        assert_eq!(client_hello, parse_extensions(&raw, HelloContext::ClientHello).unwrap());
+        assert_eq!(b"\x00\x09\x00\x00\x06bar.cz".to_vec(), Extension::ServerName(Some("bar.cz".to_string())).encode().data);
+
+        let server_hello = vec![
+            Extension::ServerName(None),
+            Extension::Alpn(vec![b"h2".to_vec()]),
+            Extension::EncryptThenMac,
+            Extension::ExtendedMasterSecret,
+            Extension::SelectedVersion(ProtocolVersion::TLS1_3),
+            Extension::SessionTicket(vec![]),
+            Extension::SelectedPsk(0),
+        ];
+        assert_eq!(server_hello, parse_extensions(&encode_extensions(&server_hello), HelloContext::ServerHello).unwrap());
+        // signature_algorithms has no place in ServerHello and is left for the caller to refuse
+        let raw = Extension::SignatureAlgorithms(vec![0x0804]).encode();
+        assert_eq!(Extension::Unknown(raw.clone()), Extension::parse(&raw, HelloContext::ServerHello).unwrap());
+        let certificate_request = vec![
+            Extension::SignatureAlgorithms(vec![0x0804]),
+            Extension::CertificateAuthorities(vec![vec![0x30, 0x00]]),
+        ];
+        assert_eq!(certificate_request, parse_extensions(&encode_extensions(&certificate_request), HelloContext::CertificateRequest).unwrap());
+        let two = Extension::Alpn(vec![b"h2".to_vec(), b"http/1.1".to_vec()]).encode();
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&Extension::parse(&two, HelloContext::EncryptedExtensions).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_choose_server_name_indication() {
+        assert_eq!(Some("www.example.com".to_string()), server_name_indication("WWW.example.com."));
+        assert_eq!(None, server_name_indication("127.0.0.1"));
+        assert_eq!(None, server_name_indication("[::1]"));
+
+        // a U-label is sent as its A-label, which the server side accepts
+        let name = server_name_indication("B\u{fc}cher.example").unwrap();
+        assert_eq!("xn--bcher-kva.example", name);
+        let raw = Extension::ServerName(Some(name.clone())).encode();
+        assert_eq!(Extension::ServerName(Some(name)), Extension::parse(&raw, HelloContext::ClientHello).unwrap());
+    }
+}
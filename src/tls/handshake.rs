@@ -0,0 +1,1009 @@
+use anyhow::Result;
+
+use crate::digest::HashAlgorithm;
+
+use super::alert::{
+    self,
+    DECODE_ERROR,
+    ILLEGAL_PARAMETER,
+};
+use super::codec::{
+    Reader,
+    put_u8,
+    put_u16,
+    put_u32,
+    put_vec8,
+    put_vec16,
+    put_vec24,
+};
+use super::record::{
+    HandshakeMessage,
+    ProtocolVersion,
+};
+
+// HandshakeType (RFC 5246 section 7.4, RFC 8446 section 4)
+pub const HELLO_REQUEST: u8 = 0;
+pub const CLIENT_HELLO: u8 = 1;
+pub const SERVER_HELLO: u8 = 2;
+pub const NEW_SESSION_TICKET: u8 = 4;
+pub const ENCRYPTED_EXTENSIONS: u8 = 8;
+pub const CERTIFICATE: u8 = 11;
+pub const SERVER_KEY_EXCHANGE: u8 = 12;
+pub const CERTIFICATE_REQUEST: u8 = 13;
+pub const SERVER_HELLO_DONE: u8 = 14;
+pub const CERTIFICATE_VERIFY: u8 = 15;
+pub const CLIENT_KEY_EXCHANGE: u8 = 16;
+pub const FINISHED: u8 = 20;
+pub const KEY_UPDATE: u8 = 24;
+/// Stands in for the first ClientHello in the transcript after a HelloRetryRequest
+pub const MESSAGE_HASH: u8 = 254;
+
+pub const RANDOM_LEN: usize = 32;
+pub const MAX_SESSION_ID_LEN: usize = 32;
+pub const PRE_MASTER_SECRET_LEN: usize = 48;
+
+/// ServerHello.random of a HelloRetryRequest, SHA-256("HelloRetryRequest") (RFC 8446 section 4.1.3)
+pub const HELLO_RETRY_REQUEST_RANDOM: [u8; RANDOM_LEN] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+/// Last 8 bytes of ServerHello.random from a TLS 1.3 server negotiating TLS 1.2
+pub const DOWNGRADE_TLS12: [u8; 8] = *b"DOWNGRD\x01";
+/// Last 8 bytes of ServerHello.random from a TLS 1.2 or 1.3 server negotiating TLS 1.1 or below
+pub const DOWNGRADE_TLS11: [u8; 8] = *b"DOWNGRD\x00";
+
+pub fn handshake_type_name(msg_type: u8) -> &'static str {
+    match msg_type {
+        HELLO_REQUEST => "HelloRequest",
+        CLIENT_HELLO => "ClientHello",
+        SERVER_HELLO => "ServerHello",
+        NEW_SESSION_TICKET => "NewSessionTicket",
+        ENCRYPTED_EXTENSIONS => "EncryptedExtensions",
+        CERTIFICATE => "Certificate",
+        SERVER_KEY_EXCHANGE => "ServerKeyExchange",
+        CERTIFICATE_REQUEST => "CertificateRequest",
+        SERVER_HELLO_DONE => "ServerHelloDone",
+        CERTIFICATE_VERIFY => "CertificateVerify",
+        CLIENT_KEY_EXCHANGE => "ClientKeyExchange",
+        FINISHED => "Finished",
+        KEY_UPDATE => "KeyUpdate",
+        MESSAGE_HASH => "MessageHash",
+        _ => "Unknown",
+    }
+}
+
+pub const EXTENSION_SERVER_NAME: u16 = 0;
+pub const EXTENSION_SUPPORTED_GROUPS: u16 = 10;
+pub const EXTENSION_EC_POINT_FORMATS: u16 = 11;
+pub const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 13;
+pub const EXTENSION_ALPN: u16 = 16;
+pub const EXTENSION_ENCRYPT_THEN_MAC: u16 = 22;
+pub const EXTENSION_EXTENDED_MASTER_SECRET: u16 = 23;
+pub const EXTENSION_SESSION_TICKET: u16 = 35;
+pub const EXTENSION_PRE_SHARED_KEY: u16 = 41;
+pub const EXTENSION_SUPPORTED_VERSIONS: u16 = 43;
+pub const EXTENSION_COOKIE: u16 = 44;
+pub const EXTENSION_PSK_KEY_EXCHANGE_MODES: u16 = 45;
+pub const EXTENSION_CERTIFICATE_AUTHORITIES: u16 = 47;
+pub const EXTENSION_KEY_SHARE: u16 = 51;
+pub const EXTENSION_RENEGOTIATION_INFO: u16 = 0xff01;
+
+/// Extension in wire form, (extension_type, extension_data)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawExtension {
+    pub extension_type: u16,
+    pub data: Vec<u8>,
+}
+
+fn encode_extensions(out: &mut Vec<u8>, extensions: &[RawExtension]) {
+    if extensions.is_empty() {
+        return;
+    }
+    let mut body: Vec<u8> = vec![];
+    for ext in extensions {
+        put_u16(&mut body, ext.extension_type);
+        put_vec16(&mut body, &ext.data);
+    }
+    put_vec16(out, &body);
+}
+
+/// The extensions block is optional at the end of both hellos
+fn parse_extensions(reader: &mut Reader) -> Result<Vec<RawExtension>> {
+    let mut extensions: Vec<RawExtension> = vec![];
+    if reader.is_empty() {
+        return Ok(extensions);
+    }
+    let mut ext_reader = Reader::new(reader.read_vec16()?);
+    while !ext_reader.is_empty() {
+        let extension_type = ext_reader.read_u16()?;
+        if extensions.iter().any(|e| e.extension_type == extension_type) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Duplicate extension {}", extension_type)));
+        }
+        extensions.push(RawExtension {
+            extension_type,
+            data: ext_reader.read_vec16()?.to_vec(),
+        });
+    }
+    Ok(extensions)
+}
+
+/// Returns the data of the extension of `extension_type`, if present
+pub fn find_extension(extensions: &[RawExtension], extension_type: u16) -> Option<&[u8]> {
+    extensions.iter().find(|e| e.extension_type == extension_type).map(|e| e.data.as_slice())
+}
+
+/// The only ECPointFormat we send or accept (RFC 8422 section 5.1.2)
+pub const EC_POINT_FORMAT_UNCOMPRESSED: u8 = 0;
+
+/// An extension whose data is a vector<2..2^16-2> of u16 code points,
+/// such as signature_algorithms and supported_groups
+pub fn u16_list_extension(extension_type: u16, values: &[u16]) -> RawExtension {
+    let mut list: Vec<u8> = vec![];
+    for value in values {
+        put_u16(&mut list, *value);
+    }
+    let mut data: Vec<u8> = vec![];
+    put_vec16(&mut data, &list);
+    RawExtension {
+        extension_type,
+        data,
+    }
+}
+
+pub fn parse_u16_list(data: &[u8]) -> Result<Vec<u16>> {
+    let mut reader = Reader::new(data);
+    let list = reader.read_vec16()?;
+    reader.expect_end()?;
+    if list.is_empty() || list.len() % 2 != 0 {
+        return Err(alert::fatal(DECODE_ERROR, "Malformed list in extension"));
+    }
+    Ok(list.chunks(2).map(|v| u16::from_be_bytes([v[0], v[1]])).collect())
+}
+
+/// ec_point_formats offering only the uncompressed form
+pub fn ec_point_formats_extension() -> RawExtension {
+    let mut data: Vec<u8> = vec![];
+    put_vec8(&mut data, &[EC_POINT_FORMAT_UNCOMPRESSED]);
+    RawExtension {
+        extension_type: EXTENSION_EC_POINT_FORMATS,
+        data,
+    }
+}
+
+/// Checks that a peer's ec_point_formats lists the uncompressed form
+pub fn check_ec_point_formats(data: &[u8]) -> Result<()> {
+    let mut reader = Reader::new(data);
+    let formats = reader.read_vec8()?;
+    reader.expect_end()?;
+    if !formats.contains(&EC_POINT_FORMAT_UNCOMPRESSED) {
+        return Err(alert::fatal(ILLEGAL_PARAMETER, "Peer does not support uncompressed points"));
+    }
+    Ok(())
+}
+
+/// supported_versions of ClientHello: the versions offered, most preferred first
+pub fn supported_versions_client_hello(versions: &[ProtocolVersion]) -> RawExtension {
+    let mut list: Vec<u8> = vec![];
+    for version in versions {
+        put_u16(&mut list, version.as_u16());
+    }
+    let mut data: Vec<u8> = vec![];
+    put_vec8(&mut data, &list);
+    RawExtension {
+        extension_type: EXTENSION_SUPPORTED_VERSIONS,
+        data,
+    }
+}
+
+pub fn parse_supported_versions_client_hello(data: &[u8]) -> Result<Vec<ProtocolVersion>> {
+    let mut reader = Reader::new(data);
+    let list = reader.read_vec8()?;
+    reader.expect_end()?;
+    if list.is_empty() || list.len() % 2 != 0 {
+        return Err(alert::fatal(DECODE_ERROR, "Malformed supported_versions"));
+    }
+    Ok(list.chunks(2).map(|v| ProtocolVersion::from_u16(u16::from_be_bytes([v[0], v[1]]))).collect())
+}
+
+/// supported_versions of ServerHello and HelloRetryRequest: the selected version
+pub fn supported_versions_server_hello(version: ProtocolVersion) -> RawExtension {
+    RawExtension {
+        extension_type: EXTENSION_SUPPORTED_VERSIONS,
+        data: version.as_u16().to_be_bytes().to_vec(),
+    }
+}
+
+pub fn parse_supported_versions_server_hello(data: &[u8]) -> Result<ProtocolVersion> {
+    let mut reader = Reader::new(data);
+    let version = ProtocolVersion::from_u16(reader.read_u16()?);
+    reader.expect_end()?;
+    Ok(version)
+}
+
+/// KeyShareEntry (RFC 8446 section 4.2.8)
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyShareEntry {
+    pub group: u16,
+    pub key_exchange: Vec<u8>,
+}
+
+impl KeyShareEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_u16(out, self.group);
+        put_vec16(out, &self.key_exchange);
+    }
+
+    fn parse(reader: &mut Reader) -> Result<Self> {
+        let group = reader.read_u16()?;
+        let key_exchange = reader.read_vec16()?;
+        if key_exchange.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty key share"));
+        }
+        Ok(Self {
+            group,
+            key_exchange: key_exchange.to_vec(),
+        })
+    }
+}
+
+/// key_share of ClientHello: one share per group, in the order of supported_groups
+pub fn key_share_client_hello(entries: &[KeyShareEntry]) -> RawExtension {
+    let mut list: Vec<u8> = vec![];
+    for entry in entries {
+        entry.encode(&mut list);
+    }
+    let mut data: Vec<u8> = vec![];
+    put_vec16(&mut data, &list);
+    RawExtension {
+        extension_type: EXTENSION_KEY_SHARE,
+        data,
+    }
+}
+
+pub fn parse_key_share_client_hello(data: &[u8]) -> Result<Vec<KeyShareEntry>> {
+    let mut reader = Reader::new(data);
+    let mut list = Reader::new(reader.read_vec16()?);
+    reader.expect_end()?;
+    let mut entries: Vec<KeyShareEntry> = vec![];
+    while !list.is_empty() {
+        let entry = KeyShareEntry::parse(&mut list)?;
+        if entries.iter().any(|e| e.group == entry.group) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Duplicate key share for group {}", entry.group)));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// key_share of ServerHello: the server's share in the selected group
+pub fn key_share_server_hello(entry: &KeyShareEntry) -> RawExtension {
+    let mut data: Vec<u8> = vec![];
+    entry.encode(&mut data);
+    RawExtension {
+        extension_type: EXTENSION_KEY_SHARE,
+        data,
+    }
+}
+
+pub fn parse_key_share_server_hello(data: &[u8]) -> Result<KeyShareEntry> {
+    let mut reader = Reader::new(data);
+    let entry = KeyShareEntry::parse(&mut reader)?;
+    reader.expect_end()?;
+    Ok(entry)
+}
+
+/// key_share of HelloRetryRequest: only the group the client should send a share for
+pub fn key_share_hello_retry_request(group: u16) -> RawExtension {
+    RawExtension {
+        extension_type: EXTENSION_KEY_SHARE,
+        data: group.to_be_bytes().to_vec(),
+    }
+}
+
+pub fn parse_key_share_hello_retry_request(data: &[u8]) -> Result<u16> {
+    let mut reader = Reader::new(data);
+    let group = reader.read_u16()?;
+    reader.expect_end()?;
+    Ok(group)
+}
+
+/// cookie of HelloRetryRequest, echoed in the second ClientHello
+pub fn cookie_extension(cookie: &[u8]) -> RawExtension {
+    let mut data: Vec<u8> = vec![];
+    put_vec16(&mut data, cookie);
+    RawExtension {
+        extension_type: EXTENSION_COOKIE,
+        data,
+    }
+}
+
+pub fn parse_cookie(data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(data);
+    let cookie = reader.read_vec16()?;
+    reader.expect_end()?;
+    if cookie.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Empty cookie"));
+    }
+    Ok(cookie.to_vec())
+}
+
+/// PskKeyExchangeMode where the PSK is combined with an (EC)DHE key share (RFC 8446 section 4.2.9)
+pub const PSK_DHE_KE: u8 = 1;
+
+/// PskIdentity of pre_shared_key: a ticket and its obfuscated age in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct PskIdentity {
+    pub identity: Vec<u8>,
+    pub obfuscated_ticket_age: u32,
+}
+
+/// pre_shared_key of ClientHello (RFC 8446 section 4.2.11), always the last extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferedPsks {
+    pub identities: Vec<PskIdentity>,
+    /// one binder per identity, each an HMAC over the ClientHello up to the binders
+    pub binders: Vec<Vec<u8>>,
+}
+
+impl OfferedPsks {
+    /// Length of the binders list as encoded, which ends the ClientHello
+    pub fn binders_len(&self) -> usize {
+        2 + self.binders.iter().map(|b| 1 + b.len()).sum::<usize>()
+    }
+}
+
+pub fn pre_shared_key_client_hello(psks: &OfferedPsks) -> RawExtension {
+    let mut identities: Vec<u8> = vec![];
+    for identity in &psks.identities {
+        put_vec16(&mut identities, &identity.identity);
+        put_u32(&mut identities, identity.obfuscated_ticket_age);
+    }
+    let mut binders: Vec<u8> = vec![];
+    for binder in &psks.binders {
+        put_vec8(&mut binders, binder);
+    }
+    let mut data: Vec<u8> = vec![];
+    put_vec16(&mut data, &identities);
+    put_vec16(&mut data, &binders);
+    RawExtension {
+        extension_type: EXTENSION_PRE_SHARED_KEY,
+        data,
+    }
+}
+
+pub fn parse_pre_shared_key_client_hello(data: &[u8]) -> Result<OfferedPsks> {
+    let mut reader = Reader::new(data);
+    let mut identity_reader = Reader::new(reader.read_vec16()?);
+    let mut binder_reader = Reader::new(reader.read_vec16()?);
+    reader.expect_end()?;
+    let mut identities: Vec<PskIdentity> = vec![];
+    while !identity_reader.is_empty() {
+        let identity = identity_reader.read_vec16()?.to_vec();
+        let obfuscated_ticket_age = identity_reader.read_u32()?;
+        identities.push(PskIdentity {
+            identity,
+            obfuscated_ticket_age,
+        });
+    }
+    let mut binders: Vec<Vec<u8>> = vec![];
+    while !binder_reader.is_empty() {
+        binders.push(binder_reader.read_vec8()?.to_vec());
+    }
+    if identities.is_empty() || identities.len() != binders.len() {
+        return Err(alert::fatal(ILLEGAL_PARAMETER, "pre_shared_key needs one binder for each identity"));
+    }
+    Ok(OfferedPsks {
+        identities,
+        binders,
+    })
+}
+
+/// pre_shared_key of ServerHello: the index of the identity the server accepted
+pub fn pre_shared_key_server_hello(selected_identity: u16) -> RawExtension {
+    RawExtension {
+        extension_type: EXTENSION_PRE_SHARED_KEY,
+        data: selected_identity.to_be_bytes().to_vec(),
+    }
+}
+
+pub fn parse_pre_shared_key_server_hello(data: &[u8]) -> Result<u16> {
+    let mut reader = Reader::new(data);
+    let selected_identity = reader.read_u16()?;
+    reader.expect_end()?;
+    Ok(selected_identity)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientHello {
+    pub version: ProtocolVersion,
+    pub random: [u8; RANDOM_LEN],
+    pub session_id: Vec<u8>,
+    pub cipher_suites: Vec<u16>,
+    pub compression_methods: Vec<u8>,
+    pub extensions: Vec<RawExtension>,
+}
+
+impl ClientHello {
+    pub fn encode(&self) -> HandshakeMessage {
+        let mut body: Vec<u8> = vec![];
+        put_u16(&mut body, self.version.as_u16());
+        body.extend_from_slice(&self.random);
+        put_vec8(&mut body, &self.session_id);
+        let suites: Vec<u8> = self.cipher_suites.iter().flat_map(|s| s.to_be_bytes().to_vec()).collect();
+        put_vec16(&mut body, &suites);
+        put_vec8(&mut body, &self.compression_methods);
+        encode_extensions(&mut body, &self.extensions);
+        HandshakeMessage::new(CLIENT_HELLO, body)
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let version = ProtocolVersion::from_u16(reader.read_u16()?);
+        let random = reader.read_array::<RANDOM_LEN>()?;
+        let session_id = reader.read_vec8()?.to_vec();
+        if session_id.len() > MAX_SESSION_ID_LEN {
+            return Err(alert::fatal(DECODE_ERROR, "Session ID is too long"));
+        }
+        let suites = reader.read_vec16()?;
+        if suites.is_empty() || suites.len() % 2 != 0 {
+            return Err(alert::fatal(DECODE_ERROR, "Malformed cipher suite list"));
+        }
+        let cipher_suites = suites.chunks(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect();
+        let compression_methods = reader.read_vec8()?.to_vec();
+        if compression_methods.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty compression method list"));
+        }
+        let extensions = parse_extensions(&mut reader)?;
+        reader.expect_end()?;
+        Ok(Self {
+            version,
+            random,
+            session_id,
+            cipher_suites,
+            compression_methods,
+            extensions,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerHello {
+    pub version: ProtocolVersion,
+    pub random: [u8; RANDOM_LEN],
+    pub session_id: Vec<u8>,
+    pub cipher_suite: u16,
+    pub compression_method: u8,
+    pub extensions: Vec<RawExtension>,
+}
+
+impl ServerHello {
+    pub fn encode(&self) -> HandshakeMessage {
+        let mut body: Vec<u8> = vec![];
+        put_u16(&mut body, self.version.as_u16());
+        body.extend_from_slice(&self.random);
+        put_vec8(&mut body, &self.session_id);
+        put_u16(&mut body, self.cipher_suite);
+        put_u8(&mut body, self.compression_method);
+        encode_extensions(&mut body, &self.extensions);
+        HandshakeMessage::new(SERVER_HELLO, body)
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let version = ProtocolVersion::from_u16(reader.read_u16()?);
+        let random = reader.read_array::<RANDOM_LEN>()?;
+        let session_id = reader.read_vec8()?.to_vec();
+        if session_id.len() > MAX_SESSION_ID_LEN {
+            return Err(alert::fatal(DECODE_ERROR, "Session ID is too long"));
+        }
+        let cipher_suite = reader.read_u16()?;
+        let compression_method = reader.read_u8()?;
+        let extensions = parse_extensions(&mut reader)?;
+        reader.expect_end()?;
+        Ok(Self {
+            version,
+            random,
+            session_id,
+            cipher_suite,
+            compression_method,
+            extensions,
+        })
+    }
+}
+
+/// Certificate message: DER certificates, sender's certificate first
+pub fn encode_certificate(chain: &[Vec<u8>]) -> HandshakeMessage {
+    let mut list: Vec<u8> = vec![];
+    for cert in chain {
+        put_vec24(&mut list, cert);
+    }
+    let mut body: Vec<u8> = vec![];
+    put_vec24(&mut body, &list);
+    HandshakeMessage::new(CERTIFICATE, body)
+}
+
+pub fn parse_certificate(body: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(body);
+    let mut list = Reader::new(reader.read_vec24()?);
+    reader.expect_end()?;
+    let mut chain: Vec<Vec<u8>> = vec![];
+    while !list.is_empty() {
+        let cert = list.read_vec24()?;
+        if cert.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty certificate in Certificate message"));
+        }
+        chain.push(cert.to_vec());
+    }
+    Ok(chain)
+}
+
+/// EncryptedExtensions: unlike the hellos, the extension block is always present
+pub fn encode_encrypted_extensions(extensions: &[RawExtension]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    encode_extensions(&mut body, extensions);
+    if body.is_empty() {
+        put_vec16(&mut body, &[]);
+    }
+    HandshakeMessage::new(ENCRYPTED_EXTENSIONS, body)
+}
+
+pub fn parse_encrypted_extensions(body: &[u8]) -> Result<Vec<RawExtension>> {
+    let mut reader = Reader::new(body);
+    if reader.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Malformed EncryptedExtensions"));
+    }
+    let extensions = parse_extensions(&mut reader)?;
+    reader.expect_end()?;
+    Ok(extensions)
+}
+
+/// TLS 1.3 Certificate message: a request context and entries that carry
+/// extensions after each certificate (RFC 8446 section 4.4.2). We send none.
+pub fn encode_certificate_tls13(context: &[u8], chain: &[Vec<u8>]) -> HandshakeMessage {
+    let mut list: Vec<u8> = vec![];
+    for cert in chain {
+        put_vec24(&mut list, cert);
+        put_vec16(&mut list, &[]);
+    }
+    let mut body: Vec<u8> = vec![];
+    put_vec8(&mut body, context);
+    put_vec24(&mut body, &list);
+    HandshakeMessage::new(CERTIFICATE, body)
+}
+
+/// Returns the request context and the chain. Entry extensions are skipped.
+pub fn parse_certificate_tls13(body: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>)> {
+    let mut reader = Reader::new(body);
+    let context = reader.read_vec8()?.to_vec();
+    let mut list = Reader::new(reader.read_vec24()?);
+    reader.expect_end()?;
+    let mut chain: Vec<Vec<u8>> = vec![];
+    while !list.is_empty() {
+        let cert = list.read_vec24()?;
+        if cert.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty certificate in Certificate message"));
+        }
+        list.read_vec16()?;
+        chain.push(cert.to_vec());
+    }
+    Ok((context, chain))
+}
+
+// ClientCertificateType of a TLS 1.2 CertificateRequest (RFC 5246 section 7.4.4, RFC 8422 section 5.5)
+pub const CERTIFICATE_TYPE_RSA_SIGN: u8 = 1;
+pub const CERTIFICATE_TYPE_ECDSA_SIGN: u8 = 64;
+
+/// TLS 1.2 CertificateRequest (RFC 5246 section 7.4.4). Earlier versions send no
+/// signature schemes (RFC 4346 section 7.4.4), which leaves `signature_schemes` empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateRequest {
+    pub certificate_types: Vec<u8>,
+    pub signature_schemes: Vec<u16>,
+    /// DER distinguished names of the CAs the server accepts. Empty accepts any.
+    pub certificate_authorities: Vec<Vec<u8>>,
+}
+
+impl CertificateRequest {
+    pub fn encode(&self, version: ProtocolVersion) -> HandshakeMessage {
+        let mut body: Vec<u8> = vec![];
+        put_vec8(&mut body, &self.certificate_types);
+        if version >= ProtocolVersion::TLS1_2 {
+            let mut schemes: Vec<u8> = vec![];
+            for scheme in &self.signature_schemes {
+                put_u16(&mut schemes, *scheme);
+            }
+            put_vec16(&mut body, &schemes);
+        }
+        put_vec16(&mut body, &encode_distinguished_names(&self.certificate_authorities));
+        HandshakeMessage::new(CERTIFICATE_REQUEST, body)
+    }
+
+    pub fn parse(body: &[u8], version: ProtocolVersion) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let certificate_types = reader.read_vec8()?.to_vec();
+        let legacy = version < ProtocolVersion::TLS1_2;
+        let schemes = if legacy { &[][..] } else { reader.read_vec16()? };
+        let certificate_authorities = parse_distinguished_names(reader.read_vec16()?)?;
+        reader.expect_end()?;
+        if certificate_types.is_empty() || (schemes.is_empty() && !legacy) || schemes.len() % 2 != 0 {
+            return Err(alert::fatal(DECODE_ERROR, "Malformed CertificateRequest"));
+        }
+        let signature_schemes = schemes.chunks(2).map(|v| u16::from_be_bytes([v[0], v[1]])).collect();
+        Ok(Self {
+            certificate_types,
+            signature_schemes,
+            certificate_authorities,
+        })
+    }
+}
+
+/// TLS 1.3 CertificateRequest: a context echoed in the client's Certificate and
+/// extensions, of which signature_algorithms is required (RFC 8446 section 4.3.2)
+pub fn encode_certificate_request_tls13(context: &[u8], extensions: &[RawExtension]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    put_vec8(&mut body, context);
+    encode_extensions(&mut body, extensions);
+    HandshakeMessage::new(CERTIFICATE_REQUEST, body)
+}
+
+pub fn parse_certificate_request_tls13(body: &[u8]) -> Result<(Vec<u8>, Vec<RawExtension>)> {
+    let mut reader = Reader::new(body);
+    let context = reader.read_vec8()?.to_vec();
+    if reader.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Malformed CertificateRequest"));
+    }
+    let extensions = parse_extensions(&mut reader)?;
+    reader.expect_end()?;
+    Ok((context, extensions))
+}
+
+/// DistinguishedName list of CertificateRequest and certificate_authorities, without its length
+pub fn encode_distinguished_names(names: &[Vec<u8>]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    for name in names {
+        put_vec16(&mut out, name);
+    }
+    out
+}
+
+pub fn parse_distinguished_names(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(data);
+    let mut names: Vec<Vec<u8>> = vec![];
+    while !reader.is_empty() {
+        let name = reader.read_vec16()?;
+        if name.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty distinguished name"));
+        }
+        names.push(name.to_vec());
+    }
+    Ok(names)
+}
+
+pub fn encode_certificate_verify(signature_scheme: u16, signature: &[u8]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    put_u16(&mut body, signature_scheme);
+    put_vec16(&mut body, signature);
+    HandshakeMessage::new(CERTIFICATE_VERIFY, body)
+}
+
+pub fn parse_certificate_verify(body: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let mut reader = Reader::new(body);
+    let signature_scheme = reader.read_u16()?;
+    let signature = reader.read_vec16()?.to_vec();
+    reader.expect_end()?;
+    Ok((signature_scheme, signature))
+}
+
+/// CertificateVerify before TLS 1.2, a signature without a scheme (RFC 4346 section 7.4.8)
+#[cfg(feature = "legacy-protocols")]
+pub fn encode_legacy_certificate_verify(signature: &[u8]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    put_vec16(&mut body, signature);
+    HandshakeMessage::new(CERTIFICATE_VERIFY, body)
+}
+
+#[cfg(feature = "legacy-protocols")]
+pub fn parse_legacy_certificate_verify(body: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(body);
+    let signature = reader.read_vec16()?.to_vec();
+    reader.expect_end()?;
+    Ok(signature)
+}
+
+/// What CertificateVerify signs: 64 spaces, a context string naming the sender,
+/// a zero byte and the transcript hash (RFC 8446 section 4.4.3)
+pub fn certificate_verify_content(server: bool, transcript_hash: &[u8]) -> Vec<u8> {
+    let mut content = vec![0x20u8; 64];
+    let context: &[u8] = if server { b"TLS 1.3, server CertificateVerify" } else { b"TLS 1.3, client CertificateVerify" };
+    content.extend_from_slice(context);
+    content.push(0);
+    content.extend_from_slice(transcript_hash);
+    content
+}
+
+/// ClientKeyExchange for RSA key exchange: the encrypted premaster secret, which
+/// SSL 3.0 sends without a length (RFC 6101 section 5.6.7.1)
+pub fn encode_rsa_client_key_exchange(encrypted_pre_master_secret: &[u8], version: ProtocolVersion) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    if version == ProtocolVersion::SSL3_0 {
+        body.extend_from_slice(encrypted_pre_master_secret);
+    } else {
+        put_vec16(&mut body, encrypted_pre_master_secret);
+    }
+    HandshakeMessage::new(CLIENT_KEY_EXCHANGE, body)
+}
+
+pub fn parse_rsa_client_key_exchange(body: &[u8], version: ProtocolVersion) -> Result<Vec<u8>> {
+    if version == ProtocolVersion::SSL3_0 {
+        return Ok(body.to_vec());
+    }
+    let mut reader = Reader::new(body);
+    let encrypted = reader.read_vec16()?.to_vec();
+    reader.expect_end()?;
+    Ok(encrypted)
+}
+
+/// Running record of handshake messages, hashed for Finished
+/// Messages are kept rather than hashed because the hash is not known before ServerHello.
+/// NewSessionTicket of TLS 1.2 (RFC 5077 section 3.3). A lifetime hint of 0 leaves it unspecified.
+pub fn encode_new_session_ticket(lifetime_hint: u32, ticket: &[u8]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    put_u32(&mut body, lifetime_hint);
+    put_vec16(&mut body, ticket);
+    HandshakeMessage::new(NEW_SESSION_TICKET, body)
+}
+
+pub fn parse_new_session_ticket(body: &[u8]) -> Result<(u32, Vec<u8>)> {
+    let mut reader = Reader::new(body);
+    let lifetime_hint = reader.read_u32()?;
+    let ticket = reader.read_vec16()?.to_vec();
+    reader.expect_end()?;
+    Ok((lifetime_hint, ticket))
+}
+
+/// NewSessionTicket of TLS 1.3 (RFC 8446 section 4.6.1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewSessionTicketTls13 {
+    /// seconds the ticket may be used for, at most a week
+    pub lifetime: u32,
+    /// added to the ticket age the client reports, so that it cannot be linked
+    pub age_add: u32,
+    /// makes the PSK of each ticket of a connection different
+    pub nonce: Vec<u8>,
+    pub ticket: Vec<u8>,
+    pub extensions: Vec<RawExtension>,
+}
+
+impl NewSessionTicketTls13 {
+    pub fn encode(&self) -> HandshakeMessage {
+        let mut body: Vec<u8> = vec![];
+        put_u32(&mut body, self.lifetime);
+        put_u32(&mut body, self.age_add);
+        put_vec8(&mut body, &self.nonce);
+        put_vec16(&mut body, &self.ticket);
+        let extensions_start = body.len();
+        encode_extensions(&mut body, &self.extensions);
+        if body.len() == extensions_start {
+            put_vec16(&mut body, &[]);
+        }
+        HandshakeMessage::new(NEW_SESSION_TICKET, body)
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let lifetime = reader.read_u32()?;
+        let age_add = reader.read_u32()?;
+        let nonce = reader.read_vec8()?.to_vec();
+        let ticket = reader.read_vec16()?.to_vec();
+        if ticket.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "Empty ticket in NewSessionTicket"));
+        }
+        if reader.is_empty() {
+            return Err(alert::fatal(DECODE_ERROR, "NewSessionTicket without extensions"));
+        }
+        let extensions = parse_extensions(&mut reader)?;
+        reader.expect_end()?;
+        Ok(Self {
+            lifetime,
+            age_add,
+            nonce,
+            ticket,
+            extensions,
+        })
+    }
+}
+
+/// KeyUpdate (RFC 8446 section 4.6.3). `update_requested` asks the peer to update its
+/// sending keys as well.
+pub fn encode_key_update(update_requested: bool) -> HandshakeMessage {
+    HandshakeMessage::new(KEY_UPDATE, vec![update_requested as u8])
+}
+
+pub fn parse_key_update(body: &[u8]) -> Result<bool> {
+    match body {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        [_] => Err(alert::fatal(ILLEGAL_PARAMETER, format!("Unknown KeyUpdateRequest {}", body[0]))),
+        _ => Err(alert::fatal(DECODE_ERROR, "Malformed KeyUpdate")),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    messages: Vec<u8>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self {
+            messages: vec![],
+        }
+    }
+
+    pub fn add(&mut self, message: &HandshakeMessage) {
+        self.messages.extend(message.encode());
+    }
+
+    pub fn hash(&self, hash: HashAlgorithm) -> Vec<u8> {
+        hash.digest(&self.messages)
+    }
+
+    /// The messages themselves, which a TLS 1.2 CertificateVerify signs
+    pub fn messages(&self) -> &[u8] {
+        &self.messages
+    }
+
+    /// The hash without the last `truncate` bytes, which leaves out the binders
+    /// that end a ClientHello with pre_shared_key (RFC 8446 section 4.2.11.2)
+    pub fn hash_truncated(&self, hash: HashAlgorithm, truncate: usize) -> Vec<u8> {
+        hash.digest(&self.messages[..self.messages.len() - truncate])
+    }
+
+    /// Replaces the messages so far, i.e. the first ClientHello, with a message_hash
+    /// message holding their hash, as TLS 1.3 does after a HelloRetryRequest
+    pub fn replace_with_message_hash(&mut self, hash: HashAlgorithm) {
+        let message = HandshakeMessage::new(MESSAGE_HASH, self.hash(hash));
+        self.messages = message.encode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_round_trip_hellos() {
+        let client_hello = ClientHello {
+            version: ProtocolVersion::TLS1_2,
+            random: [7u8; RANDOM_LEN],
+            session_id: vec![],
+            cipher_suites: vec![0x002f, 0x00ff],
+            compression_methods: vec![0],
+            extensions: vec![RawExtension { extension_type: 0xff01, data: vec![0] }],
+        };
+        let message = client_hello.encode();
+        assert_eq!(CLIENT_HELLO, message.msg_type);
+        assert_eq!(client_hello, ClientHello::parse(&message.body).unwrap());
+
+        let server_hello = ServerHello {
+            version: ProtocolVersion::TLS1_2,
+            random: [9u8; RANDOM_LEN],
+            session_id: vec![1; 32],
+            cipher_suite: 0x002f,
+            compression_method: 0,
+            extensions: vec![],
+        };
+        assert_eq!(server_hello, ServerHello::parse(&server_hello.encode().body).unwrap());
+    }
+
+    #[test]
+    fn test_can_return_error_malformed_hello() {
+        let mut body = ServerHello {
+            version: ProtocolVersion::TLS1_2,
+            random: [9u8; RANDOM_LEN],
+            session_id: vec![],
+            cipher_suite: 0x002f,
+            compression_method: 0,
+            extensions: vec![],
+        }.encode().body;
+        body.push(0);
+        assert_eq!(DECODE_ERROR, alert::description_of(&ServerHello::parse(&body).unwrap_err()));
+        // the same extension twice
+        body.pop();
+        body.extend_from_slice(&[0, 8, 0xff, 0x01, 0, 0, 0xff, 0x01, 0, 0]);
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&ServerHello::parse(&body).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_round_trip_u16_list_extension() {
+        let ext = u16_list_extension(EXTENSION_SUPPORTED_GROUPS, &[29, 23]);
+        assert_eq!(vec![0, 4, 0, 29, 0, 23], ext.data);
+        assert_eq!(vec![29, 23], parse_u16_list(&ext.data).unwrap());
+        assert!(parse_u16_list(&[0, 3, 0, 29, 0]).is_err());
+        assert!(parse_u16_list(&[0, 0]).is_err());
+        check_ec_point_formats(&ec_point_formats_extension().data).unwrap();
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&check_ec_point_formats(&[1, 1]).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_round_trip_tls13_extensions() {
+        let versions = [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2];
+        let ext = supported_versions_client_hello(&versions);
+        assert_eq!(vec![4, 3, 4, 3, 3], ext.data);
+        assert_eq!(versions.to_vec(), parse_supported_versions_client_hello(&ext.data).unwrap());
+        assert_eq!(ProtocolVersion::TLS1_3, parse_supported_versions_server_hello(&supported_versions_server_hello(ProtocolVersion::TLS1_3).data).unwrap());
+
+        let entries = vec![KeyShareEntry { group: 29, key_exchange: vec![1; 32] }, KeyShareEntry { group: 23, key_exchange: vec![4; 65] }];
+        assert_eq!(entries, parse_key_share_client_hello(&key_share_client_hello(&entries).data).unwrap());
+        assert_eq!(entries[0], parse_key_share_server_hello(&key_share_server_hello(&entries[0]).data).unwrap());
+        assert_eq!(24, parse_key_share_hello_retry_request(&key_share_hello_retry_request(24).data).unwrap());
+        let duplicate = key_share_client_hello(&[entries[0].clone(), entries[0].clone()]);
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&parse_key_share_client_hello(&duplicate.data).unwrap_err()));
+        assert_eq!(vec![7, 7], parse_cookie(&cookie_extension(&[7, 7]).data).unwrap());
+    }
+
+    #[test]
+    fn test_can_round_trip_tls13_messages() {
+        assert_eq!(vec![0, 0], encode_encrypted_extensions(&[]).body);
+        assert!(parse_encrypted_extensions(&[0, 0]).unwrap().is_empty());
+        assert!(parse_encrypted_extensions(&[]).is_err());
+
+        let chain = vec![vec![1u8, 2, 3], vec![4u8; 300]];
+        let message = encode_certificate_tls13(&[], &chain);
+        assert_eq!((vec![], chain), parse_certificate_tls13(&message.body).unwrap());
+
+        let message = encode_certificate_verify(0x0804, &[9; 256]);
+        assert_eq!((0x0804, vec![9; 256]), parse_certificate_verify(&message.body).unwrap());
+        let content = certificate_verify_content(true, &[0xaa; 32]);
+        assert_eq!(64 + 33 + 1 + 32, content.len());
+        assert_eq!(b"TLS 1.3, server CertificateVerify\x00", &content[64..98]);
+
+        assert!(parse_key_update(&encode_key_update(true).body).unwrap());
+        assert!(!parse_key_update(&encode_key_update(false).body).unwrap());
+        assert!(parse_key_update(&[2]).is_err());
+        assert!(parse_key_update(&[]).is_err());
+    }
+
+    #[test]
+    fn test_can_replace_transcript_with_message_hash() {
+        let client_hello = HandshakeMessage::new(CLIENT_HELLO, vec![1, 2, 3]);
+        let mut transcript = Transcript::new();
+        transcript.add(&client_hello);
+        transcript.replace_with_message_hash(HashAlgorithm::Sha256);
+        let mut expected = vec![MESSAGE_HASH, 0, 0, 32];
+        expected.extend(HashAlgorithm::Sha256.digest(&client_hello.encode()));
+        assert_eq!(HashAlgorithm::Sha256.digest(&expected), transcript.hash(HashAlgorithm::Sha256));
+    }
+
+    #[test]
+    fn test_can_round_trip_certificate_chain() {
+        let chain = vec![vec![1u8, 2, 3], vec![4u8; 300]];
+        assert_eq!(chain, parse_certificate(&encode_certificate(&chain).body).unwrap());
+        assert!(parse_certificate(&[0, 0, 3, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_can_round_trip_certificate_requests() {
+        let request = CertificateRequest {
+            certificate_types: vec![CERTIFICATE_TYPE_RSA_SIGN, CERTIFICATE_TYPE_ECDSA_SIGN],
+            signature_schemes: vec![0x0804, 0x0401],
+            certificate_authorities: vec![vec![0x30, 0x00], vec![0x30, 0x03, 1, 2, 3]],
+        };
+        assert_eq!(request, CertificateRequest::parse(&request.encode(ProtocolVersion::TLS1_2).body, ProtocolVersion::TLS1_2).unwrap());
+        // no certificate types
+        assert!(CertificateRequest::parse(&[0, 0, 2, 8, 4, 0, 0], ProtocolVersion::TLS1_2).is_err());
+        // no signature schemes before TLS 1.2
+        let request = CertificateRequest {
+            signature_schemes: vec![],
+            ..request
+        };
+        let message = request.encode(ProtocolVersion::TLS1_1);
+        assert_eq!(request, CertificateRequest::parse(&message.body, ProtocolVersion::TLS1_1).unwrap());
+        assert!(CertificateRequest::parse(&message.body, ProtocolVersion::TLS1_2).is_err());
+
+        let extensions = vec![u16_list_extension(EXTENSION_SIGNATURE_ALGORITHMS, &[0x0804])];
+        let message = encode_certificate_request_tls13(&[7], &extensions);
+        assert_eq!((vec![7], extensions), parse_certificate_request_tls13(&message.body).unwrap());
+        assert!(parse_certificate_request_tls13(&[0]).is_err());
+    }
+}
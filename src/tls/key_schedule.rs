@@ -0,0 +1,114 @@
+use crate::digest::HashAlgorithm;
+use crate::hkdf::{
+    hkdf_expand,
+    hkdf_extract,
+};
+use crate::hmac::hmac;
+
+use super::codec::{
+    put_u16,
+    put_vec8,
+};
+
+/// HKDF-Expand-Label (RFC 8446 section 7.1)
+pub fn hkdf_expand_label(hash: HashAlgorithm, secret: &[u8], label: &[u8], context: &[u8], len: usize) -> Vec<u8> {
+    let mut full_label = b"tls13 ".to_vec();
+    full_label.extend_from_slice(label);
+    let mut info: Vec<u8> = vec![];
+    put_u16(&mut info, len as u16);
+    put_vec8(&mut info, &full_label);
+    put_vec8(&mut info, context);
+    hkdf_expand(hash, secret, &info, len)
+}
+
+/// Derive-Secret(secret, label, messages), with the transcript already hashed
+pub fn derive_secret(hash: HashAlgorithm, secret: &[u8], label: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+    hkdf_expand_label(hash, secret, label, transcript_hash, hash.output_size())
+}
+
+/// The chain early secret -> handshake secret -> master secret, one stage at a time
+pub struct KeySchedule {
+    hash: HashAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl KeySchedule {
+    /// Starts at the early secret. Without a PSK the input is a string of zeros.
+    pub fn new(hash: HashAlgorithm, psk: Option<&[u8]>) -> Self {
+        let zeros = vec![0u8; hash.output_size()];
+        Self {
+            hash,
+            secret: hkdf_extract(hash, &[], psk.unwrap_or(&zeros)),
+        }
+    }
+
+    pub fn hash(&self) -> HashAlgorithm {
+        self.hash
+    }
+
+    pub fn current_secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Moves to the next stage, mixing in the (EC)DHE shared secret, or zeros for the master secret
+    pub fn advance(&mut self, input: Option<&[u8]>) {
+        let zeros = vec![0u8; self.hash.output_size()];
+        let salt = self.derive(b"derived", &self.hash.digest(&[]));
+        self.secret = hkdf_extract(self.hash, &salt, input.unwrap_or(&zeros));
+    }
+
+    pub fn derive(&self, label: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+        derive_secret(self.hash, &self.secret, label, transcript_hash)
+    }
+}
+
+/// The record protection key and IV of a traffic secret
+pub fn traffic_key_iv(hash: HashAlgorithm, traffic_secret: &[u8], key_len: usize, iv_len: usize) -> (Vec<u8>, Vec<u8>) {
+    let key = hkdf_expand_label(hash, traffic_secret, b"key", &[], key_len);
+    let iv = hkdf_expand_label(hash, traffic_secret, b"iv", &[], iv_len);
+    (key, iv)
+}
+
+/// The application traffic secret that follows `traffic_secret` after a KeyUpdate (RFC 8446 section 7.2)
+pub fn next_traffic_secret(hash: HashAlgorithm, traffic_secret: &[u8]) -> Vec<u8> {
+    hkdf_expand_label(hash, traffic_secret, b"traffic upd", &[], hash.output_size())
+}
+
+/// Finished verify_data: HMAC(finished_key, transcript_hash) (RFC 8446 section 4.4.4)
+pub fn finished_verify_data(hash: HashAlgorithm, base_key: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+    let finished_key = hkdf_expand_label(hash, base_key, b"finished", &[], hash.output_size());
+    hmac(hash, &finished_key, transcript_hash)
+}
+
+/// The PSK of a ticket, from the resumption master secret and the ticket nonce (RFC 8446 section 4.6.1)
+pub fn resumption_psk(hash: HashAlgorithm, resumption_master_secret: &[u8], nonce: &[u8]) -> Vec<u8> {
+    hkdf_expand_label(hash, resumption_master_secret, b"resumption", nonce, hash.output_size())
+}
+
+/// The binder proving knowledge of a resumption PSK, over the transcript hash up to
+/// the binders of the ClientHello (RFC 8446 section 4.2.11.2)
+pub fn psk_binder(hash: HashAlgorithm, psk: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+    let binder_key = KeySchedule::new(hash, Some(psk)).derive(b"res binder", &hash.digest(&[]));
+    finished_verify_data(hash, &binder_key, transcript_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::from_hex;
+
+    #[test]
+    fn test_can_derive_rfc8448_secrets() {
+        // RFC 8448 section 3, simple 1-RTT handshake
+        let mut schedule = KeySchedule::new(HashAlgorithm::Sha256, None);
+        assert_eq!(from_hex("33ad0a1c607ec03b09e6cd9893680ce210adf300aa1f2660e1b22e10f170f92a"), schedule.current_secret());
+        schedule.advance(Some(&from_hex("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d")));
+        assert_eq!(from_hex("1dc826e93606aa6fdc0aadc12f741b01046aa6b99f691ed221a9f0ca043fbeac"), schedule.current_secret());
+        let transcript_hash = from_hex("860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8");
+        let server_handshake = schedule.derive(b"s hs traffic", &transcript_hash);
+        assert_eq!(from_hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38"), server_handshake);
+        let (key, iv) = traffic_key_iv(HashAlgorithm::Sha256, &server_handshake, 16, 12);
+        assert_eq!(from_hex("3fce516009c21727d0f2e4e86ee403bc"), key);
+        assert_eq!(from_hex("5d313eb2671276ee13000b30"), iv);
+    }
+}
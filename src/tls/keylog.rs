@@ -0,0 +1,89 @@
+use std::fs::{
+    File,
+    OpenOptions,
+};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{
+    Context as _,
+    Result,
+};
+
+use crate::hex::hex_encode;
+
+/// Environment variable naming the file that `KeyLogFile::from_env` appends to
+pub const SSLKEYLOGFILE: &str = "SSLKEYLOGFILE";
+
+/// TLS 1.2: the master secret
+pub const CLIENT_RANDOM: &str = "CLIENT_RANDOM";
+/// TLS 1.3 handshake and first application traffic secrets, and the exporter master secret
+pub const CLIENT_HANDSHAKE_TRAFFIC_SECRET: &str = "CLIENT_HANDSHAKE_TRAFFIC_SECRET";
+pub const SERVER_HANDSHAKE_TRAFFIC_SECRET: &str = "SERVER_HANDSHAKE_TRAFFIC_SECRET";
+pub const CLIENT_TRAFFIC_SECRET_0: &str = "CLIENT_TRAFFIC_SECRET_0";
+pub const SERVER_TRAFFIC_SECRET_0: &str = "SERVER_TRAFFIC_SECRET_0";
+pub const EXPORTER_SECRET: &str = "EXPORTER_SECRET";
+
+/// Takes the secrets of each handshake, so that tools like Wireshark can decrypt the
+/// traffic. Handshakes are told apart by the client random.
+pub trait KeyLog: Send + Sync {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]);
+}
+
+/// One line of the NSS key log format: `<label> <client random> <secret>`, both in hex
+pub fn format_line(label: &str, client_random: &[u8], secret: &[u8]) -> String {
+    format!("{} {} {}\n", label, hex_encode(client_random), hex_encode(secret))
+}
+
+/// Appends key log lines to a file, which may be shared with other programs
+pub struct KeyLogFile {
+    file: Mutex<File>,
+}
+
+impl KeyLogFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Unable to open key log file {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// The file named by SSLKEYLOGFILE, or None if it is not set
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var_os(SSLKEYLOGFILE) {
+            Some(path) if !path.is_empty() => Ok(Some(Self::new(path)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl KeyLog for KeyLogFile {
+    /// A line that cannot be written is dropped rather than failing the handshake
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format_line(label, client_random, secret);
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_append_lines_to_key_log_file() {
+        let path = std::env::temp_dir().join(format!("toyssl-keylog-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key_log = KeyLogFile::new(&path).unwrap();
+        key_log.log(CLIENT_RANDOM, &[0x01, 0xab], &[0xff]);
+        let key_log = KeyLogFile::new(&path).unwrap();
+        key_log.log(EXPORTER_SECRET, &[0x02], &[0x00, 0x10]);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("CLIENT_RANDOM 01ab ff\nEXPORTER_SECRET 02 0010\n", contents);
+    }
+}
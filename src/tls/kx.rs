@@ -0,0 +1,345 @@
+use anyhow::Result;
+
+use crate::dh::DhParams;
+use crate::ecc::Curve;
+use crate::huge::Huge;
+use crate::x25519::{
+    self,
+    X25519_KEY_LEN,
+};
+
+use super::alert::{
+    self,
+    DECODE_ERROR,
+    ILLEGAL_PARAMETER,
+};
+use super::codec::{
+    Reader,
+    put_u8,
+    put_u16,
+    put_vec8,
+    put_vec16,
+};
+use super::handshake::{
+    self,
+    RANDOM_LEN,
+};
+use super::record::{
+    HandshakeMessage,
+    ProtocolVersion,
+};
+use super::suites::KeyExchange;
+
+/// ECCurveType named_curve, the only one still in use (RFC 8422 section 5.4)
+const CURVE_TYPE_NAMED_CURVE: u8 = 3;
+
+/// NamedGroup (RFC 8422 section 5.1.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedGroup {
+    Secp256r1,
+    Secp384r1,
+    X25519,
+}
+
+/// Every implemented group, in order of preference
+pub static ALL_GROUPS: [NamedGroup; 3] = [
+    NamedGroup::X25519,
+    NamedGroup::Secp256r1,
+    NamedGroup::Secp384r1,
+];
+
+impl NamedGroup {
+    pub fn id(self) -> u16 {
+        match self {
+            NamedGroup::Secp256r1 => 23,
+            NamedGroup::Secp384r1 => 24,
+            NamedGroup::X25519 => 29,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        ALL_GROUPS.iter().find(|g| g.id() == id).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NamedGroup::Secp256r1 => "secp256r1",
+            NamedGroup::Secp384r1 => "secp384r1",
+            NamedGroup::X25519 => "x25519",
+        }
+    }
+
+    fn curve(self) -> Option<Curve> {
+        match self {
+            NamedGroup::Secp256r1 => Some(Curve::p256()),
+            NamedGroup::Secp384r1 => Some(Curve::p384()),
+            NamedGroup::X25519 => None,
+        }
+    }
+}
+
+/// Our half of an ephemeral Diffie-Hellman exchange
+pub enum EphemeralKey {
+    X25519 {
+        private: [u8; X25519_KEY_LEN],
+        public: [u8; X25519_KEY_LEN],
+    },
+    Ecdh {
+        curve: Curve,
+        private: Huge,
+        public: Vec<u8>,
+    },
+    Dh {
+        params: DhParams,
+        private: Huge,
+        public: Huge,
+    },
+}
+
+impl EphemeralKey {
+    pub fn generate_ecdhe(group: NamedGroup) -> Result<Self> {
+        match group.curve() {
+            None => {
+                let (private, public) = x25519::generate_keypair()?;
+                Ok(EphemeralKey::X25519 {
+                    private,
+                    public,
+                })
+            },
+            Some(curve) => {
+                let (private, public) = curve.generate_keypair()?;
+                let public = curve.encode_point(&public);
+                Ok(EphemeralKey::Ecdh {
+                    curve,
+                    private,
+                    public,
+                })
+            },
+        }
+    }
+
+    pub fn generate_dhe(params: &DhParams) -> Result<Self> {
+        let (private, public) = params.generate_keypair()?;
+        Ok(EphemeralKey::Dh {
+            params: params.clone(),
+            private,
+            public,
+        })
+    }
+
+    /// The public value as it goes on the wire
+    pub fn public_bytes(&self) -> Vec<u8> {
+        match self {
+            EphemeralKey::X25519 { public, .. } => public.to_vec(),
+            EphemeralKey::Ecdh { public, .. } => public.clone(),
+            EphemeralKey::Dh { public, .. } => public.to_bytes(),
+        }
+    }
+
+    /// Combines our private key with the peer's public value into the premaster secret.
+    /// An unusable peer value is an illegal_parameter alert.
+    pub fn agree(&self, peer: &[u8]) -> Result<Vec<u8>> {
+        let result = match self {
+            EphemeralKey::X25519 { private, .. } => x25519::shared_secret(private, peer).map(|s| s.to_vec()),
+            EphemeralKey::Ecdh { curve, private, .. } => curve.decode_point(peer).and_then(|p| curve.shared_secret(private, &p)),
+            EphemeralKey::Dh { params, private, .. } => params.shared_secret(private, &Huge::from_bytes(peer)),
+        };
+        result.map_err(|e| alert::fatal(ILLEGAL_PARAMETER, format!("Invalid key share from peer: {}", e)))
+    }
+}
+
+/// The server's ephemeral public key in ServerKeyExchange
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerKeyExchangeParams {
+    Ecdhe {
+        group: NamedGroup,
+        public: Vec<u8>,
+    },
+    Dhe {
+        params: DhParams,
+        public: Vec<u8>,
+    },
+}
+
+impl ServerKeyExchangeParams {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![];
+        match self {
+            ServerKeyExchangeParams::Ecdhe { group, public } => {
+                put_u8(&mut out, CURVE_TYPE_NAMED_CURVE);
+                put_u16(&mut out, group.id());
+                put_vec8(&mut out, public);
+            },
+            ServerKeyExchangeParams::Dhe { params, public } => {
+                put_vec16(&mut out, &params.p.to_bytes());
+                put_vec16(&mut out, &params.g.to_bytes());
+                put_vec16(&mut out, public);
+            },
+        }
+        out
+    }
+
+    fn parse(reader: &mut Reader, key_exchange: KeyExchange) -> Result<Self> {
+        match key_exchange {
+            KeyExchange::Ecdhe => {
+                if reader.read_u8()? != CURVE_TYPE_NAMED_CURVE {
+                    return Err(alert::fatal(ILLEGAL_PARAMETER, "Server did not use a named curve"));
+                }
+                let id = reader.read_u16()?;
+                let group = NamedGroup::from_id(id).ok_or_else(|| alert::fatal(ILLEGAL_PARAMETER, format!("Server selected unknown group {}", id)))?;
+                let public = read_nonempty(reader.read_vec8()?)?;
+                Ok(ServerKeyExchangeParams::Ecdhe {
+                    group,
+                    public,
+                })
+            },
+            KeyExchange::Dhe => {
+                let p = Huge::from_bytes(&read_nonempty(reader.read_vec16()?)?);
+                let g = Huge::from_bytes(&read_nonempty(reader.read_vec16()?)?);
+                let public = read_nonempty(reader.read_vec16()?)?;
+                Ok(ServerKeyExchangeParams::Dhe {
+                    params: DhParams {
+                        p,
+                        g,
+                    },
+                    public,
+                })
+            },
+            KeyExchange::Rsa | KeyExchange::Any => Err(alert::fatal(DECODE_ERROR, "No ServerKeyExchange for this key exchange")),
+        }
+    }
+}
+
+fn read_nonempty(data: &[u8]) -> Result<Vec<u8>> {
+    if data.is_empty() {
+        return Err(alert::fatal(DECODE_ERROR, "Empty key exchange value"));
+    }
+    Ok(data.to_vec())
+}
+
+/// ServerKeyExchange for ephemeral suites: the parameters and a signature over
+/// both randoms and the parameters (RFC 5246 section 7.4.3)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerKeyExchange {
+    pub params: ServerKeyExchangeParams,
+    /// None before TLS 1.2, where the key type alone decides the signature (RFC 4346 section 7.4.3)
+    pub signature_scheme: Option<u16>,
+    pub signature: Vec<u8>,
+}
+
+impl ServerKeyExchange {
+    pub fn encode(&self) -> HandshakeMessage {
+        let mut body = self.params.encode();
+        if let Some(scheme) = self.signature_scheme {
+            put_u16(&mut body, scheme);
+        }
+        put_vec16(&mut body, &self.signature);
+        HandshakeMessage::new(handshake::SERVER_KEY_EXCHANGE, body)
+    }
+
+    pub fn parse(body: &[u8], key_exchange: KeyExchange, version: ProtocolVersion) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let params = ServerKeyExchangeParams::parse(&mut reader, key_exchange)?;
+        let signature_scheme = if version >= ProtocolVersion::TLS1_2 { Some(reader.read_u16()?) } else { None };
+        let signature = reader.read_vec16()?.to_vec();
+        reader.expect_end()?;
+        Ok(Self {
+            params,
+            signature_scheme,
+            signature,
+        })
+    }
+
+    /// client_random || server_random || params, the input of the signature
+    pub fn signed_data(params: &ServerKeyExchangeParams, client_random: &[u8; RANDOM_LEN], server_random: &[u8; RANDOM_LEN]) -> Vec<u8> {
+        let mut data = client_random.to_vec();
+        data.extend_from_slice(server_random);
+        data.extend(params.encode());
+        data
+    }
+}
+
+/// ClientKeyExchange carrying the client's ephemeral public value:
+/// an opaque vector<1..2^8-1> for ECDHE and <1..2^16-1> for DHE
+pub fn encode_ephemeral_client_key_exchange(key_exchange: KeyExchange, public: &[u8]) -> HandshakeMessage {
+    let mut body: Vec<u8> = vec![];
+    if key_exchange == KeyExchange::Ecdhe {
+        put_vec8(&mut body, public);
+    } else {
+        put_vec16(&mut body, public);
+    }
+    HandshakeMessage::new(handshake::CLIENT_KEY_EXCHANGE, body)
+}
+
+pub fn parse_ephemeral_client_key_exchange(body: &[u8], key_exchange: KeyExchange) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(body);
+    let public = if key_exchange == KeyExchange::Ecdhe {
+        reader.read_vec8()?
+    } else {
+        reader.read_vec16()?
+    };
+    reader.expect_end()?;
+    read_nonempty(public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::signature;
+
+    #[test]
+    fn test_can_agree_on_premaster_secret() {
+        for group in ALL_GROUPS.iter() {
+            let server = EphemeralKey::generate_ecdhe(*group).unwrap();
+            let client = EphemeralKey::generate_ecdhe(*group).unwrap();
+            let premaster = client.agree(&server.public_bytes()).unwrap();
+            assert_eq!(premaster, server.agree(&client.public_bytes()).unwrap());
+        }
+        let params = DhParams::ffdhe2048();
+        let server = EphemeralKey::generate_dhe(&params).unwrap();
+        let client = EphemeralKey::generate_dhe(&params).unwrap();
+        assert_eq!(client.agree(&server.public_bytes()).unwrap(), server.agree(&client.public_bytes()).unwrap());
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&client.agree(&[1]).unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_round_trip_server_key_exchange() {
+        let ske = ServerKeyExchange {
+            params: ServerKeyExchangeParams::Ecdhe {
+                group: NamedGroup::X25519,
+                public: vec![9; 32],
+            },
+            signature_scheme: Some(signature::RSA_PKCS1_SHA256),
+            signature: vec![1, 2, 3],
+        };
+        let message = ske.encode();
+        assert_eq!(ske, ServerKeyExchange::parse(&message.body, KeyExchange::Ecdhe, ProtocolVersion::TLS1_2).unwrap());
+        assert_eq!(&[3, 0, 29, 32], &message.body[..4]);
+        assert!(ServerKeyExchange::parse(&message.body, KeyExchange::Dhe, ProtocolVersion::TLS1_2).is_err());
+
+        let ske = ServerKeyExchange {
+            params: ServerKeyExchangeParams::Dhe {
+                params: DhParams::ffdhe2048(),
+                public: vec![5; 256],
+            },
+            signature_scheme: Some(signature::RSA_PKCS1_SHA1),
+            signature: vec![4; 256],
+        };
+        assert_eq!(ske, ServerKeyExchange::parse(&ske.encode().body, KeyExchange::Dhe, ProtocolVersion::TLS1_2).unwrap());
+
+        // no scheme before TLS 1.2
+        let ske = ServerKeyExchange {
+            signature_scheme: None,
+            ..ske
+        };
+        assert_eq!(ske, ServerKeyExchange::parse(&ske.encode().body, KeyExchange::Dhe, ProtocolVersion::TLS1_1).unwrap());
+    }
+
+    #[test]
+    fn test_can_return_error_unknown_curve() {
+        let body = [3, 0, 25, 1, 4, 0, 0x04, 0, 0];
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&ServerKeyExchange::parse(&body, KeyExchange::Ecdhe, ProtocolVersion::TLS1_2).unwrap_err()));
+        let body = [1, 0, 23, 1, 4, 0, 0x04, 0, 0];
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&ServerKeyExchange::parse(&body, KeyExchange::Ecdhe, ProtocolVersion::TLS1_2).unwrap_err()));
+    }
+}
@@ -0,0 +1,412 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::aes::{
+    AES_BLOCK_SIZE,
+    Aes,
+    aes_cbc_decrypt,
+    aes_cbc_encrypt,
+};
+use crate::digest::{
+    Digest,
+    HashAlgorithm,
+};
+use crate::hmac::{
+    Hmac,
+    constant_time_eq,
+};
+
+use super::alert;
+use super::cipher::{
+    bad_record_mac,
+    open_padded_record,
+    pad,
+};
+use super::prf::{
+    p_hash,
+    Prf,
+};
+use super::record::{
+    ContentType,
+    ProtocolVersion,
+    Record,
+    RecordProtection,
+};
+use super::suites::{
+    CipherSuite,
+    Mac,
+};
+
+/// Finished senders of SSL 3.0 (RFC 6101 section 5.6.9)
+const SSL3_CLIENT_SENDER: &[u8] = b"CLNT";
+const SSL3_SERVER_SENDER: &[u8] = b"SRVR";
+const SSL3_PAD1: u8 = 0x36;
+const SSL3_PAD2: u8 = 0x5c;
+
+/// TLS 1.0 PRF: P_MD5 over the first half of the secret XOR P_SHA-1 over the second.
+/// The halves share the middle byte of a secret of odd length (RFC 2246 section 5).
+pub fn tls10_prf(secret: &[u8], label: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let half = secret.len().div_ceil(2);
+    let label_seed = [label, seed].concat();
+    let md5 = p_hash(HashAlgorithm::Md5, &secret[..half], &label_seed, len);
+    let sha1 = p_hash(HashAlgorithm::Sha1, &secret[secret.len() - half..], &label_seed, len);
+    md5.iter().zip(sha1.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+/// MD5(data) || SHA-1(data), the handshake hash before TLS 1.2
+pub fn md5_sha1(data: &[u8]) -> Vec<u8> {
+    let mut result = HashAlgorithm::Md5.digest(data);
+    result.extend(HashAlgorithm::Sha1.digest(data));
+    result
+}
+
+/// SSL 3.0 master secret and key block (RFC 6101 section 6.1 and 6.2.2):
+/// MD5(secret + SHA-1(salt + secret + first + second)) for the salts 'A', 'BB', 'CCC' and so on
+pub fn ssl3_expand(secret: &[u8], first: &[u8], second: &[u8], len: usize) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(len + 16);
+    let mut i = 0;
+    while result.len() < len {
+        let mut sha1 = HashAlgorithm::Sha1.new_digest();
+        sha1.update(&vec![b'A' + i as u8; i + 1]);
+        sha1.update(secret);
+        sha1.update(first);
+        sha1.update(second);
+        let mut md5 = HashAlgorithm::Md5.new_digest();
+        md5.update(secret);
+        md5.update(&sha1.finish());
+        result.extend(md5.finish());
+        i += 1;
+    }
+    result.truncate(len);
+    result
+}
+
+/// The pads fill a 64-byte block after the 16-byte MD5 and 20-byte SHA-1 secrets
+fn ssl3_pad_len(hash: HashAlgorithm) -> usize {
+    if hash == HashAlgorithm::Md5 { 48 } else { 40 }
+}
+
+/// SSL 3.0 Finished, 36 bytes of MD5 and SHA-1 of
+/// hash(master_secret + pad2 + hash(handshake_messages + sender + master_secret + pad1))
+pub fn ssl3_finished(master_secret: &[u8], client: bool, messages: &[u8]) -> Vec<u8> {
+    let sender = if client { SSL3_CLIENT_SENDER } else { SSL3_SERVER_SENDER };
+    let mut result: Vec<u8> = vec![];
+    for hash in [HashAlgorithm::Md5, HashAlgorithm::Sha1].iter() {
+        let pad_len = ssl3_pad_len(*hash);
+        let mut inner = hash.new_digest();
+        inner.update(messages);
+        inner.update(sender);
+        inner.update(master_secret);
+        inner.update(&vec![SSL3_PAD1; pad_len]);
+        let mut outer = hash.new_digest();
+        outer.update(master_secret);
+        outer.update(&vec![SSL3_PAD2; pad_len]);
+        outer.update(&inner.finish());
+        result.extend(outer.finish());
+    }
+    result
+}
+
+/// The record MAC of SSL 3.0 (RFC 6101 section 5.2.3.1), nested like HMAC but with the
+/// secret followed by the pads instead of XORed with them
+struct Ssl3Mac {
+    inner: Box<dyn Digest>,
+    outer: Box<dyn Digest>,
+}
+
+impl Ssl3Mac {
+    fn new(hash: HashAlgorithm, secret: &[u8]) -> Self {
+        let pad_len = ssl3_pad_len(hash);
+        let mut inner = hash.new_digest();
+        inner.update(secret);
+        inner.update(&vec![SSL3_PAD1; pad_len]);
+        let mut outer = hash.new_digest();
+        outer.update(secret);
+        outer.update(&vec![SSL3_PAD2; pad_len]);
+        Self {
+            inner,
+            outer,
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut outer = self.outer.box_clone();
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+impl Clone for Ssl3Mac {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.box_clone(),
+            outer: self.outer.box_clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum RecordMac {
+    Hmac(Hmac),
+    Ssl3(Ssl3Mac),
+}
+
+impl RecordMac {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            RecordMac::Hmac(mac) => mac.update(data),
+            RecordMac::Ssl3(mac) => mac.inner.update(data),
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        match self {
+            RecordMac::Hmac(mac) => mac.finish(),
+            RecordMac::Ssl3(mac) => mac.finish(),
+        }
+    }
+
+    fn output_size(&self) -> usize {
+        match self {
+            RecordMac::Hmac(mac) => mac.output_size(),
+            RecordMac::Ssl3(mac) => mac.outer.output_size(),
+        }
+    }
+
+    fn keyed_digests(&self) -> (&dyn Digest, &dyn Digest) {
+        match self {
+            RecordMac::Hmac(mac) => mac.keyed_digests(),
+            RecordMac::Ssl3(mac) => (&*mac.inner, &*mac.outer),
+        }
+    }
+}
+
+/// CBC protection of SSL 3.0 and TLS 1.0, where the IV of a record is the last ciphertext
+/// block of the one before (RFC 2246 section 6.2.3.2). As that IV is known before the
+/// plaintext is chosen, application data is split 1/n-1. SSL 3.0 does not fix the value
+/// of its padding bytes, so only their length is checked, which is what POODLE exploits.
+pub struct LegacyCbcProtection {
+    aes: Aes,
+    mac: RecordMac,
+    ssl3: bool,
+    encrypt_then_mac: bool,
+    iv: [u8; AES_BLOCK_SIZE],
+    sequence_number: u64,
+}
+
+impl LegacyCbcProtection {
+    pub fn new(version: ProtocolVersion, key: &[u8], iv: &[u8], mac_hash: HashAlgorithm, mac_key: &[u8], encrypt_then_mac: bool) -> Result<Self> {
+        if iv.len() != AES_BLOCK_SIZE {
+            return Err(anyhow!("Invalid CBC IV length: {}", iv.len()));
+        }
+        let ssl3 = version == ProtocolVersion::SSL3_0;
+        let mac = if ssl3 { RecordMac::Ssl3(Ssl3Mac::new(mac_hash, mac_key)) } else { RecordMac::Hmac(Hmac::new(mac_hash, mac_key)) };
+        let mut fixed_iv = [0u8; AES_BLOCK_SIZE];
+        fixed_iv.copy_from_slice(iv);
+        Ok(Self {
+            aes: Aes::new(key)?,
+            mac,
+            ssl3,
+            encrypt_then_mac,
+            iv: fixed_iv,
+            sequence_number: 0,
+        })
+    }
+
+    /// seq_num + type + version, without the version in SSL 3.0, which the MAC covers
+    /// ahead of the length and content
+    fn mac_header(&self, content_type: ContentType, version: ProtocolVersion) -> Vec<u8> {
+        let mut header = self.sequence_number.to_be_bytes().to_vec();
+        header.push(content_type.as_u8());
+        if !self.ssl3 {
+            header.extend_from_slice(&version.as_u16().to_be_bytes());
+        }
+        header
+    }
+
+    fn compute_mac(&self, content_type: ContentType, version: ProtocolVersion, content: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac.clone();
+        mac.update(&self.mac_header(content_type, version));
+        mac.update(&(content.len() as u16).to_be_bytes());
+        mac.update(content);
+        mac.finish()
+    }
+
+    fn next_sequence_number(&mut self) -> Result<()> {
+        self.sequence_number = self.sequence_number.checked_add(1).ok_or_else(|| alert::fatal(alert::INTERNAL_ERROR, "Sequence number overflow"))?;
+        Ok(())
+    }
+
+    /// Decrypts under the chained IV and keeps the last block as the next one
+    fn cbc_decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let data = aes_cbc_decrypt(&self.aes, &self.iv, ciphertext)?;
+        self.iv.copy_from_slice(&ciphertext[ciphertext.len() - AES_BLOCK_SIZE..]);
+        Ok(data)
+    }
+
+    /// Opens a MAC-then-encrypt record in constant time as `CbcHmacProtection` does
+    fn open_mac_then_encrypt(&mut self, record: &Record) -> Result<Vec<u8>> {
+        let ciphertext = &record.fragment;
+        let mac_len = self.mac.output_size();
+        if ciphertext.is_empty() || !ciphertext.len().is_multiple_of(AES_BLOCK_SIZE) || ciphertext.len() < mac_len + 1 {
+            return Err(bad_record_mac());
+        }
+        let data = self.cbc_decrypt(ciphertext)?;
+        let (inner, outer) = self.mac.keyed_digests();
+        open_padded_record(&data, inner, outer, &self.mac_header(record.content_type, record.version), self.ssl3)
+    }
+
+    /// Opens a TLS 1.0 encrypt-then-MAC record, whose MAC covers the ciphertext alone
+    /// as there is no IV on the wire (RFC 7366 section 3)
+    fn open_encrypt_then_mac(&mut self, record: &Record) -> Result<Vec<u8>> {
+        let mac_len = self.mac.output_size();
+        if record.fragment.len() < AES_BLOCK_SIZE + mac_len {
+            return Err(bad_record_mac());
+        }
+        let (ciphertext, received) = record.fragment.split_at(record.fragment.len() - mac_len);
+        let expected = self.compute_mac(record.content_type, record.version, ciphertext);
+        if !constant_time_eq(&expected, received) || !ciphertext.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(bad_record_mac());
+        }
+        let mut data = self.cbc_decrypt(ciphertext)?;
+        let padding_len = data[data.len() - 1] as usize;
+        if padding_len + 1 > data.len() || data[data.len() - padding_len - 1..].iter().any(|b| *b as usize != padding_len) {
+            return Err(bad_record_mac());
+        }
+        data.truncate(data.len() - padding_len - 1);
+        Ok(data)
+    }
+}
+
+impl RecordProtection for LegacyCbcProtection {
+    fn encrypt(&mut self, record: Record) -> Result<Record> {
+        let mut data = record.fragment.clone();
+        if !self.encrypt_then_mac {
+            data.extend(self.compute_mac(record.content_type, record.version, &record.fragment));
+        }
+        pad(&mut data);
+        let mut result = aes_cbc_encrypt(&self.aes, &self.iv, &data)?;
+        self.iv.copy_from_slice(&result[result.len() - AES_BLOCK_SIZE..]);
+        if self.encrypt_then_mac {
+            result.extend(self.compute_mac(record.content_type, record.version, &result));
+        }
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment: result,
+            ..record
+        })
+    }
+
+    fn decrypt(&mut self, record: Record) -> Result<Record> {
+        let fragment = if self.encrypt_then_mac { self.open_encrypt_then_mac(&record)? } else { self.open_mac_then_encrypt(&record)? };
+        self.next_sequence_number()?;
+        Ok(Record {
+            fragment,
+            ..record
+        })
+    }
+
+    fn split_application_data(&self) -> bool {
+        true
+    }
+}
+
+/// The (client write, server write) protections of SSL 3.0 and TLS 1.0, whose key block
+/// ends with the initial IV of each direction
+pub fn derive_protections(suite: &CipherSuite, version: ProtocolVersion, master_secret: &[u8], client_random: &[u8], server_random: &[u8], encrypt_then_mac: bool) -> Result<(Box<dyn RecordProtection>, Box<dyn RecordProtection>)> {
+    let mac_hash = match suite.mac {
+        Mac::Hmac(hash) => hash,
+        Mac::Aead => return Err(anyhow!("{} is not available in {}", suite.name, version)),
+    };
+    let mac_key_len = mac_hash.output_size();
+    let key_len = suite.bulk_cipher.key_len();
+    let key_block = Prf::new(version, suite).key_block(master_secret, client_random, server_random, 2 * (mac_key_len + key_len + AES_BLOCK_SIZE));
+    let (client_mac_key, rest) = key_block.split_at(mac_key_len);
+    let (server_mac_key, rest) = rest.split_at(mac_key_len);
+    let (client_key, rest) = rest.split_at(key_len);
+    let (server_key, rest) = rest.split_at(key_len);
+    let (client_iv, server_iv) = rest.split_at(AES_BLOCK_SIZE);
+    let client_write = LegacyCbcProtection::new(version, client_key, client_iv, mac_hash, client_mac_key, encrypt_then_mac)?;
+    let server_write = LegacyCbcProtection::new(version, server_key, server_iv, mac_hash, server_mac_key, encrypt_then_mac)?;
+    Ok((Box::new(client_write), Box::new(server_write)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::suites::TLS_RSA_WITH_AES_128_CBC_SHA;
+
+    fn record(version: ProtocolVersion, fragment: &[u8]) -> Record {
+        Record {
+            content_type: ContentType::ApplicationData,
+            version,
+            fragment: fragment.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_can_compute_tls10_prf() {
+        // the halves of a secret of odd length overlap by a byte
+        let output = tls10_prf(&[0xab; 47], b"master secret", &[0xcd; 64], 48);
+        let half = 24;
+        let label_seed = [&b"master secret"[..], &[0xcd; 64]].concat();
+        let md5 = p_hash(HashAlgorithm::Md5, &[0xab; 24], &label_seed, 48);
+        let sha1 = p_hash(HashAlgorithm::Sha1, &[0xab; 24], &label_seed, 48);
+        assert_eq!(48, output.len());
+        assert_eq!(md5[..half].iter().zip(sha1.iter()).map(|(a, b)| a ^ b).collect::<Vec<u8>>(), output[..half].to_vec());
+    }
+
+    #[test]
+    fn test_can_expand_ssl3_secret() {
+        let output = ssl3_expand(b"pms", b"cr", b"sr", 40);
+        assert_eq!(40, output.len());
+        let mut md5 = b"pms".to_vec();
+        md5.extend(HashAlgorithm::Sha1.digest(b"BBpmscrsr"));
+        assert_eq!(HashAlgorithm::Md5.digest(&md5), output[16..32].to_vec());
+    }
+
+    #[test]
+    fn test_can_round_trip_records_with_chained_ivs() {
+        for version in [ProtocolVersion::TLS1_0, ProtocolVersion::SSL3_0].iter() {
+            for encrypt_then_mac in [false, true].iter() {
+                if *version == ProtocolVersion::SSL3_0 && *encrypt_then_mac {
+                    continue;
+                }
+                let suite = &TLS_RSA_WITH_AES_128_CBC_SHA;
+                let (mut client, _) = derive_protections(suite, *version, &[1u8; 48], &[2u8; 32], &[3u8; 32], *encrypt_then_mac).unwrap();
+                let (mut peer, _) = derive_protections(suite, *version, &[1u8; 48], &[2u8; 32], &[3u8; 32], *encrypt_then_mac).unwrap();
+                assert!(client.split_application_data());
+                for len in [0usize, 1, 15, 16, 1000].iter() {
+                    let plaintext = record(*version, &vec![0x5au8; *len]);
+                    let ciphertext = client.encrypt(plaintext.clone()).unwrap();
+                    // no IV goes on the wire
+                    let mac_len = if *encrypt_then_mac { 20 } else { 0 };
+                    assert!((ciphertext.fragment.len() - mac_len).is_multiple_of(AES_BLOCK_SIZE));
+                    assert!(ciphertext.fragment.len() < len + 20 + 2 * AES_BLOCK_SIZE);
+                    assert_eq!(plaintext, peer.decrypt(ciphertext).unwrap());
+                }
+                let mut ciphertext = client.encrypt(record(*version, b"finished")).unwrap();
+                ciphertext.fragment[0] ^= 1;
+                assert_eq!(alert::BAD_RECORD_MAC, alert::description_of(&peer.decrypt(ciphertext).unwrap_err()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_can_compute_ssl3_finished() {
+        let client = ssl3_finished(&[1u8; 48], true, b"handshake");
+        assert_eq!(36, client.len());
+        assert_ne!(client, ssl3_finished(&[1u8; 48], false, b"handshake"));
+        // the MD5 half on its own
+        let mut inner = b"handshake".to_vec();
+        inner.extend_from_slice(b"CLNT");
+        inner.extend_from_slice(&[1u8; 48]);
+        inner.extend_from_slice(&[SSL3_PAD1; 48]);
+        let mut outer = vec![1u8; 48];
+        outer.extend_from_slice(&[SSL3_PAD2; 48]);
+        outer.extend(HashAlgorithm::Md5.digest(&inner));
+        assert_eq!(HashAlgorithm::Md5.digest(&outer), client[..16].to_vec());
+    }
+}
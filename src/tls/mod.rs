@@ -0,0 +1,21 @@
+pub mod alert;
+pub mod cipher;
+pub mod client;
+pub mod codec;
+pub mod connection;
+pub mod extensions;
+pub mod handshake;
+pub mod key_schedule;
+pub mod keylog;
+pub mod kx;
+#[cfg(feature = "legacy-protocols")]
+pub mod legacy;
+pub mod policy;
+pub mod prf;
+pub mod record;
+pub mod server;
+pub mod session;
+pub mod signature;
+pub mod stream;
+pub mod suites;
+pub mod trace;
@@ -0,0 +1,113 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::record::ProtocolVersion;
+use super::suites::{
+    ALL_SUITES,
+    CipherSuite,
+};
+
+/// Named sets of versions and cipher suites a client or server is willing to negotiate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// everything implemented, including static RSA and CBC suites
+    Legacy,
+    /// TLS 1.3, and TLS 1.2 with forward secret AEAD suites only
+    Intermediate,
+    /// TLS 1.3 only
+    Modern,
+}
+
+impl SecurityLevel {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "legacy" => Ok(SecurityLevel::Legacy),
+            "intermediate" => Ok(SecurityLevel::Intermediate),
+            "modern" => Ok(SecurityLevel::Modern),
+            _ => Err(anyhow!("Unknown security level '{}', expected legacy, intermediate or modern", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SecurityLevel::Legacy => "legacy",
+            SecurityLevel::Intermediate => "intermediate",
+            SecurityLevel::Modern => "modern",
+        }
+    }
+
+    pub fn allows_version(self, version: ProtocolVersion) -> bool {
+        match self {
+            // which takes in SSL 3.0, TLS 1.0 and TLS 1.1 when built with legacy-protocols
+            SecurityLevel::Legacy => true,
+            SecurityLevel::Intermediate => version >= ProtocolVersion::TLS1_2,
+            SecurityLevel::Modern => version >= ProtocolVersion::TLS1_3,
+        }
+    }
+
+    pub fn allows_suite(self, suite: &CipherSuite) -> bool {
+        match self {
+            SecurityLevel::Legacy => true,
+            SecurityLevel::Intermediate => suite.is_forward_secret_aead(),
+            SecurityLevel::Modern => suite.is_tls13(),
+        }
+    }
+
+    /// Whether TLS 1.2 sessions are only resumed if their handshake used
+    /// extended_master_secret, which keeps them out of reach of the triple handshake attack
+    pub fn requires_extended_master_secret(self) -> bool {
+        self != SecurityLevel::Legacy
+    }
+
+    /// The implemented versions of this level, highest first
+    pub fn versions(self) -> Vec<ProtocolVersion> {
+        ProtocolVersion::IMPLEMENTED.iter()
+            .copied()
+            .filter(|v| self.allows_version(*v))
+            .collect()
+    }
+
+    /// The implemented suites of this level, strongest first
+    pub fn cipher_suites(self) -> Vec<&'static CipherSuite> {
+        ALL_SUITES.iter()
+            .copied()
+            .filter(|s| self.allows_suite(s))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::suites::{
+        TLS_AES_128_GCM_SHA256,
+        TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256,
+        TLS_RSA_WITH_AES_128_GCM_SHA256,
+    };
+
+    #[test]
+    fn test_can_restrict_suites_by_level() {
+        let intermediate = SecurityLevel::from_name("intermediate").unwrap();
+        assert!(intermediate.allows_suite(&TLS_AES_128_GCM_SHA256));
+        assert!(intermediate.allows_suite(&TLS_DHE_RSA_WITH_AES_128_GCM_SHA256));
+        assert!(!intermediate.allows_suite(&TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256));
+        assert!(!intermediate.allows_suite(&TLS_RSA_WITH_AES_128_GCM_SHA256));
+        assert_eq!(vec![ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2], intermediate.versions());
+        assert!(SecurityLevel::Modern.cipher_suites().iter().all(|s| s.is_tls13()));
+        assert_eq!(vec![ProtocolVersion::TLS1_3], SecurityLevel::Modern.versions());
+        assert_eq!(ALL_SUITES.len(), SecurityLevel::Legacy.cipher_suites().len());
+        assert!(intermediate.requires_extended_master_secret());
+        assert!(!SecurityLevel::Legacy.requires_extended_master_secret());
+        assert!(SecurityLevel::from_name("paranoid").is_err());
+    }
+
+    #[cfg(feature = "legacy-protocols")]
+    #[test]
+    fn test_can_allow_legacy_versions() {
+        assert_eq!(ProtocolVersion::IMPLEMENTED.to_vec(), SecurityLevel::Legacy.versions());
+        assert!(!SecurityLevel::Intermediate.allows_version(ProtocolVersion::TLS1_1));
+    }
+}
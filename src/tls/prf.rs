@@ -0,0 +1,145 @@
+use crate::digest::HashAlgorithm;
+use crate::hmac::Hmac;
+
+use super::handshake::Transcript;
+#[cfg(feature = "legacy-protocols")]
+use super::legacy;
+use super::record::ProtocolVersion;
+use super::suites::CipherSuite;
+
+pub const MASTER_SECRET_LEN: usize = 48;
+pub const VERIFY_DATA_LEN: usize = 12;
+
+/// P_hash data expansion (RFC 5246 section 5)
+pub(super) fn p_hash(hash: HashAlgorithm, secret: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let mac = Hmac::new(hash, secret);
+    let mut result: Vec<u8> = Vec::with_capacity(len + hash.output_size());
+    // A(0) = seed, A(i) = HMAC(secret, A(i-1))
+    let mut a = seed.to_vec();
+    while result.len() < len {
+        let mut m = mac.clone();
+        m.update(&a);
+        a = m.finish();
+        let mut m = mac.clone();
+        m.update(&a);
+        m.update(seed);
+        result.extend(m.finish());
+    }
+    result.truncate(len);
+    result
+}
+
+/// TLS 1.2 PRF(secret, label, seed) = P_<hash>(secret, label + seed)
+pub fn prf(hash: HashAlgorithm, secret: &[u8], label: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let mut label_seed = label.to_vec();
+    label_seed.extend_from_slice(seed);
+    p_hash(hash, secret, &label_seed, len)
+}
+
+/// How a handshake before TLS 1.3 turns its secrets into keys and Finished messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prf {
+    /// TLS 1.2: P_<hash> with the hash of the suite, which hashes the handshake as well
+    Tls12(HashAlgorithm),
+    /// TLS 1.0 and 1.1: P_MD5 XOR P_SHA-1 over a handshake hash of MD5 || SHA-1 (RFC 2246 section 5)
+    #[cfg(feature = "legacy-protocols")]
+    Tls10,
+    /// SSL 3.0: nested MD5 and SHA-1 instead of a PRF (RFC 6101 section 6)
+    #[cfg(feature = "legacy-protocols")]
+    Ssl3,
+}
+
+impl Prf {
+    pub fn new(version: ProtocolVersion, suite: &CipherSuite) -> Self {
+        match version {
+            #[cfg(feature = "legacy-protocols")]
+            ProtocolVersion::SSL3_0 => Prf::Ssl3,
+            #[cfg(feature = "legacy-protocols")]
+            ProtocolVersion::TLS1_0 | ProtocolVersion::TLS1_1 => Prf::Tls10,
+            _ => Prf::Tls12(suite.prf_hash),
+        }
+    }
+
+    /// PRF(secret, label, seed)
+    fn expand(self, secret: &[u8], label: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+        match self {
+            Prf::Tls12(hash) => prf(hash, secret, label, seed, len),
+            #[cfg(feature = "legacy-protocols")]
+            Prf::Tls10 => legacy::tls10_prf(secret, label, seed, len),
+            // extended_master_secret is never negotiated in SSL 3.0, which leaves it no labelled PRF
+            #[cfg(feature = "legacy-protocols")]
+            Prf::Ssl3 => unreachable!("SSL 3.0 has no PRF"),
+        }
+    }
+
+    /// The hash of the messages so far, as the session hash and Finished take it
+    pub fn handshake_hash(self, transcript: &Transcript) -> Vec<u8> {
+        match self {
+            Prf::Tls12(hash) => transcript.hash(hash),
+            #[cfg(feature = "legacy-protocols")]
+            Prf::Tls10 | Prf::Ssl3 => legacy::md5_sha1(transcript.messages()),
+        }
+    }
+
+    pub fn master_secret(self, pre_master_secret: &[u8], client_random: &[u8], server_random: &[u8]) -> Vec<u8> {
+        #[cfg(feature = "legacy-protocols")]
+        if self == Prf::Ssl3 {
+            return legacy::ssl3_expand(pre_master_secret, client_random, server_random, MASTER_SECRET_LEN);
+        }
+        let seed = [client_random, server_random].concat();
+        self.expand(pre_master_secret, b"master secret", &seed, MASTER_SECRET_LEN)
+    }
+
+    /// Master secret bound to the hash of the handshake up to and including ClientKeyExchange,
+    /// from extended_master_secret (RFC 7627 section 4)
+    pub fn extended_master_secret(self, pre_master_secret: &[u8], transcript: &Transcript) -> Vec<u8> {
+        self.expand(pre_master_secret, b"extended master secret", &self.handshake_hash(transcript), MASTER_SECRET_LEN)
+    }
+
+    /// Note that the randoms are in the opposite order of `master_secret`
+    pub fn key_block(self, master_secret: &[u8], client_random: &[u8], server_random: &[u8], len: usize) -> Vec<u8> {
+        #[cfg(feature = "legacy-protocols")]
+        if self == Prf::Ssl3 {
+            return legacy::ssl3_expand(master_secret, server_random, client_random, len);
+        }
+        let seed = [server_random, client_random].concat();
+        self.expand(master_secret, b"key expansion", &seed, len)
+    }
+
+    /// verify_data of the client's or the server's Finished over the messages so far
+    pub fn verify_data(self, master_secret: &[u8], client: bool, transcript: &Transcript) -> Vec<u8> {
+        #[cfg(feature = "legacy-protocols")]
+        if self == Prf::Ssl3 {
+            return legacy::ssl3_finished(master_secret, client, transcript.messages());
+        }
+        let label: &[u8] = if client { b"client finished" } else { b"server finished" };
+        self.expand(master_secret, label, &self.handshake_hash(transcript), VERIFY_DATA_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::from_hex;
+
+    #[test]
+    fn test_can_compute_prf_sha256() {
+        let secret = from_hex("9bbe436ba940f017b17652849a71db35");
+        let seed = from_hex("a0ba9f936cda311827a6f796ffd5198c");
+        let expected = from_hex(
+            "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a6b301791e90d35c9c9a46b4e14baf9af0fa022f7077def17abfd3797c0564bab\
+             4fbc91666e9def9b97fce34f796789baa48082d122ee42c5a72e5a5110fff70187347b66",
+        );
+        assert_eq!(expected, prf(HashAlgorithm::Sha256, &secret, b"test label", &seed, 100));
+    }
+
+    #[test]
+    fn test_can_compute_prf_sha384() {
+        let secret = from_hex("b80b733d6ceefcdc71566ea48e5567df");
+        let seed = from_hex("cd665cf6a8447dd6ff8b27555edb7465");
+        let output = prf(HashAlgorithm::Sha384, &secret, b"test label", &seed, 148);
+        assert_eq!(148, output.len());
+        assert_eq!(from_hex("7b0c18e9ced410ed1804f2cfa34a336a"), output[..16].to_vec());
+        assert_eq!(from_hex("8d5eaa3be85e0990703d73e56f"), output[135..].to_vec());
+    }
+}
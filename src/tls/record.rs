@@ -0,0 +1,662 @@
+use std::fmt;
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::sync::Arc;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::alert::{
+    self,
+    Alert,
+    AlertDescription,
+    AlertLevel,
+    PeerAlert,
+    CLOSE_NOTIFY,
+    DECODE_ERROR,
+    NO_RENEGOTIATION,
+    PROTOCOL_VERSION,
+    RECORD_OVERFLOW,
+    UNEXPECTED_MESSAGE,
+};
+use super::trace::{
+    Direction,
+    TraceEvent,
+    Tracer,
+};
+
+/// Maximum TLSPlaintext.fragment length, 2^14 (RFC 5246 section 6.2.1)
+pub const MAX_FRAGMENT_LEN: usize = 1 << 14;
+/// Protection may expand a fragment by at most 2048 bytes (RFC 5246 section 6.2.3)
+pub const MAX_CIPHERTEXT_LEN: usize = MAX_FRAGMENT_LEN + 2048;
+/// Upper bound for a reassembled handshake message. Certificate chains are the largest ones.
+pub const MAX_HANDSHAKE_MESSAGE_LEN: usize = 128 * 1024;
+
+pub const RECORD_HEADER_LEN: usize = 5;
+pub const HANDSHAKE_HEADER_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    ChangeCipherSpec,
+    Alert,
+    Handshake,
+    ApplicationData,
+}
+
+impl ContentType {
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            20 => Ok(ContentType::ChangeCipherSpec),
+            21 => Ok(ContentType::Alert),
+            22 => Ok(ContentType::Handshake),
+            23 => Ok(ContentType::ApplicationData),
+            _ => Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unknown record content type: {}", value))),
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            ContentType::ChangeCipherSpec => 20,
+            ContentType::Alert => 21,
+            ContentType::Handshake => 22,
+            ContentType::ApplicationData => 23,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl ProtocolVersion {
+    pub const SSL3_0: ProtocolVersion = ProtocolVersion { major: 3, minor: 0 };
+    pub const TLS1_0: ProtocolVersion = ProtocolVersion { major: 3, minor: 1 };
+    pub const TLS1_1: ProtocolVersion = ProtocolVersion { major: 3, minor: 2 };
+    pub const TLS1_2: ProtocolVersion = ProtocolVersion { major: 3, minor: 3 };
+    pub const TLS1_3: ProtocolVersion = ProtocolVersion { major: 3, minor: 4 };
+
+    /// The versions this build can negotiate, highest first
+    #[cfg(not(feature = "legacy-protocols"))]
+    pub const IMPLEMENTED: &'static [ProtocolVersion] = &[Self::TLS1_3, Self::TLS1_2];
+    /// The versions this build can negotiate, highest first
+    #[cfg(feature = "legacy-protocols")]
+    pub const IMPLEMENTED: &'static [ProtocolVersion] = &[Self::TLS1_3, Self::TLS1_2, Self::TLS1_1, Self::TLS1_0, Self::SSL3_0];
+
+    pub fn from_u16(value: u16) -> Self {
+        Self {
+            major: (value >> 8) as u8,
+            minor: value as u8,
+        }
+    }
+
+    pub fn as_u16(self) -> u16 {
+        (self.major as u16) << 8 | self.minor as u16
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.major, self.minor) {
+            (3, 0) => write!(f, "SSL 3.0"),
+            (3, minor) => write!(f, "TLS 1.{}", minor - 1),
+            (major, minor) => write!(f, "unknown version {}.{}", major, minor),
+        }
+    }
+}
+
+/// TLSPlaintext
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub content_type: ContentType,
+    pub version: ProtocolVersion,
+    pub fragment: Vec<u8>,
+}
+
+impl Record {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::with_capacity(RECORD_HEADER_LEN + self.fragment.len());
+        result.push(self.content_type.as_u8());
+        result.extend_from_slice(&self.version.as_u16().to_be_bytes());
+        result.extend_from_slice(&(self.fragment.len() as u16).to_be_bytes());
+        result.extend_from_slice(&self.fragment);
+        result
+    }
+}
+
+/// Encryption and integrity protection of records in one direction, turning
+/// TLSPlaintext into TLSCiphertext and back. TLS 1.3 hides the real content type
+/// inside the ciphertext, so the header may change as well as the fragment.
+/// Implementations keep their own sequence numbers.
+pub trait RecordProtection: Send {
+    fn encrypt(&mut self, record: Record) -> Result<Record>;
+    /// Fails with a bad_record_mac alert when the record does not authenticate
+    fn decrypt(&mut self, record: Record) -> Result<Record>;
+    /// Whether application data goes out with its first byte in a record of its own,
+    /// the 1/n-1 split that denies BEAST the chosen first block under a predictable IV
+    fn split_application_data(&self) -> bool {
+        false
+    }
+}
+
+/// A complete handshake message, possibly reassembled from several records
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandshakeMessage {
+    pub msg_type: u8,
+    pub body: Vec<u8>,
+}
+
+impl HandshakeMessage {
+    pub fn new(msg_type: u8, body: Vec<u8>) -> Self {
+        Self {
+            msg_type,
+            body,
+        }
+    }
+
+    /// msg_type || uint24 length || body, the form that goes into the transcript
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::with_capacity(HANDSHAKE_HEADER_LEN + self.body.len());
+        result.push(self.msg_type);
+        result.extend_from_slice(&(self.body.len() as u32).to_be_bytes()[1..]);
+        result.extend_from_slice(&self.body);
+        result
+    }
+}
+
+/// Unit delivered by `RecordLayer::read_message`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Handshake(HandshakeMessage),
+    ChangeCipherSpec,
+    Alert(Alert),
+    ApplicationData(Vec<u8>),
+}
+
+/// Frames records over any byte stream
+/// Outgoing data is split into fragments of at most 2^14 bytes; incoming handshake
+/// messages are reassembled across record boundaries. `Read`/`Write` carry application data.
+pub struct RecordLayer<S: Read + Write> {
+    stream: S,
+    version: ProtocolVersion,
+    read_protection: Option<Box<dyn RecordProtection>>,
+    write_protection: Option<Box<dyn RecordProtection>>,
+    max_handshake_message_len: usize,
+    allow_plaintext_change_cipher_spec: bool,
+    handshake_buffer: Vec<u8>,
+    application_buffer: Vec<u8>,
+    application_pos: usize,
+    received_close_notify: bool,
+    /// records and plaintext bytes written under the current write protection
+    records_written: u64,
+    bytes_written: u64,
+    tracer: Option<Arc<dyn Tracer>>,
+}
+
+impl<S: Read + Write> RecordLayer<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            // what most implementations put in the record carrying the first ClientHello
+            version: ProtocolVersion::TLS1_0,
+            read_protection: None,
+            write_protection: None,
+            max_handshake_message_len: MAX_HANDSHAKE_MESSAGE_LEN,
+            allow_plaintext_change_cipher_spec: false,
+            handshake_buffer: vec![],
+            application_buffer: vec![],
+            application_pos: 0,
+            received_close_notify: false,
+            records_written: 0,
+            bytes_written: 0,
+            tracer: None,
+        }
+    }
+
+    /// Reports every record, handshake message and alert sent or received to `tracer`
+    pub fn set_tracer(&mut self, tracer: Option<Arc<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(tracer) = &self.tracer {
+            tracer.trace(&event());
+        }
+    }
+
+    /// Version written to outgoing record headers
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Protects every record read from now on, i.e. after receiving ChangeCipherSpec
+    pub fn set_read_protection(&mut self, protection: Box<dyn RecordProtection>) {
+        self.read_protection = Some(protection);
+    }
+
+    /// Protects every record written from now on, i.e. after sending ChangeCipherSpec
+    pub fn set_write_protection(&mut self, protection: Box<dyn RecordProtection>) {
+        self.write_protection = Some(protection);
+        self.records_written = 0;
+        self.bytes_written = 0;
+    }
+
+    /// Lets ChangeCipherSpec records bypass protection, as during a TLS 1.3 handshake
+    pub fn set_allow_plaintext_change_cipher_spec(&mut self, allow: bool) {
+        self.allow_plaintext_change_cipher_spec = allow;
+    }
+
+    pub fn is_read_protected(&self) -> bool {
+        self.read_protection.is_some()
+    }
+
+    /// Records written since the write protection last changed
+    pub fn records_written(&self) -> u64 {
+        self.records_written
+    }
+
+    /// Plaintext bytes written since the write protection last changed
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Whether the last handshake message read ended its record, as a message
+    /// that changes keys has to (RFC 8446 section 5.1)
+    pub fn at_record_boundary(&self) -> bool {
+        self.handshake_buffer.is_empty()
+    }
+
+    pub fn set_max_handshake_message_len(&mut self, len: usize) {
+        self.max_handshake_message_len = len;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Reads one record. Returns `None` on end of stream at a record boundary.
+    pub fn read_record(&mut self) -> Result<Option<Record>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        let mut filled = 0;
+        while filled < RECORD_HEADER_LEN {
+            match self.stream.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(anyhow!("Connection closed in the middle of a record header")),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let content_type = ContentType::from_u8(header[0])?;
+        let version = ProtocolVersion::from_u16(u16::from_be_bytes([header[1], header[2]]));
+        if version.major != 3 {
+            return Err(alert::fatal(PROTOCOL_VERSION, format!("Unsupported record version: {}", version)));
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if length > MAX_CIPHERTEXT_LEN {
+            return Err(alert::fatal(RECORD_OVERFLOW, format!("Record overflow: {} bytes", length)));
+        }
+        let mut fragment = vec![0u8; length];
+        self.stream.read_exact(&mut fragment)?;
+        let mut record = Record {
+            content_type,
+            version,
+            fragment,
+        };
+        // TLS 1.3 middlebox compatibility sends ChangeCipherSpec in the clear (RFC 8446 section 5)
+        let compat_change_cipher_spec = self.allow_plaintext_change_cipher_spec && content_type == ContentType::ChangeCipherSpec;
+        let protected = self.read_protection.is_some() && !compat_change_cipher_spec;
+        if let (Some(protection), false) = (self.read_protection.as_mut(), compat_change_cipher_spec) {
+            record = protection.decrypt(record)?;
+        }
+        self.trace(|| TraceEvent::record(Direction::Received, record.content_type, record.version, record.fragment.len(), protected));
+        if record.fragment.len() > MAX_FRAGMENT_LEN {
+            return Err(alert::fatal(RECORD_OVERFLOW, format!("Record overflow: plaintext of {} bytes", record.fragment.len())));
+        }
+        if record.fragment.is_empty() && record.content_type != ContentType::ApplicationData {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Empty {:?} record", record.content_type)));
+        }
+        Ok(Some(record))
+    }
+
+    /// Protects and writes one record. The fragment must not exceed 2^14 bytes.
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let encoded = self.encode_record(record.content_type, record.version, &record.fragment)?;
+        self.stream.write_all(&encoded)?;
+        Ok(())
+    }
+
+    fn encode_record(&mut self, content_type: ContentType, version: ProtocolVersion, fragment: &[u8]) -> Result<Vec<u8>> {
+        if fragment.len() > MAX_FRAGMENT_LEN {
+            return Err(anyhow!("Fragment too large for a single record: {} bytes", fragment.len()));
+        }
+        let record = Record {
+            content_type,
+            version,
+            fragment: fragment.to_vec(),
+        };
+        // see read_record
+        let compat_change_cipher_spec = self.allow_plaintext_change_cipher_spec && content_type == ContentType::ChangeCipherSpec;
+        self.trace(|| TraceEvent::record(Direction::Sent, content_type, version, fragment.len(), self.write_protection.is_some() && !compat_change_cipher_spec));
+        let record = match self.write_protection.as_mut() {
+            Some(_) if compat_change_cipher_spec => record,
+            Some(protection) => protection.encrypt(record)?,
+            None => record,
+        };
+        self.records_written += 1;
+        self.bytes_written += fragment.len() as u64;
+        Ok(record.encode())
+    }
+
+    /// Sends `data` as one or more records of `content_type`
+    pub fn send(&mut self, content_type: ContentType, data: &[u8]) -> Result<()> {
+        let mut out: Vec<u8> = Vec::with_capacity(data.len() + (data.len() / MAX_FRAGMENT_LEN + 1) * 2 * RECORD_HEADER_LEN);
+        let split = content_type == ContentType::ApplicationData && self.write_protection.as_ref().is_some_and(|p| p.split_application_data());
+        for chunk in data.chunks(MAX_FRAGMENT_LEN) {
+            if split && chunk.len() > 1 {
+                let encoded = self.encode_record(content_type, self.version, &chunk[..1])?;
+                out.extend_from_slice(&encoded);
+                let encoded = self.encode_record(content_type, self.version, &chunk[1..])?;
+                out.extend_from_slice(&encoded);
+            } else {
+                let encoded = self.encode_record(content_type, self.version, chunk)?;
+                out.extend_from_slice(&encoded);
+            }
+        }
+        self.stream.write_all(&out)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    pub fn send_handshake(&mut self, message: &HandshakeMessage) -> Result<()> {
+        self.trace(|| TraceEvent::handshake(Direction::Sent, message));
+        self.send(ContentType::Handshake, &message.encode())
+    }
+
+    pub fn send_alert(&mut self, alert: Alert) -> Result<()> {
+        self.trace(|| TraceEvent::alert(Direction::Sent, &alert));
+        self.send(ContentType::Alert, &alert.encode())
+    }
+
+    pub fn send_fatal_alert(&mut self, description: AlertDescription) -> Result<()> {
+        self.send_alert(Alert::new(AlertLevel::Fatal, description))
+    }
+
+    pub fn send_change_cipher_spec(&mut self) -> Result<()> {
+        self.send(ContentType::ChangeCipherSpec, &[1])
+    }
+
+    /// Reads the next complete message. Returns `None` on end of stream.
+    pub fn read_message(&mut self) -> Result<Option<Message>> {
+        loop {
+            if let Some(message) = self.take_handshake_message()? {
+                return Ok(Some(Message::Handshake(message)));
+            }
+            let record = match self.read_record()? {
+                Some(record) => record,
+                None if self.handshake_buffer.is_empty() => return Ok(None),
+                None => return Err(anyhow!("Connection closed in the middle of a handshake message")),
+            };
+            if record.content_type != ContentType::Handshake && !self.handshake_buffer.is_empty() {
+                return Err(alert::fatal(UNEXPECTED_MESSAGE, format!("{:?} record interleaved with a fragmented handshake message", record.content_type)));
+            }
+            match record.content_type {
+                ContentType::Handshake => self.handshake_buffer.extend_from_slice(&record.fragment),
+                ContentType::ChangeCipherSpec => {
+                    if record.fragment != [1] {
+                        return Err(alert::fatal(DECODE_ERROR, "Malformed ChangeCipherSpec"));
+                    }
+                    return Ok(Some(Message::ChangeCipherSpec));
+                },
+                ContentType::Alert => {
+                    let alert = Alert::parse(&record.fragment)?;
+                    self.trace(|| TraceEvent::alert(Direction::Received, &alert));
+                    return Ok(Some(Message::Alert(alert)));
+                },
+                ContentType::ApplicationData => return Ok(Some(Message::ApplicationData(record.fragment))),
+            }
+        }
+    }
+
+    fn take_handshake_message(&mut self) -> Result<Option<HandshakeMessage>> {
+        if self.handshake_buffer.len() < HANDSHAKE_HEADER_LEN {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([0, self.handshake_buffer[1], self.handshake_buffer[2], self.handshake_buffer[3]]) as usize;
+        if length > self.max_handshake_message_len {
+            return Err(alert::fatal(DECODE_ERROR, format!("Handshake message too large: {} bytes", length)));
+        }
+        if self.handshake_buffer.len() < HANDSHAKE_HEADER_LEN + length {
+            return Ok(None);
+        }
+        let rest = self.handshake_buffer.split_off(HANDSHAKE_HEADER_LEN + length);
+        let message = std::mem::replace(&mut self.handshake_buffer, rest);
+        let message = HandshakeMessage {
+            msg_type: message[0],
+            body: message[HANDSHAKE_HEADER_LEN..].to_vec(),
+        };
+        self.trace(|| TraceEvent::handshake(Direction::Received, &message));
+        Ok(Some(message))
+    }
+
+    /// Sends close_notify. Application data sent afterwards is a protocol error.
+    pub fn close(&mut self) -> Result<()> {
+        self.send_alert(Alert::new(AlertLevel::Warning, CLOSE_NOTIFY))
+    }
+}
+
+pub(crate) fn to_io_error(e: anyhow::Error) -> io::Error {
+    if let Some(alert) = e.downcast_ref::<PeerAlert>() {
+        return io::Error::new(io::ErrorKind::ConnectionAborted, *alert);
+    }
+    match e.downcast::<io::Error>() {
+        Ok(e) => e,
+        Err(e) => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+impl<S: Read + Write> RecordLayer<S> {
+    /// Sends the fatal alert for a local failure after the handshake, unless the
+    /// transport itself failed or the peer sent one, and turns the error into an `io::Error`
+    fn fail(&mut self, e: anyhow::Error) -> io::Error {
+        if !e.is::<io::Error>() && !e.is::<PeerAlert>() {
+            let _ = self.send_fatal_alert(alert::description_of(&e));
+        }
+        to_io_error(e)
+    }
+
+    /// Keeps application data that arrived during a renegotiation for the next read
+    pub fn buffer_application_data(&mut self, data: Vec<u8>) {
+        self.application_buffer.drain(..self.application_pos);
+        self.application_pos = 0;
+        self.application_buffer.extend(data);
+    }
+
+    /// Reads application data like `Read::read`, passing any handshake message that
+    /// arrives first to `on_handshake`, e.g. TLS 1.3 NewSessionTicket
+    pub fn read_application_data<F>(&mut self, buf: &mut [u8], mut on_handshake: F) -> io::Result<usize>
+    where
+        F: FnMut(&mut Self, HandshakeMessage) -> Result<()>,
+    {
+        while self.application_pos == self.application_buffer.len() {
+            if self.received_close_notify {
+                return Ok(0);
+            }
+            let message = match self.read_message() {
+                Ok(message) => message,
+                Err(e) => return Err(self.fail(e)),
+            };
+            match message {
+                Some(Message::ApplicationData(data)) => self.buffer_application_data(data),
+                Some(Message::Handshake(message)) => {
+                    if let Err(e) = on_handshake(self, message) {
+                        return Err(self.fail(e));
+                    }
+                },
+                Some(Message::Alert(alert)) if alert.description == CLOSE_NOTIFY => self.received_close_notify = true,
+                // a TLS 1.2 peer turning down our HelloRequest, which leaves the connection as it is
+                Some(Message::Alert(alert)) if alert.level == AlertLevel::Warning && alert.description == NO_RENEGOTIATION => (),
+                Some(Message::Alert(alert)) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, PeerAlert(alert))),
+                Some(message) => {
+                    return Err(self.fail(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected message while reading application data: {:?}", message))));
+                },
+                // a peer that closes without close_notify may be truncating the data
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed without close_notify")),
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.application_buffer.len() - self.application_pos);
+        buf[..n].copy_from_slice(&self.application_buffer[self.application_pos..self.application_pos + n]);
+        self.application_pos += n;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Read for RecordLayer<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_application_data(buf, |_, message| {
+            Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected handshake message {} after the handshake", message.msg_type)))
+        })
+    }
+}
+
+impl<S: Read + Write> Write for RecordLayer<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(ContentType::ApplicationData, buf).map_err(to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockStream;
+
+    fn record(content_type: ContentType, fragment: &[u8]) -> Vec<u8> {
+        Record {
+            content_type,
+            version: ProtocolVersion::TLS1_2,
+            fragment: fragment.to_vec(),
+        }.encode()
+    }
+
+    #[test]
+    fn test_can_fragment_outgoing_data() {
+        let mut layer = RecordLayer::new(MockStream::new(vec![]));
+        layer.set_version(ProtocolVersion::TLS1_2);
+        let data = vec![0xabu8; 40_000];
+        layer.write_all(&data).unwrap();
+        let output = layer.into_inner().output;
+        let mut reader = RecordLayer::new(MockStream::new(output));
+        let mut lengths: Vec<usize> = vec![];
+        while let Some(record) = reader.read_record().unwrap() {
+            assert_eq!(ContentType::ApplicationData, record.content_type);
+            assert_eq!(ProtocolVersion::TLS1_2, record.version);
+            lengths.push(record.fragment.len());
+        }
+        assert_eq!(vec![16384, 16384, 7232], lengths);
+    }
+
+    #[test]
+    fn test_can_reassemble_handshake_messages() {
+        let first = HandshakeMessage::new(11, vec![7u8; 20_000]).encode();
+        let second = HandshakeMessage::new(14, vec![]).encode();
+        let third = HandshakeMessage::new(16, vec![1, 2, 3]).encode();
+        let mut input: Vec<u8> = vec![];
+        // first message split across three records, the tail sharing a record with the next two
+        let mut tail = first[16384 + 2..].to_vec();
+        tail.extend_from_slice(&second);
+        tail.extend_from_slice(&third);
+        input.extend(record(ContentType::Handshake, &first[..2]));
+        input.extend(record(ContentType::Handshake, &first[2..16384 + 2]));
+        input.extend(record(ContentType::Handshake, &tail));
+        input.extend(record(ContentType::ChangeCipherSpec, &[1]));
+        let mut layer = RecordLayer::new(MockStream::new(input));
+        assert_eq!(Some(Message::Handshake(HandshakeMessage::new(11, vec![7u8; 20_000]))), layer.read_message().unwrap());
+        assert_eq!(Some(Message::Handshake(HandshakeMessage::new(14, vec![]))), layer.read_message().unwrap());
+        assert_eq!(Some(Message::Handshake(HandshakeMessage::new(16, vec![1, 2, 3]))), layer.read_message().unwrap());
+        assert_eq!(Some(Message::ChangeCipherSpec), layer.read_message().unwrap());
+        assert_eq!(None, layer.read_message().unwrap());
+    }
+
+    #[test]
+    fn test_can_read_application_data_until_close_notify() {
+        let mut input: Vec<u8> = vec![];
+        input.extend(record(ContentType::ApplicationData, b"HTTP/1.1 200 OK\r\n"));
+        input.extend(record(ContentType::ApplicationData, b""));
+        input.extend(record(ContentType::ApplicationData, b"\r\n"));
+        input.extend(record(ContentType::Alert, &Alert::new(AlertLevel::Warning, CLOSE_NOTIFY).encode()));
+        let mut layer = RecordLayer::new(MockStream::new(input));
+        let mut response = String::new();
+        layer.read_to_string(&mut response).unwrap();
+        assert_eq!("HTTP/1.1 200 OK\r\n\r\n", response);
+    }
+
+    #[test]
+    fn test_can_surface_received_alert() {
+        let mut input = record(ContentType::ApplicationData, b"partial");
+        input.extend(record(ContentType::Alert, &Alert::new(AlertLevel::Fatal, alert::INTERNAL_ERROR).encode()));
+        let mut layer = RecordLayer::new(MockStream::new(input));
+        let mut response = vec![];
+        let e = layer.read_to_end(&mut response).unwrap_err();
+        assert_eq!(b"partial", &response[..]);
+        assert_eq!(Some(Alert::new(AlertLevel::Fatal, alert::INTERNAL_ERROR)), alert::received_alert_io(&e));
+
+        // a malformed alert is answered with decode_error
+        let input = record(ContentType::Alert, &[2]);
+        let mut layer = RecordLayer::new(MockStream::new(input));
+        assert!(layer.read(&mut [0u8; 16]).is_err());
+        assert!(layer.stream.output.ends_with(&Alert::new(AlertLevel::Fatal, DECODE_ERROR).encode()));
+    }
+
+    #[test]
+    fn test_can_return_error_oversized_record() {
+        let mut input = record(ContentType::ApplicationData, &[]);
+        let length = (MAX_CIPHERTEXT_LEN + 1) as u16;
+        input[3..5].copy_from_slice(&length.to_be_bytes());
+        assert!(RecordLayer::new(MockStream::new(input)).read_record().is_err());
+        // no protection is active, so the plaintext limit applies
+        let mut input = record(ContentType::ApplicationData, &[]);
+        input[3..5].copy_from_slice(&((MAX_FRAGMENT_LEN + 1) as u16).to_be_bytes());
+        input.resize(RECORD_HEADER_LEN + MAX_FRAGMENT_LEN + 1, 0);
+        assert!(RecordLayer::new(MockStream::new(input)).read_record().is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_malformed_handshake_stream() {
+        // declared length exceeds the limit
+        let input = record(ContentType::Handshake, &[11, 0xff, 0xff, 0xff]);
+        assert!(RecordLayer::new(MockStream::new(input)).read_message().is_err());
+        // alert in the middle of a fragmented message
+        let mut input = record(ContentType::Handshake, &[11, 0, 0, 10, 1]);
+        input.extend(record(ContentType::Alert, &Alert::new(AlertLevel::Fatal, alert::HANDSHAKE_FAILURE).encode()));
+        assert!(RecordLayer::new(MockStream::new(input)).read_message().is_err());
+        // empty handshake record
+        let input = record(ContentType::Handshake, &[]);
+        assert!(RecordLayer::new(MockStream::new(input)).read_message().is_err());
+        // truncated message at end of stream
+        let input = record(ContentType::Handshake, &[11, 0, 0, 10, 1]);
+        assert!(RecordLayer::new(MockStream::new(input)).read_message().is_err());
+    }
+}
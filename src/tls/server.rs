@@ -0,0 +1,1925 @@
+use std::io::{
+    Read,
+    Write,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::asn1::Asn1Time;
+use crate::dh::DhParams;
+use crate::hmac::constant_time_eq;
+use crate::random::{
+    fill_random,
+    random_bytes,
+};
+use crate::rsa::RsaPrivateKey;
+use crate::verify::{
+    KeyPurpose,
+    TrustStore,
+};
+use crate::x509::{
+    Certificate,
+    KeyUsage,
+};
+
+use super::alert::{
+    self,
+    PeerAlert,
+    BAD_CERTIFICATE,
+    CERTIFICATE_REQUIRED,
+    DECODE_ERROR,
+    DECRYPT_ERROR,
+    HANDSHAKE_FAILURE,
+    ILLEGAL_PARAMETER,
+    MISSING_EXTENSION,
+    NO_APPLICATION_PROTOCOL,
+    PROTOCOL_VERSION,
+    UNEXPECTED_MESSAGE,
+};
+use super::cipher::{
+    derive_protections,
+    tls13_protection,
+};
+use super::extensions::{
+    self,
+    Extension,
+    HelloContext,
+};
+use super::handshake::{
+    self,
+    CertificateRequest,
+    ClientHello,
+    KeyShareEntry,
+    NewSessionTicketTls13,
+    OfferedPsks,
+    ServerHello,
+    Transcript,
+    HELLO_RETRY_REQUEST_RANDOM,
+    MAX_SESSION_ID_LEN,
+    PRE_MASTER_SECRET_LEN,
+    RANDOM_LEN,
+};
+use super::keylog::{
+    self,
+    KeyLog,
+};
+use super::key_schedule::{
+    finished_verify_data,
+    psk_binder,
+    resumption_psk,
+    KeySchedule,
+};
+use super::kx::{
+    self,
+    EphemeralKey,
+    NamedGroup,
+    ServerKeyExchange,
+    ServerKeyExchangeParams,
+    ALL_GROUPS,
+};
+use super::policy::SecurityLevel;
+use super::prf::Prf;
+use super::signature;
+use super::record::{
+    HandshakeMessage,
+    Message,
+    ProtocolVersion,
+    RecordLayer,
+    RecordProtection,
+};
+use super::session::{
+    ServerSession,
+    ServerSessionCache,
+    TicketKeys,
+    DEFAULT_CACHE_CAPACITY,
+    DEFAULT_SESSION_LIFETIME,
+    MAX_TICKET_LIFETIME,
+};
+use super::stream::{
+    Endpoint,
+    Rekeying,
+    SecureRenegotiation,
+    SessionInfo,
+    TlsStream,
+};
+use super::suites::{
+    ALL_SUITES,
+    Authentication,
+    CipherSuite,
+    KeyExchange,
+    TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+};
+use super::trace::Tracer;
+
+/// Signature schemes for ServerKeyExchange, in order of the server's preference
+const SUPPORTED_SIGNATURE_SCHEMES: [u16; 4] = [
+    signature::RSA_PKCS1_SHA256,
+    signature::RSA_PKCS1_SHA384,
+    signature::RSA_PKCS1_SHA512,
+    signature::RSA_PKCS1_SHA1,
+];
+
+/// Signature schemes for TLS 1.3 CertificateVerify, in order of the server's preference
+const TLS13_SIGNATURE_SCHEMES: [u16; 3] = [
+    signature::RSA_PSS_RSAE_SHA256,
+    signature::RSA_PSS_RSAE_SHA384,
+    signature::RSA_PSS_RSAE_SHA512,
+];
+
+/// Signature schemes we accept in a client's CertificateVerify. TLS 1.3 leaves out the PKCS#1 ones.
+const CLIENT_SIGNATURE_SCHEMES: [u16; 9] = [
+    signature::ECDSA_SECP256R1_SHA256,
+    signature::ECDSA_SECP384R1_SHA384,
+    signature::RSA_PSS_RSAE_SHA256,
+    signature::RSA_PSS_RSAE_SHA384,
+    signature::RSA_PSS_RSAE_SHA512,
+    signature::RSA_PKCS1_SHA256,
+    signature::RSA_PKCS1_SHA384,
+    signature::RSA_PKCS1_SHA512,
+    signature::RSA_PKCS1_SHA1,
+];
+
+/// Whether clients are asked for a certificate. Their chains have to lead to the
+/// trust store given here, which is kept apart from the anchors used for servers.
+#[derive(Debug, Clone)]
+pub enum ClientAuth {
+    None,
+    /// a client may send no certificate, but one it sends has to verify
+    Optional(TrustStore),
+    /// a client without a valid certificate fails the handshake
+    Required(TrustStore),
+}
+
+impl ClientAuth {
+    fn trust_store(&self) -> Option<&TrustStore> {
+        match self {
+            ClientAuth::None => None,
+            ClientAuth::Optional(trust_store) | ClientAuth::Required(trust_store) => Some(trust_store),
+        }
+    }
+
+    fn is_required(&self) -> bool {
+        matches!(self, ClientAuth::Required(_))
+    }
+}
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// DER certificates, the server's own first
+    pub certificate_chain: Vec<Vec<u8>>,
+    pub private_key: RsaPrivateKey,
+    /// in order of the server's preference
+    pub cipher_suites: Vec<&'static CipherSuite>,
+    /// picks the first of our suites the client offers rather than the first of the client's we support
+    pub prefer_server_cipher_suites: bool,
+    /// ECDHE groups in order of the server's preference
+    pub groups: Vec<NamedGroup>,
+    /// group for DHE suites
+    pub dh_params: DhParams,
+    /// protocol versions to accept, in order of the server's preference
+    pub versions: Vec<ProtocolVersion>,
+    /// application protocols for ALPN in order of the server's preference. Empty ignores ALPN.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// TLS 1.2 sessions by session ID. None gives out no session IDs.
+    pub session_cache: Option<Arc<ServerSessionCache>>,
+    /// keys for RFC 5077 and TLS 1.3 tickets. None issues no tickets.
+    pub ticket_keys: Option<Arc<TicketKeys>>,
+    /// seconds a session can be resumed for
+    pub session_lifetime: u32,
+    pub client_auth: ClientAuth,
+    /// TLS 1.2: lets clients renegotiate when they choose to rather than only after
+    /// our HelloRequest. Clients without secure renegotiation never can.
+    pub allow_client_renegotiation: bool,
+    /// where the secrets of each handshake go, e.g. a `KeyLogFile` for SSLKEYLOGFILE
+    pub key_log: Option<Arc<dyn KeyLog>>,
+    /// takes every record, handshake message and alert of the connections
+    pub tracer: Option<Arc<dyn Tracer>>,
+}
+
+impl ServerConfig {
+    pub fn new(certificate_chain: Vec<Vec<u8>>, private_key: RsaPrivateKey) -> Result<Self> {
+        signature::check_certificate_key(&certificate_chain, &private_key)?;
+        Ok(Self {
+            certificate_chain,
+            private_key,
+            cipher_suites: ALL_SUITES.to_vec(),
+            prefer_server_cipher_suites: true,
+            groups: ALL_GROUPS.to_vec(),
+            dh_params: DhParams::ffdhe2048(),
+            versions: vec![ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2],
+            alpn_protocols: vec![],
+            session_cache: Some(Arc::new(ServerSessionCache::new(DEFAULT_CACHE_CAPACITY))),
+            ticket_keys: Some(Arc::new(TicketKeys::new()?)),
+            session_lifetime: DEFAULT_SESSION_LIFETIME,
+            client_auth: ClientAuth::None,
+            allow_client_renegotiation: false,
+            key_log: None,
+            tracer: None,
+        })
+    }
+
+    /// Loads the chain (leaf first) and the key from PEM files. Both may be the same file.
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(certificate_path: P, key_path: Q) -> Result<Self> {
+        let (chain, key) = signature::read_certificate_and_key(certificate_path.as_ref(), key_path.as_ref())?;
+        Self::new(chain, key)
+    }
+
+    /// Restricts the versions and cipher suites to those of `level`, keeping its order
+    pub fn apply_security_level(&mut self, level: SecurityLevel) {
+        self.versions = level.versions();
+        self.cipher_suites = level.cipher_suites();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    ExpectClientHello,
+    ExpectCertificate,
+    ExpectClientKeyExchange,
+    ExpectCertificateVerify,
+    ExpectChangeCipherSpec,
+    ExpectFinished,
+    Connected,
+}
+
+pub(crate) struct ServerHandshake {
+    config: Arc<ServerConfig>,
+    state: State,
+    /// the negotiated version, known once ServerHello or HelloRetryRequest is sent
+    version: Option<ProtocolVersion>,
+    transcript: Transcript,
+    client_version: ProtocolVersion,
+    client_random: [u8; RANDOM_LEN],
+    server_random: [u8; RANDOM_LEN],
+    cipher_suite: Option<&'static CipherSuite>,
+    /// TLS 1.2: the ID the session is cached under, empty if it is not
+    session_id: Vec<u8>,
+    resumed: bool,
+    /// a ticket is sent, in TLS 1.2 because the client sent session_ticket and in
+    /// TLS 1.3 because it allows psk_dhe_ke
+    send_ticket: bool,
+    /// our half of the DHE or ECDHE exchange, sent in ServerKeyExchange
+    ephemeral_key: Option<EphemeralKey>,
+    /// TLS 1.2: agreed with the client, encrypt_then_mac only for a CBC suite
+    encrypt_then_mac: bool,
+    extended_master_secret: bool,
+    master_secret: Vec<u8>,
+    /// protections for (client write, server write), activated by each side's ChangeCipherSpec
+    pending_read_protection: Option<Box<dyn RecordProtection>>,
+    pending_write_protection: Option<Box<dyn RecordProtection>>,
+    /// TLS 1.3: the group asked for in HelloRetryRequest, and the client secrets
+    /// still needed after our flight
+    hello_retry_group: Option<NamedGroup>,
+    client_handshake_secret: Vec<u8>,
+    client_application_secret: Vec<u8>,
+    /// TLS 1.3: our traffic secret, which KeyUpdate steps forward after the handshake
+    server_application_secret: Vec<u8>,
+    /// the master secret stage, for the resumption master secret
+    key_schedule: Option<KeySchedule>,
+    /// a CertificateRequest was sent
+    requested_certificate: bool,
+    /// the client's verified chain, leaf first
+    client_certificates: Vec<Certificate>,
+    /// ClientHello extensions, for the session
+    client_extensions: Vec<Extension>,
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    /// TLS 1.2: the Finished messages of the previous handshake, replaced by ours when done
+    secure_renegotiation: SecureRenegotiation,
+}
+
+/// Performs the server side of a TLS 1.3 or 1.2 handshake over `stream`.
+/// On failure the matching fatal alert is sent before the error is returned.
+pub fn accept<S: Read + Write>(stream: S, config: &ServerConfig) -> Result<TlsStream<S>> {
+    let mut record_layer = RecordLayer::new(stream);
+    record_layer.set_tracer(config.tracer.clone());
+    let mut handshake = ServerHandshake::new(Arc::new(config.clone()))?;
+    if let Err(e) = handshake.run(&mut record_layer) {
+        if !e.is::<PeerAlert>() {
+            let _ = record_layer.send_fatal_alert(alert::description_of(&e));
+        }
+        return Err(e);
+    }
+    Ok(handshake.into_stream(record_layer))
+}
+
+/// Answers the ClientHello of a client renegotiating an established TLS 1.2 connection,
+/// which has to bind it to the previous handshake with renegotiation_info (RFC 5746)
+pub(crate) fn renegotiate<S: Read + Write>(record_layer: &mut RecordLayer<S>, config: &Arc<ServerConfig>, previous: &SecureRenegotiation, client_hello: HandshakeMessage) -> Result<(SessionInfo, SecureRenegotiation)> {
+    let mut handshake = ServerHandshake::new(Arc::clone(config))?;
+    handshake.secure_renegotiation = previous.clone();
+    handshake.handle_handshake(record_layer, client_hello)?;
+    handshake.run(record_layer)?;
+    Ok((handshake.session_info(), handshake.secure_renegotiation))
+}
+
+impl ServerHandshake {
+    pub(crate) fn new(config: Arc<ServerConfig>) -> Result<Self> {
+        let mut server_random = [0u8; RANDOM_LEN];
+        fill_random(&mut server_random)?;
+        Ok(Self {
+            config,
+            state: State::ExpectClientHello,
+            version: None,
+            transcript: Transcript::new(),
+            client_version: ProtocolVersion::TLS1_2,
+            client_random: [0u8; RANDOM_LEN],
+            server_random,
+            cipher_suite: None,
+            session_id: vec![],
+            resumed: false,
+            send_ticket: false,
+            ephemeral_key: None,
+            encrypt_then_mac: false,
+            extended_master_secret: false,
+            master_secret: vec![],
+            pending_read_protection: None,
+            pending_write_protection: None,
+            hello_retry_group: None,
+            client_handshake_secret: vec![],
+            client_application_secret: vec![],
+            server_application_secret: vec![],
+            key_schedule: None,
+            requested_certificate: false,
+            client_certificates: vec![],
+            client_extensions: vec![],
+            server_name: None,
+            alpn_protocol: None,
+            secure_renegotiation: SecureRenegotiation::default(),
+        })
+    }
+
+    /// The established connection, once the handshake is done
+    pub(crate) fn into_stream<S: Read + Write>(mut self, record_layer: RecordLayer<S>) -> TlsStream<S> {
+        let rekeying = if self.is_tls13() {
+            Rekeying::KeyUpdate {
+                read_secret: std::mem::take(&mut self.client_application_secret),
+                write_secret: std::mem::take(&mut self.server_application_secret),
+            }
+        } else {
+            Rekeying::Renegotiation {
+                endpoint: Endpoint::Server {
+                    config: Arc::clone(&self.config),
+                },
+                secure_renegotiation: self.secure_renegotiation.clone(),
+            }
+        };
+        let session = self.session_info();
+        TlsStream::new(record_layer, session, None, rekeying)
+    }
+
+    fn session_info(&mut self) -> SessionInfo {
+        SessionInfo {
+            version: self.version.unwrap(),
+            cipher_suite: self.cipher_suite.unwrap(),
+            peer_certificates: std::mem::take(&mut self.client_certificates),
+            server_name: self.server_name.take(),
+            alpn_protocol: self.alpn_protocol.take(),
+            peer_extensions: std::mem::take(&mut self.client_extensions),
+            resumed: self.resumed,
+        }
+    }
+
+    fn is_tls13(&self) -> bool {
+        self.version == Some(ProtocolVersion::TLS1_3)
+    }
+
+    /// Passes a secret of this handshake to the key log, if there is one
+    fn log_key(&self, label: &str, secret: &[u8]) {
+        if let Some(key_log) = &self.config.key_log {
+            key_log.log(label, &self.client_random, secret);
+        }
+    }
+
+    /// The key derivation of the negotiated version and suite, before TLS 1.3
+    fn prf(&self) -> Prf {
+        Prf::new(self.version.unwrap(), self.cipher_suite.unwrap())
+    }
+
+    fn run<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>) -> Result<()> {
+        while !self.is_connected() {
+            let message = record_layer.read_message()?.ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "Connection closed during the handshake"))?;
+            self.handle_message(record_layer, message)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Takes the next message from the client, answering it if it ends a flight
+    pub(crate) fn handle_message<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, message: Message) -> Result<()> {
+        match message {
+            Message::Handshake(message) => self.handle_handshake(record_layer, message),
+            Message::ChangeCipherSpec => self.handle_change_cipher_spec(record_layer),
+            Message::Alert(alert) => Err(PeerAlert(alert).into()),
+            // the client may carry on sending while it renegotiates
+            Message::ApplicationData(data) if self.secure_renegotiation.is_renegotiation() => {
+                record_layer.buffer_application_data(data);
+                Ok(())
+            },
+            Message::ApplicationData(_) => Err(alert::fatal(UNEXPECTED_MESSAGE, "Application data during the handshake")),
+        }
+    }
+
+    fn send(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        self.transcript.add(&message);
+        record_layer.send_handshake(&message)
+    }
+
+    fn handle_handshake(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        match (self.state, message.msg_type) {
+            (State::ExpectClientHello, handshake::CLIENT_HELLO) => {
+                self.transcript.add(&message);
+                self.handle_client_hello(record_layer, &message.body)
+            },
+            (State::ExpectCertificate, handshake::CERTIFICATE) => {
+                self.transcript.add(&message);
+                self.handle_certificate(&message.body)
+            },
+            (State::ExpectClientKeyExchange, handshake::CLIENT_KEY_EXCHANGE) => {
+                self.transcript.add(&message);
+                self.handle_client_key_exchange(&message.body)
+            },
+            (State::ExpectCertificateVerify, handshake::CERTIFICATE_VERIFY) => {
+                self.handle_certificate_verify(&message.body)?;
+                self.transcript.add(&message);
+                self.state = if self.is_tls13() { State::ExpectFinished } else { State::ExpectChangeCipherSpec };
+                Ok(())
+            },
+            (State::ExpectFinished, handshake::FINISHED) if self.is_tls13() => self.handle_finished_tls13(record_layer, message),
+            (State::ExpectFinished, handshake::FINISHED) => {
+                self.handle_finished(&message.body)?;
+                self.transcript.add(&message);
+                // in an abbreviated handshake we have already finished
+                if !self.resumed {
+                    self.send_finished(record_layer)?;
+                }
+                self.cache_session();
+                self.state = State::Connected;
+                Ok(())
+            },
+            (state, msg_type) => Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected {} in state {:?}", handshake::handshake_type_name(msg_type), state))),
+        }
+    }
+
+    /// Chooses the parameters and answers with ServerHello, Certificate and ServerHelloDone
+    fn handle_client_hello(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, body: &[u8]) -> Result<()> {
+        let client_hello = ClientHello::parse(body)?;
+        self.handle_client_extensions(&client_hello)?;
+        let renegotiation = self.secure_renegotiation.is_renegotiation();
+        let version = if renegotiation {
+            // the version stays the one of the connection
+            let version = record_layer.version();
+            if client_hello.version < version {
+                return Err(alert::fatal(PROTOCOL_VERSION, format!("Client renegotiates with {}", client_hello.version)));
+            }
+            version
+        } else {
+            self.select_version(&client_hello)?
+        };
+        self.client_random = client_hello.random;
+        if version == ProtocolVersion::TLS1_3 {
+            return self.handle_client_hello_tls13(record_layer, &client_hello);
+        }
+        if self.hello_retry_group.is_some() {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Client gave up TLS 1.3 after HelloRetryRequest"));
+        }
+        if !client_hello.compression_methods.contains(&0) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Client did not offer null compression"));
+        }
+        // a server that settles for less than it supports says so in its random (RFC 8446 section 4.1.3)
+        let highest = self.enabled_versions().max().unwrap();
+        if !renegotiation && version == ProtocolVersion::TLS1_2 && highest == ProtocolVersion::TLS1_3 {
+            self.server_random[RANDOM_LEN - 8..].copy_from_slice(&handshake::DOWNGRADE_TLS12);
+        } else if !renegotiation && version < ProtocolVersion::TLS1_2 && highest >= ProtocolVersion::TLS1_2 {
+            self.server_random[RANDOM_LEN - 8..].copy_from_slice(&handshake::DOWNGRADE_TLS11);
+        }
+        // neither extension has a definition for SSL 3.0
+        let ssl3 = version == ProtocolVersion::SSL3_0;
+        self.extended_master_secret = !ssl3 && self.client_extensions.contains(&Extension::ExtendedMasterSecret);
+        let session = self.find_session(&client_hello, version);
+        // a session with an extended master secret is only resumed with one (RFC 7627 section 5.3)
+        if session.as_ref().is_some_and(|s| s.extended_master_secret && !self.extended_master_secret) {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Client resumes an extended master secret session without extended_master_secret"));
+        }
+        let group = self.select_group(&client_hello)?;
+        // before TLS 1.2 the key type alone decides the signature
+        let legacy_signature = version < ProtocolVersion::TLS1_2;
+        let signature_scheme = if legacy_signature { None } else { select_signature_scheme(&client_hello)? };
+        let signs = legacy_signature || signature_scheme.is_some();
+        // ECDHE needs a common group and both ephemeral suites a signature the client accepts
+        let suite = match &session {
+            Some(session) => session.cipher_suite,
+            None => self.select_cipher_suite(&client_hello, |s| s.supports_version(version) && match s.key_exchange {
+                    KeyExchange::Rsa => true,
+                    KeyExchange::Dhe => signs,
+                    KeyExchange::Ecdhe => signs && group.is_some(),
+                    KeyExchange::Any => false,
+                })
+                .ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "No cipher suite in common"))?,
+        };
+        self.check_renegotiation_info(&client_hello)?;
+        self.encrypt_then_mac = !ssl3 && suite.is_cbc() && self.client_extensions.contains(&Extension::EncryptThenMac);
+        if renegotiation && self.secure_renegotiation.encrypt_then_mac && suite.is_cbc() && !self.encrypt_then_mac {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Client gave up encrypt_then_mac when renegotiating"));
+        }
+        self.secure_renegotiation.encrypt_then_mac = self.encrypt_then_mac;
+        self.client_version = client_hello.version;
+        self.cipher_suite = Some(suite);
+        self.version = Some(version);
+        record_layer.set_version(version);
+        self.send_ticket = self.config.ticket_keys.is_some() && self.client_extensions.iter().any(|e| matches!(e, Extension::SessionTicket(_)));
+        self.session_id = match (&session, &self.config.session_cache) {
+            // resuming echoes the client's ID, whether it came with a ticket or not
+            (Some(_), _) => client_hello.session_id.clone(),
+            (None, Some(_)) => random_bytes(MAX_SESSION_ID_LEN)?,
+            // an empty session ID tells the client that the session cannot be resumed by ID
+            (None, None) => vec![],
+        };
+
+        let mut extensions: Vec<Extension> = vec![];
+        if self.secure_renegotiation.supported {
+            let previous = &self.secure_renegotiation;
+            extensions.push(Extension::RenegotiationInfo([&previous.client_verify_data[..], &previous.server_verify_data[..]].concat()));
+        }
+        if self.extended_master_secret {
+            extensions.push(Extension::ExtendedMasterSecret);
+        }
+        if self.encrypt_then_mac {
+            extensions.push(Extension::EncryptThenMac);
+        }
+        if suite.key_exchange == KeyExchange::Ecdhe && handshake::find_extension(&client_hello.extensions, handshake::EXTENSION_EC_POINT_FORMATS).is_some() {
+            extensions.push(Extension::EcPointFormats(vec![handshake::EC_POINT_FORMAT_UNCOMPRESSED]));
+        }
+        if let Some(protocol) = &self.alpn_protocol {
+            extensions.push(Extension::Alpn(vec![protocol.clone()]));
+        }
+        if self.send_ticket {
+            extensions.push(Extension::SessionTicket(vec![]));
+        }
+        let server_hello = ServerHello {
+            version,
+            random: self.server_random,
+            session_id: self.session_id.clone(),
+            cipher_suite: suite.id,
+            compression_method: 0,
+            extensions: extensions::encode_extensions(&extensions),
+        };
+        self.send(record_layer, server_hello.encode())?;
+        if let Some(session) = session {
+            return self.resume_session(record_layer, session);
+        }
+        self.send(record_layer, handshake::encode_certificate(&self.config.certificate_chain))?;
+        match suite.key_exchange {
+            KeyExchange::Rsa | KeyExchange::Any => (),
+            KeyExchange::Dhe => self.send_server_key_exchange(record_layer, None, signature_scheme)?,
+            KeyExchange::Ecdhe => self.send_server_key_exchange(record_layer, group, signature_scheme)?,
+        }
+        // SSL 3.0 CertificateVerify is keyed with the master secret, which we do not implement
+        if ssl3 && self.config.client_auth.is_required() {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Client certificates are not supported in SSL 3.0"));
+        }
+        if let Some(trust_store) = self.config.client_auth.trust_store().filter(|_| !ssl3) {
+            let certificate_request = CertificateRequest {
+                certificate_types: vec![handshake::CERTIFICATE_TYPE_RSA_SIGN, handshake::CERTIFICATE_TYPE_ECDSA_SIGN],
+                signature_schemes: CLIENT_SIGNATURE_SCHEMES.to_vec(),
+                certificate_authorities: certificate_authorities(trust_store),
+            };
+            self.send(record_layer, certificate_request.encode(version))?;
+            self.requested_certificate = true;
+        }
+        self.send(record_layer, HandshakeMessage::new(handshake::SERVER_HELLO_DONE, vec![]))?;
+        self.state = if self.requested_certificate { State::ExpectCertificate } else { State::ExpectClientKeyExchange };
+        Ok(())
+    }
+
+    /// Checks the client's RFC 5746 signal: the SCSV or an empty renegotiation_info in the
+    /// first handshake, renegotiation_info with our last client verify_data in a renegotiation
+    fn check_renegotiation_info(&mut self, client_hello: &ClientHello) -> Result<()> {
+        let scsv = client_hello.cipher_suites.contains(&TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+        let renegotiation_info = self.client_extensions.iter().find_map(|e| match e {
+            Extension::RenegotiationInfo(renegotiated_connection) => Some(renegotiated_connection),
+            _ => None,
+        });
+        if !self.secure_renegotiation.is_renegotiation() {
+            if renegotiation_info.is_some_and(|r| !r.is_empty()) {
+                return Err(alert::fatal(HANDSHAKE_FAILURE, "Non-empty renegotiation_info in the initial handshake"));
+            }
+            self.secure_renegotiation.supported = scsv || renegotiation_info.is_some();
+            return Ok(());
+        }
+        // RFC 5746 section 3.7
+        if scsv {
+            return Err(alert::fatal(HANDSHAKE_FAILURE, "Renegotiating ClientHello with the SCSV"));
+        }
+        match renegotiation_info {
+            Some(r) if constant_time_eq(r, &self.secure_renegotiation.client_verify_data) => Ok(()),
+            Some(_) => Err(alert::fatal(HANDSHAKE_FAILURE, "renegotiation_info does not match the previous handshake")),
+            None => Err(alert::fatal(HANDSHAKE_FAILURE, "Renegotiating ClientHello without renegotiation_info")),
+        }
+    }
+
+    /// The session of `version` from the client's ticket or, without one, from its session ID,
+    /// if it has not expired and its suite is still enabled on both sides. A session
+    /// without an extended master secret is not resumed by a client that now offers one.
+    fn find_session(&self, client_hello: &ClientHello, version: ProtocolVersion) -> Option<ServerSession> {
+        let ticket = self.client_extensions.iter().find_map(|e| match e {
+            Extension::SessionTicket(ticket) if !ticket.is_empty() => Some(ticket),
+            _ => None,
+        });
+        let session = match ticket {
+            Some(ticket) => self.config.ticket_keys.as_ref()?.open(ticket)?,
+            None if client_hello.session_id.is_empty() => return None,
+            None => self.config.session_cache.as_ref()?.get(&client_hello.session_id)?,
+        };
+        Some(session)
+            .filter(|s| s.version == version && self.is_resumable(s))
+            .filter(|s| client_hello.cipher_suites.contains(&s.cipher_suite.id) && self.config.cipher_suites.contains(&s.cipher_suite))
+            .filter(|s| s.extended_master_secret || !self.extended_master_secret)
+    }
+
+    /// Whether `session` is recent enough and authenticated the client as we require now
+    fn is_resumable(&self, session: &ServerSession) -> bool {
+        let authenticated = !session.peer_certificates.is_empty() || !self.config.client_auth.is_required();
+        authenticated && !session.is_expired(self.config.session_lifetime)
+    }
+
+    /// Takes back the client's chain from a resumed session
+    fn restore_client_certificates(&mut self, session: &ServerSession) -> Result<()> {
+        self.client_certificates = session.peer_certificates.iter()
+            .map(|der| Certificate::from_der(der))
+            .collect::<Result<Vec<Certificate>>>()?;
+        Ok(())
+    }
+
+    /// DER chain of the client, for the session
+    fn client_chain(&self) -> Vec<Vec<u8>> {
+        self.client_certificates.iter().map(|c| c.raw.clone()).collect()
+    }
+
+    /// Continues the abbreviated handshake after ServerHello: a new ticket if asked for,
+    /// then ChangeCipherSpec and Finished under the keys of the resumed master secret
+    fn resume_session(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, session: ServerSession) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        self.restore_client_certificates(&session)?;
+        self.master_secret = session.secret;
+        self.log_key(keylog::CLIENT_RANDOM, &self.master_secret);
+        self.resumed = true;
+        if self.send_ticket {
+            self.send_new_session_ticket(record_layer)?;
+        }
+        let (client_write, server_write) = derive_protections(suite, self.version.unwrap(), &self.master_secret, &self.client_random, &self.server_random, self.encrypt_then_mac)?;
+        self.pending_read_protection = Some(client_write);
+        self.pending_write_protection = Some(server_write);
+        record_layer.send_change_cipher_spec()?;
+        record_layer.set_write_protection(self.pending_write_protection.take().unwrap());
+        let verify_data = self.prf().verify_data(&self.master_secret, false, &self.transcript);
+        self.secure_renegotiation.server_verify_data = verify_data.clone();
+        self.send(record_layer, HandshakeMessage::new(handshake::FINISHED, verify_data))?;
+        self.state = State::ExpectChangeCipherSpec;
+        Ok(())
+    }
+
+    /// RFC 5077 NewSessionTicket with the session sealed under the current ticket key
+    fn send_new_session_ticket(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        let ticket = self.config.ticket_keys.as_ref().unwrap().seal(&self.tls12_session())?;
+        self.send(record_layer, handshake::encode_new_session_ticket(self.config.session_lifetime, &ticket))
+    }
+
+    /// Keeps a completed TLS 1.2 session under its session ID
+    fn cache_session(&self) {
+        if let (Some(cache), false) = (&self.config.session_cache, self.session_id.is_empty()) {
+            cache.insert(&self.session_id, self.tls12_session());
+        }
+    }
+
+    fn tls12_session(&self) -> ServerSession {
+        let mut session = ServerSession::new(self.version.unwrap(), self.cipher_suite.unwrap(), self.master_secret.clone(), self.client_chain());
+        session.extended_master_secret = self.extended_master_secret;
+        session
+    }
+
+    /// Decodes the ClientHello extensions and picks up server_name and the application protocol
+    fn handle_client_extensions(&mut self, client_hello: &ClientHello) -> Result<()> {
+        let client_extensions = extensions::parse_extensions(&client_hello.extensions, HelloContext::ClientHello)?;
+        self.server_name = None;
+        self.alpn_protocol = None;
+        for extension in &client_extensions {
+            match extension {
+                Extension::ServerName(name) => self.server_name = name.clone(),
+                Extension::PreSharedKey(_) if client_hello.extensions.last().map(|e| e.extension_type) != Some(handshake::EXTENSION_PRE_SHARED_KEY) => {
+                    return Err(alert::fatal(ILLEGAL_PARAMETER, "pre_shared_key is not the last extension"));
+                },
+                Extension::Alpn(offered) if !self.config.alpn_protocols.is_empty() => {
+                    // no protocol in common ends the handshake (RFC 7301 section 3.2)
+                    let protocol = self.config.alpn_protocols.iter().find(|p| offered.contains(p))
+                        .ok_or_else(|| alert::fatal(NO_APPLICATION_PROTOCOL, "No application protocol in common"))?;
+                    self.alpn_protocol = Some(protocol.clone());
+                },
+                _ => (),
+            }
+        }
+        self.client_extensions = client_extensions;
+        Ok(())
+    }
+
+    /// The suite both sides enable that `usable` accepts, by the server's order or by the
+    /// client's. Our key is RSA, so ECDSA suites are never usable.
+    fn select_cipher_suite(&self, client_hello: &ClientHello, usable: impl Fn(&CipherSuite) -> bool) -> Option<&'static CipherSuite> {
+        let usable = |s: &CipherSuite| s.authentication != Authentication::Ecdsa && usable(s);
+        if self.config.prefer_server_cipher_suites {
+            self.config.cipher_suites.iter()
+                .find(|s| client_hello.cipher_suites.contains(&s.id) && usable(s))
+                .copied()
+        } else {
+            client_hello.cipher_suites.iter()
+                .filter_map(|id| self.config.cipher_suites.iter().find(|s| s.id == *id))
+                .find(|s| usable(s))
+                .copied()
+        }
+    }
+
+    /// The configured versions this build implements and has a configured cipher suite for,
+    /// in order of preference
+    fn enabled_versions(&self) -> impl Iterator<Item = ProtocolVersion> + '_ {
+        self.config.versions.iter().copied()
+            .filter(|v| ProtocolVersion::IMPLEMENTED.contains(v))
+            .filter(move |v| self.config.cipher_suites.iter().any(|s| s.supports_version(*v)))
+    }
+
+    /// Our most preferred version among those the client lists in supported_versions or,
+    /// for a client without it, the highest we enable up to its ClientHello.version
+    fn select_version(&self, client_hello: &ClientHello) -> Result<ProtocolVersion> {
+        let selected = match handshake::find_extension(&client_hello.extensions, handshake::EXTENSION_SUPPORTED_VERSIONS) {
+            Some(data) => {
+                let offered = handshake::parse_supported_versions_client_hello(data)?;
+                self.enabled_versions().find(|v| offered.contains(v))
+            },
+            None => self.enabled_versions().filter(|v| *v <= client_hello.version && *v < ProtocolVersion::TLS1_3).max(),
+        };
+        selected.ok_or_else(|| alert::fatal(PROTOCOL_VERSION, format!("No protocol version in common, client offered up to {}", client_hello.version)))
+    }
+
+    /// Picks the suite, signature scheme and group, and either asks for another key share
+    /// with HelloRetryRequest or sends ServerHello and the rest of our flight at once
+    fn handle_client_hello_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, client_hello: &ClientHello) -> Result<()> {
+        if client_hello.compression_methods != [0] {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "TLS 1.3 ClientHello with compression methods"));
+        }
+        let suite = self.select_cipher_suite(client_hello, CipherSuite::is_tls13)
+            .ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "No cipher suite in common"))?;
+        if self.hello_retry_group.is_some() && self.cipher_suite != Some(suite) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Client changed its cipher suites after HelloRetryRequest"));
+        }
+        let extension = |extension_type: u16, name: &str| {
+            handshake::find_extension(&client_hello.extensions, extension_type)
+                .ok_or_else(|| alert::fatal(MISSING_EXTENSION, format!("TLS 1.3 ClientHello without {}", name)))
+        };
+        let offered_schemes = handshake::parse_u16_list(extension(handshake::EXTENSION_SIGNATURE_ALGORITHMS, "signature_algorithms")?)?;
+        let signature_scheme = TLS13_SIGNATURE_SCHEMES.iter().find(|s| offered_schemes.contains(s)).copied()
+            .ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "No signature scheme in common"))?;
+        let offered_groups = handshake::parse_u16_list(extension(handshake::EXTENSION_SUPPORTED_GROUPS, "supported_groups")?)?;
+        let key_shares = handshake::parse_key_share_client_hello(extension(handshake::EXTENSION_KEY_SHARE, "key_share")?)?;
+        if key_shares.iter().any(|e| !offered_groups.contains(&e.group)) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, "Key share for a group missing from supported_groups"));
+        }
+        let key_share = self.config.groups.iter()
+            .find_map(|g| key_shares.iter().find(|e| e.group == g.id()).map(|e| (*g, e)));
+        let (group, client_share) = match (key_share, self.hello_retry_group) {
+            (Some((group, share)), None) => (group, share),
+            (Some((group, share)), Some(requested)) if group == requested => (group, share),
+            (_, Some(_)) => return Err(alert::fatal(ILLEGAL_PARAMETER, "No key share for the group requested in HelloRetryRequest")),
+            (None, None) => {
+                let group = self.config.groups.iter().find(|g| offered_groups.contains(&g.id())).copied()
+                    .ok_or_else(|| alert::fatal(HANDSHAKE_FAILURE, "No group in common"))?;
+                return self.send_hello_retry_request(record_layer, client_hello, suite, group);
+            },
+        };
+
+        let key = EphemeralKey::generate_ecdhe(group)?;
+        let shared_secret = key.agree(&client_share.key_exchange)?;
+        self.cipher_suite = Some(suite);
+        self.version = Some(ProtocolVersion::TLS1_3);
+        let psk_modes = self.client_extensions.iter().find_map(|e| match e {
+            Extension::PskKeyExchangeModes(modes) => Some(modes.clone()),
+            _ => None,
+        });
+        let psk_dhe_ke = psk_modes.as_ref().is_some_and(|modes| modes.contains(&handshake::PSK_DHE_KE));
+        self.send_ticket = self.config.ticket_keys.is_some() && psk_dhe_ke;
+        let offered_psks = self.client_extensions.iter().find_map(|e| match e {
+            Extension::PreSharedKey(psks) => Some(psks.clone()),
+            _ => None,
+        });
+        let psk = match offered_psks {
+            Some(_) if psk_modes.is_none() => return Err(alert::fatal(MISSING_EXTENSION, "pre_shared_key without psk_key_exchange_modes")),
+            Some(psks) if psk_dhe_ke => self.accept_psk(&psks, suite)?,
+            _ => None,
+        };
+        let mut extensions = vec![
+            handshake::supported_versions_server_hello(ProtocolVersion::TLS1_3),
+            handshake::key_share_server_hello(&KeyShareEntry {
+                group: group.id(),
+                key_exchange: key.public_bytes(),
+            }),
+        ];
+        if let Some((index, _)) = &psk {
+            extensions.push(handshake::pre_shared_key_server_hello(*index));
+            self.resumed = true;
+        }
+        let server_hello = ServerHello {
+            version: ProtocolVersion::TLS1_2,
+            random: self.server_random,
+            session_id: client_hello.session_id.clone(),
+            cipher_suite: suite.id,
+            compression_method: 0,
+            extensions,
+        };
+        self.send(record_layer, server_hello.encode())?;
+        record_layer.set_version(ProtocolVersion::TLS1_2);
+        record_layer.set_allow_plaintext_change_cipher_spec(true);
+        // middlebox compatibility: a ChangeCipherSpec after our first handshake message
+        if self.hello_retry_group.is_none() {
+            record_layer.send_change_cipher_spec()?;
+        }
+
+        let hash = suite.prf_hash;
+        let mut key_schedule = KeySchedule::new(hash, psk.as_ref().map(|(_, psk)| psk.as_slice()));
+        key_schedule.advance(Some(&shared_secret));
+        let transcript_hash = self.transcript.hash(hash);
+        self.client_handshake_secret = key_schedule.derive(b"c hs traffic", &transcript_hash);
+        let server_handshake_secret = key_schedule.derive(b"s hs traffic", &transcript_hash);
+        self.log_key(keylog::CLIENT_HANDSHAKE_TRAFFIC_SECRET, &self.client_handshake_secret);
+        self.log_key(keylog::SERVER_HANDSHAKE_TRAFFIC_SECRET, &server_handshake_secret);
+        record_layer.set_write_protection(tls13_protection(suite, &server_handshake_secret)?);
+
+        let encrypted_extensions: Vec<Extension> = self.alpn_protocol.iter().map(|p| Extension::Alpn(vec![p.clone()])).collect();
+        self.send(record_layer, handshake::encode_encrypted_extensions(&extensions::encode_extensions(&encrypted_extensions)))?;
+        // a resumed session is authenticated by the PSK
+        if !self.resumed {
+            let config = Arc::clone(&self.config);
+            if let Some(trust_store) = config.client_auth.trust_store() {
+                self.send_certificate_request_tls13(record_layer, trust_store)?;
+            }
+            self.send(record_layer, handshake::encode_certificate_tls13(&[], &self.config.certificate_chain))?;
+            let content = handshake::certificate_verify_content(true, &self.transcript.hash(hash));
+            let signature = signature::sign(&self.config.private_key, signature_scheme, &content)?;
+            self.send(record_layer, handshake::encode_certificate_verify(signature_scheme, &signature))?;
+        }
+        let verify_data = finished_verify_data(hash, &server_handshake_secret, &self.transcript.hash(hash));
+        self.send(record_layer, HandshakeMessage::new(handshake::FINISHED, verify_data))?;
+
+        key_schedule.advance(None);
+        let transcript_hash = self.transcript.hash(hash);
+        self.client_application_secret = key_schedule.derive(b"c ap traffic", &transcript_hash);
+        self.server_application_secret = key_schedule.derive(b"s ap traffic", &transcript_hash);
+        self.log_key(keylog::CLIENT_TRAFFIC_SECRET_0, &self.client_application_secret);
+        self.log_key(keylog::SERVER_TRAFFIC_SECRET_0, &self.server_application_secret);
+        if self.config.key_log.is_some() {
+            self.log_key(keylog::EXPORTER_SECRET, &key_schedule.derive(b"exp master", &transcript_hash));
+        }
+        record_layer.set_write_protection(tls13_protection(suite, &self.server_application_secret)?);
+        record_layer.set_read_protection(tls13_protection(suite, &self.client_handshake_secret)?);
+        self.key_schedule = Some(key_schedule);
+        self.state = if self.requested_certificate { State::ExpectCertificate } else { State::ExpectFinished };
+        Ok(())
+    }
+
+    /// CertificateRequest with an empty context, as the handshake has only one (RFC 8446 section 4.3.2)
+    fn send_certificate_request_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, trust_store: &TrustStore) -> Result<()> {
+        let schemes = CLIENT_SIGNATURE_SCHEMES.iter().copied().filter(|s| signature::is_allowed_in_tls13(*s)).collect();
+        let mut extensions = vec![Extension::SignatureAlgorithms(schemes)];
+        let authorities = certificate_authorities(trust_store);
+        if !authorities.is_empty() {
+            extensions.push(Extension::CertificateAuthorities(authorities));
+        }
+        self.send(record_layer, handshake::encode_certificate_request_tls13(&[], &extensions::encode_extensions(&extensions)))?;
+        self.requested_certificate = true;
+        Ok(())
+    }
+
+    /// The index and PSK of the first offered ticket we can open for the hash of `suite`.
+    /// Its binder has to check out, or the handshake fails (RFC 8446 section 4.2.11).
+    fn accept_psk(&mut self, psks: &OfferedPsks, suite: &'static CipherSuite) -> Result<Option<(u16, Vec<u8>)>> {
+        let ticket_keys = match &self.config.ticket_keys {
+            Some(ticket_keys) => ticket_keys,
+            None => return Ok(None),
+        };
+        let hash = suite.prf_hash;
+        let found = psks.identities.iter().enumerate().find_map(|(i, identity)| {
+            ticket_keys.open(&identity.identity)
+                .filter(|s| s.version == ProtocolVersion::TLS1_3 && s.cipher_suite.prf_hash == hash)
+                .filter(|s| self.is_resumable(s))
+                .map(|s| (i, s))
+        });
+        let (index, session) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        // the transcript ends with this ClientHello, whose binders are left out
+        let expected = psk_binder(hash, &session.secret, &self.transcript.hash_truncated(hash, psks.binders_len()));
+        if !constant_time_eq(&expected, &psks.binders[index]) {
+            return Err(alert::fatal(DECRYPT_ERROR, "PSK binder mismatch"));
+        }
+        self.restore_client_certificates(&session)?;
+        Ok(Some((index as u16, session.secret)))
+    }
+
+    /// Asks the client for a key share in `group`. The first ClientHello is kept
+    /// in the transcript only as a hash (RFC 8446 section 4.4.1).
+    fn send_hello_retry_request(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, client_hello: &ClientHello, suite: &'static CipherSuite, group: NamedGroup) -> Result<()> {
+        self.transcript.replace_with_message_hash(suite.prf_hash);
+        let hello_retry_request = ServerHello {
+            version: ProtocolVersion::TLS1_2,
+            random: HELLO_RETRY_REQUEST_RANDOM,
+            session_id: client_hello.session_id.clone(),
+            cipher_suite: suite.id,
+            compression_method: 0,
+            extensions: vec![
+                handshake::supported_versions_server_hello(ProtocolVersion::TLS1_3),
+                handshake::key_share_hello_retry_request(group.id()),
+            ],
+        };
+        self.send(record_layer, hello_retry_request.encode())?;
+        record_layer.set_version(ProtocolVersion::TLS1_2);
+        record_layer.set_allow_plaintext_change_cipher_spec(true);
+        record_layer.send_change_cipher_spec()?;
+        self.hello_retry_group = Some(group);
+        self.cipher_suite = Some(suite);
+        self.version = Some(ProtocolVersion::TLS1_3);
+        Ok(())
+    }
+
+    /// The first of our groups that the client supports. A client without
+    /// supported_groups may be sent any curve (RFC 8422 section 4), so P-256 is used.
+    fn select_group(&self, client_hello: &ClientHello) -> Result<Option<NamedGroup>> {
+        if let Some(formats) = handshake::find_extension(&client_hello.extensions, handshake::EXTENSION_EC_POINT_FORMATS) {
+            handshake::check_ec_point_formats(formats)?;
+        }
+        let offered = match handshake::find_extension(&client_hello.extensions, handshake::EXTENSION_SUPPORTED_GROUPS) {
+            Some(data) => handshake::parse_u16_list(data)?,
+            None => vec![NamedGroup::Secp256r1.id()],
+        };
+        Ok(self.config.groups.iter().find(|g| offered.contains(&g.id())).copied())
+    }
+
+    /// Generates the ephemeral key for `group`, or for the DHE group if None, and sends it signed,
+    /// with `signature_scheme` from TLS 1.2 on
+    fn send_server_key_exchange(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, group: Option<NamedGroup>, signature_scheme: Option<u16>) -> Result<()> {
+        let (key, params) = match group {
+            Some(group) => {
+                let key = EphemeralKey::generate_ecdhe(group)?;
+                let public = key.public_bytes();
+                (key, ServerKeyExchangeParams::Ecdhe { group, public })
+            },
+            None => {
+                let key = EphemeralKey::generate_dhe(&self.config.dh_params)?;
+                let public = key.public_bytes();
+                (key, ServerKeyExchangeParams::Dhe { params: self.config.dh_params.clone(), public })
+            },
+        };
+        let signed_data = ServerKeyExchange::signed_data(&params, &self.client_random, &self.server_random);
+        let signature = match signature_scheme {
+            Some(scheme) => signature::sign(&self.config.private_key, scheme, &signed_data)?,
+            #[cfg(feature = "legacy-protocols")]
+            None => signature::sign_legacy(&self.config.private_key, &signed_data)?,
+            #[cfg(not(feature = "legacy-protocols"))]
+            None => unreachable!("TLS 1.2 always names the signature scheme"),
+        };
+        let server_key_exchange = ServerKeyExchange {
+            params,
+            signature_scheme,
+            signature,
+        };
+        self.ephemeral_key = Some(key);
+        self.send(record_layer, server_key_exchange.encode())
+    }
+
+    fn handle_client_key_exchange(&mut self, body: &[u8]) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        let pre_master_secret = match self.ephemeral_key.take() {
+            Some(key) => key.agree(&kx::parse_ephemeral_client_key_exchange(body, suite.key_exchange)?)?,
+            None => self.decrypt_pre_master_secret(body)?,
+        };
+        // the transcript ends with ClientKeyExchange here, as the session hash does
+        let prf = self.prf();
+        self.master_secret = if self.extended_master_secret {
+            prf.extended_master_secret(&pre_master_secret, &self.transcript)
+        } else {
+            prf.master_secret(&pre_master_secret, &self.client_random, &self.server_random)
+        };
+        self.log_key(keylog::CLIENT_RANDOM, &self.master_secret);
+        let (client_write, server_write) = derive_protections(suite, self.version.unwrap(), &self.master_secret, &self.client_random, &self.server_random, self.encrypt_then_mac)?;
+        self.pending_read_protection = Some(client_write);
+        self.pending_write_protection = Some(server_write);
+        // a client with a certificate proves it holds the key next
+        self.state = if self.client_certificates.is_empty() { State::ExpectChangeCipherSpec } else { State::ExpectCertificateVerify };
+        Ok(())
+    }
+
+    /// Verifies the client's chain against the client-auth trust store. An empty one is
+    /// accepted unless a certificate is required.
+    fn handle_certificate(&mut self, body: &[u8]) -> Result<()> {
+        let tls13 = self.is_tls13();
+        let chain = if tls13 {
+            let (context, chain) = handshake::parse_certificate_tls13(body)?;
+            if !context.is_empty() {
+                return Err(alert::fatal(ILLEGAL_PARAMETER, "Client Certificate with a request context we did not send"));
+            }
+            chain
+        } else {
+            handshake::parse_certificate(body)?
+        };
+        if chain.is_empty() {
+            if self.config.client_auth.is_required() {
+                // TLS 1.2 has no certificate_required alert (RFC 5246 section 7.4.6)
+                let description = if tls13 { CERTIFICATE_REQUIRED } else { HANDSHAKE_FAILURE };
+                return Err(alert::fatal(description, "Client sent no certificate"));
+            }
+            self.state = if tls13 { State::ExpectFinished } else { State::ExpectClientKeyExchange };
+            return Ok(());
+        }
+        let certificates = chain.iter()
+            .map(|der| Certificate::from_der(der).map_err(|e| alert::fatal(BAD_CERTIFICATE, format!("Malformed client certificate: {}", e))))
+            .collect::<Result<Vec<Certificate>>>()?;
+        let leaf = &certificates[0];
+        let trust_store = self.config.client_auth.trust_store().unwrap();
+        trust_store.verify_chain(leaf, &certificates[1..], KeyPurpose::ClientAuth, Asn1Time::now())?;
+        if leaf.key_usage.is_some_and(|key_usage| !key_usage.contains(KeyUsage::DIGITAL_SIGNATURE)) {
+            return Err(alert::fatal(BAD_CERTIFICATE, "Client certificate does not allow digital signatures"));
+        }
+        self.client_certificates = certificates;
+        self.state = if tls13 { State::ExpectCertificateVerify } else { State::ExpectClientKeyExchange };
+        Ok(())
+    }
+
+    /// Checks the client's signature over the transcript: in TLS 1.3 over its hash
+    /// up to the client Certificate, in TLS 1.2 over the messages up to ClientKeyExchange
+    fn handle_certificate_verify(&mut self, body: &[u8]) -> Result<()> {
+        let version = self.version.unwrap();
+        #[cfg(feature = "legacy-protocols")]
+        if version < ProtocolVersion::TLS1_2 {
+            let signature = handshake::parse_legacy_certificate_verify(body)?;
+            return signature::verify_legacy(&self.client_certificates[0].subject_public_key_info, self.transcript.messages(), &signature);
+        }
+        let (scheme, signature) = handshake::parse_certificate_verify(body)?;
+        let tls13 = self.is_tls13();
+        if !CLIENT_SIGNATURE_SCHEMES.contains(&scheme) || (tls13 && !signature::is_allowed_in_tls13(scheme)) {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Client used signature scheme {:04x} that was not offered", scheme)));
+        }
+        let content = if tls13 {
+            handshake::certificate_verify_content(false, &self.transcript.hash(self.cipher_suite.unwrap().prf_hash))
+        } else {
+            self.transcript.messages().to_vec()
+        };
+        signature::verify(&self.client_certificates[0].subject_public_key_info, version, scheme, &content, &signature)
+    }
+
+    fn decrypt_pre_master_secret(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let encrypted = handshake::parse_rsa_client_key_exchange(body, self.version.unwrap())?;
+        // on any decryption or version failure continue with a random premaster secret,
+        // so the handshake only fails later at Finished (RFC 5246 section 7.4.7.1)
+        let fallback = random_bytes(PRE_MASTER_SECRET_LEN)?;
+        let version = self.client_version.as_u16().to_be_bytes();
+        Ok(match self.config.private_key.decrypt_pkcs1_v15(&encrypted) {
+            Ok(secret) if secret.len() == PRE_MASTER_SECRET_LEN && secret[..2] == version => secret,
+            _ => fallback,
+        })
+    }
+
+    fn handle_change_cipher_spec(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        // TLS 1.3 only sends it for middleboxes and it is dropped (RFC 8446 section 5)
+        if self.is_tls13() {
+            return Ok(());
+        }
+        if self.state != State::ExpectChangeCipherSpec {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected ChangeCipherSpec in state {:?}", self.state)));
+        }
+        record_layer.set_read_protection(self.pending_read_protection.take().unwrap());
+        self.state = State::ExpectFinished;
+        Ok(())
+    }
+
+    fn handle_finished(&mut self, body: &[u8]) -> Result<()> {
+        let expected = self.prf().verify_data(&self.master_secret, true, &self.transcript);
+        if body.len() != expected.len() {
+            return Err(alert::fatal(DECODE_ERROR, "Malformed Finished"));
+        }
+        if !constant_time_eq(&expected, body) {
+            return Err(alert::fatal(DECRYPT_ERROR, "Client Finished verify_data mismatch"));
+        }
+        self.secure_renegotiation.client_verify_data = expected;
+        Ok(())
+    }
+
+    /// Checks the client Finished, moves reading to the application traffic key and
+    /// sends a ticket if the client can resume with it
+    fn handle_finished_tls13(&mut self, record_layer: &mut RecordLayer<impl Read + Write>, message: HandshakeMessage) -> Result<()> {
+        let suite = self.cipher_suite.unwrap();
+        let hash = suite.prf_hash;
+        let expected = finished_verify_data(hash, &self.client_handshake_secret, &self.transcript.hash(hash));
+        if !constant_time_eq(&expected, &message.body) {
+            return Err(alert::fatal(DECRYPT_ERROR, "Client Finished verify_data mismatch"));
+        }
+        self.transcript.add(&message);
+        record_layer.set_read_protection(tls13_protection(suite, &self.client_application_secret)?);
+        // from here on a ChangeCipherSpec is an unexpected record
+        record_layer.set_allow_plaintext_change_cipher_spec(false);
+        if self.send_ticket {
+            let resumption_master_secret = self.key_schedule.take().unwrap().derive(b"res master", &self.transcript.hash(hash));
+            let nonce = vec![0];
+            let session = ServerSession::new(ProtocolVersion::TLS1_3, suite, resumption_psk(hash, &resumption_master_secret, &nonce), self.client_chain());
+            let ticket = NewSessionTicketTls13 {
+                lifetime: self.config.session_lifetime.min(MAX_TICKET_LIFETIME),
+                age_add: random_bytes(4)?.iter().fold(0, |n, &b| n << 8 | b as u32),
+                nonce,
+                ticket: self.config.ticket_keys.as_ref().unwrap().seal(&session)?,
+                extensions: vec![],
+            };
+            // after the handshake, so it is not part of the transcript
+            record_layer.send_handshake(&ticket.encode())?;
+        }
+        self.state = State::Connected;
+        Ok(())
+    }
+
+    /// NewSessionTicket if the client asked for one, ChangeCipherSpec and Finished
+    fn send_finished(&mut self, record_layer: &mut RecordLayer<impl Read + Write>) -> Result<()> {
+        if self.send_ticket {
+            self.send_new_session_ticket(record_layer)?;
+        }
+        record_layer.send_change_cipher_spec()?;
+        record_layer.set_write_protection(self.pending_write_protection.take().unwrap());
+        let verify_data = self.prf().verify_data(&self.master_secret, false, &self.transcript);
+        self.secure_renegotiation.server_verify_data = verify_data.clone();
+        self.send(record_layer, HandshakeMessage::new(handshake::FINISHED, verify_data))
+    }
+}
+
+/// Subjects of the client-auth anchors, as certificate_authorities hints for the client
+fn certificate_authorities(trust_store: &TrustStore) -> Vec<Vec<u8>> {
+    trust_store.roots().iter().map(|root| root.subject.raw.clone()).collect()
+}
+
+/// The RSA signature scheme for ServerKeyExchange: our most preferred one that the
+/// client offered, or SHA-1 when it sent no signature_algorithms (RFC 5246 section 7.4.1.4.1).
+/// None means the client accepts no RSA PKCS#1 signature we can make.
+fn select_signature_scheme(client_hello: &ClientHello) -> Result<Option<u16>> {
+    let offered = match handshake::find_extension(&client_hello.extensions, handshake::EXTENSION_SIGNATURE_ALGORITHMS) {
+        Some(data) => handshake::parse_u16_list(data)?,
+        None => return Ok(Some(signature::RSA_PKCS1_SHA1)),
+    };
+    Ok(SUPPORTED_SIGNATURE_SCHEMES.iter().find(|s| offered.contains(s)).copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::testdata;
+    use std::net::{
+        TcpListener,
+        TcpStream,
+    };
+    use std::sync::Mutex;
+    use std::thread;
+
+    use crate::pem;
+    use super::super::client::{
+        self,
+        ClientConfig,
+    };
+    use crate::huge::Huge;
+    use super::super::alert::{
+        Alert,
+        AlertLevel,
+        INSUFFICIENT_SECURITY,
+        NO_RENEGOTIATION,
+        UNKNOWN_CA,
+    };
+    use super::super::stream::RekeyLimit;
+    use super::super::trace::{
+        Direction,
+        TraceEvent,
+        TraceValue,
+    };
+    use super::super::suites::{
+        TLS_AES_256_GCM_SHA384,
+        TLS_DHE_RSA_WITH_AES_128_CBC_SHA256,
+        TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        TLS_RSA_WITH_AES_128_CBC_SHA,
+        TLS_RSA_WITH_AES_128_GCM_SHA256,
+        TLS_RSA_WITH_AES_256_CBC_SHA256,
+    };
+
+    fn server_config() -> ServerConfig {
+        let mut config = ServerConfig::from_pem_files(testdata("leaf.pem"), testdata("leaf.key")).unwrap();
+        config.certificate_chain.push(pem::read_file(testdata("inter.pem")).unwrap().remove(0).contents);
+        config
+    }
+
+    fn client_config() -> ClientConfig {
+        ClientConfig::new(TrustStore::from_pem_dir(testdata("roots")).unwrap())
+    }
+
+    /// Keeps the key log lines in memory
+    #[derive(Default)]
+    struct MemoryKeyLog(Mutex<Vec<String>>);
+
+    impl KeyLog for MemoryKeyLog {
+        fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+            self.0.lock().unwrap().push(keylog::format_line(label, client_random, secret));
+        }
+    }
+
+    /// Keeps the trace events in memory
+    #[derive(Default)]
+    struct MemoryTracer(Mutex<Vec<TraceEvent>>);
+
+    impl Tracer for MemoryTracer {
+        fn trace(&self, event: &TraceEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    fn client_ca() -> TrustStore {
+        let mut trust_store = TrustStore::new();
+        trust_store.add_pem_file(testdata("client-ca.pem")).unwrap();
+        trust_store
+    }
+
+    /// Runs `accept` on a loopback socket in another thread and connects to it
+    fn handshake(server: ServerConfig, client: ClientConfig) -> (Result<TlsStream<TcpStream>>, thread::JoinHandle<Result<TlsStream<TcpStream>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            accept(stream, &server)
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        (client::connect(stream, &client, "localhost"), server)
+    }
+
+    /// Connects over loopback while `serve` runs on the accepted stream in another thread
+    fn connected<T: Send + 'static>(server: ServerConfig, client: ClientConfig, serve: impl FnOnce(TlsStream<TcpStream>) -> Result<T> + Send + 'static) -> (TlsStream<TcpStream>, thread::JoinHandle<Result<T>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            serve(accept(stream, &server)?)
+        });
+        (client::connect(TcpStream::connect(addr).unwrap(), &client, "localhost").unwrap(), server)
+    }
+
+    /// Connects with the client's session cache, reading once so that a TLS 1.3 ticket
+    /// arrives, and returns whether the client and the server resumed
+    fn resume(server: &Arc<ServerConfig>, client: &ClientConfig) -> (bool, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = server.clone();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            let mut stream = accept(stream, &config)?;
+            stream.write_all(b"hi")?;
+            Ok::<_, anyhow::Error>(stream.session().resumed)
+        });
+        let mut stream = client::connect_resumable(TcpStream::connect(addr).unwrap(), client, "localhost", "localhost").unwrap();
+        let mut hi = [0u8; 2];
+        stream.read_exact(&mut hi).unwrap();
+        (stream.session().resumed, server.join().unwrap().unwrap())
+    }
+
+    #[test]
+    fn test_can_exchange_data_with_client() {
+        let (client, server) = handshake(server_config(), client_config());
+        let mut client = client.unwrap();
+        let server = thread::spawn(move || {
+            let mut server = server.join().unwrap()?;
+            let mut request = [0u8; 5];
+            server.read_exact(&mut request)?;
+            server.write_all(&request.repeat(10_000))?;
+            server.close()?;
+            Ok::<_, anyhow::Error>(server.session().cipher_suite)
+        });
+        client.write_all(b"hello").unwrap();
+        let mut response: Vec<u8> = vec![];
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(b"hello".repeat(10_000), response);
+        assert_eq!(ProtocolVersion::TLS1_3, client.session().version);
+        assert_eq!(&TLS_AES_256_GCM_SHA384, client.session().cipher_suite);
+        assert_eq!(2, client.session().peer_certificates.len());
+        assert_eq!(&TLS_AES_256_GCM_SHA384, server.join().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_can_prefer_server_cipher_suite_order() {
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_RSA_WITH_AES_128_CBC_SHA, &TLS_RSA_WITH_AES_256_CBC_SHA256];
+        let (client, server) = handshake(server, client_config());
+        assert_eq!(&TLS_RSA_WITH_AES_128_CBC_SHA, client.unwrap().session().cipher_suite);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_can_negotiate_ephemeral_key_exchange() {
+        for group in ALL_GROUPS.iter() {
+            let mut client = client_config();
+            client.groups = vec![*group];
+            client.versions = vec![ProtocolVersion::TLS1_2];
+            let (client, server) = handshake(server_config(), client);
+            let mut client = client.unwrap();
+            let mut server = server.join().unwrap().unwrap();
+            client.write_all(b"ping").unwrap();
+            let mut ping = [0u8; 4];
+            server.read_exact(&mut ping).unwrap();
+            assert_eq!(b"ping", &ping);
+        }
+
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_DHE_RSA_WITH_AES_128_CBC_SHA256, &TLS_RSA_WITH_AES_128_CBC_SHA];
+        let (client, server) = handshake(server, client_config());
+        assert_eq!(&TLS_DHE_RSA_WITH_AES_128_CBC_SHA256, client.unwrap().session().cipher_suite);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_can_send_hello_retry_request() {
+        let mut server = server_config();
+        server.groups = vec![NamedGroup::Secp384r1];
+        let mut client = client_config();
+        client.groups = vec![NamedGroup::X25519, NamedGroup::Secp384r1];
+        let (client, server) = handshake(server, client);
+        let mut client = client.unwrap();
+        let mut server = server.join().unwrap().unwrap();
+        assert_eq!(ProtocolVersion::TLS1_3, server.session().version);
+        client.write_all(b"ping").unwrap();
+        let mut ping = [0u8; 4];
+        server.read_exact(&mut ping).unwrap();
+        assert_eq!(b"ping", &ping);
+
+        // a group the client cannot share at all
+        let mut server = server_config();
+        server.groups = vec![NamedGroup::Secp384r1];
+        let mut client = client_config();
+        client.groups = vec![NamedGroup::X25519];
+        client.versions = vec![ProtocolVersion::TLS1_3];
+        let (client, server) = handshake(server, client);
+        assert!(client.is_err());
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&server.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_negotiate_tls12_with_tls13_client() {
+        let mut server = server_config();
+        server.versions = vec![ProtocolVersion::TLS1_2];
+        let (client, server) = handshake(server, client_config());
+        let client = client.unwrap();
+        assert_eq!(ProtocolVersion::TLS1_2, client.session().version);
+        // the ECDSA suites ahead of it need a key we do not have
+        assert_eq!(&TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, client.session().cipher_suite);
+        server.join().unwrap().unwrap();
+
+        // a TLS 1.3 server falling back marks its random, which a TLS 1.2 client ignores
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        let (client, server) = handshake(server_config(), client);
+        assert_eq!(ProtocolVersion::TLS1_2, client.unwrap().session().version);
+        server.join().unwrap().unwrap();
+
+        let mut server = server_config();
+        server.versions = vec![ProtocolVersion::TLS1_2];
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_3];
+        let (client, server) = handshake(server, client);
+        assert!(client.is_err());
+        assert_eq!(PROTOCOL_VERSION, alert::description_of(&server.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_negotiate_alpn_and_receive_server_name() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2].iter() {
+            let mut server = server_config();
+            server.alpn_protocols = vec![b"http/1.1".to_vec(), b"h2".to_vec()];
+            let mut client = client_config();
+            client.versions = vec![*version];
+            client.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            let (client_stream, server_stream) = handshake(server, client);
+            assert_eq!(Some(b"http/1.1".to_vec()), client_stream.unwrap().session().alpn_protocol);
+            let server_stream = server_stream.join().unwrap().unwrap();
+            let session = server_stream.session();
+            assert_eq!(Some(b"http/1.1".to_vec()), session.alpn_protocol);
+            assert_eq!(Some("localhost".to_string()), session.server_name);
+            assert!(session.peer_extensions.contains(&Extension::ServerName(Some("localhost".to_string()))));
+        }
+
+        // a client without ALPN gets none
+        let mut server = server_config();
+        server.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let (client_stream, server_stream) = handshake(server, client_config());
+        assert_eq!(None, client_stream.unwrap().session().alpn_protocol);
+        server_stream.join().unwrap().unwrap();
+
+        let mut server = server_config();
+        server.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let mut client = client_config();
+        client.alpn_protocols = vec![b"h2".to_vec()];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert!(client_stream.is_err());
+        assert_eq!(NO_APPLICATION_PROTOCOL, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_select_suite_by_server_or_client_preference() {
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_RSA_WITH_AES_128_CBC_SHA, &TLS_RSA_WITH_AES_256_CBC_SHA256];
+        let mut client = client_config();
+        client.cipher_suites = vec![&TLS_RSA_WITH_AES_256_CBC_SHA256, &TLS_RSA_WITH_AES_128_CBC_SHA];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert_eq!(&TLS_RSA_WITH_AES_128_CBC_SHA, client_stream.unwrap().session().cipher_suite);
+        server_stream.join().unwrap().unwrap();
+
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_RSA_WITH_AES_128_CBC_SHA, &TLS_RSA_WITH_AES_256_CBC_SHA256];
+        server.prefer_server_cipher_suites = false;
+        let mut client = client_config();
+        client.cipher_suites = vec![&TLS_RSA_WITH_AES_256_CBC_SHA256, &TLS_RSA_WITH_AES_128_CBC_SHA];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert_eq!(&TLS_RSA_WITH_AES_256_CBC_SHA256, client_stream.unwrap().session().cipher_suite);
+        server_stream.join().unwrap().unwrap();
+
+        // an RSA server cannot serve a client that only offers ECDSA suites
+        let mut client = client_config();
+        client.cipher_suites = vec![&TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256];
+        let (client_stream, server_stream) = handshake(server_config(), client);
+        assert!(client_stream.is_err());
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_enforce_security_level() {
+        let mut server = server_config();
+        server.apply_security_level(SecurityLevel::Intermediate);
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert_eq!(&TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, client_stream.unwrap().session().cipher_suite);
+        server_stream.join().unwrap().unwrap();
+
+        let mut server = server_config();
+        server.apply_security_level(SecurityLevel::Intermediate);
+        let mut client = client_config();
+        client.cipher_suites = vec![&TLS_RSA_WITH_AES_128_GCM_SHA256];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert!(client_stream.is_err());
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+
+        let mut server = server_config();
+        server.apply_security_level(SecurityLevel::Modern);
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        let (client_stream, server_stream) = handshake(server, client);
+        assert!(client_stream.is_err());
+        assert_eq!(PROTOCOL_VERSION, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_skip_ecdhe_without_common_group() {
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA, &TLS_RSA_WITH_AES_128_CBC_SHA];
+        server.groups = vec![NamedGroup::Secp384r1];
+        let mut client = client_config();
+        client.groups = vec![NamedGroup::X25519];
+        let (client, server) = handshake(server, client);
+        assert_eq!(&TLS_RSA_WITH_AES_128_CBC_SHA, client.unwrap().session().cipher_suite);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_can_negotiate_encrypt_then_mac_and_extended_master_secret() {
+        for (suite, encrypt_then_mac) in [(&TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA, true), (&TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, false)] {
+            let mut server = server_config();
+            server.cipher_suites = vec![suite];
+            let mut client = client_config();
+            client.versions = vec![ProtocolVersion::TLS1_2];
+            let (mut client, server) = connected(server, client, |mut stream| {
+                stream.write_all(b"hi")?;
+                Ok(stream.session().peer_extensions.clone())
+            });
+            let mut hi = [0u8; 2];
+            client.read_exact(&mut hi).unwrap();
+            let client_extensions = server.join().unwrap().unwrap();
+            assert!(client_extensions.contains(&Extension::EncryptThenMac));
+            assert!(client_extensions.contains(&Extension::ExtendedMasterSecret));
+            // encrypt_then_mac is left out for AEAD suites
+            assert_eq!(encrypt_then_mac, client.session().peer_extensions.contains(&Extension::EncryptThenMac));
+            assert!(client.session().peer_extensions.contains(&Extension::ExtendedMasterSecret));
+        }
+    }
+
+    #[test]
+    fn test_can_log_secrets() {
+        let tls13_labels = vec![
+            keylog::CLIENT_HANDSHAKE_TRAFFIC_SECRET,
+            keylog::SERVER_HANDSHAKE_TRAFFIC_SECRET,
+            keylog::CLIENT_TRAFFIC_SECRET_0,
+            keylog::SERVER_TRAFFIC_SECRET_0,
+            keylog::EXPORTER_SECRET,
+        ];
+        for (version, labels) in [(ProtocolVersion::TLS1_3, tls13_labels), (ProtocolVersion::TLS1_2, vec![keylog::CLIENT_RANDOM])] {
+            let server_log = Arc::new(MemoryKeyLog::default());
+            let client_log = Arc::new(MemoryKeyLog::default());
+            let mut server = server_config();
+            server.key_log = Some(server_log.clone());
+            let mut client = client_config();
+            client.versions = vec![version];
+            client.key_log = Some(client_log.clone());
+            let (client_stream, server_stream) = handshake(server, client);
+            client_stream.unwrap();
+            server_stream.join().unwrap().unwrap();
+            let lines = client_log.0.lock().unwrap().clone();
+            assert_eq!(labels, lines.iter().map(|l| l.split(' ').next().unwrap()).collect::<Vec<&str>>());
+            // both sides have the same secrets for the same client random
+            assert_eq!(lines, *server_log.0.lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_can_trace_handshake() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2] {
+            let server_tracer = Arc::new(MemoryTracer::default());
+            let client_tracer = Arc::new(MemoryTracer::default());
+            let mut server = server_config();
+            server.tracer = Some(server_tracer.clone());
+            let mut client = client_config();
+            client.versions = vec![version];
+            client.tracer = Some(client_tracer.clone());
+            let first_suite = client.cipher_suites.iter().find(|s| s.is_tls13() == (version == ProtocolVersion::TLS1_3)).unwrap().name.to_string();
+            let (client_stream, server_stream) = handshake(server, client);
+            client_stream.unwrap();
+            server_stream.join().unwrap().unwrap();
+
+            let client_events = client_tracer.0.lock().unwrap().clone();
+            let hello = client_events.iter().find(|e| e.kind == "handshake").unwrap();
+            assert_eq!((Direction::Sent, "ClientHello"), (hello.direction, &*hello.name));
+            assert!(hello.fields.iter().any(|(name, value)| *name == "cipher_suites" && matches!(value, TraceValue::List(suites) if suites.contains(&first_suite))));
+            let certificate = client_events.iter().find(|e| e.name == "Certificate").unwrap();
+            assert_eq!(Direction::Received, certificate.direction);
+            assert!(certificate.fields.iter().any(|(name, value)| *name == "subjects" && matches!(value, TraceValue::List(subjects) if subjects.len() == 2 && subjects[0].contains("CN=localhost"))));
+            assert!(client_events.iter().any(|e| e.kind == "record" && e.fields.contains(&("protected", TraceValue::Bool(true)))));
+
+            // what one side sends, the other receives
+            let server_events = server_tracer.0.lock().unwrap().clone();
+            let names = |events: &[TraceEvent], direction| events.iter().filter(|e| e.kind == "handshake" && e.direction == direction).map(|e| e.name.clone()).collect::<Vec<String>>();
+            assert_eq!(names(&client_events, Direction::Sent), names(&server_events, Direction::Received));
+            assert_eq!(names(&server_events, Direction::Sent), names(&client_events, Direction::Received));
+        }
+    }
+
+    #[test]
+    fn test_can_refuse_resumption_without_extended_master_secret() {
+        let server = Arc::new(server_config());
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        assert_eq!((false, false), resume(&server, &client));
+        let cache = client.session_cache.clone().unwrap();
+        let mut session = cache.get("localhost").unwrap();
+        assert!(session.extended_master_secret);
+
+        // the server resumes with an extended master secret that the session did not have
+        session.extended_master_secret = false;
+        cache.insert("localhost", session.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = server.clone();
+        let server_stream = thread::spawn(move || accept(listener.accept()?.0, &config));
+        let err = client::connect_resumable(TcpStream::connect(addr).unwrap(), &client, "localhost", "localhost").err().unwrap();
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&err));
+        assert!(server_stream.join().unwrap().is_err());
+
+        // a client that requires one does not offer such a session
+        cache.insert("localhost", session);
+        client.require_extended_master_secret = true;
+        assert_eq!((false, false), resume(&server, &client));
+        assert_eq!((true, true), resume(&server, &client));
+    }
+
+    #[test]
+    fn test_can_resume_sessions() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2].iter() {
+            let server = Arc::new(server_config());
+            let mut client = client_config();
+            client.versions = vec![*version];
+            assert_eq!((false, false), resume(&server, &client));
+            assert_eq!((true, true), resume(&server, &client));
+            assert_eq!((true, true), resume(&server, &client));
+        }
+
+        // TLS 1.2 by session ID alone and by ticket alone
+        let mut server = server_config();
+        server.ticket_keys = None;
+        let server = Arc::new(server);
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        assert_eq!((false, false), resume(&server, &client));
+        assert_eq!((true, true), resume(&server, &client));
+
+        let mut server = server_config();
+        server.session_cache = None;
+        let server = Arc::new(server);
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        assert_eq!((false, false), resume(&server, &client));
+        assert_eq!((true, true), resume(&server, &client));
+    }
+
+    #[test]
+    fn test_can_fall_back_to_full_handshake() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2].iter() {
+            let mut client = client_config();
+            client.versions = vec![*version];
+            let server = Arc::new(server_config());
+            assert_eq!((false, false), resume(&server, &client));
+            // another server knows neither the session ID nor the ticket key
+            let other = Arc::new(server_config());
+            assert_eq!((false, false), resume(&other, &client));
+            assert_eq!((true, true), resume(&other, &client));
+
+            // tickets of the previous key still open after a rotation, but not after two
+            let mut server = server_config();
+            server.session_cache = None;
+            let server = Arc::new(server);
+            assert_eq!((false, false), resume(&server, &client));
+            server.ticket_keys.as_ref().unwrap().rotate().unwrap();
+            assert_eq!((true, true), resume(&server, &client));
+            server.ticket_keys.as_ref().unwrap().rotate().unwrap();
+            server.ticket_keys.as_ref().unwrap().rotate().unwrap();
+            assert_eq!((false, false), resume(&server, &client));
+        }
+    }
+
+    #[test]
+    fn test_can_authenticate_client_certificate() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2].iter() {
+            let mut server = server_config();
+            server.client_auth = ClientAuth::Required(client_ca());
+            let mut client = client_config();
+            client.versions = vec![*version];
+            client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+            let (client_stream, server_stream) = handshake(server, client);
+            let mut client_stream = client_stream.unwrap();
+            let mut server_stream = server_stream.join().unwrap().unwrap();
+            let peer_certificates = &server_stream.session().peer_certificates;
+            assert_eq!(1, peer_certificates.len());
+            assert_eq!(pem::read_file(testdata("client.pem")).unwrap()[0].contents, peer_certificates[0].raw);
+            client_stream.write_all(b"ping").unwrap();
+            let mut ping = [0u8; 4];
+            server_stream.read_exact(&mut ping).unwrap();
+            assert_eq!(b"ping", &ping);
+
+            // without a certificate only an optional policy lets the client in
+            let mut server = server_config();
+            server.client_auth = ClientAuth::Optional(client_ca());
+            let mut client = client_config();
+            client.versions = vec![*version];
+            let (client_stream, server_stream) = handshake(server, client);
+            client_stream.unwrap();
+            assert!(server_stream.join().unwrap().unwrap().session().peer_certificates.is_empty());
+
+            let mut server = server_config();
+            server.client_auth = ClientAuth::Required(client_ca());
+            let mut client = client_config();
+            client.versions = vec![*version];
+            let (_, server_stream) = handshake(server, client);
+            let expected = if *version == ProtocolVersion::TLS1_3 { CERTIFICATE_REQUIRED } else { HANDSHAKE_FAILURE };
+            assert_eq!(expected, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+
+            // the client CA store is separate from the one server certificates chain to
+            let mut server = server_config();
+            server.client_auth = ClientAuth::Optional(TrustStore::from_pem_dir(testdata("roots")).unwrap());
+            let mut client = client_config();
+            client.versions = vec![*version];
+            client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+            let (_, server_stream) = handshake(server, client);
+            assert_eq!(UNKNOWN_CA, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+        }
+    }
+
+    #[test]
+    fn test_can_resume_client_authenticated_sessions() {
+        for version in [ProtocolVersion::TLS1_3, ProtocolVersion::TLS1_2].iter() {
+            let mut server = server_config();
+            server.client_auth = ClientAuth::Required(client_ca());
+            let server = Arc::new(server);
+            let mut client = client_config();
+            client.versions = vec![*version];
+            client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+            assert_eq!((false, false), resume(&server, &client));
+            assert_eq!((true, true), resume(&server, &client));
+        }
+    }
+
+    #[test]
+    fn test_can_update_tls13_keys() {
+        let (mut client, server) = connected(server_config(), client_config(), |mut stream| {
+            let mut hello = [0u8; 5];
+            stream.read_exact(&mut hello)?;
+            stream.write_all(b"world")?;
+            // a KeyUpdate before every record after the first
+            stream.set_rekey_limit(Some(RekeyLimit::Records(1)));
+            for data in [b"a", b"b", b"c"] {
+                stream.write_all(data)?;
+            }
+            let mut done = [0u8; 1];
+            stream.read_exact(&mut done)?;
+            Ok(stream.rekeys())
+        });
+        client.rekey().unwrap();
+        client.write_all(b"hello").unwrap();
+        let mut data = [0u8; 8];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(b"worldabc", &data);
+        client.write_all(b"!").unwrap();
+        // ours and the server's answer, then three from the server with our answers
+        assert_eq!(8, client.rekeys());
+        assert_eq!(8, server.join().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_can_renegotiate_tls12() {
+        let mut server = server_config();
+        server.allow_client_renegotiation = true;
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+        let (mut client, server) = connected(server, client, |mut stream| {
+            // the client renegotiates before it sends this
+            let mut hello = [0u8; 5];
+            stream.read_exact(&mut hello)?;
+            stream.rekey()?;
+            stream.write_all(b"x")?;
+            stream.read_exact(&mut hello[..1])?;
+            Ok(stream.rekeys())
+        });
+        client.rekey().unwrap();
+        // Finished is the only record under the new keys so far
+        assert_eq!(1, client.records_written());
+        client.write_all(b"hello").unwrap();
+        let mut x = [0u8; 1];
+        client.read_exact(&mut x).unwrap();
+        assert_eq!(b"x", &x);
+        client.write_all(b"y").unwrap();
+        assert_eq!(2, client.rekeys());
+        assert_eq!(2, server.join().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_can_refuse_client_renegotiation() {
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_2];
+        let (mut client, server) = connected(server_config(), client, |mut stream| {
+            let mut data = [0u8; 2];
+            stream.read_exact(&mut data)?;
+            Ok((data, stream.rekeys()))
+        });
+        let err = client.rekey().unwrap_err();
+        assert_eq!(Some(Alert::new(AlertLevel::Warning, NO_RENEGOTIATION)), alert::received_alert(&err));
+        // the connection carries on under the first keys
+        client.write_all(b"ok").unwrap();
+        assert_eq!((*b"ok", 0), server.join().unwrap().unwrap());
+        assert_eq!(0, client.rekeys());
+    }
+
+    #[cfg(feature = "legacy-protocols")]
+    #[test]
+    fn test_can_negotiate_legacy_versions() {
+        use super::super::suites::TLS_DHE_RSA_WITH_AES_256_CBC_SHA;
+        let legacy = [ProtocolVersion::TLS1_1, ProtocolVersion::TLS1_0, ProtocolVersion::SSL3_0];
+        for version in legacy.iter() {
+            for suite in [&TLS_RSA_WITH_AES_128_CBC_SHA, &TLS_DHE_RSA_WITH_AES_256_CBC_SHA, &TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA].iter() {
+                if !suite.supports_version(*version) {
+                    continue;
+                }
+                let mut server = server_config();
+                server.versions = ProtocolVersion::IMPLEMENTED.to_vec();
+                let mut client = client_config();
+                client.versions = vec![*version];
+                client.cipher_suites = vec![*suite];
+                let (mut client, server) = connected(server, client, |mut stream| {
+                    let mut hello = [0u8; 5];
+                    stream.read_exact(&mut hello)?;
+                    stream.write_all(&hello.repeat(10_000))?;
+                    Ok(stream.session().version)
+                });
+                assert_eq!(*version, client.session().version);
+                let written = client.records_written();
+                client.write_all(b"hello").unwrap();
+                // split 1/n-1 against BEAST except in TLS 1.1, which has explicit IVs
+                let expected = if *version == ProtocolVersion::TLS1_1 { 1 } else { 2 };
+                assert_eq!(expected, client.records_written() - written);
+                let mut response = vec![0u8; 50_000];
+                client.read_exact(&mut response).unwrap();
+                assert_eq!(b"hello".repeat(10_000), response);
+                assert_eq!(*version, server.join().unwrap().unwrap());
+            }
+            let mut client = client_config();
+            client.versions = vec![*version];
+            let mut server = server_config();
+            server.versions = vec![*version];
+            let server = Arc::new(server);
+            assert_eq!((false, false), resume(&server, &client));
+            assert_eq!((true, true), resume(&server, &client));
+        }
+
+        // the highest version in common, without extensions SSL 3.0 does not define
+        let mut server = server_config();
+        server.versions = legacy.to_vec();
+        let mut client = client_config();
+        client.versions = ProtocolVersion::IMPLEMENTED.to_vec();
+        let (client, server) = handshake(server, client);
+        assert_eq!(ProtocolVersion::TLS1_1, client.unwrap().session().version);
+        server.join().unwrap().unwrap();
+        let mut server = server_config();
+        server.versions = vec![ProtocolVersion::SSL3_0];
+        let mut client = client_config();
+        client.versions = legacy.to_vec();
+        let (client, server) = handshake(server, client);
+        let session = client.unwrap().session().clone();
+        assert_eq!(ProtocolVersion::SSL3_0, session.version);
+        assert!(!session.peer_extensions.contains(&Extension::ExtendedMasterSecret));
+        assert!(!session.peer_extensions.contains(&Extension::EncryptThenMac));
+        server.join().unwrap().unwrap();
+
+        // a client that does not offer them never gets them
+        let mut server = server_config();
+        server.versions = ProtocolVersion::IMPLEMENTED.to_vec();
+        let (client, server) = handshake(server, client_config());
+        assert_eq!(ProtocolVersion::TLS1_3, client.unwrap().session().version);
+        server.join().unwrap().unwrap();
+    }
+
+    #[cfg(feature = "legacy-protocols")]
+    #[test]
+    fn test_can_authenticate_client_certificate_in_legacy_versions() {
+        let mut server = server_config();
+        server.versions = vec![ProtocolVersion::TLS1_0];
+        server.client_auth = ClientAuth::Required(client_ca());
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::TLS1_0];
+        client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+        let (client_stream, server_stream) = handshake(server, client);
+        client_stream.unwrap();
+        assert_eq!(1, server_stream.join().unwrap().unwrap().session().peer_certificates.len());
+
+        let mut server = server_config();
+        server.versions = vec![ProtocolVersion::SSL3_0];
+        server.client_auth = ClientAuth::Required(client_ca());
+        let mut client = client_config();
+        client.versions = vec![ProtocolVersion::SSL3_0];
+        client.set_certificate_from_pem_files(testdata("client.pem"), testdata("client.key")).unwrap();
+        let (_, server_stream) = handshake(server, client);
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&server_stream.join().unwrap().unwrap_err()));
+    }
+
+    #[test]
+    fn test_can_return_error_weak_dh_group() {
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_DHE_RSA_WITH_AES_128_CBC_SHA256];
+        // the 1024-bit MODP group of RFC 2409 section 6.2
+        server.dh_params = DhParams {
+            p: Huge::from_hex(concat!(
+                "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+                "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+                "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+                "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF",
+            )),
+            g: Huge::from_u64(2),
+        };
+        let (client, server) = handshake(server, client_config());
+        assert_eq!(INSUFFICIENT_SECURITY, alert::description_of(&client.unwrap_err()));
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_no_shared_cipher_suite() {
+        let mut server = server_config();
+        server.cipher_suites = vec![&TLS_RSA_WITH_AES_128_CBC_SHA];
+        let mut client = client_config();
+        client.cipher_suites = vec![&TLS_RSA_WITH_AES_256_CBC_SHA256];
+        let (client, server) = handshake(server, client);
+        let err = server.join().unwrap().unwrap_err();
+        assert_eq!(HANDSHAKE_FAILURE, alert::description_of(&err));
+        let received = alert::received_alert(&client.unwrap_err()).unwrap();
+        assert_eq!(Alert::new(AlertLevel::Fatal, HANDSHAKE_FAILURE), received);
+    }
+
+    #[test]
+    fn test_can_return_error_mismatched_key() {
+        let chain = vec![pem::read_file(testdata("inter.pem")).unwrap().remove(0).contents];
+        let key = server_config().private_key;
+        assert!(ServerConfig::new(chain, key).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::aes::{
+    aes_cbc_decrypt,
+    aes_cbc_encrypt,
+    Aes,
+    AES_BLOCK_SIZE,
+};
+use crate::digest::HashAlgorithm;
+use crate::hmac::{
+    constant_time_eq,
+    hmac,
+};
+use crate::random::{
+    fill_random,
+    random_bytes,
+};
+use crate::x509::Certificate;
+
+use super::alert::{
+    self,
+    ILLEGAL_PARAMETER,
+};
+use super::codec::{
+    Reader,
+    put_u8,
+    put_u16,
+    put_u64,
+    put_vec8,
+    put_vec16,
+    put_vec24,
+};
+use super::handshake::NewSessionTicketTls13;
+use super::key_schedule::resumption_psk;
+use super::record::ProtocolVersion;
+use super::suites::{
+    self,
+    CipherSuite,
+};
+
+/// Seconds a session can be resumed for, unless configured otherwise
+pub const DEFAULT_SESSION_LIFETIME: u32 = 7200;
+/// Longest lifetime of a TLS 1.3 ticket (RFC 8446 section 4.6.1)
+pub const MAX_TICKET_LIFETIME: u32 = 7 * 24 * 3600;
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+const TICKET_KEY_NAME_LEN: usize = 16;
+const TICKET_AES_KEY_LEN: usize = 16;
+const TICKET_HMAC_KEY_LEN: usize = 32;
+const TICKET_MAC_LEN: usize = 32;
+/// The key tickets are sealed with, and the one it replaced
+const MAX_TICKET_KEYS: usize = 2;
+
+/// What a client keeps to resume a session with a server
+#[derive(Debug, Clone)]
+pub struct ClientSession {
+    pub version: ProtocolVersion,
+    pub cipher_suite: &'static CipherSuite,
+    /// TLS 1.2: the server's session ID, empty if it only gave a ticket
+    pub session_id: Vec<u8>,
+    /// RFC 5077 or TLS 1.3 ticket, empty without one
+    pub ticket: Vec<u8>,
+    /// TLS 1.2 master secret, or the PSK of a TLS 1.3 ticket
+    pub secret: Vec<u8>,
+    /// TLS 1.2: the master secret came from extended_master_secret (RFC 7627)
+    pub extended_master_secret: bool,
+    /// TLS 1.3: added to the ticket age the client reports
+    pub ticket_age_add: u32,
+    /// the server's chain from the full handshake, which resumed connections inherit
+    pub peer_certificates: Vec<Certificate>,
+    pub received: Instant,
+    pub lifetime: Duration,
+}
+
+impl ClientSession {
+    pub fn is_expired(&self) -> bool {
+        self.received.elapsed() >= self.lifetime
+    }
+
+    /// obfuscated_ticket_age of pre_shared_key: milliseconds since the ticket arrived, plus age_add
+    pub fn obfuscated_ticket_age(&self) -> u32 {
+        (self.received.elapsed().as_millis() as u32).wrapping_add(self.ticket_age_add)
+    }
+}
+
+/// A client's sessions keyed by "host:port". Expired sessions are dropped when looked up
+/// and the oldest one makes room when the cache is full.
+pub struct ClientSessionCache {
+    capacity: usize,
+    sessions: Mutex<HashMap<String, ClientSession>>,
+}
+
+impl ClientSessionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<ClientSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(key)?.is_expired() {
+            sessions.remove(key);
+            return None;
+        }
+        sessions.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, session: ClientSession) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if !sessions.contains_key(key) && sessions.len() >= self.capacity {
+            sessions.retain(|_, s| !s.is_expired());
+            let oldest = sessions.iter().min_by_key(|(_, s)| s.received).map(|(k, _)| k.clone());
+            if let (Some(oldest), true) = (oldest, sessions.len() >= self.capacity) {
+                sessions.remove(&oldest);
+            }
+        }
+        if self.capacity > 0 {
+            sessions.insert(key.to_string(), session);
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.sessions.lock().unwrap().remove(key);
+    }
+}
+
+/// What a server needs to resume a session, kept in its cache or sealed into a ticket
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSession {
+    pub version: ProtocolVersion,
+    pub cipher_suite: &'static CipherSuite,
+    /// TLS 1.2 master secret, or the PSK of a TLS 1.3 ticket
+    pub secret: Vec<u8>,
+    /// TLS 1.2: the master secret came from extended_master_secret (RFC 7627)
+    pub extended_master_secret: bool,
+    /// DER chain the client authenticated with, if it did
+    pub peer_certificates: Vec<Vec<u8>>,
+    /// seconds since the Unix epoch
+    pub issued: u64,
+}
+
+impl ServerSession {
+    pub fn new(version: ProtocolVersion, cipher_suite: &'static CipherSuite, secret: Vec<u8>, peer_certificates: Vec<Vec<u8>>) -> Self {
+        Self {
+            version,
+            cipher_suite,
+            secret,
+            extended_master_secret: false,
+            peer_certificates,
+            issued: unix_time(),
+        }
+    }
+
+    pub fn is_expired(&self, lifetime: u32) -> bool {
+        unix_time().saturating_sub(self.issued) >= lifetime as u64
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![];
+        put_u16(&mut out, self.version.as_u16());
+        put_u16(&mut out, self.cipher_suite.id);
+        put_vec8(&mut out, &self.secret);
+        put_u8(&mut out, self.extended_master_secret as u8);
+        let mut chain: Vec<u8> = vec![];
+        for cert in &self.peer_certificates {
+            put_vec24(&mut chain, cert);
+        }
+        put_vec24(&mut out, &chain);
+        put_u64(&mut out, self.issued);
+        out
+    }
+
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let version = ProtocolVersion::from_u16(reader.read_u16()?);
+        let suite_id = reader.read_u16()?;
+        let cipher_suite = suites::find(suite_id).ok_or_else(|| anyhow!("Unknown cipher suite {:04x} in session", suite_id))?;
+        let secret = reader.read_vec8()?.to_vec();
+        let extended_master_secret = match reader.read_u8()? {
+            0 => false,
+            1 => true,
+            value => return Err(anyhow!("Invalid extended_master_secret flag {} in session", value)),
+        };
+        let mut chain = Reader::new(reader.read_vec24()?);
+        let mut peer_certificates: Vec<Vec<u8>> = vec![];
+        while !chain.is_empty() {
+            peer_certificates.push(chain.read_vec24()?.to_vec());
+        }
+        let issued = reader.read_u64()?;
+        reader.expect_end()?;
+        Ok(Self {
+            version,
+            cipher_suite,
+            secret,
+            extended_master_secret,
+            peer_certificates,
+            issued,
+        })
+    }
+}
+
+/// A server's TLS 1.2 sessions keyed by session ID, the oldest making room when it is full
+pub struct ServerSessionCache {
+    capacity: usize,
+    sessions: Mutex<HashMap<Vec<u8>, ServerSession>>,
+}
+
+impl ServerSessionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, session_id: &[u8]) -> Option<ServerSession> {
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    pub fn insert(&self, session_id: &[u8], session: ServerSession) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if !sessions.contains_key(session_id) && sessions.len() >= self.capacity {
+            let oldest = sessions.iter().min_by_key(|(_, s)| s.issued).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+        if self.capacity > 0 {
+            sessions.insert(session_id.to_vec(), session);
+        }
+    }
+
+    pub fn remove(&self, session_id: &[u8]) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+}
+
+struct TicketKey {
+    name: [u8; TICKET_KEY_NAME_LEN],
+    aes: Aes,
+    hmac_key: [u8; TICKET_HMAC_KEY_LEN],
+}
+
+impl TicketKey {
+    fn generate() -> Result<Self> {
+        let mut name = [0u8; TICKET_KEY_NAME_LEN];
+        fill_random(&mut name)?;
+        let mut hmac_key = [0u8; TICKET_HMAC_KEY_LEN];
+        fill_random(&mut hmac_key)?;
+        Ok(Self {
+            name,
+            aes: Aes::new(&random_bytes(TICKET_AES_KEY_LEN)?)?,
+            hmac_key,
+        })
+    }
+}
+
+/// Keys that seal sessions into tickets in the form RFC 5077 section 4 recommends:
+/// key_name, IV, the state encrypted with AES-128-CBC, and an HMAC-SHA256 over all of it.
+/// The newest key seals; tickets of the key it replaced still open until the next rotation.
+pub struct TicketKeys {
+    /// newest first
+    keys: Mutex<Vec<TicketKey>>,
+}
+
+impl TicketKeys {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            keys: Mutex::new(vec![TicketKey::generate()?]),
+        })
+    }
+
+    /// Starts sealing with a fresh key. Tickets of the key before the current one stop working.
+    pub fn rotate(&self) -> Result<()> {
+        let key = TicketKey::generate()?;
+        let mut keys = self.keys.lock().unwrap();
+        keys.insert(0, key);
+        keys.truncate(MAX_TICKET_KEYS);
+        Ok(())
+    }
+
+    pub fn seal(&self, session: &ServerSession) -> Result<Vec<u8>> {
+        let keys = self.keys.lock().unwrap();
+        let key = &keys[0];
+        let mut state = session.encode();
+        let padding = AES_BLOCK_SIZE - state.len() % AES_BLOCK_SIZE;
+        state.extend(std::iter::repeat_n(padding as u8, padding));
+        let iv = random_bytes(AES_BLOCK_SIZE)?;
+        let mut ticket = key.name.to_vec();
+        ticket.extend_from_slice(&iv);
+        put_vec16(&mut ticket, &aes_cbc_encrypt(&key.aes, &iv, &state)?);
+        let mac = hmac(HashAlgorithm::Sha256, &key.hmac_key, &ticket);
+        ticket.extend(mac);
+        Ok(ticket)
+    }
+
+    /// The session in `ticket`, or None if no current key sealed it
+    pub fn open(&self, ticket: &[u8]) -> Option<ServerSession> {
+        if ticket.len() < TICKET_KEY_NAME_LEN + AES_BLOCK_SIZE + 2 + TICKET_MAC_LEN {
+            return None;
+        }
+        let (sealed, mac) = ticket.split_at(ticket.len() - TICKET_MAC_LEN);
+        let keys = self.keys.lock().unwrap();
+        let key = keys.iter().find(|k| k.name == sealed[..TICKET_KEY_NAME_LEN])?;
+        if !constant_time_eq(&hmac(HashAlgorithm::Sha256, &key.hmac_key, sealed), mac) {
+            return None;
+        }
+        let mut reader = Reader::new(&sealed[TICKET_KEY_NAME_LEN..]);
+        let iv = reader.read_bytes(AES_BLOCK_SIZE).ok()?;
+        let encrypted = reader.read_vec16().ok()?;
+        reader.expect_end().ok()?;
+        let mut state = aes_cbc_decrypt(&key.aes, iv, encrypted).ok()?;
+        let padding = *state.last()? as usize;
+        if padding == 0 || padding > AES_BLOCK_SIZE || padding > state.len() {
+            return None;
+        }
+        state.truncate(state.len() - padding);
+        ServerSession::parse(&state).ok()
+    }
+}
+
+/// Caches the sessions of TLS 1.3 NewSessionTicket messages, which arrive after the handshake
+pub(crate) struct TicketReceiver {
+    pub(crate) cache: Arc<ClientSessionCache>,
+    pub(crate) key: String,
+    pub(crate) cipher_suite: &'static CipherSuite,
+    pub(crate) peer_certificates: Vec<Certificate>,
+    pub(crate) resumption_master_secret: Vec<u8>,
+}
+
+impl TicketReceiver {
+    pub(crate) fn receive(&self, body: &[u8]) -> Result<()> {
+        let ticket = NewSessionTicketTls13::parse(body)?;
+        if ticket.lifetime > MAX_TICKET_LIFETIME {
+            return Err(alert::fatal(ILLEGAL_PARAMETER, format!("Ticket lifetime of {} seconds", ticket.lifetime)));
+        }
+        // a lifetime of zero means the ticket is not to be used
+        if ticket.lifetime == 0 {
+            return Ok(());
+        }
+        let hash = self.cipher_suite.prf_hash;
+        self.cache.insert(&self.key, ClientSession {
+            version: ProtocolVersion::TLS1_3,
+            cipher_suite: self.cipher_suite,
+            session_id: vec![],
+            ticket: ticket.ticket,
+            secret: resumption_psk(hash, &self.resumption_master_secret, &ticket.nonce),
+            extended_master_secret: false,
+            ticket_age_add: ticket.age_add,
+            peer_certificates: self.peer_certificates.clone(),
+            received: Instant::now(),
+            lifetime: Duration::from_secs(ticket.lifetime as u64),
+        });
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
+
+    #[test]
+    fn test_can_seal_tickets_across_rotation() {
+        let keys = TicketKeys::new().unwrap();
+        let mut session = ServerSession::new(ProtocolVersion::TLS1_2, &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, vec![7; 48], vec![vec![1, 2, 3]]);
+        session.extended_master_secret = true;
+        let ticket = keys.seal(&session).unwrap();
+        assert_eq!(Some(session.clone()), keys.open(&ticket));
+
+        let mut tampered = ticket.clone();
+        tampered[TICKET_KEY_NAME_LEN + 1] ^= 1;
+        assert_eq!(None, keys.open(&tampered));
+
+        // the previous key still opens its tickets, the one before it no longer does
+        keys.rotate().unwrap();
+        assert_eq!(Some(session.clone()), keys.open(&ticket));
+        assert_ne!(ticket[..TICKET_KEY_NAME_LEN], keys.seal(&session).unwrap()[..TICKET_KEY_NAME_LEN]);
+        keys.rotate().unwrap();
+        assert_eq!(None, keys.open(&ticket));
+    }
+
+    #[test]
+    fn test_can_evict_oldest_client_session() {
+        let cache = ClientSessionCache::new(2);
+        let session = |age: u64| ClientSession {
+            version: ProtocolVersion::TLS1_2,
+            cipher_suite: &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+            session_id: vec![1; 32],
+            ticket: vec![],
+            secret: vec![2; 48],
+            extended_master_secret: true,
+            ticket_age_add: 0,
+            peer_certificates: vec![],
+            received: Instant::now() - Duration::from_secs(age),
+            lifetime: Duration::from_secs(DEFAULT_SESSION_LIFETIME as u64),
+        };
+        cache.insert("a:443", session(20));
+        cache.insert("b:443", session(10));
+        cache.insert("c:443", session(0));
+        assert!(cache.get("a:443").is_none());
+        assert!(cache.get("b:443").is_some());
+        cache.insert("d:443", session(DEFAULT_SESSION_LIFETIME as u64));
+        assert!(cache.get("d:443").is_none());
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use anyhow::{
+    Result,
+    anyhow,
+    Context as _,
+};
+
+use crate::digest::HashAlgorithm;
+use crate::ecc::Curve;
+use crate::ecdsa::EcPublicKey;
+use crate::pem::{
+    self,
+    LABEL_CERTIFICATE,
+};
+use crate::rsa::{
+    RsaPrivateKey,
+    RsaPublicKey,
+};
+use crate::x509::{
+    Certificate,
+    SubjectPublicKeyInfo,
+};
+
+use super::alert::{
+    self,
+    DECRYPT_ERROR,
+    ILLEGAL_PARAMETER,
+    INTERNAL_ERROR,
+};
+use super::record::ProtocolVersion;
+
+// SignatureScheme (RFC 8446 section 4.2.3), which reuses the TLS 1.2 (hash, signature) pairs
+pub const RSA_PKCS1_SHA1: u16 = 0x0201;
+pub const RSA_PKCS1_SHA256: u16 = 0x0401;
+pub const RSA_PKCS1_SHA384: u16 = 0x0501;
+pub const RSA_PKCS1_SHA512: u16 = 0x0601;
+pub const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+pub const ECDSA_SECP384R1_SHA384: u16 = 0x0503;
+pub const RSA_PSS_RSAE_SHA256: u16 = 0x0804;
+pub const RSA_PSS_RSAE_SHA384: u16 = 0x0805;
+pub const RSA_PSS_RSAE_SHA512: u16 = 0x0806;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    RsaPkcs1,
+    RsaPss,
+    Ecdsa,
+}
+
+fn algorithm_and_hash(scheme: u16) -> Option<(Algorithm, HashAlgorithm)> {
+    match scheme {
+        RSA_PKCS1_SHA1 => Some((Algorithm::RsaPkcs1, HashAlgorithm::Sha1)),
+        RSA_PKCS1_SHA256 => Some((Algorithm::RsaPkcs1, HashAlgorithm::Sha256)),
+        RSA_PKCS1_SHA384 => Some((Algorithm::RsaPkcs1, HashAlgorithm::Sha384)),
+        RSA_PKCS1_SHA512 => Some((Algorithm::RsaPkcs1, HashAlgorithm::Sha512)),
+        ECDSA_SECP256R1_SHA256 => Some((Algorithm::Ecdsa, HashAlgorithm::Sha256)),
+        ECDSA_SECP384R1_SHA384 => Some((Algorithm::Ecdsa, HashAlgorithm::Sha384)),
+        RSA_PSS_RSAE_SHA256 => Some((Algorithm::RsaPss, HashAlgorithm::Sha256)),
+        RSA_PSS_RSAE_SHA384 => Some((Algorithm::RsaPss, HashAlgorithm::Sha384)),
+        RSA_PSS_RSAE_SHA512 => Some((Algorithm::RsaPss, HashAlgorithm::Sha512)),
+        _ => None,
+    }
+}
+
+/// TLS 1.3 drops RSASSA-PKCS1-v1_5 and SHA-1 for handshake signatures (RFC 8446 section 4.4.3)
+pub fn is_allowed_in_tls13(scheme: u16) -> bool {
+    match algorithm_and_hash(scheme) {
+        Some((algorithm, hash)) => algorithm != Algorithm::RsaPkcs1 && hash != HashAlgorithm::Sha1,
+        None => false,
+    }
+}
+
+/// Checks `signature` over `message` with the key of `spki`. A scheme that does not fit
+/// the key is an illegal_parameter alert, a bad signature a decrypt_error alert.
+pub fn verify(spki: &SubjectPublicKeyInfo, version: ProtocolVersion, scheme: u16, message: &[u8], signature: &[u8]) -> Result<()> {
+    let mismatch = || alert::fatal(ILLEGAL_PARAMETER, format!("Signature scheme {:04x} does not match the peer's key", scheme));
+    let (algorithm, hash) = algorithm_and_hash(scheme).ok_or_else(mismatch)?;
+    let verified = match algorithm {
+        Algorithm::RsaPkcs1 | Algorithm::RsaPss => {
+            let key = RsaPublicKey::from_spki(spki).map_err(|_| mismatch())?;
+            if algorithm == Algorithm::RsaPss {
+                key.verify_pss(hash, message, signature)
+            } else {
+                key.verify_pkcs1_v15(hash, message, signature)
+            }
+        },
+        Algorithm::Ecdsa => {
+            let key = EcPublicKey::from_spki(spki).map_err(|_| mismatch())?;
+            // the TLS 1.3 ECDSA schemes name the curve as well as the hash, the TLS 1.2 ones only the hash
+            let curve = if hash == HashAlgorithm::Sha256 { Curve::p256() } else { Curve::p384() };
+            if version >= ProtocolVersion::TLS1_3 && key.curve.name != curve.name {
+                return Err(mismatch());
+            }
+            key.verify(hash, message, signature)
+        },
+    };
+    verified.map_err(|e| alert::fatal(DECRYPT_ERROR, format!("Invalid signature: {}", e)))
+}
+
+/// Checks a signature of TLS 1.1 and earlier, which names no scheme: PKCS#1 over MD5 || SHA-1
+/// without a DigestInfo for RSA keys, SHA-1 for ECDSA (RFC 4346 section 7.4.3, RFC 4492 section 5.4)
+#[cfg(feature = "legacy-protocols")]
+pub fn verify_legacy(spki: &SubjectPublicKeyInfo, message: &[u8], signature: &[u8]) -> Result<()> {
+    let verified = match RsaPublicKey::from_spki(spki) {
+        Ok(key) => key.verify_pkcs1_v15_md5_sha1(message, signature),
+        Err(_) => {
+            let key = EcPublicKey::from_spki(spki).map_err(|e| alert::fatal(ILLEGAL_PARAMETER, format!("Unsupported peer key: {}", e)))?;
+            key.verify(HashAlgorithm::Sha1, message, signature)
+        },
+    };
+    verified.map_err(|e| alert::fatal(DECRYPT_ERROR, format!("Invalid signature: {}", e)))
+}
+
+/// Signs `message` with an RSA key the way TLS 1.1 and earlier do
+#[cfg(feature = "legacy-protocols")]
+pub fn sign_legacy(key: &RsaPrivateKey, message: &[u8]) -> Result<Vec<u8>> {
+    key.sign_pkcs1_v15_md5_sha1(message)
+}
+
+/// Signs `message` with an RSA key under a PKCS#1 or PSS `scheme`
+pub fn sign(key: &RsaPrivateKey, scheme: u16, message: &[u8]) -> Result<Vec<u8>> {
+    match algorithm_and_hash(scheme) {
+        Some((Algorithm::RsaPkcs1, hash)) => key.sign_pkcs1_v15(hash, message),
+        Some((Algorithm::RsaPss, hash)) => key.sign_pss(hash, message),
+        _ => Err(alert::fatal(INTERNAL_ERROR, format!("Unable to sign with scheme {:04x} and an RSA key", scheme))),
+    }
+}
+
+/// Checks that `private_key` belongs to the first certificate of `chain`
+pub fn check_certificate_key(chain: &[Vec<u8>], private_key: &RsaPrivateKey) -> Result<()> {
+    let leaf = chain.first().ok_or_else(|| anyhow!("Certificate chain is empty"))?;
+    let leaf = Certificate::from_der(leaf)?;
+    if RsaPublicKey::from_spki(&leaf.subject_public_key_info)? != private_key.public_key() {
+        return Err(anyhow!("Private key does not match the certificate '{}'", leaf.subject));
+    }
+    Ok(())
+}
+
+/// Loads a chain (leaf first) and its RSA key from PEM files. Both may be the same file.
+pub fn read_certificate_and_key(certificate_path: &Path, key_path: &Path) -> Result<(Vec<Vec<u8>>, RsaPrivateKey)> {
+    let chain: Vec<Vec<u8>> = pem::read_file(certificate_path)?
+        .into_iter()
+        .filter(|b| b.label == LABEL_CERTIFICATE)
+        .map(|b| b.contents)
+        .collect();
+    let key = pem::read_file(key_path)?
+        .iter()
+        .find(|b| b.label.ends_with("PRIVATE KEY"))
+        .map(RsaPrivateKey::from_pem)
+        .ok_or_else(|| anyhow!("No private key in {}", key_path.display()))?
+        .with_context(|| format!("Unable to load the private key in {}", key_path.display()))?;
+    Ok((chain, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::testdata;
+    use crate::pem;
+    use crate::x509::Certificate;
+
+    #[test]
+    fn test_can_sign_and_verify_schemes() {
+        let key = RsaPrivateKey::from_pem(&pem::read_file(testdata("leaf.key")).unwrap()[0]).unwrap();
+        let leaf = Certificate::from_der(&pem::read_file(testdata("leaf.pem")).unwrap()[0].contents).unwrap();
+        let spki = &leaf.subject_public_key_info;
+        for scheme in [RSA_PKCS1_SHA256, RSA_PSS_RSAE_SHA256, RSA_PSS_RSAE_SHA512].iter() {
+            let signature = sign(&key, *scheme, b"message").unwrap();
+            verify(spki, ProtocolVersion::TLS1_3, *scheme, b"message", &signature).unwrap();
+            assert_eq!(DECRYPT_ERROR, alert::description_of(&verify(spki, ProtocolVersion::TLS1_3, *scheme, b"massage", &signature).unwrap_err()));
+        }
+        let signature = sign(&key, RSA_PSS_RSAE_SHA256, b"message").unwrap();
+        assert_eq!(ILLEGAL_PARAMETER, alert::description_of(&verify(spki, ProtocolVersion::TLS1_3, ECDSA_SECP256R1_SHA256, b"message", &signature).unwrap_err()));
+        assert!(sign(&key, ECDSA_SECP256R1_SHA256, b"message").is_err());
+        assert!(is_allowed_in_tls13(RSA_PSS_RSAE_SHA384));
+        assert!(!is_allowed_in_tls13(RSA_PKCS1_SHA256));
+    }
+
+    #[cfg(feature = "legacy-protocols")]
+    #[test]
+    fn test_can_sign_and_verify_legacy() {
+        let key = RsaPrivateKey::from_pem(&pem::read_file(testdata("leaf.key")).unwrap()[0]).unwrap();
+        let leaf = Certificate::from_der(&pem::read_file(testdata("leaf.pem")).unwrap()[0].contents).unwrap();
+        let signature = sign_legacy(&key, b"message").unwrap();
+        verify_legacy(&leaf.subject_public_key_info, b"message", &signature).unwrap();
+        assert_eq!(DECRYPT_ERROR, alert::description_of(&verify_legacy(&leaf.subject_public_key_info, b"massage", &signature).unwrap_err()));
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt;
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::sync::Arc;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::x509::Certificate;
+
+use super::alert::{
+    self,
+    Alert,
+    AlertLevel,
+    PeerAlert,
+    DECODE_ERROR,
+    NO_RENEGOTIATION,
+    UNEXPECTED_MESSAGE,
+};
+use super::cipher::tls13_protection;
+use super::client::{
+    self,
+    ClientConfig,
+};
+use super::extensions::Extension;
+use super::handshake;
+use super::key_schedule::next_traffic_secret;
+use super::record::{
+    self,
+    HandshakeMessage,
+    ProtocolVersion,
+    RecordLayer,
+    RecordProtection,
+};
+use super::server::{
+    self,
+    ServerConfig,
+};
+use super::session::TicketReceiver;
+use super::suites::CipherSuite;
+
+/// Parameters agreed on during the handshake
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub version: ProtocolVersion,
+    pub cipher_suite: &'static CipherSuite,
+    /// the peer's chain as sent, its own certificate first
+    pub peer_certificates: Vec<Certificate>,
+    /// the name in server_name, as sent by the client
+    pub server_name: Option<String>,
+    /// the application protocol agreed on with ALPN
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// the extensions of the peer's hello, unknown ones included
+    pub peer_extensions: Vec<Extension>,
+    /// whether an earlier session was resumed rather than a full handshake made
+    pub resumed: bool,
+}
+
+/// The RFC 5746 binding of a TLS 1.2 handshake to the one before it on the connection
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SecureRenegotiation {
+    /// both sides signalled support in the first handshake, with renegotiation_info or the SCSV
+    pub supported: bool,
+    /// verify_data of the client and server Finished of the last handshake, empty before the first
+    pub client_verify_data: Vec<u8>,
+    pub server_verify_data: Vec<u8>,
+    /// the last handshake agreed on encrypt_then_mac, which a renegotiation to another
+    /// CBC suite may not give up (RFC 7366 section 3.1)
+    pub encrypt_then_mac: bool,
+}
+
+impl SecureRenegotiation {
+    /// Whether an earlier handshake completed, so that this one renegotiates
+    pub fn is_renegotiation(&self) -> bool {
+        !self.client_verify_data.is_empty()
+    }
+}
+
+/// Our side of the connection, with the config another handshake needs
+pub(crate) enum Endpoint {
+    Client {
+        config: Arc<ClientConfig>,
+        server_name: String,
+    },
+    Server {
+        config: Arc<ServerConfig>,
+    },
+}
+
+/// How the keys change once the connection is established
+pub(crate) enum Rekeying {
+    /// TLS 1.3: KeyUpdate steps each direction's application traffic secret forward
+    KeyUpdate {
+        read_secret: Vec<u8>,
+        write_secret: Vec<u8>,
+    },
+    /// TLS 1.2: a new handshake over the protected connection
+    Renegotiation {
+        endpoint: Endpoint,
+        secure_renegotiation: SecureRenegotiation,
+    },
+}
+
+/// When a stream changes its keys by itself, counted since they last changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyLimit {
+    /// plaintext bytes written, handshake messages and alerts included
+    Bytes(u64),
+    Records(u64),
+}
+
+/// Established TLS connection. Reads and writes carry application data.
+pub struct TlsStream<S: Read + Write> {
+    record_layer: RecordLayer<S>,
+    state: ConnectionState,
+    rekey_limit: Option<RekeyLimit>,
+}
+
+/// Everything but the record layer, so that handshake messages read after the
+/// handshake can be handled while the record layer is busy reading
+struct ConnectionState {
+    session: SessionInfo,
+    /// client side of TLS 1.3 with a session cache: where tickets go
+    ticket_receiver: Option<TicketReceiver>,
+    rekeying: Rekeying,
+    /// TLS 1.2 server: a HelloRequest went out and the client has not renegotiated yet
+    hello_request_sent: bool,
+    /// TLS 1.2: whether another handshake can run in place, which blocks until it is done
+    renegotiation_allowed: bool,
+    rekeys: u64,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    pub(crate) fn new(record_layer: RecordLayer<S>, session: SessionInfo, ticket_receiver: Option<TicketReceiver>, rekeying: Rekeying) -> Self {
+        Self {
+            record_layer,
+            state: ConnectionState {
+                session,
+                ticket_receiver,
+                rekeying,
+                hello_request_sent: false,
+                renegotiation_allowed: true,
+                rekeys: 0,
+            },
+            rekey_limit: None,
+        }
+    }
+
+    pub fn session(&self) -> &SessionInfo {
+        &self.state.session
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.record_layer.get_ref()
+    }
+
+    /// Writing to or reading from the stream directly corrupts the connection
+    pub fn get_mut(&mut self) -> &mut S {
+        self.record_layer.get_mut()
+    }
+
+    /// Turns down renegotiation from either side with a no_renegotiation warning, for
+    /// streams that cannot block until a new handshake is done
+    pub(crate) fn disallow_renegotiation(&mut self) {
+        self.state.renegotiation_allowed = false;
+    }
+
+    /// Sends close_notify. The underlying stream is left open.
+    pub fn close(&mut self) -> Result<()> {
+        self.record_layer.close()
+    }
+
+    /// Changes the keys. TLS 1.3 sends KeyUpdate and asks the peer to update its own
+    /// keys too. A TLS 1.2 client renegotiates and returns once the new handshake is
+    /// done; a server sends HelloRequest and takes the client's renegotiation in a
+    /// later read. TLS 1.2 peers have to support secure renegotiation (RFC 5746).
+    pub fn rekey(&mut self) -> Result<()> {
+        let result = self.state.rekey(&mut self.record_layer);
+        result.inspect_err(|e| {
+            // a refused renegotiation leaves the connection usable
+            if !e.is::<PeerAlert>() && !e.is::<io::Error>() {
+                let _ = self.record_layer.send_fatal_alert(alert::description_of(e));
+            }
+        })
+    }
+
+    /// Makes writes call `rekey` first once the current keys have protected `limit`.
+    /// Writes fail if the keys cannot be changed.
+    pub fn set_rekey_limit(&mut self, limit: Option<RekeyLimit>) {
+        self.rekey_limit = limit;
+    }
+
+    /// Times the keys changed since the first handshake: KeyUpdates sent and received
+    /// in TLS 1.3, renegotiations in TLS 1.2
+    pub fn rekeys(&self) -> u64 {
+        self.state.rekeys
+    }
+
+    /// Records written under the current keys
+    pub fn records_written(&self) -> u64 {
+        self.record_layer.records_written()
+    }
+
+    /// Plaintext bytes written under the current keys
+    pub fn bytes_written(&self) -> u64 {
+        self.record_layer.bytes_written()
+    }
+
+    fn rekey_due(&self) -> bool {
+        match self.rekey_limit {
+            // the client has yet to answer the last HelloRequest
+            _ if self.state.hello_request_sent => false,
+            Some(RekeyLimit::Bytes(limit)) => self.record_layer.bytes_written() >= limit,
+            Some(RekeyLimit::Records(limit)) => self.record_layer.records_written() >= limit,
+            None => false,
+        }
+    }
+}
+
+impl ConnectionState {
+    fn rekey<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>) -> Result<()> {
+        match &self.rekeying {
+            Rekeying::KeyUpdate { .. } => self.send_key_update(record_layer, true),
+            Rekeying::Renegotiation { secure_renegotiation, .. } if !secure_renegotiation.supported => {
+                Err(anyhow!("The peer does not support secure renegotiation"))
+            },
+            Rekeying::Renegotiation { .. } if !self.renegotiation_allowed => Err(anyhow!("Renegotiation is not allowed on this connection")),
+            Rekeying::Renegotiation { endpoint: Endpoint::Client { .. }, .. } => self.renegotiate(record_layer, None),
+            Rekeying::Renegotiation { endpoint: Endpoint::Server { .. }, .. } => {
+                // not part of any transcript (RFC 5246 section 7.4.1.1)
+                record_layer.send_handshake(&HandshakeMessage::new(handshake::HELLO_REQUEST, vec![]))?;
+                self.hello_request_sent = true;
+                Ok(())
+            },
+        }
+    }
+
+    /// Handles a handshake message that arrives after the handshake
+    fn handle_handshake<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, message: HandshakeMessage) -> Result<()> {
+        match (&self.rekeying, message.msg_type) {
+            // tickets are dropped unless there is a session cache to keep them in
+            (Rekeying::KeyUpdate { .. }, handshake::NEW_SESSION_TICKET) => match &self.ticket_receiver {
+                Some(receiver) => receiver.receive(&message.body),
+                None => Ok(()),
+            },
+            (Rekeying::KeyUpdate { .. }, handshake::KEY_UPDATE) => self.handle_key_update(record_layer, &message.body),
+            (Rekeying::Renegotiation { endpoint: Endpoint::Client { .. }, secure_renegotiation }, handshake::HELLO_REQUEST) => {
+                if !message.body.is_empty() {
+                    return Err(alert::fatal(DECODE_ERROR, "HelloRequest is not empty"));
+                }
+                // the connection carries on under the old keys (RFC 5746 section 4.2)
+                if !secure_renegotiation.supported || !self.renegotiation_allowed {
+                    return record_layer.send_alert(Alert::new(AlertLevel::Warning, NO_RENEGOTIATION));
+                }
+                self.renegotiate(record_layer, None)
+            },
+            (Rekeying::Renegotiation { endpoint: Endpoint::Server { config }, secure_renegotiation }, handshake::CLIENT_HELLO) => {
+                let allowed = (self.hello_request_sent || config.allow_client_renegotiation) && self.renegotiation_allowed;
+                if !secure_renegotiation.supported || !allowed {
+                    return record_layer.send_alert(Alert::new(AlertLevel::Warning, NO_RENEGOTIATION));
+                }
+                self.renegotiate(record_layer, Some(message))
+            },
+            (_, msg_type) => Err(alert::fatal(UNEXPECTED_MESSAGE, format!("Unexpected {} after the handshake", handshake::handshake_type_name(msg_type)))),
+        }
+    }
+
+    /// Moves reading to the next traffic secret, and writing too if the peer asks
+    fn handle_key_update<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, body: &[u8]) -> Result<()> {
+        let update_requested = handshake::parse_key_update(body)?;
+        if !record_layer.at_record_boundary() {
+            return Err(alert::fatal(UNEXPECTED_MESSAGE, "KeyUpdate is not the last message of its record"));
+        }
+        if let Rekeying::KeyUpdate { read_secret, .. } = &mut self.rekeying {
+            record_layer.set_read_protection(update_traffic_secret(self.session.cipher_suite, read_secret)?);
+            self.rekeys += 1;
+        }
+        if update_requested {
+            self.send_key_update(record_layer, false)?;
+        }
+        Ok(())
+    }
+
+    /// KeyUpdate under the current write key, then the switch to the next one
+    fn send_key_update<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, update_requested: bool) -> Result<()> {
+        if let Rekeying::KeyUpdate { write_secret, .. } = &mut self.rekeying {
+            record_layer.send_handshake(&handshake::encode_key_update(update_requested))?;
+            record_layer.set_write_protection(update_traffic_secret(self.session.cipher_suite, write_secret)?);
+            self.rekeys += 1;
+        }
+        Ok(())
+    }
+
+    /// Runs a TLS 1.2 handshake over the established connection. A server has the
+    /// client's ClientHello already.
+    fn renegotiate<S: Read + Write>(&mut self, record_layer: &mut RecordLayer<S>, client_hello: Option<HandshakeMessage>) -> Result<()> {
+        let Rekeying::Renegotiation { endpoint, secure_renegotiation } = &mut self.rekeying else {
+            return Err(anyhow!("Only TLS 1.2 renegotiates"));
+        };
+        let (session, next) = match (endpoint, client_hello) {
+            (Endpoint::Client { config, server_name }, _) => client::renegotiate(record_layer, config, server_name, secure_renegotiation)?,
+            (Endpoint::Server { config }, Some(client_hello)) => server::renegotiate(record_layer, config, secure_renegotiation, client_hello)?,
+            (Endpoint::Server { .. }, None) => return Err(anyhow!("The client starts a renegotiation")),
+        };
+        self.session = session;
+        *secure_renegotiation = next;
+        self.hello_request_sent = false;
+        self.rekeys += 1;
+        Ok(())
+    }
+}
+
+/// Steps `secret` forward (RFC 8446 section 7.2) and returns the protection under the new one
+fn update_traffic_secret(suite: &CipherSuite, secret: &mut Vec<u8>) -> Result<Box<dyn RecordProtection>> {
+    *secret = next_traffic_secret(suite.prf_hash, secret);
+    tls13_protection(suite, secret)
+}
+
+impl<S: Read + Write> fmt::Debug for TlsStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsStream").field("session", &self.state.session).finish()
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let state = &mut self.state;
+        self.record_layer.read_application_data(buf, |record_layer, message| state.handle_handshake(record_layer, message))
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.rekey_due() {
+            self.rekey().map_err(record::to_io_error)?;
+        }
+        self.record_layer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.record_layer.flush()
+    }
+}
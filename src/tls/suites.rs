@@ -0,0 +1,428 @@
+use crate::digest::HashAlgorithm;
+
+use super::record::ProtocolVersion;
+
+/// Signalling cipher suite value for secure renegotiation (RFC 5746)
+pub const TLS_EMPTY_RENEGOTIATION_INFO_SCSV: u16 = 0x00ff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyExchange {
+    /// premaster secret encrypted with the server's RSA key
+    Rsa,
+    /// ephemeral finite field Diffie-Hellman
+    Dhe,
+    /// ephemeral elliptic curve Diffie-Hellman
+    Ecdhe,
+    /// TLS 1.3 suites leave key exchange to key_share
+    Any,
+}
+
+impl KeyExchange {
+    /// Whether the server sends its (signed) key share in ServerKeyExchange
+    pub fn is_ephemeral(self) -> bool {
+        self == KeyExchange::Dhe || self == KeyExchange::Ecdhe
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyExchange::Rsa => "RSA",
+            KeyExchange::Dhe => "DHE",
+            KeyExchange::Ecdhe => "ECDHE",
+            KeyExchange::Any => "any",
+        }
+    }
+}
+
+/// The kind of key in the server certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authentication {
+    Rsa,
+    Ecdsa,
+    /// TLS 1.3 suites leave authentication to signature_algorithms
+    Any,
+}
+
+impl Authentication {
+    pub fn name(self) -> &'static str {
+        match self {
+            Authentication::Rsa => "RSA",
+            Authentication::Ecdsa => "ECDSA",
+            Authentication::Any => "any",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkCipher {
+    Aes128Cbc,
+    Aes256Cbc,
+    Aes128Gcm,
+    Aes256Gcm,
+}
+
+impl BulkCipher {
+    pub fn name(self) -> &'static str {
+        match self {
+            BulkCipher::Aes128Cbc => "AES-128-CBC",
+            BulkCipher::Aes256Cbc => "AES-256-CBC",
+            BulkCipher::Aes128Gcm => "AES-128-GCM",
+            BulkCipher::Aes256Gcm => "AES-256-GCM",
+        }
+    }
+
+    pub fn key_len(self) -> usize {
+        match self {
+            BulkCipher::Aes128Cbc | BulkCipher::Aes128Gcm => 16,
+            BulkCipher::Aes256Cbc | BulkCipher::Aes256Gcm => 32,
+        }
+    }
+}
+
+/// Record integrity: an HMAC next to a CBC cipher, or built into an AEAD cipher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mac {
+    Hmac(HashAlgorithm),
+    Aead,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CipherSuite {
+    pub id: u16,
+    pub name: &'static str,
+    pub key_exchange: KeyExchange,
+    pub authentication: Authentication,
+    pub bulk_cipher: BulkCipher,
+    pub mac: Mac,
+    /// PRF hash in TLS 1.2, HKDF and transcript hash in TLS 1.3
+    pub prf_hash: HashAlgorithm,
+}
+
+impl CipherSuite {
+    pub fn is_tls13(&self) -> bool {
+        self.key_exchange == KeyExchange::Any
+    }
+
+    /// Ephemeral key exchange and an AEAD cipher, the suites current guidance keeps
+    /// CBC with HMAC, the suites encrypt_then_mac applies to
+    pub fn is_cbc(&self) -> bool {
+        matches!(self.mac, Mac::Hmac(_))
+    }
+
+    pub fn is_forward_secret_aead(&self) -> bool {
+        (self.is_tls13() || self.key_exchange.is_ephemeral()) && self.mac == Mac::Aead
+    }
+
+    /// TLS 1.3 suites only work in TLS 1.3 and the others in TLS 1.2. The versions before
+    /// know neither AEAD ciphers nor a choice of PRF hash, so only the HMAC-SHA1 suites
+    /// carry over to them, and SSL 3.0 predates ECDHE as well.
+    pub fn supports_version(&self, version: ProtocolVersion) -> bool {
+        match version {
+            v if v >= ProtocolVersion::TLS1_3 => self.is_tls13(),
+            _ if self.is_tls13() => false,
+            ProtocolVersion::TLS1_2 => true,
+            ProtocolVersion::SSL3_0 => self.mac == Mac::Hmac(HashAlgorithm::Sha1) && self.key_exchange != KeyExchange::Ecdhe,
+            _ => self.mac == Mac::Hmac(HashAlgorithm::Sha1),
+        }
+    }
+}
+
+pub const TLS_RSA_WITH_AES_128_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0x002f,
+    name: "TLS_RSA_WITH_AES_128_CBC_SHA",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_RSA_WITH_AES_256_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0x0035,
+    name: "TLS_RSA_WITH_AES_256_CBC_SHA",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_RSA_WITH_AES_128_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0x003c,
+    name: "TLS_RSA_WITH_AES_128_CBC_SHA256",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_RSA_WITH_AES_256_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0x003d,
+    name: "TLS_RSA_WITH_AES_256_CBC_SHA256",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_AES_128_GCM_SHA256: CipherSuite = CipherSuite {
+    id: 0x1301,
+    name: "TLS_AES_128_GCM_SHA256",
+    key_exchange: KeyExchange::Any,
+    authentication: Authentication::Any,
+    bulk_cipher: BulkCipher::Aes128Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_AES_256_GCM_SHA384: CipherSuite = CipherSuite {
+    id: 0x1302,
+    name: "TLS_AES_256_GCM_SHA384",
+    key_exchange: KeyExchange::Any,
+    authentication: Authentication::Any,
+    bulk_cipher: BulkCipher::Aes256Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_128_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0x0033,
+    name: "TLS_DHE_RSA_WITH_AES_128_CBC_SHA",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_256_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0x0039,
+    name: "TLS_DHE_RSA_WITH_AES_256_CBC_SHA",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_128_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0x0067,
+    name: "TLS_DHE_RSA_WITH_AES_128_CBC_SHA256",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_256_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0x006b,
+    name: "TLS_DHE_RSA_WITH_AES_256_CBC_SHA256",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0xc013,
+    name: "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0xc014,
+    name: "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0xc027,
+    name: "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384: CipherSuite = CipherSuite {
+    id: 0xc028,
+    name: "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha384),
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_RSA_WITH_AES_128_GCM_SHA256: CipherSuite = CipherSuite {
+    id: 0x009c,
+    name: "TLS_RSA_WITH_AES_128_GCM_SHA256",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_RSA_WITH_AES_256_GCM_SHA384: CipherSuite = CipherSuite {
+    id: 0x009d,
+    name: "TLS_RSA_WITH_AES_256_GCM_SHA384",
+    key_exchange: KeyExchange::Rsa,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_128_GCM_SHA256: CipherSuite = CipherSuite {
+    id: 0x009e,
+    name: "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_DHE_RSA_WITH_AES_256_GCM_SHA384: CipherSuite = CipherSuite {
+    id: 0x009f,
+    name: "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384",
+    key_exchange: KeyExchange::Dhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0xc009,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA: CipherSuite = CipherSuite {
+    id: 0xc00a,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha1),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256: CipherSuite = CipherSuite {
+    id: 0xc023,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes128Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha256),
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384: CipherSuite = CipherSuite {
+    id: 0xc024,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes256Cbc,
+    mac: Mac::Hmac(HashAlgorithm::Sha384),
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: CipherSuite = CipherSuite {
+    id: 0xc02b,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes128Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: CipherSuite = CipherSuite {
+    id: 0xc02c,
+    name: "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Ecdsa,
+    bulk_cipher: BulkCipher::Aes256Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: CipherSuite = CipherSuite {
+    id: 0xc02f,
+    name: "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes128Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha256,
+};
+
+pub const TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: CipherSuite = CipherSuite {
+    id: 0xc030,
+    name: "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+    key_exchange: KeyExchange::Ecdhe,
+    authentication: Authentication::Rsa,
+    bulk_cipher: BulkCipher::Aes256Gcm,
+    mac: Mac::Aead,
+    prf_hash: HashAlgorithm::Sha384,
+};
+
+/// Every implemented suite, strongest first: TLS 1.3, then forward secret AEAD suites,
+/// then forward secret CBC suites, with static RSA last
+pub static ALL_SUITES: [&CipherSuite; 26] = [
+    &TLS_AES_256_GCM_SHA384,
+    &TLS_AES_128_GCM_SHA256,
+    &TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    &TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    &TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    &TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+    &TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+    &TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384,
+    &TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384,
+    &TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256,
+    &TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256,
+    &TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA,
+    &TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA,
+    &TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA,
+    &TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA,
+    &TLS_DHE_RSA_WITH_AES_256_CBC_SHA256,
+    &TLS_DHE_RSA_WITH_AES_128_CBC_SHA256,
+    &TLS_DHE_RSA_WITH_AES_256_CBC_SHA,
+    &TLS_DHE_RSA_WITH_AES_128_CBC_SHA,
+    &TLS_RSA_WITH_AES_256_GCM_SHA384,
+    &TLS_RSA_WITH_AES_128_GCM_SHA256,
+    &TLS_RSA_WITH_AES_256_CBC_SHA256,
+    &TLS_RSA_WITH_AES_128_CBC_SHA256,
+    &TLS_RSA_WITH_AES_256_CBC_SHA,
+    &TLS_RSA_WITH_AES_128_CBC_SHA,
+];
+
+/// Looks a suite up by its IANA code point
+pub fn find(id: u16) -> Option<&'static CipherSuite> {
+    ALL_SUITES.iter().find(|s| s.id == id).copied()
+}
+
+/// Looks a suite up by its IANA name, ignoring case
+pub fn find_by_name(name: &str) -> Option<&'static CipherSuite> {
+    ALL_SUITES.iter().find(|s| s.name.eq_ignore_ascii_case(name)).copied()
+}
@@ -0,0 +1,314 @@
+use std::fmt::Write as _;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use crate::x509::Certificate;
+
+use super::alert::{
+    Alert,
+    AlertLevel,
+};
+use super::extensions::{
+    self,
+    Extension,
+    HelloContext,
+};
+use super::handshake::{
+    self,
+    ClientHello,
+    RawExtension,
+    ServerHello,
+    HELLO_RETRY_REQUEST_RANDOM,
+};
+use super::record::{
+    ContentType,
+    HandshakeMessage,
+    ProtocolVersion,
+};
+use super::suites;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceValue {
+    Bool(bool),
+    Number(u64),
+    Text(String),
+    List(Vec<String>),
+}
+
+/// One record, message or request that went over the connection, with its decoded fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub direction: Direction,
+    /// "record", "handshake", "alert" or "http"
+    pub kind: &'static str,
+    /// the content type of a record, the type of a message, ...
+    pub name: String,
+    pub fields: Vec<(&'static str, TraceValue)>,
+}
+
+/// How a tracer prints events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// a line per event, then one per field
+    Text,
+    /// a JSON object per line
+    Json,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(anyhow!("Unknown trace format '{}'", name)),
+        }
+    }
+}
+
+/// Takes every event of the connections it is configured for
+pub trait Tracer: Send + Sync {
+    fn trace(&self, event: &TraceEvent);
+}
+
+/// Prints events to stderr, where they stay apart from the data on stdout
+pub struct StderrTracer {
+    format: TraceFormat,
+}
+
+impl StderrTracer {
+    pub fn new(format: TraceFormat) -> Self {
+        Self {
+            format,
+        }
+    }
+}
+
+impl Tracer for StderrTracer {
+    fn trace(&self, event: &TraceEvent) {
+        match self.format {
+            TraceFormat::Text => eprint!("{}", event.to_text()),
+            TraceFormat::Json => eprintln!("{}", event.to_json()),
+        }
+    }
+}
+
+impl TraceEvent {
+    pub fn new(direction: Direction, kind: &'static str, name: &str) -> Self {
+        Self {
+            direction,
+            kind,
+            name: name.to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn field(mut self, name: &'static str, value: TraceValue) -> Self {
+        self.fields.push((name, value));
+        self
+    }
+
+    /// A record as it is before protection and after it is removed
+    pub fn record(direction: Direction, content_type: ContentType, version: ProtocolVersion, length: usize, protected: bool) -> Self {
+        Self::new(direction, "record", content_type_name(content_type))
+            .field("version", TraceValue::Text(version.to_string()))
+            .field("length", TraceValue::Number(length as u64))
+            .field("protected", TraceValue::Bool(protected))
+    }
+
+    pub fn alert(direction: Direction, alert: &Alert) -> Self {
+        let level = match alert.level {
+            AlertLevel::Warning => "warning",
+            AlertLevel::Fatal => "fatal",
+        };
+        Self::new(direction, "alert", alert.description.name())
+            .field("level", TraceValue::Text(level.to_string()))
+    }
+
+    /// A handshake message with what can be decoded of it without knowing the connection.
+    /// A message that does not decode only gets its length.
+    pub fn handshake(direction: Direction, message: &HandshakeMessage) -> Self {
+        let mut name = handshake::handshake_type_name(message.msg_type);
+        let mut fields = vec![("length", TraceValue::Number(message.body.len() as u64))];
+        match message.msg_type {
+            handshake::CLIENT_HELLO => if let Ok(hello) = ClientHello::parse(&message.body) {
+                fields.push(("version", TraceValue::Text(hello.version.to_string())));
+                fields.push(("cipher_suites", TraceValue::List(hello.cipher_suites.iter().map(|id| suite_name(*id)).collect())));
+                fields.extend(extension_fields(&hello.extensions, HelloContext::ClientHello));
+            },
+            handshake::SERVER_HELLO => if let Ok(hello) = ServerHello::parse(&message.body) {
+                let context = if hello.random == HELLO_RETRY_REQUEST_RANDOM {
+                    name = "HelloRetryRequest";
+                    HelloContext::HelloRetryRequest
+                } else {
+                    HelloContext::ServerHello
+                };
+                fields.push(("version", TraceValue::Text(hello.version.to_string())));
+                fields.push(("cipher_suite", TraceValue::Text(suite_name(hello.cipher_suite))));
+                fields.extend(extension_fields(&hello.extensions, context));
+            },
+            handshake::ENCRYPTED_EXTENSIONS => if let Ok(raw) = handshake::parse_encrypted_extensions(&message.body) {
+                fields.extend(extension_fields(&raw, HelloContext::EncryptedExtensions));
+            },
+            // the TLS 1.3 form starts with a request context
+            handshake::CERTIFICATE => if let Ok(chain) = handshake::parse_certificate(&message.body).or_else(|_| handshake::parse_certificate_tls13(&message.body).map(|(_, chain)| chain)) {
+                let subjects = chain.iter()
+                    .map(|der| Certificate::from_der(der).map(|c| c.subject.to_string()).unwrap_or_else(|_| "malformed certificate".to_string()))
+                    .collect();
+                fields.push(("subjects", TraceValue::List(subjects)));
+            },
+            _ => (),
+        }
+        Self {
+            direction,
+            kind: "handshake",
+            name: name.to_string(),
+            fields,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        let mut text = format!("{} {} {}\n", arrow, self.kind, self.name);
+        for (name, value) in &self.fields {
+            let value = match value {
+                TraceValue::Bool(b) => b.to_string(),
+                TraceValue::Number(n) => n.to_string(),
+                TraceValue::Text(s) => s.clone(),
+                TraceValue::List(items) => items.join(", "),
+            };
+            let _ = writeln!(text, "     {}: {}", name, value);
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = self.fields.iter().map(|(name, value)| {
+            let value = match value {
+                TraceValue::Bool(b) => b.to_string(),
+                TraceValue::Number(n) => n.to_string(),
+                TraceValue::Text(s) => json_string(s),
+                TraceValue::List(items) => format!("[{}]", items.iter().map(|s| json_string(s)).collect::<Vec<String>>().join(",")),
+            };
+            format!("{}:{}", json_string(name), value)
+        }).collect();
+        format!(
+            "{{\"direction\":{},\"kind\":{},\"name\":{},\"fields\":{{{}}}}}",
+            json_string(self.direction.name()), json_string(self.kind), json_string(&self.name), fields.join(","),
+        )
+    }
+}
+
+fn content_type_name(content_type: ContentType) -> &'static str {
+    match content_type {
+        ContentType::ChangeCipherSpec => "change_cipher_spec",
+        ContentType::Alert => "alert",
+        ContentType::Handshake => "handshake",
+        ContentType::ApplicationData => "application_data",
+    }
+}
+
+fn suite_name(id: u16) -> String {
+    match suites::find(id) {
+        Some(suite) => suite.name.to_string(),
+        None if id == suites::TLS_EMPTY_RENEGOTIATION_INFO_SCSV => "TLS_EMPTY_RENEGOTIATION_INFO_SCSV".to_string(),
+        None => format!("0x{:04x}", id),
+    }
+}
+
+/// The extension names, and the values worth reading of a few of them
+fn extension_fields(raw: &[RawExtension], context: HelloContext) -> Vec<(&'static str, TraceValue)> {
+    let mut fields = vec![("extensions", TraceValue::List(raw.iter().map(|e| extensions::extension_name(e.extension_type).to_string()).collect()))];
+    for extension in raw.iter().filter_map(|e| Extension::parse(e, context).ok()) {
+        match extension {
+            Extension::ServerName(Some(name)) => fields.push(("server_name", TraceValue::Text(name))),
+            Extension::Alpn(protocols) => fields.push(("alpn", TraceValue::List(protocols.iter().map(|p| String::from_utf8_lossy(p).into_owned()).collect()))),
+            Extension::SupportedVersions(versions) => fields.push(("supported_versions", TraceValue::List(versions.iter().map(|v| v.to_string()).collect()))),
+            Extension::SelectedVersion(version) => fields.push(("selected_version", TraceValue::Text(version.to_string()))),
+            _ => (),
+        }
+    }
+    fields
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_format_events_as_text_and_json() {
+        let event = TraceEvent::new(Direction::Sent, "http", "request")
+            .field("request_line", TraceValue::Text("GET /\"a\"\r\n".to_string()))
+            .field("headers", TraceValue::List(vec!["Host: localhost".to_string(), "Connection: close".to_string()]))
+            .field("length", TraceValue::Number(3))
+            .field("protected", TraceValue::Bool(false));
+        assert_eq!(
+            "-> http request\n     request_line: GET /\"a\"\r\n\n     headers: Host: localhost, Connection: close\n     length: 3\n     protected: false\n",
+            event.to_text(),
+        );
+        assert_eq!(
+            r#"{"direction":"sent","kind":"http","name":"request","fields":{"request_line":"GET /\"a\"\r\n","headers":["Host: localhost","Connection: close"],"length":3,"protected":false}}"#,
+            event.to_json(),
+        );
+        assert_eq!("\"\\u0001\"", json_string("\u{1}"));
+    }
+
+    #[test]
+    fn test_can_decode_client_hello() {
+        let hello = ClientHello {
+            version: ProtocolVersion::TLS1_2,
+            random: [0u8; handshake::RANDOM_LEN],
+            session_id: vec![],
+            cipher_suites: vec![suites::TLS_AES_128_GCM_SHA256.id, suites::TLS_EMPTY_RENEGOTIATION_INFO_SCSV, 0xfafa],
+            compression_methods: vec![0],
+            extensions: extensions::encode_extensions(&[Extension::ServerName(Some("localhost".to_string())), Extension::ExtendedMasterSecret]),
+        };
+        let event = TraceEvent::handshake(Direction::Received, &hello.encode());
+        assert_eq!("ClientHello", event.name);
+        assert!(event.fields.contains(&("version", TraceValue::Text("TLS 1.2".to_string()))));
+        let suites = vec!["TLS_AES_128_GCM_SHA256".to_string(), "TLS_EMPTY_RENEGOTIATION_INFO_SCSV".to_string(), "0xfafa".to_string()];
+        assert!(event.fields.contains(&("cipher_suites", TraceValue::List(suites))));
+        assert!(event.fields.contains(&("extensions", TraceValue::List(vec!["server_name".to_string(), "extended_master_secret".to_string()]))));
+        assert!(event.fields.contains(&("server_name", TraceValue::Text("localhost".to_string()))));
+    }
+}
@@ -0,0 +1,627 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{
+    Result,
+    Context as _,
+};
+
+use super::asn1::Asn1Time;
+use super::digest::HashAlgorithm;
+use super::ecdsa::EcPublicKey;
+use super::idna::domain_to_ascii;
+use super::pem::{
+    self,
+    LABEL_CERTIFICATE,
+};
+use super::rsa::RsaPublicKey;
+use super::x509::{
+    Certificate,
+    GeneralName,
+    OID_ANY_EXTENDED_KEY_USAGE,
+    OID_AUTHORITY_KEY_IDENTIFIER,
+    OID_BASIC_CONSTRAINTS,
+    OID_ECDSA_WITH_SHA256,
+    OID_ECDSA_WITH_SHA384,
+    OID_EXTENDED_KEY_USAGE,
+    OID_KEY_USAGE,
+    OID_KP_CLIENT_AUTH,
+    OID_KP_SERVER_AUTH,
+    OID_SHA1_WITH_RSA,
+    OID_SHA256_WITH_RSA,
+    OID_SHA384_WITH_RSA,
+    OID_SHA512_WITH_RSA,
+    OID_SUBJECT_ALT_NAME,
+    OID_SUBJECT_KEY_IDENTIFIER,
+    KeyUsage,
+};
+
+/// CA bundles of common distributions, tried in order by `TrustStore::system`
+const SYSTEM_CA_FILES: [&str; 3] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+const SYSTEM_CA_DIR: &str = "/etc/ssl/certs";
+
+/// Longest chain (including leaf and trust anchor) that will be built
+const MAX_CHAIN_LENGTH: usize = 8;
+
+/// Extensions this validator understands, so they may be marked critical
+const HANDLED_EXTENSIONS: [&[u64]; 6] = [
+    OID_SUBJECT_KEY_IDENTIFIER,
+    OID_KEY_USAGE,
+    OID_SUBJECT_ALT_NAME,
+    OID_BASIC_CONSTRAINTS,
+    OID_AUTHORITY_KEY_IDENTIFIER,
+    OID_EXTENDED_KEY_USAGE,
+];
+
+/// Why a certificate chain was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum CertificateError {
+    /// no issuer of `subject` was found among the intermediates or trust anchors
+    UnknownIssuer { subject: String },
+    BadSignature { subject: String },
+    UnsupportedSignatureAlgorithm { subject: String, algorithm: String },
+    Expired { subject: String, not_after: Asn1Time },
+    NotYetValid { subject: String, not_before: Asn1Time },
+    /// an issuing certificate is not a CA according to BasicConstraints
+    NotCa { subject: String },
+    PathLenExceeded { subject: String },
+    /// an issuing certificate's KeyUsage lacks keyCertSign
+    InvalidKeyUsage { subject: String },
+    /// ExtendedKeyUsage does not permit the requested purpose
+    InvalidPurpose { subject: String },
+    UnhandledCriticalExtension { subject: String, oid: String },
+    ChainTooLong,
+    /// the certificate is not valid for the host that was connected to
+    HostnameMismatch { host: String, presented: Vec<String> },
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CertificateError::*;
+        match self {
+            UnknownIssuer { subject } => write!(f, "Unable to find a trusted issuer of '{}'", subject),
+            BadSignature { subject } => write!(f, "Invalid signature on '{}'", subject),
+            UnsupportedSignatureAlgorithm { subject, algorithm } => write!(f, "Unsupported signature algorithm {} on '{}'", algorithm, subject),
+            Expired { subject, not_after } => write!(f, "Certificate '{}' expired at {}", subject, not_after),
+            NotYetValid { subject, not_before } => write!(f, "Certificate '{}' is not valid before {}", subject, not_before),
+            NotCa { subject } => write!(f, "Certificate '{}' is not allowed to issue certificates", subject),
+            PathLenExceeded { subject } => write!(f, "Certificate '{}' exceeds the path length constraint of its issuers", subject),
+            InvalidKeyUsage { subject } => write!(f, "KeyUsage of '{}' does not allow certificate signing", subject),
+            InvalidPurpose { subject } => write!(f, "ExtendedKeyUsage of '{}' does not allow the requested purpose", subject),
+            UnhandledCriticalExtension { subject, oid } => write!(f, "Unhandled critical extension {} in '{}'", oid, subject),
+            ChainTooLong => write!(f, "Certificate chain is longer than {}", MAX_CHAIN_LENGTH),
+            HostnameMismatch { host, presented } => write!(f, "Certificate is not valid for '{}', only for: {}", host, presented.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// What the end-entity certificate is going to be used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyPurpose {
+    ServerAuth,
+    ClientAuth,
+}
+
+impl KeyPurpose {
+    fn oid(&self) -> &'static [u64] {
+        match self {
+            KeyPurpose::ServerAuth => OID_KP_SERVER_AUTH,
+            KeyPurpose::ClientAuth => OID_KP_CLIENT_AUTH,
+        }
+    }
+}
+
+/// Set of trust anchors
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    roots: Vec<Certificate>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self {
+            roots: vec![],
+        }
+    }
+
+    /// Loads every certificate in the `*.pem` and `*.crt` files of a directory
+    pub fn from_pem_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut store = Self::new();
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Unable to read trust store directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("pem") | Some("crt")))
+            .collect();
+        paths.sort();
+        for path in paths {
+            store.add_pem_file(&path)?;
+        }
+        Ok(store)
+    }
+
+    /// Loads the operating system's trust anchors. `SSL_CERT_FILE` and `SSL_CERT_DIR`
+    /// override the default locations, as with OpenSSL.
+    pub fn system() -> Result<Self> {
+        if let Some(file) = env::var_os("SSL_CERT_FILE") {
+            let mut store = Self::new();
+            store.add_pem_file(file)?;
+            return Ok(store);
+        }
+        if let Some(dir) = env::var_os("SSL_CERT_DIR") {
+            return Self::from_pem_dir(dir);
+        }
+        if let Some(file) = SYSTEM_CA_FILES.iter().find(|f| Path::new(f).is_file()) {
+            let mut store = Self::new();
+            store.add_pem_file(file)?;
+            return Ok(store);
+        }
+        Self::from_pem_dir(SYSTEM_CA_DIR)
+    }
+
+    pub fn add_pem_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        for block in pem::read_file(path)?.iter().filter(|b| b.label == LABEL_CERTIFICATE) {
+            let cert = Certificate::from_der(&block.contents).with_context(|| format!("Malformed certificate in {}", path.display()))?;
+            self.add(cert);
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, cert: Certificate) {
+        if !self.roots.iter().any(|r| r.raw == cert.raw) {
+            self.roots.push(cert);
+        }
+    }
+
+    pub fn roots(&self) -> &[Certificate] {
+        &self.roots
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Builds a path from `leaf` through `intermediates` (in any order) to a trust
+    /// anchor and validates it at `time` as described in RFC 5280 section 6.
+    /// Returns the validated path, starting with the leaf and ending with the anchor.
+    pub fn verify_chain(&self, leaf: &Certificate, intermediates: &[Certificate], purpose: KeyPurpose, time: Asn1Time) -> Result<Vec<Certificate>, CertificateError> {
+        if let Some(anchor) = self.roots.iter().find(|r| r.raw == leaf.raw) {
+            check_validity(anchor, time)?;
+            return Ok(vec![anchor.clone()]);
+        }
+        let mut path: Vec<&Certificate> = vec![leaf];
+        self.build_path(&mut path, intermediates, purpose, time)?;
+        Ok(path.into_iter().cloned().collect())
+    }
+
+    /// Depth-first search for a valid path. Trust anchors are tried before intermediates.
+    fn build_path<'a>(&'a self, path: &mut Vec<&'a Certificate>, intermediates: &'a [Certificate], purpose: KeyPurpose, time: Asn1Time) -> Result<(), CertificateError> {
+        let current = *path.last().unwrap();
+        let mut first_error: Option<CertificateError> = None;
+        for anchor in self.roots.iter().filter(|r| is_issued_by(current, r)) {
+            path.push(anchor);
+            match check_path(path, purpose, time) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                },
+            }
+            path.pop();
+        }
+        let candidates: Vec<&Certificate> = intermediates.iter()
+            .filter(|c| is_issued_by(current, c) && !path.iter().any(|p| p.raw == c.raw))
+            .collect();
+        if !candidates.is_empty() && path.len() + 1 >= MAX_CHAIN_LENGTH {
+            return Err(first_error.unwrap_or(CertificateError::ChainTooLong));
+        }
+        for candidate in candidates {
+            path.push(candidate);
+            match self.build_path(path, intermediates, purpose, time) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                },
+            }
+            path.pop();
+        }
+        Err(first_error.unwrap_or_else(|| CertificateError::UnknownIssuer {
+            subject: current.subject.to_string(),
+        }))
+    }
+}
+
+/// Name chaining, narrowed down by the key identifiers when both are present
+fn is_issued_by(cert: &Certificate, issuer: &Certificate) -> bool {
+    if cert.issuer.raw != issuer.subject.raw {
+        return false;
+    }
+    match (&cert.authority_key_identifier, &issuer.subject_key_identifier) {
+        (Some(aki), Some(ski)) => aki == ski,
+        _ => true,
+    }
+}
+
+/// Validates a complete path [leaf, intermediates..., anchor], processing from the anchor down
+fn check_path(path: &[&Certificate], purpose: KeyPurpose, time: Asn1Time) -> Result<(), CertificateError> {
+    let anchor_idx = path.len() - 1;
+    let anchor = path[anchor_idx];
+    check_validity(anchor, time)?;
+    check_issuer(anchor, true)?;
+    let mut max_path_length = anchor.basic_constraints.and_then(|bc| bc.path_len_constraint);
+    for i in (0..anchor_idx).rev() {
+        let cert = path[i];
+        verify_signed_by(cert, path[i + 1])?;
+        check_validity(cert, time)?;
+        check_critical_extensions(cert)?;
+        check_purpose(cert, purpose)?;
+        if i == 0 {
+            break;
+        }
+        // cert is an intermediate CA
+        check_issuer(cert, false)?;
+        if !cert.is_self_issued() {
+            max_path_length = match max_path_length {
+                Some(0) => {
+                    return Err(CertificateError::PathLenExceeded {
+                        subject: cert.subject.to_string(),
+                    });
+                },
+                Some(n) => Some(n - 1),
+                None => None,
+            };
+        }
+        if let Some(n) = cert.basic_constraints.and_then(|bc| bc.path_len_constraint) {
+            max_path_length = Some(max_path_length.map_or(n, |max| std::cmp::min(max, n)));
+        }
+    }
+    Ok(())
+}
+
+fn check_validity(cert: &Certificate, time: Asn1Time) -> Result<(), CertificateError> {
+    if time < cert.validity.not_before {
+        return Err(CertificateError::NotYetValid {
+            subject: cert.subject.to_string(),
+            not_before: cert.validity.not_before,
+        });
+    }
+    if time > cert.validity.not_after {
+        return Err(CertificateError::Expired {
+            subject: cert.subject.to_string(),
+            not_after: cert.validity.not_after,
+        });
+    }
+    Ok(())
+}
+
+fn check_issuer(issuer: &Certificate, is_anchor: bool) -> Result<(), CertificateError> {
+    match issuer.basic_constraints {
+        Some(bc) if bc.ca => {},
+        // legacy v1 roots carry no extensions at all
+        None if is_anchor && issuer.version < 3 => {},
+        _ => {
+            return Err(CertificateError::NotCa {
+                subject: issuer.subject.to_string(),
+            });
+        },
+    }
+    if let Some(key_usage) = issuer.key_usage {
+        if !key_usage.contains(KeyUsage::KEY_CERT_SIGN) {
+            return Err(CertificateError::InvalidKeyUsage {
+                subject: issuer.subject.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_critical_extensions(cert: &Certificate) -> Result<(), CertificateError> {
+    for extension in cert.extensions.iter().filter(|e| e.critical) {
+        if !HANDLED_EXTENSIONS.iter().any(|oid| extension.extn_id == **oid) {
+            return Err(CertificateError::UnhandledCriticalExtension {
+                subject: cert.subject.to_string(),
+                oid: extension.extn_id.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// ExtendedKeyUsage, when present in any non-anchor certificate, must allow the purpose
+fn check_purpose(cert: &Certificate, purpose: KeyPurpose) -> Result<(), CertificateError> {
+    if let Some(purposes) = &cert.extended_key_usage {
+        if !purposes.iter().any(|p| *p == *purpose.oid() || *p == *OID_ANY_EXTENDED_KEY_USAGE) {
+            return Err(CertificateError::InvalidPurpose {
+                subject: cert.subject.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Verifies the signature of `cert` with the public key of `issuer`
+pub fn verify_signed_by(cert: &Certificate, issuer: &Certificate) -> Result<(), CertificateError> {
+    let algorithm = &cert.signature_algorithm.algorithm;
+    let (hash, ecdsa) = if *algorithm == *OID_SHA1_WITH_RSA {
+        (HashAlgorithm::Sha1, false)
+    } else if *algorithm == *OID_SHA256_WITH_RSA {
+        (HashAlgorithm::Sha256, false)
+    } else if *algorithm == *OID_SHA384_WITH_RSA {
+        (HashAlgorithm::Sha384, false)
+    } else if *algorithm == *OID_SHA512_WITH_RSA {
+        (HashAlgorithm::Sha512, false)
+    } else if *algorithm == *OID_ECDSA_WITH_SHA256 {
+        (HashAlgorithm::Sha256, true)
+    } else if *algorithm == *OID_ECDSA_WITH_SHA384 {
+        (HashAlgorithm::Sha384, true)
+    } else {
+        return Err(CertificateError::UnsupportedSignatureAlgorithm {
+            subject: cert.subject.to_string(),
+            algorithm: algorithm.to_string(),
+        });
+    };
+    let bad_signature = || CertificateError::BadSignature {
+        subject: cert.subject.to_string(),
+    };
+    let spki = &issuer.subject_public_key_info;
+    let verified = if ecdsa {
+        EcPublicKey::from_spki(spki).and_then(|key| key.verify(hash, &cert.tbs_certificate, &cert.signature_value))
+    } else {
+        RsaPublicKey::from_spki(spki).and_then(|key| key.verify_pkcs1_v15(hash, &cert.tbs_certificate, &cert.signature_value))
+    };
+    verified.map_err(|_| bad_signature())
+}
+
+/// Checks that `cert` identifies `host` as described in RFC 6125.
+/// IP literals only match iPAddress SANs. DNS names match dNSName SANs, and
+/// the subject CN is consulted only when the certificate has no SAN at all.
+pub fn verify_hostname(cert: &Certificate, host: &str) -> Result<(), CertificateError> {
+    let presented = presented_identifiers(cert);
+    let mismatch = || CertificateError::HostnameMismatch {
+        host: host.to_string(),
+        presented: presented.clone(),
+    };
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        let octets = match ip {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        let matched = cert.subject_alt_names.iter().any(|n| matches!(n, GeneralName::IpAddress(a) if *a == octets));
+        return if matched { Ok(()) } else { Err(mismatch()) };
+    }
+
+    let reference = domain_to_ascii(host.trim_end_matches('.')).map_err(|_| mismatch())?;
+    let dns_names: Vec<&str> = if cert.subject_alt_names.is_empty() {
+        cert.subject.common_name().into_iter().collect()
+    } else {
+        cert.subject_alt_names.iter().filter_map(|n| match n {
+            GeneralName::DnsName(name) => Some(name.as_str()),
+            _ => None,
+        }).collect()
+    };
+    if dns_names.iter().any(|name| dns_name_matches(name, &reference)) {
+        Ok(())
+    } else {
+        Err(mismatch())
+    }
+}
+
+fn presented_identifiers(cert: &Certificate) -> Vec<String> {
+    if cert.subject_alt_names.is_empty() {
+        return cert.subject.common_name().map(|cn| vec![cn.to_string()]).unwrap_or_default();
+    }
+    cert.subject_alt_names.iter().filter_map(|n| match n {
+        GeneralName::DnsName(name) => Some(name.clone()),
+        GeneralName::IpAddress(octets) if octets.len() == 4 => {
+            Some(IpAddr::from([octets[0], octets[1], octets[2], octets[3]]).to_string())
+        },
+        GeneralName::IpAddress(octets) => {
+            let mut v6 = [0u8; 16];
+            v6.copy_from_slice(octets);
+            Some(IpAddr::from(v6).to_string())
+        },
+        _ => None,
+    }).collect()
+}
+
+/// Compares a presented DNS identifier with an A-label reference identifier.
+/// A wildcard is only honoured as the whole left-most label, matches exactly
+/// one label, and needs at least two labels to its right ("*.com" never matches).
+fn dns_name_matches(presented: &str, reference: &str) -> bool {
+    let presented = presented.trim_end_matches('.').to_ascii_lowercase();
+    let reference = reference.to_ascii_lowercase();
+    if presented.is_empty() || !presented.is_ascii() {
+        return false;
+    }
+    let presented_labels: Vec<&str> = presented.split('.').collect();
+    let reference_labels: Vec<&str> = reference.split('.').collect();
+    if presented_labels.len() != reference_labels.len() {
+        return false;
+    }
+    if presented_labels[0] == "*" {
+        if presented_labels.len() < 3 || reference_labels[0].is_empty() {
+            return false;
+        }
+        return presented_labels[1..] == reference_labels[1..];
+    }
+    if presented.contains('*') {
+        return false;
+    }
+    presented_labels == reference_labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str) -> Certificate {
+        let path = format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let blocks = pem::read_file(path).unwrap();
+        Certificate::from_der(&blocks[0].contents).unwrap()
+    }
+
+    fn store() -> TrustStore {
+        TrustStore::from_pem_dir(format!("{}/testdata/roots", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    fn now() -> Asn1Time {
+        Asn1Time::parse_generalized_time("20500101000000Z").unwrap()
+    }
+
+    #[test]
+    fn test_can_load_trust_store_from_directory() {
+        let store = store();
+        assert_eq!(1, store.roots().len());
+        assert_eq!(Some("toyssl Test Root CA"), store.roots()[0].subject.common_name());
+    }
+
+    #[test]
+    fn test_can_verify_chain() {
+        let leaf = load("leaf.pem");
+        let path = store().verify_chain(&leaf, &[load("inter.pem")], KeyPurpose::ServerAuth, now()).unwrap();
+        let names: Vec<&str> = path.iter().map(|c| c.subject.common_name().unwrap()).collect();
+        assert_eq!(vec!["localhost", "toyssl Test Intermediate CA", "toyssl Test Root CA"], names);
+    }
+
+    #[test]
+    fn test_can_build_path_from_unordered_intermediates() {
+        let leaf = load("leaf.pem");
+        let intermediates = vec![load("other.pem"), load("inter2.pem"), load("inter.pem")];
+        let path = store().verify_chain(&leaf, &intermediates, KeyPurpose::ServerAuth, now()).unwrap();
+        assert_eq!(3, path.len());
+    }
+
+    #[test]
+    fn test_can_return_error_unknown_issuer() {
+        let leaf = load("leaf.pem");
+        let result = store().verify_chain(&leaf, &[], KeyPurpose::ServerAuth, now());
+        assert_eq!(Err(CertificateError::UnknownIssuer { subject: leaf.subject.to_string() }), result);
+        let other = load("other.pem");
+        let result = store().verify_chain(&other, &[], KeyPurpose::ServerAuth, now());
+        assert!(matches!(result, Err(CertificateError::UnknownIssuer { .. })));
+    }
+
+    #[test]
+    fn test_can_return_error_bad_signature() {
+        let mut leaf = load("leaf.pem");
+        leaf.signature_value[100] ^= 0x01;
+        let result = store().verify_chain(&leaf, &[load("inter.pem")], KeyPurpose::ServerAuth, now());
+        assert!(matches!(result, Err(CertificateError::BadSignature { .. })));
+    }
+
+    #[test]
+    fn test_can_return_error_outside_validity() {
+        let leaf = load("leaf.pem");
+        let past = Asn1Time::parse_utc_time("000101000000Z").unwrap();
+        let result = store().verify_chain(&leaf, &[load("inter.pem")], KeyPurpose::ServerAuth, past);
+        assert!(matches!(result, Err(CertificateError::NotYetValid { .. })));
+        let future = Asn1Time::parse_generalized_time("22000101000000Z").unwrap();
+        let result = store().verify_chain(&leaf, &[load("inter.pem")], KeyPurpose::ServerAuth, future);
+        assert!(matches!(result, Err(CertificateError::Expired { .. })));
+    }
+
+    #[test]
+    fn test_can_return_error_issuer_not_ca() {
+        let cert = load("notca.pem");
+        let result = store().verify_chain(&cert, &[load("leaf.pem"), load("inter.pem")], KeyPurpose::ServerAuth, now());
+        assert_eq!(Err(CertificateError::NotCa { subject: String::from("C=JP, O=toyssl, CN=localhost") }), result);
+    }
+
+    #[test]
+    fn test_can_return_error_path_len_exceeded() {
+        let cert = load("deep.pem");
+        let result = store().verify_chain(&cert, &[load("inter.pem"), load("inter2.pem")], KeyPurpose::ServerAuth, now());
+        assert_eq!(Err(CertificateError::PathLenExceeded { subject: String::from("C=JP, O=toyssl, CN=toyssl Test Sub Intermediate CA") }), result);
+    }
+
+    #[test]
+    fn test_can_return_error_unhandled_critical_extension() {
+        let cert = load("crit.pem");
+        let result = store().verify_chain(&cert, &[load("inter.pem")], KeyPurpose::ServerAuth, now());
+        assert!(matches!(result, Err(CertificateError::UnhandledCriticalExtension { ref oid, .. }) if oid == "1.2.3.4.5.6.7"));
+    }
+
+    #[test]
+    fn test_can_return_error_wrong_purpose() {
+        let leaf = load("leaf.pem");
+        let result = store().verify_chain(&leaf, &[load("inter.pem")], KeyPurpose::ClientAuth, now());
+        assert!(matches!(result, Err(CertificateError::InvalidPurpose { .. })));
+    }
+
+    #[test]
+    fn test_can_verify_hostname_with_dns_san() {
+        let leaf = load("leaf.pem");
+        assert_eq!(Ok(()), verify_hostname(&leaf, "localhost"));
+        assert_eq!(Ok(()), verify_hostname(&leaf, "LocalHost."));
+        assert_eq!(Ok(()), verify_hostname(&leaf, "www.example.com"));
+        assert!(verify_hostname(&leaf, "example.com").is_err());
+        assert!(verify_hostname(&leaf, "a.b.example.com").is_err());
+        assert!(verify_hostname(&leaf, "localhost.example.org").is_err());
+    }
+
+    #[test]
+    fn test_can_verify_hostname_with_ip_san() {
+        let leaf = load("leaf.pem");
+        assert_eq!(Ok(()), verify_hostname(&leaf, "127.0.0.1"));
+        assert_eq!(Ok(()), verify_hostname(&leaf, "[::1]"));
+        assert!(verify_hostname(&leaf, "127.0.0.2").is_err());
+    }
+
+    #[test]
+    fn test_can_fall_back_to_common_name_without_san() {
+        // the root has no SAN, so its CN is used
+        let root = store().roots()[0].clone();
+        assert!(verify_hostname(&root, "toyssl test root ca").is_ok());
+        // the leaf has a SAN, so its CN must be ignored even though it would match
+        let mut leaf = load("leaf.pem");
+        leaf.subject_alt_names = vec![GeneralName::DnsName(String::from("other.example.org"))];
+        assert!(verify_hostname(&leaf, "localhost").is_err());
+    }
+
+    #[test]
+    fn test_can_return_typed_hostname_mismatch() {
+        let leaf = load("leaf.pem");
+        let result = verify_hostname(&leaf, "evil.test");
+        assert_eq!(
+            Err(CertificateError::HostnameMismatch {
+                host: String::from("evil.test"),
+                presented: vec![
+                    String::from("localhost"),
+                    String::from("*.example.com"),
+                    String::from("127.0.0.1"),
+                    String::from("::1"),
+                ],
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn test_can_match_wildcards_per_rfc6125() {
+        assert!(dns_name_matches("*.example.com", "foo.example.com"));
+        assert!(!dns_name_matches("*.example.com", "example.com"));
+        assert!(!dns_name_matches("*.com", "example.com"));
+        assert!(!dns_name_matches("f*.example.com", "foo.example.com"));
+        assert!(!dns_name_matches("www.*.com", "www.example.com"));
+        assert!(dns_name_matches("*.xn--bcher-kva.example", "shop.xn--bcher-kva.example"));
+    }
+
+    #[test]
+    fn test_can_compare_idna_names_as_a_labels() {
+        let mut leaf = load("leaf.pem");
+        leaf.subject_alt_names = vec![GeneralName::DnsName(String::from("xn--bcher-kva.example"))];
+        assert!(verify_hostname(&leaf, "bücher.example").is_ok());
+        assert!(verify_hostname(&leaf, "BÜCHER.example").is_ok());
+        assert!(verify_hostname(&leaf, "bucher.example").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::random::fill_random;
+
+pub const X25519_KEY_LEN: usize = 32;
+
+/// Element of GF(2^255 - 19) as five 51-bit limbs, least significant first
+type Fe = [u64; 5];
+
+const MASK51: u64 = (1 << 51) - 1;
+/// (A - 2) / 4 for curve25519
+const A24: u64 = 121665;
+/// The u-coordinate of the base point
+const BASE_POINT: [u8; X25519_KEY_LEN] = {
+    let mut b = [0u8; X25519_KEY_LEN];
+    b[0] = 9;
+    b
+};
+
+fn fe_from_bytes(bytes: &[u8; X25519_KEY_LEN]) -> Fe {
+    let load = |i: usize| {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&bytes[i..i + 8]);
+        u64::from_le_bytes(word)
+    };
+    // the most significant bit is ignored (RFC 7748 section 5)
+    [
+        load(0) & MASK51,
+        (load(6) >> 3) & MASK51,
+        (load(12) >> 6) & MASK51,
+        (load(19) >> 1) & MASK51,
+        (load(24) >> 12) & MASK51,
+    ]
+}
+
+/// Propagates carries so that every limb fits in 51 bits (plus a small excess in limb 0)
+fn fe_carry(mut f: Fe) -> Fe {
+    for i in 0..4 {
+        f[i + 1] += f[i] >> 51;
+        f[i] &= MASK51;
+    }
+    f[0] += 19 * (f[4] >> 51);
+    f[4] &= MASK51;
+    f
+}
+
+fn fe_to_bytes(f: &Fe) -> [u8; X25519_KEY_LEN] {
+    let mut t = fe_carry(fe_carry(*f));
+    // t < 2p here; subtract p once if t >= p, detected by whether t + 19 overflows 2^255
+    let mut q = (t[0] + 19) >> 51;
+    for limb in t.iter().skip(1) {
+        q = (limb + q) >> 51;
+    }
+    t[0] += 19 * q;
+    for i in 0..4 {
+        t[i + 1] += t[i] >> 51;
+        t[i] &= MASK51;
+    }
+    t[4] &= MASK51;
+    let mut result = [0u8; X25519_KEY_LEN];
+    let mut acc: u128 = 0;
+    let mut acc_bits = 0;
+    let mut pos = 0;
+    for limb in t.iter() {
+        acc |= (*limb as u128) << acc_bits;
+        acc_bits += 51;
+        while acc_bits >= 8 && pos < X25519_KEY_LEN {
+            result[pos] = acc as u8;
+            acc >>= 8;
+            acc_bits -= 8;
+            pos += 1;
+        }
+    }
+    if pos < X25519_KEY_LEN {
+        result[pos] = acc as u8;
+    }
+    result
+}
+
+fn fe_add(a: &Fe, b: &Fe) -> Fe {
+    fe_carry([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3], a[4] + b[4]])
+}
+
+/// a - b computed as a + 4p - b so no limb goes negative
+fn fe_sub(a: &Fe, b: &Fe) -> Fe {
+    const FOUR_P0: u64 = 4 * ((1 << 51) - 19);
+    const FOUR_P: u64 = 4 * MASK51;
+    fe_carry([a[0] + FOUR_P0 - b[0], a[1] + FOUR_P - b[1], a[2] + FOUR_P - b[2], a[3] + FOUR_P - b[3], a[4] + FOUR_P - b[4]])
+}
+
+fn fe_mul(a: &Fe, b: &Fe) -> Fe {
+    let m = |x: u64, y: u64| (x as u128) * (y as u128);
+    // limbs above 2^255 wrap around multiplied by 19
+    let b19 = [b[0], b[1] * 19, b[2] * 19, b[3] * 19, b[4] * 19];
+    let r0 = m(a[0], b[0]) + m(a[1], b19[4]) + m(a[2], b19[3]) + m(a[3], b19[2]) + m(a[4], b19[1]);
+    let r1 = m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b19[4]) + m(a[3], b19[3]) + m(a[4], b19[2]);
+    let r2 = m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b19[4]) + m(a[4], b19[3]);
+    let r3 = m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b19[4]);
+    let r4 = m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0]);
+    let mut r = [r0, r1, r2, r3, r4];
+    for i in 0..4 {
+        r[i + 1] += r[i] >> 51;
+        r[i] &= MASK51 as u128;
+    }
+    // the carry out of limb 4 can reach 2^59, so fold it in while still in u128
+    r[0] += (r[4] >> 51) * 19;
+    r[4] &= MASK51 as u128;
+    r[1] += r[0] >> 51;
+    r[0] &= MASK51 as u128;
+    [r[0] as u64, r[1] as u64, r[2] as u64, r[3] as u64, r[4] as u64]
+}
+
+fn fe_mul_small(a: &Fe, n: u64) -> Fe {
+    fe_mul(a, &[n, 0, 0, 0, 0])
+}
+
+/// a^(p - 2) = a^-1 by Fermat's little theorem
+fn fe_invert(a: &Fe) -> Fe {
+    // p - 2 = 2^255 - 21: bits 254..5 are set, bits 4..0 are 01011
+    let mut result: Fe = [1, 0, 0, 0, 0];
+    for bit in (0..255).rev() {
+        result = fe_mul(&result, &result);
+        if bit >= 5 || (0b01011 >> bit) & 1 == 1 {
+            result = fe_mul(&result, a);
+        }
+    }
+    result
+}
+
+fn fe_cswap(swap: u64, a: &mut Fe, b: &mut Fe) {
+    let mask = 0u64.wrapping_sub(swap);
+    for i in 0..5 {
+        let t = mask & (a[i] ^ b[i]);
+        a[i] ^= t;
+        b[i] ^= t;
+    }
+}
+
+/// The X25519 function of RFC 7748 section 5: scalar multiplication on the u-coordinate
+pub fn x25519(scalar: &[u8; X25519_KEY_LEN], u: &[u8; X25519_KEY_LEN]) -> [u8; X25519_KEY_LEN] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+    let x1 = fe_from_bytes(u);
+    let mut x2: Fe = [1, 0, 0, 0, 0];
+    let mut z2: Fe = [0; 5];
+    let mut x3 = x1;
+    let mut z3: Fe = [1, 0, 0, 0, 0];
+    let mut swap = 0u64;
+    for t in (0..255).rev() {
+        let k_t = ((k[t / 8] >> (t % 8)) & 1) as u64;
+        swap ^= k_t;
+        fe_cswap(swap, &mut x2, &mut x3);
+        fe_cswap(swap, &mut z2, &mut z3);
+        swap = k_t;
+
+        let a = fe_add(&x2, &z2);
+        let aa = fe_mul(&a, &a);
+        let b = fe_sub(&x2, &z2);
+        let bb = fe_mul(&b, &b);
+        let e = fe_sub(&aa, &bb);
+        let c = fe_add(&x3, &z3);
+        let d = fe_sub(&x3, &z3);
+        let da = fe_mul(&d, &a);
+        let cb = fe_mul(&c, &b);
+        let sum = fe_add(&da, &cb);
+        x3 = fe_mul(&sum, &sum);
+        let diff = fe_sub(&da, &cb);
+        z3 = fe_mul(&x1, &fe_mul(&diff, &diff));
+        x2 = fe_mul(&aa, &bb);
+        z2 = fe_mul(&e, &fe_add(&aa, &fe_mul_small(&e, A24)));
+    }
+    fe_cswap(swap, &mut x2, &mut x3);
+    fe_cswap(swap, &mut z2, &mut z3);
+    fe_to_bytes(&fe_mul(&x2, &fe_invert(&z2)))
+}
+
+/// Returns (private key, public key)
+pub fn generate_keypair() -> Result<([u8; X25519_KEY_LEN], [u8; X25519_KEY_LEN])> {
+    let mut private_key = [0u8; X25519_KEY_LEN];
+    fill_random(&mut private_key)?;
+    let public_key = x25519(&private_key, &BASE_POINT);
+    Ok((private_key, public_key))
+}
+
+/// Computes the shared secret, rejecting peer keys that yield the all-zero value
+pub fn shared_secret(private_key: &[u8; X25519_KEY_LEN], peer_public_key: &[u8]) -> Result<[u8; X25519_KEY_LEN]> {
+    if peer_public_key.len() != X25519_KEY_LEN {
+        return Err(anyhow!("Invalid X25519 public key length: {}", peer_public_key.len()));
+    }
+    let mut u = [0u8; X25519_KEY_LEN];
+    u.copy_from_slice(peer_public_key);
+    let shared = x25519(private_key, &u);
+    if shared.iter().all(|b| *b == 0) {
+        return Err(anyhow!("X25519 shared secret is zero"));
+    }
+    Ok(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::from_hex;
+
+    fn key_from_hex(s: &str) -> [u8; X25519_KEY_LEN] {
+        let mut key = [0u8; X25519_KEY_LEN];
+        key.copy_from_slice(&from_hex(s));
+        key
+    }
+
+    #[test]
+    fn test_can_compute_rfc7748_vectors() {
+        let scalar = key_from_hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = key_from_hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(key_from_hex("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"), x25519(&scalar, &u));
+        let scalar = key_from_hex("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d");
+        let u = key_from_hex("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493");
+        assert_eq!(key_from_hex("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957"), x25519(&scalar, &u));
+    }
+
+    #[test]
+    fn test_can_agree_on_shared_secret() {
+        // RFC 7748 section 6.1
+        let alice = key_from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob_public = key_from_hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+        assert_eq!(key_from_hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"), x25519(&alice, &BASE_POINT));
+        let shared = shared_secret(&alice, &bob_public).unwrap();
+        assert_eq!(key_from_hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"), shared);
+
+        let (a, a_public) = generate_keypair().unwrap();
+        let (b, b_public) = generate_keypair().unwrap();
+        assert_eq!(shared_secret(&a, &b_public).unwrap(), shared_secret(&b, &a_public).unwrap());
+        assert!(shared_secret(&a, &[0u8; 32]).is_err());
+        assert!(shared_secret(&a, &[9u8; 31]).is_err());
+    }
+}
@@ -0,0 +1,568 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::{
+    Result,
+    anyhow,
+};
+
+use super::asn1::{
+    self,
+    Asn1Time,
+    DerElement,
+    ObjectIdentifier,
+    TAG_BOOLEAN,
+    TAG_INTEGER,
+    TAG_OCTET_STRING,
+    TAG_OID,
+    TAG_SEQUENCE,
+    TAG_SET,
+    context_explicit,
+    context_implicit,
+};
+use super::hex::hex_encode;
+
+pub const OID_RSA_ENCRYPTION: &[u64] = &[1, 2, 840, 113549, 1, 1, 1];
+pub const OID_SHA1_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 5];
+pub const OID_SHA256_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 11];
+pub const OID_SHA384_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 12];
+pub const OID_SHA512_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 13];
+pub const OID_RSASSA_PSS: &[u64] = &[1, 2, 840, 113549, 1, 1, 10];
+pub const OID_EC_PUBLIC_KEY: &[u64] = &[1, 2, 840, 10045, 2, 1];
+pub const OID_ECDSA_WITH_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+pub const OID_ECDSA_WITH_SHA384: &[u64] = &[1, 2, 840, 10045, 4, 3, 3];
+
+pub const OID_COMMON_NAME: &[u64] = &[2, 5, 4, 3];
+pub const OID_COUNTRY_NAME: &[u64] = &[2, 5, 4, 6];
+pub const OID_LOCALITY_NAME: &[u64] = &[2, 5, 4, 7];
+pub const OID_STATE_OR_PROVINCE_NAME: &[u64] = &[2, 5, 4, 8];
+pub const OID_ORGANIZATION_NAME: &[u64] = &[2, 5, 4, 10];
+pub const OID_ORGANIZATIONAL_UNIT_NAME: &[u64] = &[2, 5, 4, 11];
+
+pub const OID_SUBJECT_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 14];
+pub const OID_KEY_USAGE: &[u64] = &[2, 5, 29, 15];
+pub const OID_SUBJECT_ALT_NAME: &[u64] = &[2, 5, 29, 17];
+pub const OID_BASIC_CONSTRAINTS: &[u64] = &[2, 5, 29, 19];
+pub const OID_AUTHORITY_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 35];
+pub const OID_EXTENDED_KEY_USAGE: &[u64] = &[2, 5, 29, 37];
+
+pub const OID_KP_SERVER_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 1];
+pub const OID_KP_CLIENT_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 2];
+pub const OID_ANY_EXTENDED_KEY_USAGE: &[u64] = &[2, 5, 29, 37, 0];
+
+/// X.509 v3 certificate (RFC 5280)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Certificate {
+    /// whole DER encoding of the certificate
+    pub raw: Vec<u8>,
+    /// DER encoding of the TBSCertificate, which is the input of the signature
+    pub tbs_certificate: Vec<u8>,
+    /// X.509 version number (1, 2 or 3)
+    pub version: u8,
+    /// big-endian serial number
+    pub serial_number: Vec<u8>,
+    pub signature_algorithm: AlgorithmIdentifier,
+    pub issuer: DistinguishedName,
+    pub validity: Validity,
+    pub subject: DistinguishedName,
+    pub subject_public_key_info: SubjectPublicKeyInfo,
+    pub extensions: Vec<Extension>,
+    pub subject_alt_names: Vec<GeneralName>,
+    pub basic_constraints: Option<BasicConstraints>,
+    pub key_usage: Option<KeyUsage>,
+    pub extended_key_usage: Option<Vec<ObjectIdentifier>>,
+    pub authority_key_identifier: Option<Vec<u8>>,
+    pub subject_key_identifier: Option<Vec<u8>>,
+    pub signature_value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlgorithmIdentifier {
+    pub algorithm: ObjectIdentifier,
+    /// DER encoding of the parameters, if present
+    pub parameters: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeTypeAndValue {
+    pub attribute_type: ObjectIdentifier,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistinguishedName {
+    /// DER encoding of the Name, used for issuer/subject chaining
+    pub raw: Vec<u8>,
+    pub rdns: Vec<Vec<AttributeTypeAndValue>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Validity {
+    pub not_before: Asn1Time,
+    pub not_after: Asn1Time,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubjectPublicKeyInfo {
+    /// DER encoding of the SubjectPublicKeyInfo
+    pub raw: Vec<u8>,
+    pub algorithm: AlgorithmIdentifier,
+    /// contents of the subjectPublicKey bit string
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extension {
+    pub extn_id: ObjectIdentifier,
+    pub critical: bool,
+    pub extn_value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneralName {
+    Rfc822Name(String),
+    DnsName(String),
+    DirectoryName(DistinguishedName),
+    Uri(String),
+    IpAddress(Vec<u8>),
+    /// any other form, kept as (tag, contents)
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BasicConstraints {
+    pub ca: bool,
+    pub path_len_constraint: Option<u32>,
+}
+
+/// KeyUsage bits, numbered as in RFC 5280 (digitalSignature is bit 0)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyUsage(pub u16);
+
+impl KeyUsage {
+    pub const DIGITAL_SIGNATURE: u16 = 1 << 0;
+    pub const NON_REPUDIATION: u16 = 1 << 1;
+    pub const KEY_ENCIPHERMENT: u16 = 1 << 2;
+    pub const DATA_ENCIPHERMENT: u16 = 1 << 3;
+    pub const KEY_AGREEMENT: u16 = 1 << 4;
+    pub const KEY_CERT_SIGN: u16 = 1 << 5;
+    pub const CRL_SIGN: u16 = 1 << 6;
+    pub const ENCIPHER_ONLY: u16 = 1 << 7;
+    pub const DECIPHER_ONLY: u16 = 1 << 8;
+
+    pub fn contains(&self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+}
+
+impl Certificate {
+    /// Parses a DER encoded certificate
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let certificate = asn1::parse(der)?;
+        certificate.expect_tag(TAG_SEQUENCE)?;
+        let mut reader = certificate.children()?;
+        let tbs = reader.expect(TAG_SEQUENCE)?;
+        let signature_algorithm = parse_algorithm_identifier(&reader.expect(TAG_SEQUENCE)?)?;
+        let (unused_bits, signature_value) = reader.read()?.as_bit_string()?;
+        if unused_bits != 0 {
+            return Err(anyhow!("Certificate signature has unused bits"));
+        }
+        reader.expect_end()?;
+
+        let mut tbs_reader = tbs.children()?;
+        let version = match tbs_reader.read_optional(context_explicit(0))? {
+            Some(v) => {
+                let mut v = v.children()?;
+                let version = v.expect(TAG_INTEGER)?.as_u64()?;
+                v.expect_end()?;
+                if version > 2 {
+                    return Err(anyhow!("Unknown certificate version: {}", version));
+                }
+                version as u8 + 1
+            },
+            None => 1,
+        };
+        let serial_number = tbs_reader.expect(TAG_INTEGER)?.value.to_vec();
+        let tbs_signature = parse_algorithm_identifier(&tbs_reader.expect(TAG_SEQUENCE)?)?;
+        if tbs_signature != signature_algorithm {
+            return Err(anyhow!("Certificate signature algorithms mismatch: {} and {}", tbs_signature.algorithm, signature_algorithm.algorithm));
+        }
+        let issuer = DistinguishedName::from_der(&tbs_reader.expect(TAG_SEQUENCE)?)?;
+        let validity = parse_validity(&tbs_reader.expect(TAG_SEQUENCE)?)?;
+        let subject = DistinguishedName::from_der(&tbs_reader.expect(TAG_SEQUENCE)?)?;
+        let subject_public_key_info = SubjectPublicKeyInfo::from_der(&tbs_reader.expect(TAG_SEQUENCE)?)?;
+        // issuerUniqueID and subjectUniqueID are obsolete; skip them
+        tbs_reader.read_optional(context_implicit(1))?;
+        tbs_reader.read_optional(context_implicit(2))?;
+        let mut extensions: Vec<Extension> = vec![];
+        if let Some(exts) = tbs_reader.read_optional(context_explicit(3))? {
+            if version != 3 {
+                return Err(anyhow!("Extensions are only allowed in v3 certificates"));
+            }
+            let mut exts = exts.children()?;
+            let seq = exts.expect(TAG_SEQUENCE)?;
+            exts.expect_end()?;
+            let mut seq = seq.children()?;
+            while !seq.is_empty() {
+                let extension = parse_extension(&seq.expect(TAG_SEQUENCE)?)?;
+                if extensions.iter().any(|e| e.extn_id == extension.extn_id) {
+                    return Err(anyhow!("Duplicate certificate extension: {}", extension.extn_id));
+                }
+                extensions.push(extension);
+            }
+        }
+        tbs_reader.expect_end()?;
+
+        let mut certificate = Self {
+            raw: der.to_vec(),
+            tbs_certificate: tbs.raw.to_vec(),
+            version,
+            serial_number,
+            signature_algorithm,
+            issuer,
+            validity,
+            subject,
+            subject_public_key_info,
+            extensions: vec![],
+            subject_alt_names: vec![],
+            basic_constraints: None,
+            key_usage: None,
+            extended_key_usage: None,
+            authority_key_identifier: None,
+            subject_key_identifier: None,
+            signature_value: signature_value.to_vec(),
+        };
+        for extension in &extensions {
+            certificate.apply_extension(extension)?;
+        }
+        certificate.extensions = extensions;
+        Ok(certificate)
+    }
+
+    /// Returns the extension with the given OID, if present
+    pub fn extension(&self, oid: &[u64]) -> Option<&Extension> {
+        self.extensions.iter().find(|e| e.extn_id == *oid)
+    }
+
+    pub fn is_self_issued(&self) -> bool {
+        self.issuer.raw == self.subject.raw
+    }
+
+    fn apply_extension(&mut self, extension: &Extension) -> Result<()> {
+        let value = asn1::parse(&extension.extn_value)?;
+        let oid = &extension.extn_id;
+        if *oid == *OID_SUBJECT_ALT_NAME {
+            self.subject_alt_names = parse_general_names(&value)?;
+        } else if *oid == *OID_BASIC_CONSTRAINTS {
+            self.basic_constraints = Some(parse_basic_constraints(&value)?);
+        } else if *oid == *OID_KEY_USAGE {
+            self.key_usage = Some(parse_key_usage(&value)?);
+        } else if *oid == *OID_EXTENDED_KEY_USAGE {
+            value.expect_tag(TAG_SEQUENCE)?;
+            let mut reader = value.children()?;
+            let mut purposes: Vec<ObjectIdentifier> = vec![];
+            while !reader.is_empty() {
+                purposes.push(reader.expect(TAG_OID)?.as_oid()?);
+            }
+            self.extended_key_usage = Some(purposes);
+        } else if *oid == *OID_SUBJECT_KEY_IDENTIFIER {
+            value.expect_tag(TAG_OCTET_STRING)?;
+            self.subject_key_identifier = Some(value.value.to_vec());
+        } else if *oid == *OID_AUTHORITY_KEY_IDENTIFIER {
+            value.expect_tag(TAG_SEQUENCE)?;
+            let mut reader = value.children()?;
+            // only keyIdentifier [0] is used; authorityCertIssuer and serial are ignored
+            if let Some(key_id) = reader.read_optional(context_implicit(0))? {
+                self.authority_key_identifier = Some(key_id.value.to_vec());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DistinguishedName {
+    pub fn from_der(element: &DerElement) -> Result<Self> {
+        element.expect_tag(TAG_SEQUENCE)?;
+        let mut rdns: Vec<Vec<AttributeTypeAndValue>> = vec![];
+        let mut reader = element.children()?;
+        while !reader.is_empty() {
+            let mut set = reader.expect(TAG_SET)?.children()?;
+            let mut rdn: Vec<AttributeTypeAndValue> = vec![];
+            while !set.is_empty() {
+                let mut attribute = set.expect(TAG_SEQUENCE)?.children()?;
+                let attribute_type = attribute.expect(TAG_OID)?.as_oid()?;
+                let value = attribute.read()?;
+                // attributes with non-string values are kept as hex
+                let value = value.as_string().unwrap_or_else(|_| hex_encode(value.value));
+                attribute.expect_end()?;
+                rdn.push(AttributeTypeAndValue {
+                    attribute_type,
+                    value,
+                });
+            }
+            if rdn.is_empty() {
+                return Err(anyhow!("Empty relative distinguished name"));
+            }
+            rdns.push(rdn);
+        }
+        Ok(Self {
+            raw: element.raw.to_vec(),
+            rdns,
+        })
+    }
+
+    /// Returns the values of all attributes of the given type, in order
+    pub fn values(&self, oid: &[u64]) -> Vec<&str> {
+        self.rdns.iter()
+            .flatten()
+            .filter(|a| a.attribute_type == *oid)
+            .map(|a| a.value.as_str())
+            .collect()
+    }
+
+    /// Returns the most specific (last) common name
+    pub fn common_name(&self) -> Option<&str> {
+        self.values(OID_COMMON_NAME).last().copied()
+    }
+}
+
+impl fmt::Display for DistinguishedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rdns: Vec<String> = self.rdns.iter().map(|rdn| {
+            let attributes: Vec<String> = rdn.iter().map(|a| {
+                let name = attribute_short_name(&a.attribute_type).map(String::from).unwrap_or_else(|| a.attribute_type.to_string());
+                format!("{}={}", name, a.value)
+            }).collect();
+            attributes.join("+")
+        }).collect();
+        write!(f, "{}", rdns.join(", "))
+    }
+}
+
+impl SubjectPublicKeyInfo {
+    pub fn from_der(element: &DerElement) -> Result<Self> {
+        element.expect_tag(TAG_SEQUENCE)?;
+        let mut reader = element.children()?;
+        let algorithm = parse_algorithm_identifier(&reader.expect(TAG_SEQUENCE)?)?;
+        let (unused_bits, public_key) = reader.read()?.as_bit_string()?;
+        if unused_bits != 0 {
+            return Err(anyhow!("Subject public key has unused bits"));
+        }
+        reader.expect_end()?;
+        Ok(Self {
+            raw: element.raw.to_vec(),
+            algorithm,
+            public_key: public_key.to_vec(),
+        })
+    }
+}
+
+fn attribute_short_name(oid: &ObjectIdentifier) -> Option<&'static str> {
+    let names: [(&[u64], &str); 6] = [
+        (OID_COMMON_NAME, "CN"),
+        (OID_COUNTRY_NAME, "C"),
+        (OID_LOCALITY_NAME, "L"),
+        (OID_STATE_OR_PROVINCE_NAME, "ST"),
+        (OID_ORGANIZATION_NAME, "O"),
+        (OID_ORGANIZATIONAL_UNIT_NAME, "OU"),
+    ];
+    names.iter().find(|(o, _)| *oid == **o).map(|(_, name)| *name)
+}
+
+pub fn parse_algorithm_identifier(element: &DerElement) -> Result<AlgorithmIdentifier> {
+    element.expect_tag(TAG_SEQUENCE)?;
+    let mut reader = element.children()?;
+    let algorithm = reader.expect(TAG_OID)?.as_oid()?;
+    let parameters = if reader.is_empty() {
+        None
+    } else {
+        Some(reader.read()?.raw.to_vec())
+    };
+    reader.expect_end()?;
+    Ok(AlgorithmIdentifier {
+        algorithm,
+        parameters,
+    })
+}
+
+fn parse_validity(element: &DerElement) -> Result<Validity> {
+    let mut reader = element.children()?;
+    let not_before = reader.read()?.as_time()?;
+    let not_after = reader.read()?.as_time()?;
+    reader.expect_end()?;
+    Ok(Validity {
+        not_before,
+        not_after,
+    })
+}
+
+fn parse_extension(element: &DerElement) -> Result<Extension> {
+    let mut reader = element.children()?;
+    let extn_id = reader.expect(TAG_OID)?.as_oid()?;
+    let critical = match reader.read_optional(TAG_BOOLEAN)? {
+        Some(b) => b.as_bool()?,
+        None => false,
+    };
+    let extn_value = reader.expect(TAG_OCTET_STRING)?.value.to_vec();
+    reader.expect_end()?;
+    Ok(Extension {
+        extn_id,
+        critical,
+        extn_value,
+    })
+}
+
+fn parse_general_names(element: &DerElement) -> Result<Vec<GeneralName>> {
+    element.expect_tag(TAG_SEQUENCE)?;
+    let mut names: Vec<GeneralName> = vec![];
+    let mut reader = element.children()?;
+    while !reader.is_empty() {
+        let name = reader.read()?;
+        let ia5 = |name: &DerElement| -> Result<String> {
+            if !name.value.is_ascii() {
+                return Err(anyhow!("GeneralName is not IA5String"));
+            }
+            Ok(String::from_utf8_lossy(name.value).into_owned())
+        };
+        let general_name = match name.tag {
+            t if t == context_implicit(1) => GeneralName::Rfc822Name(ia5(&name)?),
+            t if t == context_implicit(2) => GeneralName::DnsName(ia5(&name)?),
+            t if t == context_explicit(4) => {
+                let mut inner = name.children()?;
+                let dn = DistinguishedName::from_der(&inner.expect(TAG_SEQUENCE)?)?;
+                inner.expect_end()?;
+                GeneralName::DirectoryName(dn)
+            },
+            t if t == context_implicit(6) => GeneralName::Uri(ia5(&name)?),
+            t if t == context_implicit(7) => {
+                if name.value.len() != 4 && name.value.len() != 16 {
+                    return Err(anyhow!("Malformed iPAddress length: {}", name.value.len()));
+                }
+                GeneralName::IpAddress(name.value.to_vec())
+            },
+            t => GeneralName::Other(t, name.value.to_vec()),
+        };
+        names.push(general_name);
+    }
+    Ok(names)
+}
+
+fn parse_basic_constraints(element: &DerElement) -> Result<BasicConstraints> {
+    element.expect_tag(TAG_SEQUENCE)?;
+    let mut reader = element.children()?;
+    let ca = match reader.read_optional(TAG_BOOLEAN)? {
+        Some(b) => b.as_bool()?,
+        None => false,
+    };
+    let path_len_constraint = match reader.read_optional(TAG_INTEGER)? {
+        Some(n) => Some(u32::try_from(n.as_u64()?).map_err(|_| anyhow!("pathLenConstraint is too large"))?),
+        None => None,
+    };
+    reader.expect_end()?;
+    Ok(BasicConstraints {
+        ca,
+        path_len_constraint,
+    })
+}
+
+fn parse_key_usage(element: &DerElement) -> Result<KeyUsage> {
+    let (_, bits) = element.as_bit_string()?;
+    if bits.len() > 2 {
+        return Err(anyhow!("KeyUsage bit string is too long"));
+    }
+    let mut usage: u16 = 0;
+    for (i, byte) in bits.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                usage |= 1 << (i * 8 + bit);
+            }
+        }
+    }
+    Ok(KeyUsage(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEAF_DER: &[u8] = include_bytes!("../testdata/leaf.der");
+    const ROOT_DER: &[u8] = include_bytes!("../testdata/root.der");
+
+    #[test]
+    fn test_can_parse_leaf_certificate() {
+        let cert = Certificate::from_der(LEAF_DER).unwrap();
+        assert_eq!(3, cert.version);
+        assert_eq!(vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef], cert.serial_number);
+        assert!(cert.signature_algorithm.algorithm == *OID_SHA256_WITH_RSA);
+        assert_eq!("C=JP, O=toyssl, CN=toyssl Test Intermediate CA", cert.issuer.to_string());
+        assert_eq!("C=JP, O=toyssl, CN=localhost", cert.subject.to_string());
+        assert_eq!(Some("localhost"), cert.subject.common_name());
+        assert!(cert.subject_public_key_info.algorithm.algorithm == *OID_RSA_ENCRYPTION);
+        assert_eq!(256, cert.signature_value.len());
+        assert!(!cert.is_self_issued());
+        // TBS is the first element of the certificate sequence
+        assert_eq!(&LEAF_DER[4..4 + cert.tbs_certificate.len()], &cert.tbs_certificate[..]);
+    }
+
+    #[test]
+    fn test_can_parse_validity_in_both_time_formats() {
+        let cert = Certificate::from_der(LEAF_DER).unwrap();
+        // notBefore is a UTCTime and notAfter (after 2049) a GeneralizedTime
+        assert!(cert.validity.not_before.year >= 2020);
+        assert_eq!(2126, cert.validity.not_after.year);
+        assert!(cert.validity.not_before < cert.validity.not_after);
+    }
+
+    #[test]
+    fn test_can_parse_leaf_extensions() {
+        let cert = Certificate::from_der(LEAF_DER).unwrap();
+        assert_eq!(
+            vec![
+                GeneralName::DnsName(String::from("localhost")),
+                GeneralName::DnsName(String::from("*.example.com")),
+                GeneralName::IpAddress(vec![127, 0, 0, 1]),
+                GeneralName::IpAddress(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            ],
+            cert.subject_alt_names
+        );
+        assert_eq!(Some(BasicConstraints { ca: false, path_len_constraint: None }), cert.basic_constraints);
+        let key_usage = cert.key_usage.unwrap();
+        assert!(key_usage.contains(KeyUsage::DIGITAL_SIGNATURE | KeyUsage::KEY_ENCIPHERMENT));
+        assert!(!key_usage.contains(KeyUsage::KEY_CERT_SIGN));
+        assert_eq!(Some(vec![ObjectIdentifier(OID_KP_SERVER_AUTH.to_vec())]), cert.extended_key_usage);
+        assert_eq!(20, cert.subject_key_identifier.as_ref().unwrap().len());
+        assert_eq!(20, cert.authority_key_identifier.as_ref().unwrap().len());
+        assert!(cert.extension(OID_BASIC_CONSTRAINTS).unwrap().critical);
+        assert!(!cert.extension(OID_SUBJECT_ALT_NAME).unwrap().critical);
+    }
+
+    #[test]
+    fn test_can_parse_root_certificate() {
+        let cert = Certificate::from_der(ROOT_DER).unwrap();
+        assert!(cert.is_self_issued());
+        assert_eq!(vec![0x01], cert.serial_number);
+        assert_eq!(Some(BasicConstraints { ca: true, path_len_constraint: None }), cert.basic_constraints);
+        assert!(cert.key_usage.unwrap().contains(KeyUsage::KEY_CERT_SIGN | KeyUsage::CRL_SIGN));
+        assert!(cert.subject_alt_names.is_empty());
+        assert!(cert.authority_key_identifier.is_none());
+    }
+
+    #[test]
+    fn test_can_return_error_path_len_constraint_too_large() {
+        // SEQUENCE { BOOLEAN TRUE, INTEGER n }
+        let constraints = |n: &[u8]| {
+            let mut der = vec![0x30, 5 + n.len() as u8, 0x01, 0x01, 0xff, 0x02, n.len() as u8];
+            der.extend_from_slice(n);
+            parse_basic_constraints(&asn1::parse(&der).unwrap())
+        };
+        assert_eq!(Some(0xffff_ffff), constraints(&[0x00, 0xff, 0xff, 0xff, 0xff]).unwrap().path_len_constraint);
+        // 2^32 must not wrap around to 0
+        assert!(constraints(&[0x01, 0x00, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_can_return_error_truncated_certificate() {
+        let result = Certificate::from_der(&LEAF_DER[..LEAF_DER.len() - 1]);
+        assert!(result.is_err());
+    }
+}